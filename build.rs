@@ -0,0 +1,56 @@
+extern crate linkgen;
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use linkgen::{FixedBytes, Kind, MemoryMap, Placement, Region};
+
+
+/// Memory map of the MK64FN1M0VLL12. See chapter 4 of the K64 reference manual.
+///
+/// SRAM is split in two at 0x2000_0000, and no access may span the boundary. The statics and stack live in SRAM_L,
+/// and the heap gets all of SRAM_U.
+fn k64() -> MemoryMap {
+    MemoryMap{
+        name: "MK64FN1M0VLL12",
+        regions: vec![
+            Region{name: "FLASH",  origin: 0x0000_0000, length: 0x0010_0000, kind: Kind::Flash},  // 1MB
+            Region{name: "SRAM_L", origin: 0x1FFF_0000, length: 0x0001_0000, kind: Kind::Ram},    // 64KB
+            Region{name: "SRAM_U", origin: 0x2000_0000, length: 0x0003_0000, kind: Kind::Ram},    // 192KB
+        ],
+        vector_section: ".interrupt_table",
+        vector_table_size: 0x400,
+        fixed: vec![
+            // flash configuration field (section 29.3.1), loaded into the FTFE registers on reset.
+            // __NOTE:__ any FSEC other than 0xFE secures the chip, and only a mass erase will recover it
+            FixedBytes{section: ".flash_config", offset: 0x400, bytes: vec![
+                0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // backdoor comparison key
+                0xFF, 0xFF, 0xFF, 0xFF,                         // FPROT3..0: no program flash protection
+                0xFE,                                           // FSEC: unsecured, backdoor key disabled
+                0xFF,                                           // FOPT: defaults (NMI enabled, boot from flash)
+                0xFF,                                           // FEPROT: no EEPROM protection
+                0xFF,                                           // FDPROT: no data flash protection
+            ]},
+        ],
+        statics: "SRAM_L",
+        heap: Placement{region: "SRAM_U", size: 0x0003_0000},
+        stack: Placement{region: "SRAM_L", size: 0x0000_4000}, // 16KB
+    }
+}
+
+fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR is not set"));
+
+    let script = match linkgen::generate(&k64()) {
+        Ok(s) => s,
+        Err(e) => panic!("invalid K64 memory map: {}", e),
+    };
+    File::create(out_dir.join("MK64FN1M0VLL12.ld"))
+        .and_then(|mut f| f.write_all(script.as_bytes()))
+        .expect("could not write the K64 linker script");
+
+    println!("cargo:rustc-link-search={}", out_dir.display());
+    println!("cargo:rerun-if-changed=build.rs");
+}
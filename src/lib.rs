@@ -1,12 +1,20 @@
 #![no_std]
 #![plugin(dynamo)]
 
+#![feature(asm)]
 #![feature(plugin)]
 #![feature(const_fn)]
 #![feature(lang_items)]
+#![feature(linkage)]
+#![feature(naked_functions)]
 #![feature(core_intrinsics)]
 #![feature(associated_consts)]
 
+// the tests run on the host, where std stands in for the core registers (see os::sync)
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
 
 //------------------------------------------------
 //
@@ -19,3 +27,7 @@ pub mod mcus;
 pub mod os;
 
 pub mod traits;
+
+// fixtures shared by the tests of several modules
+#[cfg(test)]
+mod test_support;
@@ -1,4 +1,5 @@
 use ::libc::memory::IOVec;
+use ::os::error::BitmapError;
 
 
 pub struct Bitmap {
@@ -82,9 +83,9 @@ impl Bitmap {
     /// bmap.set(7); // set the 7th bit
     /// assert!(bmap.is_set(7));
     /// ```
-    pub fn set(&mut self, index: usize) -> Result<(), &'static str> {
+    pub fn set(&mut self, index: usize) -> Result<(), BitmapError> {
         if index > (self.total-1) {
-            return Err("requested index to set is not in the bounds of the bitmap");
+            return Err(BitmapError::OutOfBounds);
         }
 
         unsafe { *(self.mem.as_mut().offset((index/8) as isize)) |= 1 << (index % 8); }
@@ -106,16 +107,16 @@ impl Bitmap {
     /// bmap.clear(5);
     /// assert!(bmap.checked_set(5).is_ok());
     /// ```
-    pub fn checked_set(&mut self, index: usize) -> Result<(), &'static str> {
+    pub fn checked_set(&mut self, index: usize) -> Result<(), BitmapError> {
         if index > self.total {
-            return Err("requested index to set is not in the bounds of the bitmap");
+            return Err(BitmapError::OutOfBounds);
         }
 
         let mask = 1 << (index % 8);
         let ptr = unsafe { self.mem.as_mut().offset((index/8) as isize) };
 
         if unsafe { *ptr } & mask != 0 {
-            return Err("bit was already set!");
+            return Err(BitmapError::AlreadySet);
         }
 
         unsafe { *ptr |= mask; }
@@ -137,7 +138,7 @@ impl Bitmap {
     /// bmap.clear_range(0, arr.len()*8).expect("could not clear");
     /// assert_eq!(0, bmap.used());
     /// ```
-    pub fn set_range(&mut self, index: usize, count: usize) -> Result<(), &'static str> {
+    pub fn set_range(&mut self, index: usize, count: usize) -> Result<(), BitmapError> {
         // TODO: this can be optimized wayyyyyyy more. but naive is ok for POC impl
         for i in index..(index+count) {
             try!(self.set(i));     // TODO: checked_set?
@@ -164,9 +165,9 @@ impl Bitmap {
     /// bmap.clear(1);                          // clear bit 1
     /// assert_eq!(0, bmap.used());                // assert nothing is used
     /// ```
-    pub fn clear(&mut self, index: usize) -> Result<(), &'static str> {
+    pub fn clear(&mut self, index: usize) -> Result<(), BitmapError> {
         if index > (self.total-1) {
-            return Err("requested index to clear is not in the bounds of the bitmap");
+            return Err(BitmapError::OutOfBounds);
         }
 
         unsafe { *(self.mem.as_mut().offset((index/8) as isize)) &= !(1 << (index % 8)); }
@@ -188,7 +189,7 @@ impl Bitmap {
     /// bmap.clear_range(0, arr.len()*8).expect("could not clear");
     /// assert_eq!(0, bmap.used());
     /// ```
-    pub fn clear_range(&mut self, index: usize, count: usize) -> Result<(), &'static str> {
+    pub fn clear_range(&mut self, index: usize, count: usize) -> Result<(), BitmapError> {
         // TODO: this can be optimized wayyyyyyy more. but naive is ok for POC impl
         for i in index..(index+count) {
             try!(self.clear(i)); // TODO: checked_clear?
@@ -209,16 +210,16 @@ impl Bitmap {
     /// assert!(bmap.set(5).is_ok());
     /// assert!(bmap.checked_clear(5).is_ok());
     /// ```
-    pub fn checked_clear(&mut self, index: usize) -> Result<(), &'static str> {
+    pub fn checked_clear(&mut self, index: usize) -> Result<(), BitmapError> {
         if index > self.total {
-            return Err("requested index to clear is not in the bounds of the bitmap");
+            return Err(BitmapError::OutOfBounds);
         }
 
         let mask = 1 << (index % 8);
         let ptr = unsafe { self.mem.as_mut().offset((index/8) as isize) };
 
         if unsafe { *ptr } & mask == 0 {
-            return Err("bit was already clear!");
+            return Err(BitmapError::AlreadyClear);
         }
 
         unsafe { *ptr &= !mask; }
@@ -283,9 +284,9 @@ impl Bitmap {
     /// bmap.find_and_set(4).unwrap();      // find and set the gap
     /// assert_eq!(6, bmap.used());
     /// ```
-    pub fn find_and_set(&mut self, count: usize) -> Result<usize, &'static str> {
+    pub fn find_and_set(&mut self, count: usize) -> Result<usize, BitmapError> {
         let found = self.find(count);
-        if found.is_none() { return Err("could not find enough contiguous bits"); }
+        if found.is_none() { return Err(BitmapError::NoSpace); }
 
         let bit = found.unwrap();
         let result = self.set_range(bit, count);
@@ -322,17 +323,17 @@ impl Bitmap {
     /// // but we CAN find it unbounded
     /// assert!(bmap.find_and_set(2).is_ok());
     /// ```
-    pub fn bounded_find_and_set(&mut self, count: usize, bound: usize) -> Result<usize, &'static str> {
+    pub fn bounded_find_and_set(&mut self, count: usize, bound: usize) -> Result<usize, BitmapError> {
         if bound >= self.count() {
-            return Err("bound is beyond the number of bits in the bitmap");
+            return Err(BitmapError::BoundTooLarge);
         }
 
         let found = self.find(count);
-        if found.is_none() { return Err("could not find enough contiguous bits"); }
+        if found.is_none() { return Err(BitmapError::NoSpace); }
 
         let bit = found.unwrap();
         if (bit + count) > bound {
-            return Err("no empty bits within the bounds");
+            return Err(BitmapError::NoSpaceInBounds);
         }
 
         let result = self.set_range(bit, count);
@@ -407,6 +408,19 @@ mod tests {
         assert!(bmap.checked_clear(bit).is_err());
     }
 
+    #[test]
+    fn error_kinds() {
+        use ::os::error::BitmapError;
+        let buff = [0u8; 1];
+        let mut bmap = super::Bitmap::new(&buff[0] as *const u8, buff.len());
+
+        assert_eq!(Err(BitmapError::OutOfBounds), bmap.set(8));
+        assert_eq!(Err(BitmapError::AlreadyClear), bmap.checked_clear(3));
+        bmap.set_range(0, 8).expect("could not set");
+        assert_eq!(Err(BitmapError::AlreadySet), bmap.checked_set(3));
+        assert_eq!(Err(BitmapError::NoSpace), bmap.find_and_set(1));
+    }
+
     #[test]
     fn set_first_and_last() {
         let buff = [0u8; 16];
@@ -1,10 +1,12 @@
 extern crate core;
 use self::core::intrinsics::atomic_cxchg;
 
+use ::os::error::LockError;
+
 
 pub struct SpinLock { in_use: u8 }
 impl SpinLock {
-    pub fn new() -> SpinLock { SpinLock{in_use:0} }
+    pub const fn new() -> SpinLock { SpinLock{in_use:0} }
 
     #[inline(always)]
     pub fn is_locked(&self) -> bool { self.in_use == 1 }
@@ -18,9 +20,9 @@ impl SpinLock {
     }
 
     /// Release the lock, but return error if not locked.
-    pub fn release(&mut self) -> Result<(), &'static str> {
+    pub fn release(&mut self) -> Result<(), LockError> {
         if ! self.is_locked() {
-            Err("attempt to release unlocked lock")
+            Err(LockError::NotHeld)
         } else {
             self.in_use = 0;
             Ok(())
@@ -60,7 +62,7 @@ mod spin_lock {
     #[test]
     fn error_on_release_unlocked() {
         let mut lock = super::SpinLock::new();
-        assert!(lock.release().is_err());
+        assert_eq!(Err(::os::error::LockError::NotHeld), lock.release());
     }
 
     static mut WAIT_TEST_LOCK: super::SpinLock = super::SpinLock{in_use: 0};
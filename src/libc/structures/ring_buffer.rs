@@ -1,12 +1,13 @@
 extern crate core;
 
 use ::libc::memory::IOVec;
+use ::os::error::RingBufferError;
 
 pub struct RingBuffer<T: Sized> {
     mem: *const T,
     num_bytes: usize,
     scribe: usize,
-    has_rolled: bool,
+    len: usize,
 }
 impl<T> RingBuffer<T> {
     /// Creates a new ring buffer spanning the region defined by the IOVec.
@@ -18,7 +19,7 @@ impl<T> RingBuffer<T> {
             mem: mem.convert_mut_ptr::<T>(),
             num_bytes: mem.size,
             scribe: 0,
-            has_rolled: false,
+            len: 0,
         }
     }
 
@@ -32,7 +33,7 @@ impl<T> RingBuffer<T> {
             mem: &mem[0] as *const T,
             num_bytes: mem.len() * core::mem::size_of::<T>(),
             scribe: 0,
-            has_rolled: false,
+            len: 0,
         }
     }
 
@@ -41,6 +42,21 @@ impl<T> RingBuffer<T> {
         self.num_bytes / core::mem::size_of::<T>()
     }
 
+    /// Returns the number of items held, which stops growing at `.size()` as old items are overwritten.
+    pub fn len(&self) -> usize { self.len }
+
+    /// Check whether the buffer holds no items.
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// Check whether the next push will overwrite the oldest item.
+    pub fn is_full(&self) -> bool { self.len == self.size() }
+
+    /// Forget every item held.
+    pub fn clear(&mut self) {
+        self.scribe = 0;
+        self.len = 0;
+    }
+
     /// Get the value at the "relative index" of the ring buffer.
     ///
     /// By "relative index", the API means the following contract:
@@ -96,21 +112,14 @@ impl<T> RingBuffer<T> {
     ///     "incorrect value for get-oldest case"
     /// );
     /// ```
-    pub fn get(&self, index: usize) -> Result<&T, &'static str> {
-        if ! self.has_rolled {
-            if index >= self.scribe {
-                Err("index is beyond the items pushed so far")
-            } else {
-                match unsafe { self.mem.offset( index as isize ).as_ref() } {
-                    None => { Err("received nullptr from ring buffer lookup") }
-                    Some(r) => { Ok(r) }
-                }
-            }
-        } else if index >= self.size() {
-            Err("index is out of bounds")
+    pub fn get(&self, index: usize) -> Result<&T, RingBufferError> {
+        if index >= self.len {
+            if self.is_full() { Err(RingBufferError::OutOfBounds) } else { Err(RingBufferError::NotPushed) }
         } else {
-            match unsafe { self.mem.offset( ((self.scribe + index) % self.size()) as isize ).as_ref() } {
-                None => { Err("received nullptr from ring buffer lookup") }
+            // the oldest item is `len` behind the scribe
+            let offset = (self.scribe + self.size() - self.len + index) % self.size();
+            match unsafe { self.mem.offset( offset as isize ).as_ref() } {
+                None => { Err(RingBufferError::NullPointer) }
                 Some(r) => { Ok(r) }
             }
         }
@@ -154,10 +163,8 @@ impl<T> RingBuffer<T> {
     pub fn push(&mut self, val: T) {
         unsafe { *(self.scribe_ptr()) = val; }
         self.scribe += 1;
-        if self.scribe == self.size() {
-            self.scribe = 0;
-            self.has_rolled = true; // no sense if loading, checking, storing
-        }
+        if self.scribe == self.size() { self.scribe = 0; }
+        if self.len < self.size() { self.len += 1; }
     }
 
     /// Returns the newest element in the buffer.
@@ -180,17 +187,11 @@ impl<T> RingBuffer<T> {
     ///     assert_eq!(i, *buff.newest().unwrap());
     /// }
     /// ```
-    pub fn newest(&self) -> Result<&T, &'static str> {
-        //self.get(if self.scribe == 0 { self.size()-1 } else { self.scribe-1 })
-        if self.scribe == 0 {
-            self.get( self.size()-1 )
+    pub fn newest(&self) -> Result<&T, RingBufferError> {
+        if self.len == 0 {
+            Err(RingBufferError::NotPushed)
         } else {
-            let as_ref = unsafe { self.scribe_ptr().offset(-1).as_ref() };
-            if as_ref.is_none() {
-                Err("received nullptr from ring buffer lookup")
-            } else {
-                Ok(as_ref.unwrap())
-            }
+            self.get(self.len - 1)
         }
     }
 
@@ -218,10 +219,39 @@ impl<T> RingBuffer<T> {
     /// buff.push(0xDEADBEEF);
     /// assert_eq!(1, *buff.oldest().unwrap());
     /// ```
-    pub fn oldest(&self) -> Result<&T, &'static str> {
+    pub fn oldest(&self) -> Result<&T, RingBufferError> {
         self.get(0)
     }
 }
+impl<T: Copy> RingBuffer<T> {
+    /// Removes and returns the oldest element in the buffer, so the buffer can be used as a FIFO queue.
+    ///
+    /// Check `.is_full()` before pushing to queue without overwriting.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use peregrine::libc::structures::RingBuffer;
+    ///
+    /// let arr = [0u8; 4];
+    /// let mut queue = RingBuffer::from(&arr);
+    ///
+    /// for b in b"abc" { queue.push(*b); }
+    /// assert_eq!(b'a', queue.pop().unwrap());
+    /// assert_eq!(2, queue.len());
+    ///
+    /// // pops free room for new items
+    /// queue.push(b'd');
+    /// queue.push(b'e');
+    /// assert!(queue.is_full());
+    /// assert_eq!(b'b', queue.pop().unwrap());
+    /// ```
+    pub fn pop(&mut self) -> Result<T, RingBufferError> {
+        let oldest = *try!(self.oldest());
+        self.len -= 1;
+        Ok(oldest)
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -456,4 +486,71 @@ mod tests {
             }
         }
     }
+
+
+    //
+    // testing queue operations
+    //
+
+    mod queue {
+        pub use super::RingBuffer;
+
+        #[test]
+        fn len_stops_at_size() {
+            let arr = [0usize; 16];
+            let mut buff = super::RingBuffer::from(&arr);
+            assert!(buff.is_empty());
+
+            for i in 0..arr.len() {
+                assert_eq!(i, buff.len());
+                buff.push(i);
+            }
+            assert!(buff.is_full());
+
+            buff.push(0x1234);
+            assert_eq!(arr.len(), buff.len());
+        }
+
+        #[test]
+        fn pop_in_order() {
+            let arr = [0usize; 16];
+            let mut buff = super::RingBuffer::from(&arr);
+
+            for i in 0..10 { buff.push(i); }
+            for i in 0..10 { assert_eq!(i, buff.pop().expect("error from `pop`")); }
+            assert!(buff.is_empty());
+            assert!(buff.pop().is_err());
+        }
+
+        #[test]
+        fn interleaved_across_wrap() {
+            let arr = [0usize; 16];
+            let mut buff = super::RingBuffer::from(&arr);
+
+            let mut next = 0;
+            for i in 0..1_000 {
+                buff.push(i);
+                if buff.len() > 8 {
+                    assert_eq!(next, buff.pop().expect("error from `pop`"));
+                    next += 1;
+                }
+                assert_eq!(i, *buff.newest().expect("error from `newest`"));
+            }
+            assert_eq!(8, buff.len());
+            assert_eq!(next, *buff.oldest().expect("error from `oldest`"));
+        }
+
+        #[test]
+        fn pop_after_overwrite_is_oldest() {
+            let arr = [0usize; 16];
+            let mut buff = super::RingBuffer::from(&arr);
+
+            for i in 0..(arr.len() + 4) { buff.push(i); }
+            assert_eq!(4, buff.pop().expect("error from `pop`"));
+
+            buff.clear();
+            assert!(buff.is_empty());
+            assert!(buff.newest().is_err());
+        }
+    }
 }
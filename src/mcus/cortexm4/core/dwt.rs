@@ -0,0 +1,423 @@
+extern crate core;
+use core::intrinsics::{volatile_load, volatile_store};
+
+use ::os::error::DwtError;
+
+/// Number of comparators described by the `DWT` block. Cortex-M4 implements up to 4.
+pub const MAX_COMPARATORS: u8 = 4;
+
+/// Largest MASK the comparators accept, watching a 32KiB range.
+pub const MAX_WATCH_MASK: u32 = 15;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Core debug registers. Only the trace enable is needed, to power the DWT.
+ioreg!(
+    name => CoreDebug;
+    doc_srcs => [
+        "http://infocenter.arm.com/help/topic/com.arm.doc.ddi0403e.b/DDI0403E_B_armv7m_arm.pdf" // section C1.6
+    ];
+
+    0x0000 => dhcsr r32 ro {};
+
+    0x000C => demcr r32 rw {
+        24 => {
+            enable_trace => [0x1];
+            disable_trace => [0x0];
+        }
+    };
+);
+
+/// Core Data Watchpoint and Trace registers.
+///
+/// Nothing in the unit runs until trace is enabled through `CoreDebug`.
+ioreg!(
+    name => DWT;
+    doc_srcs => [
+        "http://infocenter.arm.com/help/topic/com.arm.doc.ddi0403e.b/DDI0403E_B_armv7m_arm.pdf" // section C1.8
+    ];
+
+    0x0000 => ctrl r32 rw {
+        0 => {
+            enable_cycle_count => [0x1];
+            disable_cycle_count => [0x0];
+        }
+    };
+
+    0x0004 => cyccnt r32 rw {
+        0..31 => { write_cyccnt => (); }
+    };
+
+    //
+    // comparators
+    //
+
+    0x0020 => comp0 r32 rw {
+        0..31 => { write_comp0 => (); }
+    };
+    0x0024 => mask0 r32 rw {
+        0..31 => { write_mask0 => (); }
+    };
+    0x0028 => function0 r32 rw {
+        0..31 => { write_function0 => (); }
+    };
+
+    0x0030 => comp1 r32 rw {
+        0..31 => { write_comp1 => (); }
+    };
+    0x0034 => mask1 r32 rw {
+        0..31 => { write_mask1 => (); }
+    };
+    0x0038 => function1 r32 rw {
+        0..31 => { write_function1 => (); }
+    };
+
+    0x0040 => comp2 r32 rw {
+        0..31 => { write_comp2 => (); }
+    };
+    0x0044 => mask2 r32 rw {
+        0..31 => { write_mask2 => (); }
+    };
+    0x0048 => function2 r32 rw {
+        0..31 => { write_function2 => (); }
+    };
+
+    0x0050 => comp3 r32 rw {
+        0..31 => { write_comp3 => (); }
+    };
+    0x0054 => mask3 r32 rw {
+        0..31 => { write_mask3 => (); }
+    };
+    0x0058 => function3 r32 rw {
+        0..31 => { write_function3 => (); }
+    };
+);
+
+impl DWT {
+    /// Powers the unit through `CoreDebug`, then restarts the cycle counter from zero.
+    pub fn enable_cycle_counter(&self, debug: &CoreDebug) {
+        debug.enable_trace();
+        self.write_cyccnt(0);
+        self.enable_cycle_count();
+    }
+}
+
+/// Raw access to the DWT registers, separating the register layout from the logic driving it.
+pub trait DwtRegisters {
+    /// Read CTRL.
+    fn control(&self) -> u32;
+    /// Read the free running cycle counter.
+    fn read_cycles(&self) -> u32;
+    /// Write the comparator, mask, and function registers of a comparator (`< MAX_COMPARATORS`).
+    fn write_comparator(&self, n: u8, comp: u32, mask: u32, function: u32);
+}
+
+impl DwtRegisters for DWT {
+    fn control(&self) -> u32 { self.read_ctrl() }
+    fn read_cycles(&self) -> u32 { self.read_cyccnt() }
+
+    fn write_comparator(&self, n: u8, comp: u32, mask: u32, function: u32) {
+        // the comparator is disabled while it is reprogrammed
+        match n {
+            0 => {
+                self.write_function0(0);
+                self.write_comp0(comp);
+                self.write_mask0(mask);
+                self.write_function0(function);
+            }
+            1 => {
+                self.write_function1(0);
+                self.write_comp1(comp);
+                self.write_mask1(mask);
+                self.write_function1(function);
+            }
+            2 => {
+                self.write_function2(0);
+                self.write_comp2(comp);
+                self.write_mask2(mask);
+                self.write_function2(function);
+            }
+            3 => {
+                self.write_function3(0);
+                self.write_comp3(comp);
+                self.write_mask3(mask);
+                self.write_function3(function);
+            }
+            _ => {}
+        }
+    }
+}
+
+
+//
+// time keeping
+//
+
+/// Convert a number of core cycles to nanoseconds at the given core clock, which must not be zero.
+///
+/// Whole seconds and the remainder are converted apart, so large counts do not overflow.
+pub fn cycles_to_nanos(cycles: u64, core_hz: u32) -> Result<u64, DwtError> {
+    if core_hz == 0 { return Err(DwtError::NoCoreClock); }
+    Ok(to_nanos(cycles, core_hz))
+}
+
+/// Convert a number of nanoseconds to core cycles at the given core clock, rounding down. The clock must not be
+/// zero.
+pub fn nanos_to_cycles(nanos: u64, core_hz: u32) -> Result<u64, DwtError> {
+    if core_hz == 0 { return Err(DwtError::NoCoreClock); }
+    let hz = core_hz as u64;
+    Ok((nanos / NANOS_PER_SECOND) * hz + ((nanos % NANOS_PER_SECOND) * hz) / NANOS_PER_SECOND)
+}
+
+// core_hz has been checked to be non-zero
+fn to_nanos(cycles: u64, core_hz: u32) -> u64 {
+    let hz = core_hz as u64;
+    (cycles / hz) * NANOS_PER_SECOND + ((cycles % hz) * NANOS_PER_SECOND) / hz
+}
+
+/// 64 bit cycle count, extending the 32 bit CYCCNT by counting its rollovers.
+///
+/// A rollover is only seen when the counter is read, so `now()` must be called at least once per 2^32 cycles (about
+/// 35 seconds at 120MHz), such as from a periodic interrupt.
+pub struct Timestamp<'a, R: 'a + DwtRegisters> {
+    regs: &'a R,
+    core_hz: u32,
+    last: u32,
+    rollovers: u32,
+}
+impl<'a, R: DwtRegisters> Timestamp<'a, R> {
+    /// Creates a timestamp source counting from the current CYCCNT, with the core running at `core_hz`, which must
+    /// not be zero.
+    pub fn new(regs: &'a R, core_hz: u32) -> Result<Timestamp<'a, R>, DwtError> {
+        if core_hz == 0 { return Err(DwtError::NoCoreClock); }
+        Ok(Timestamp{regs: regs, core_hz: core_hz, last: regs.read_cycles(), rollovers: 0})
+    }
+
+    /// Get CYCCNT, extended with the rollovers seen since the source was created.
+    pub fn now(&mut self) -> u64 {
+        let cycles = self.regs.read_cycles();
+        if cycles < self.last { self.rollovers += 1; }
+        self.last = cycles;
+        ((self.rollovers as u64) << 32) | (cycles as u64)
+    }
+
+    /// Get `now()` in nanoseconds.
+    pub fn now_nanos(&mut self) -> u64 {
+        let cycles = self.now();
+        to_nanos(cycles, self.core_hz)
+    }
+
+    /// Get the core clock the timestamps are converted with.
+    pub fn core_hz(&self) -> u32 { self.core_hz }
+}
+
+/// Measures the cycles between two points from the raw CYCCNT, without tracking rollovers.
+///
+/// Intervals of up to 2^32 - 1 cycles are measured correctly, as the difference wraps along with the counter.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Stopwatch {
+    start: u32,
+}
+impl Stopwatch {
+    /// Start measuring from now.
+    pub fn start<R: DwtRegisters>(regs: &R) -> Stopwatch {
+        Stopwatch{start: regs.read_cycles()}
+    }
+
+    /// Get the cycles elapsed since the stopwatch was started.
+    pub fn elapsed<R: DwtRegisters>(&self, regs: &R) -> u32 {
+        regs.read_cycles().wrapping_sub(self.start)
+    }
+
+    /// Get the time elapsed since the stopwatch was started, in nanoseconds. The core clock must not be zero.
+    pub fn elapsed_nanos<R: DwtRegisters>(&self, regs: &R, core_hz: u32) -> Result<u64, DwtError> {
+        cycles_to_nanos(self.elapsed(regs) as u64, core_hz)
+    }
+
+    /// Get the cycles elapsed, and start measuring again from now.
+    pub fn lap<R: DwtRegisters>(&mut self, regs: &R) -> u32 {
+        let now = regs.read_cycles();
+        let elapsed = now.wrapping_sub(self.start);
+        self.start = now;
+        elapsed
+    }
+}
+
+
+//
+// watchpoints
+//
+
+/// Accesses a watchpoint triggers a debug event on (DWT_FUNCTION.FUNCTION).
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Watch {
+    Read = 0b0101,
+    Write = 0b0110,
+    ReadWrite = 0b0111,
+}
+
+/// Get the number of comparators the DWT implements.
+pub fn comparators<R: DwtRegisters>(regs: &R) -> u8 {
+    let implemented = (regs.control() >> 28) as u8;
+    if implemented > MAX_COMPARATORS { MAX_COMPARATORS } else { implemented }
+}
+
+/// Encode a watchpoint on the `size` bytes at `addr` into the COMP, MASK, and FUNCTION values.
+///
+/// The size must be a power of two no larger than 2^`MAX_WATCH_MASK`, and the address aligned to it.
+pub fn encode_watchpoint(addr: u32, size: u32, watch: Watch) -> Result<(u32, u32, u32), DwtError> {
+    if ! size.is_power_of_two() { return Err(DwtError::NotPowerOfTwo); }
+    let mask = size.trailing_zeros();
+    if mask > MAX_WATCH_MASK { return Err(DwtError::TooLarge); }
+    if (addr & (size - 1)) != 0 { return Err(DwtError::Misaligned); }
+    Ok((addr, mask, watch as u32))
+}
+
+/// Watch the `size` bytes at `addr` with the given comparator. A hit raises a debug monitor exception, or halts
+/// the core when a debugger is attached.
+pub fn set_watchpoint<R: DwtRegisters>(regs: &R, n: u8, addr: u32, size: u32, watch: Watch)
+    -> Result<(), DwtError>
+{
+    if n >= comparators(regs) { return Err(DwtError::BadComparator); }
+    let (comp, mask, function) = try!(encode_watchpoint(addr, size, watch));
+    regs.write_comparator(n, comp, mask, function);
+    Ok(())
+}
+
+/// Disable the given comparator.
+pub fn clear_watchpoint<R: DwtRegisters>(regs: &R, n: u8) -> Result<(), DwtError> {
+    if n >= comparators(regs) { return Err(DwtError::BadComparator); }
+    regs.write_comparator(n, 0, 0, 0);
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate core;
+    use self::core::cell::Cell;
+    use super::DwtRegisters;
+
+    /// A cycle counter that can be moved by hand, with 4 comparators.
+    struct MockDwt {
+        cycles: Cell<u32>,
+        last_comparator: Cell<(u8, u32, u32, u32)>,
+    }
+    impl MockDwt {
+        fn new(cycles: u32) -> MockDwt {
+            MockDwt{cycles: Cell::new(cycles), last_comparator: Cell::new((0xFF, 0, 0, 0))}
+        }
+        fn advance(&self, by: u32) { self.cycles.set(self.cycles.get().wrapping_add(by)); }
+    }
+    impl DwtRegisters for MockDwt {
+        fn control(&self) -> u32 { 4 << 28 }
+        fn read_cycles(&self) -> u32 { self.cycles.get() }
+        fn write_comparator(&self, n: u8, comp: u32, mask: u32, function: u32) {
+            self.last_comparator.set((n, comp, mask, function));
+        }
+    }
+
+    mod conversion {
+        use ::os::error::DwtError;
+        use super::super::{cycles_to_nanos, nanos_to_cycles};
+
+        #[test]
+        fn at_120mhz() {
+            assert_eq!(Ok(0), cycles_to_nanos(0, 120_000_000));
+            assert_eq!(Ok(8), cycles_to_nanos(1, 120_000_000));         // 8.33ns rounds down
+            assert_eq!(Ok(1_000), cycles_to_nanos(120, 120_000_000));
+            assert_eq!(Ok(1_000_000_000), cycles_to_nanos(120_000_000, 120_000_000));
+            assert_eq!(Ok(120), nanos_to_cycles(1_000, 120_000_000));
+        }
+
+        #[test]
+        fn large_counts_do_not_overflow() {
+            let day = 120_000_000u64 * 86_400;
+            assert_eq!(Ok(86_400 * 1_000_000_000), cycles_to_nanos(day, 120_000_000));
+            assert_eq!(Ok(day), nanos_to_cycles(86_400 * 1_000_000_000, 120_000_000));
+        }
+
+        #[test]
+        fn no_core_clock() {
+            assert_eq!(Err(DwtError::NoCoreClock), cycles_to_nanos(120, 0));
+            assert_eq!(Err(DwtError::NoCoreClock), nanos_to_cycles(1_000, 0));
+        }
+    }
+
+    mod timestamp {
+        use ::os::error::DwtError;
+        use super::MockDwt;
+        use super::super::{Timestamp, Stopwatch};
+
+        #[test]
+        fn tracks_rollover() {
+            let dwt = MockDwt::new(0xFFFF_FF00);
+            let mut ts = Timestamp::new(&dwt, 120_000_000).unwrap();
+            assert_eq!(0xFFFF_FF00, ts.now());
+
+            dwt.advance(0x200);
+            assert_eq!(0x1_0000_0100, ts.now());
+            dwt.advance(0xFFFF_FFFF);
+            assert_eq!(0x2_0000_00FF, ts.now());
+            assert_eq!(0x2_0000_00FF, ts.now()); // no change, no rollover
+        }
+
+        #[test]
+        fn nanos() {
+            let dwt = MockDwt::new(0);
+            let mut ts = Timestamp::new(&dwt, 120_000_000).unwrap();
+            dwt.advance(1_200);
+            assert_eq!(10_000, ts.now_nanos());
+        }
+
+        #[test]
+        fn no_core_clock() {
+            let dwt = MockDwt::new(0);
+            assert_eq!(Err(DwtError::NoCoreClock), Timestamp::new(&dwt, 0).map(|ts| ts.core_hz()));
+            assert_eq!(Err(DwtError::NoCoreClock), Stopwatch::start(&dwt).elapsed_nanos(&dwt, 0));
+        }
+
+        #[test]
+        fn stopwatch_across_wrap() {
+            let dwt = MockDwt::new(0xFFFF_FFF0);
+            let mut watch = Stopwatch::start(&dwt);
+            dwt.advance(0x20);
+            assert_eq!(0x20, watch.elapsed(&dwt));
+            assert_eq!(0x20, watch.lap(&dwt));
+            dwt.advance(120);
+            assert_eq!(Ok(1_000), watch.elapsed_nanos(&dwt, 120_000_000));
+        }
+    }
+
+    mod watchpoint {
+        use ::os::error::DwtError;
+        use super::MockDwt;
+        use super::super::{Watch, comparators, encode_watchpoint, set_watchpoint, clear_watchpoint};
+
+        #[test]
+        fn encoding() {
+            assert_eq!(Ok((0x2000_0100, 2, 0b0110)), encode_watchpoint(0x2000_0100, 4, Watch::Write));
+            assert_eq!(Ok((0x2000_0101, 0, 0b0101)), encode_watchpoint(0x2000_0101, 1, Watch::Read));
+            assert_eq!(Err(DwtError::Misaligned), encode_watchpoint(0x2000_0102, 4, Watch::ReadWrite));
+            assert_eq!(Err(DwtError::NotPowerOfTwo), encode_watchpoint(0x2000_0000, 12, Watch::ReadWrite));
+            assert_eq!(Err(DwtError::NotPowerOfTwo), encode_watchpoint(0x2000_0000, 0, Watch::ReadWrite));
+            assert_eq!(Err(DwtError::TooLarge), encode_watchpoint(0x2000_0000, 0x1_0000, Watch::ReadWrite));
+        }
+
+        #[test]
+        fn sets_and_clears() {
+            let dwt = MockDwt::new(0);
+            assert_eq!(4, comparators(&dwt));
+            set_watchpoint(&dwt, 1, 0x1FFF_8000, 32, Watch::Write).unwrap();
+            assert_eq!((1, 0x1FFF_8000, 5, 0b0110), dwt.last_comparator.get());
+            clear_watchpoint(&dwt, 1).unwrap();
+            assert_eq!((1, 0, 0, 0), dwt.last_comparator.get());
+            assert_eq!(Err(DwtError::BadComparator), set_watchpoint(&dwt, 4, 0, 4, Watch::Read));
+        }
+    }
+}
@@ -1,36 +1,331 @@
 extern crate core;
 use core::intrinsics::{volatile_load, volatile_store};
 
+use ::os::sync;
+
+/// Coprocessor Access Control Register (CPACR). CP10 and CP11 (the FPU) must be given the same access.
 ioreg!(
     name => Access;
     init => pub fn init(&self) {
         self.full_access();
-        // TODO: flush the bus
+        // the access takes effect for the instructions after the barriers
+        sync::data_barrier();
+        sync::instruction_barrier();
     };
 
     0x000 => access r32 rw{
-        20..23 => {
+        20..23 => { // CP10 in 20..21, CP11 in 22..23
             disallow => [0x0];
-            priviledged => [0x1];
-            full_access => [0x3];
+            priviledged => [0x5];
+            full_access => [0xF];
         }
     };
 );
 
+/// Floating point context control registers (FPCCR, FPCAR, FPDSCR).
+///
+/// Automatic and lazy state saving are both enabled out of reset: an exception taken with an active FP context
+/// reserves stack space for S0-S15 and FPSCR, which are only written if the handler uses the FPU.
 ioreg!(
     name => Unit;
+    doc_srcs => [
+        "http://infocenter.arm.com/help/topic/com.arm.doc.dui0553a/DUI0553A_cortex_m4_dgug.pdf" // section 4.6
+    ];
 
     0x0000 => context r32 rw {
         31 => {
             enable_automatic_state_saving => [0x1];
-            disable_automatic_state_saving => [0x1];
+            disable_automatic_state_saving => [0x0];
         }
 
         30 => {
             enable_lazy_state_saving => [0x1];
-            disable_lazy_state_saving => [0x1];
+            disable_lazy_state_saving => [0x0];
         }
     };
 
     0x0004 => context_address r32 ro {};
+
+    0x0008 => default_status_control r32 rw {
+        0..31 => { write_default_status_control => (); }
+    };
 );
+
+impl Unit {
+    /// Get the decoded FPCCR.
+    pub fn context_status(&self) -> ContextStatus { ContextStatus::from_fpccr(self.read_context()) }
+
+    /// Get the FPSCR settings given to new FP contexts.
+    pub fn defaults(&self) -> Defaults { Defaults::from_bits(self.read_default_status_control()) }
+
+    /// Set the FPSCR settings given to new FP contexts, such as on exception entry.
+    pub fn set_defaults(&self, defaults: &Defaults) { self.write_default_status_control(defaults.bits()); }
+}
+
+
+//
+// context control
+//
+
+// FPCCR
+const FPCCR_LSPACT: u32 = 1 << 0;
+const FPCCR_USER: u32 = 1 << 1;
+const FPCCR_THREAD: u32 = 1 << 3;
+const FPCCR_HFRDY: u32 = 1 << 4;
+const FPCCR_MMRDY: u32 = 1 << 5;
+const FPCCR_BFRDY: u32 = 1 << 6;
+const FPCCR_MONRDY: u32 = 1 << 8;
+const FPCCR_LSPEN: u32 = 1 << 30;
+const FPCCR_ASPEN: u32 = 1 << 31;
+
+/// Decoded FPCCR.
+///
+/// While `lazy_pending` is set, stack space has been reserved at FPCAR for an FP frame that has not been written.
+/// The remaining flags tell which handlers could have taken over the preservation.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ContextStatus {
+    pub automatic_saving: bool,
+    pub lazy_saving: bool,
+    pub lazy_pending: bool,
+    /// The pending frame was reserved in unprivileged mode.
+    pub user: bool,
+    /// The pending frame was reserved in thread mode.
+    pub thread: bool,
+    pub hard_fault_ready: bool,
+    pub mem_manage_ready: bool,
+    pub bus_fault_ready: bool,
+    pub monitor_ready: bool,
+}
+impl ContextStatus {
+    /// Decode an FPCCR value.
+    pub fn from_fpccr(fpccr: u32) -> ContextStatus {
+        ContextStatus{
+            automatic_saving: (fpccr & FPCCR_ASPEN) != 0,
+            lazy_saving: (fpccr & FPCCR_LSPEN) != 0,
+            lazy_pending: (fpccr & FPCCR_LSPACT) != 0,
+            user: (fpccr & FPCCR_USER) != 0,
+            thread: (fpccr & FPCCR_THREAD) != 0,
+            hard_fault_ready: (fpccr & FPCCR_HFRDY) != 0,
+            mem_manage_ready: (fpccr & FPCCR_MMRDY) != 0,
+            bus_fault_ready: (fpccr & FPCCR_BFRDY) != 0,
+            monitor_ready: (fpccr & FPCCR_MONRDY) != 0,
+        }
+    }
+}
+
+
+//
+// status and control
+//
+
+// FPSCR/FPDSCR
+const FPSCR_IOC: u32 = 1 << 0;
+const FPSCR_DZC: u32 = 1 << 1;
+const FPSCR_OFC: u32 = 1 << 2;
+const FPSCR_UFC: u32 = 1 << 3;
+const FPSCR_IXC: u32 = 1 << 4;
+const FPSCR_IDC: u32 = 1 << 7;
+const FPSCR_RMODE_SHIFT: u32 = 22;
+const FPSCR_FZ: u32 = 1 << 24;
+const FPSCR_DN: u32 = 1 << 25;
+const FPSCR_AHP: u32 = 1 << 26;
+
+/// Mask of the cumulative exception flags in FPSCR.
+pub const EXCEPTION_FLAGS_MASK: u32 = FPSCR_IOC | FPSCR_DZC | FPSCR_OFC | FPSCR_UFC | FPSCR_IXC | FPSCR_IDC;
+
+/// FPSCR.RMode.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum RoundingMode {
+    Nearest = 0,
+    PlusInfinity = 1,
+    MinusInfinity = 2,
+    Zero = 3,
+}
+impl RoundingMode {
+    fn from_bits(bits: u32) -> RoundingMode {
+        match bits & 0x3 {
+            0 => RoundingMode::Nearest,
+            1 => RoundingMode::PlusInfinity,
+            2 => RoundingMode::MinusInfinity,
+            _ => RoundingMode::Zero,
+        }
+    }
+}
+
+/// The settings of FPSCR a new FP context starts with (FPDSCR). The reset value is all off, rounding to nearest.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Defaults {
+    pub rounding: RoundingMode,
+    /// Denormal inputs and results are flushed to zero.
+    pub flush_to_zero: bool,
+    /// Operations involving a NaN return the default NaN, rather than propagating the input.
+    pub default_nan: bool,
+    /// Half precision values use the alternative format (no infinities or NaNs).
+    pub alternative_half: bool,
+}
+impl Defaults {
+    /// Decode an FPDSCR (or FPSCR) value.
+    pub fn from_bits(bits: u32) -> Defaults {
+        Defaults{
+            rounding: RoundingMode::from_bits(bits >> FPSCR_RMODE_SHIFT),
+            flush_to_zero: (bits & FPSCR_FZ) != 0,
+            default_nan: (bits & FPSCR_DN) != 0,
+            alternative_half: (bits & FPSCR_AHP) != 0,
+        }
+    }
+
+    /// Encode as an FPDSCR value.
+    pub fn bits(&self) -> u32 {
+        let mut bits = (self.rounding as u32) << FPSCR_RMODE_SHIFT;
+        if self.flush_to_zero { bits |= FPSCR_FZ; }
+        if self.default_nan { bits |= FPSCR_DN; }
+        if self.alternative_half { bits |= FPSCR_AHP; }
+        bits
+    }
+}
+
+/// The cumulative exception flags of FPSCR. Each stays set from the first operation raising it until cleared.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ExceptionFlags {
+    pub invalid_operation: bool,
+    pub divide_by_zero: bool,
+    pub overflow: bool,
+    pub underflow: bool,
+    pub inexact: bool,
+    pub input_denormal: bool,
+}
+impl ExceptionFlags {
+    /// Decode the flags from an FPSCR value.
+    pub fn from_fpscr(fpscr: u32) -> ExceptionFlags {
+        ExceptionFlags{
+            invalid_operation: (fpscr & FPSCR_IOC) != 0,
+            divide_by_zero: (fpscr & FPSCR_DZC) != 0,
+            overflow: (fpscr & FPSCR_OFC) != 0,
+            underflow: (fpscr & FPSCR_UFC) != 0,
+            inexact: (fpscr & FPSCR_IXC) != 0,
+            input_denormal: (fpscr & FPSCR_IDC) != 0,
+        }
+    }
+
+    /// Check whether any flag is set.
+    pub fn any(&self) -> bool {
+        self.invalid_operation || self.divide_by_zero || self.overflow || self.underflow || self.inexact
+            || self.input_denormal
+    }
+
+    /// Check whether an operation produced an unusable result (invalid operation, divide by zero, or overflow).
+    /// Underflow and inexact results are routine.
+    pub fn any_serious(&self) -> bool {
+        self.invalid_operation || self.divide_by_zero || self.overflow
+    }
+}
+
+
+//
+// core registers
+//
+// FPSCR and CONTROL are core registers rather than memory mapped, so are only reachable on the target.
+//
+
+/// CONTROL bit set while the current context has used the FPU, so has FP state to preserve.
+#[cfg(target_arch = "arm")]
+const CONTROL_FPCA: u32 = 1 << 2;
+
+/// Read FPSCR.
+#[cfg(target_arch = "arm")]
+pub fn read_fpscr() -> u32 {
+    let fpscr: u32;
+    unsafe { asm!("vmrs $0, fpscr" : "=r"(fpscr) ::: "volatile"); }
+    fpscr
+}
+
+/// Write FPSCR.
+#[cfg(target_arch = "arm")]
+pub fn write_fpscr(fpscr: u32) {
+    unsafe { asm!("vmsr fpscr, $0" :: "r"(fpscr) :: "volatile"); }
+}
+
+/// Get the exception flags raised by the current context since they were last cleared.
+#[cfg(target_arch = "arm")]
+pub fn exception_flags() -> ExceptionFlags { ExceptionFlags::from_fpscr(read_fpscr()) }
+
+/// Clear the current context's exception flags, returning those that were set.
+#[cfg(target_arch = "arm")]
+pub fn clear_exception_flags() -> ExceptionFlags {
+    let fpscr = read_fpscr();
+    write_fpscr(fpscr & !EXCEPTION_FLAGS_MASK);
+    ExceptionFlags::from_fpscr(fpscr)
+}
+
+/// Check whether the current context has an active FP frame (CONTROL.FPCA). If so, an exception taken now stacks
+/// (or reserves space for) the FP registers.
+#[cfg(target_arch = "arm")]
+pub fn has_active_context() -> bool {
+    let control: u32;
+    unsafe { asm!("mrs $0, control" : "=r"(control) ::: "volatile"); }
+    (control & CONTROL_FPCA) != 0
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{ContextStatus, Defaults, ExceptionFlags, RoundingMode};
+
+    #[test]
+    fn context_status() {
+        let reset = ContextStatus::from_fpccr(0xC000_0000);
+        assert!(reset.automatic_saving && reset.lazy_saving);
+        assert_eq!(false, reset.lazy_pending);
+
+        let pending = ContextStatus::from_fpccr((1 << 30) | (1 << 8) | (1 << 4) | (1 << 3) | 1);
+        assert_eq!(false, pending.automatic_saving);
+        assert!(pending.lazy_pending && pending.thread && pending.hard_fault_ready && pending.monitor_ready);
+        assert_eq!(false, pending.user || pending.mem_manage_ready || pending.bus_fault_ready);
+    }
+
+    #[test]
+    fn defaults_round_trip() {
+        let reset = Defaults::from_bits(0);
+        assert_eq!(RoundingMode::Nearest, reset.rounding);
+        assert_eq!(false, reset.flush_to_zero || reset.default_nan || reset.alternative_half);
+
+        let fast = Defaults{
+            rounding: RoundingMode::Zero,
+            flush_to_zero: true,
+            default_nan: true,
+            alternative_half: false,
+        };
+        assert_eq!((3 << 22) | (1 << 24) | (1 << 25), fast.bits());
+        assert_eq!(fast, Defaults::from_bits(fast.bits()));
+        assert_eq!(RoundingMode::MinusInfinity, Defaults::from_bits(2 << 22).rounding);
+        assert!(Defaults::from_bits(1 << 26).alternative_half);
+    }
+
+    #[test]
+    fn exception_flags() {
+        let none = ExceptionFlags::from_fpscr(0xF000_0000); // condition flags only
+        assert_eq!(false, none.any());
+
+        let div = ExceptionFlags::from_fpscr(0x0000_0012);
+        assert!(div.divide_by_zero && div.inexact);
+        assert_eq!(false, div.invalid_operation || div.overflow || div.underflow || div.input_denormal);
+        assert!(div.any_serious());
+
+        let routine = ExceptionFlags::from_fpscr(0x0000_0098);
+        assert!(routine.underflow && routine.inexact && routine.input_denormal);
+        assert!(routine.any());
+        assert_eq!(false, routine.any_serious());
+    }
+}
@@ -0,0 +1,340 @@
+extern crate core;
+use core::fmt;
+use core::intrinsics::{volatile_load, volatile_store};
+
+use ::os::error::ItmError;
+use ::os::log::Level;
+use super::dwt::CoreDebug;
+
+/// Number of stimulus ports.
+pub const NUM_PORTS: u8 = 32;
+
+/// Number of times a stimulus port is polled for FIFO space before a write is dropped.
+pub const DEFAULT_TIMEOUT: u32 = 10_000;
+
+/// Key unlocking writes to the ITM through LAR.
+const LAR_KEY: u32 = 0xC5AC_CE55;
+
+// TCR
+const TCR_ITMENA: u32 = 1 << 0;
+const TCR_SYNCENA: u32 = 1 << 2;
+const TCR_TRACE_BUS_ID: u32 = 1 << 16;
+
+/// TPIU SPPR value selecting asynchronous NRZ (UART) encoding on SWO.
+const SPPR_NRZ: u32 = 0x2;
+/// TPIU FFCR value disabling the formatter, as SWO carries a single source.
+const FFCR_NO_FORMATTER: u32 = 0x100;
+
+/// Core Instrumentation Trace Macrocell control registers.
+ioreg!(
+    name => ITM;
+    doc_srcs => [
+        "http://infocenter.arm.com/help/topic/com.arm.doc.ddi0403e.b/DDI0403E_B_armv7m_arm.pdf" // section C1.7
+    ];
+
+    0x0E00 => ter r32 rw {
+        0..31 => { write_ter => (); }
+    };
+
+    0x0E40 => tpr r32 rw {
+        0..31 => { write_tpr => (); }
+    };
+
+    0x0E80 => tcr r32 rw {
+        0..31 => { write_tcr => (); }
+    };
+
+    0x0FB0 => lar r32 rw {
+        0..31 => { write_lar => (); }
+    };
+);
+
+/// Core Trace Port Interface Unit registers, driving the SWO pin.
+ioreg!(
+    name => TPIU;
+    doc_srcs => [
+        "http://infocenter.arm.com/help/topic/com.arm.doc.ddi0439b/DDI0439B_cortex_m4_r0p0_trm.pdf" // section 11.2
+    ];
+
+    0x0010 => acpr r32 rw {
+        0..12 => { set_prescaler => (); }
+    };
+
+    0x00F0 => sppr r32 rw {
+        0..1 => { set_protocol => (); }
+    };
+
+    0x0304 => ffcr r32 rw {
+        0..31 => { write_ffcr => (); }
+    };
+);
+
+/// Get the SWO prescaler (TPIU ACPR) giving the nearest rate to `baud` from the trace clock.
+pub fn swo_prescaler(trace_hz: u32, baud: u32) -> Result<u32, ItmError> {
+    if baud == 0 || baud > trace_hz { return Err(ItmError::BadBaudRate); }
+    let prescaler = (trace_hz + baud / 2) / baud - 1;
+    if prescaler > 0x1FFF { return Err(ItmError::BadBaudRate); }
+    Ok(prescaler)
+}
+
+/// Route the ITM to the SWO pin at `baud` and enable the stimulus ports set in `ports`.
+///
+/// Tracing is powered through `CoreDebug`. The pin itself is muxed by the MCU.
+pub fn configure(itm: &ITM, tpiu: &TPIU, debug: &CoreDebug, trace_hz: u32, baud: u32, ports: u32)
+    -> Result<(), ItmError>
+{
+    let prescaler = try!(swo_prescaler(trace_hz, baud));
+
+    debug.enable_trace();
+    tpiu.set_protocol(SPPR_NRZ);
+    tpiu.set_prescaler(prescaler);
+    tpiu.write_ffcr(FFCR_NO_FORMATTER);
+
+    itm.write_lar(LAR_KEY);
+    itm.write_tcr(TCR_ITMENA | TCR_SYNCENA | TCR_TRACE_BUS_ID);
+    itm.write_tpr(0);  // unprivileged code may log too
+    itm.write_ter(ports);
+    Ok(())
+}
+
+/// Raw access to the stimulus ports, separating the register layout from the logic driving it.
+pub trait StimulusPorts {
+    /// Check whether the ITM and the given port are enabled.
+    fn is_enabled(&self, port: u8) -> bool;
+    /// Check whether the port's FIFO can take another write.
+    fn is_ready(&self, port: u8) -> bool;
+    /// Write a byte to the port.
+    fn write_u8(&self, port: u8, val: u8);
+    /// Write a word to the port, sent least significant byte first.
+    fn write_u32(&self, port: u8, val: u32);
+}
+
+impl StimulusPorts for ITM {
+    fn is_enabled(&self, port: u8) -> bool {
+        (self.read_tcr() & TCR_ITMENA) != 0 && (self.read_ter() & (1 << port)) != 0
+    }
+
+    fn is_ready(&self, port: u8) -> bool {
+        unsafe { (volatile_load(stimulus_port(self, port) as *const u32) & 0x1) != 0 }
+    }
+
+    fn write_u8(&self, port: u8, val: u8) {
+        unsafe { volatile_store(stimulus_port(self, port) as *mut u8, val); }
+    }
+
+    fn write_u32(&self, port: u8, val: u32) {
+        unsafe { volatile_store(stimulus_port(self, port) as *mut u32, val); }
+    }
+}
+
+// the stimulus ports open the ITM's block, and are written directly, as bytes or words
+fn stimulus_port(itm: &ITM, port: u8) -> usize {
+    itm as *const ITM as usize + 4 * port as usize
+}
+
+
+//
+// stimulus writer
+//
+
+/// Writes to one stimulus port, giving up rather than blocking when nothing drains the FIFO.
+///
+/// Without a debugger attached the port is usually disabled and writes fail straight away. If it is enabled but
+/// nothing is reading SWO, each write polls for `timeout` tries before dropping the data.
+pub struct ItmWriter<'a, R: 'a + StimulusPorts> {
+    ports: &'a R,
+    port: u8,
+    timeout: u32,
+}
+impl<'a, R: StimulusPorts> ItmWriter<'a, R> {
+    /// Creates a writer to the given port, with the `DEFAULT_TIMEOUT`.
+    pub fn new(ports: &'a R, port: u8) -> Result<ItmWriter<'a, R>, ItmError> {
+        if port >= NUM_PORTS { return Err(ItmError::BadPort); }
+        Ok(ItmWriter{ports: ports, port: port, timeout: DEFAULT_TIMEOUT})
+    }
+
+    /// Set how many times the FIFO is polled before a write is dropped.
+    pub fn set_timeout(&mut self, polls: u32) { self.timeout = polls; }
+
+    /// Wait for room in the FIFO.
+    fn wait(&self) -> Result<(), ItmError> {
+        if ! self.ports.is_enabled(self.port) { return Err(ItmError::Disabled); }
+        for _ in 0..self.timeout {
+            if self.ports.is_ready(self.port) { return Ok(()); }
+        }
+        Err(ItmError::Timeout)
+    }
+
+    /// Write a single byte.
+    pub fn write_u8(&mut self, val: u8) -> Result<(), ItmError> {
+        try!(self.wait());
+        self.ports.write_u8(self.port, val);
+        Ok(())
+    }
+
+    /// Write a word as one 4 byte packet.
+    pub fn write_u32(&mut self, val: u32) -> Result<(), ItmError> {
+        try!(self.wait());
+        self.ports.write_u32(self.port, val);
+        Ok(())
+    }
+
+    /// Write the bytes, packing them 4 to a word where possible. Stops at the first error, dropping the rest.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), ItmError> {
+        for chunk in bytes.chunks(4) {
+            if chunk.len() == 4 {
+                let word = (chunk[0] as u32) | ((chunk[1] as u32) << 8)
+                    | ((chunk[2] as u32) << 16) | ((chunk[3] as u32) << 24);
+                try!(self.write_u32(word));
+            } else {
+                for b in chunk { try!(self.write_u8(*b)); }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, R: StimulusPorts> fmt::Write for ItmWriter<'a, R> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl<'a, R: StimulusPorts> ::traits::LogBackend for ItmWriter<'a, R> {
+    fn log(&mut self, level: Level, args: fmt::Arguments) -> fmt::Result {
+        try!(fmt::Write::write_str(self, "["));
+        try!(fmt::Write::write_str(self, level.name()));
+        try!(fmt::Write::write_str(self, "] "));
+        try!(fmt::write(self, args));
+        fmt::Write::write_str(self, "\n")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use self::std::cell::{Cell, RefCell};
+    use self::std::vec::Vec;
+    use super::StimulusPorts;
+
+    /// Collects every write as (port, bytes in the packet, value). `busy` polls fail before each write succeeds.
+    struct MockPorts {
+        enabled: u32,
+        busy: u32,
+        polls: Cell<u32>,
+        writes: RefCell<Vec<(u8, u8, u32)>>,
+    }
+    impl MockPorts {
+        fn new(enabled: u32, busy: u32) -> MockPorts {
+            MockPorts{enabled: enabled, busy: busy, polls: Cell::new(0), writes: RefCell::new(Vec::new())}
+        }
+
+        fn bytes(&self) -> Vec<u8> {
+            let mut out = Vec::new();
+            for &(_, size, val) in self.writes.borrow().iter() {
+                for i in 0..size { out.push((val >> (8 * i)) as u8); }
+            }
+            out
+        }
+    }
+    impl StimulusPorts for MockPorts {
+        fn is_enabled(&self, port: u8) -> bool { (self.enabled & (1 << port)) != 0 }
+        fn is_ready(&self, _: u8) -> bool {
+            self.polls.set(self.polls.get() + 1);
+            self.polls.get() > self.busy
+        }
+        fn write_u8(&self, port: u8, val: u8) {
+            self.polls.set(0);
+            self.writes.borrow_mut().push((port, 1, val as u32));
+        }
+        fn write_u32(&self, port: u8, val: u32) {
+            self.polls.set(0);
+            self.writes.borrow_mut().push((port, 4, val));
+        }
+    }
+
+    mod writer {
+        use ::os::error::ItmError;
+        use super::MockPorts;
+        use super::super::ItmWriter;
+
+        #[test]
+        fn packs_words() {
+            let ports = MockPorts::new(1 << 2, 0);
+            let mut w = ItmWriter::new(&ports, 2).unwrap();
+            w.write_bytes(b"hello, world").unwrap();
+            w.write_bytes(b"!").unwrap();
+            assert_eq!(b"hello, world!".to_vec(), ports.bytes());
+            assert_eq!(4, ports.writes.borrow().len()); // 3 words and a byte
+            assert_eq!((2, 4, 0x6C6C_6568), ports.writes.borrow()[0]);
+        }
+
+        #[test]
+        fn disabled_port_fails_fast() {
+            let ports = MockPorts::new(1 << 0, 0);
+            let mut w = ItmWriter::new(&ports, 1).unwrap();
+            assert_eq!(Err(ItmError::Disabled), w.write_u32(7));
+            assert_eq!(0, ports.polls.get());
+        }
+
+        #[test]
+        fn times_out() {
+            let ports = MockPorts::new(1, 100);
+            let mut w = ItmWriter::new(&ports, 0).unwrap();
+            w.set_timeout(10);
+            assert_eq!(Err(ItmError::Timeout), w.write_u8(b'x'));
+            assert_eq!(10, ports.polls.get());
+            assert!(ports.writes.borrow().is_empty());
+
+            w.set_timeout(200);
+            assert_eq!(Ok(()), w.write_u8(b'x'));
+        }
+
+        #[test]
+        fn bad_port() {
+            let ports = MockPorts::new(!0, 0);
+            assert_eq!(Err(ItmError::BadPort), ItmWriter::new(&ports, 32).map(|_| ()));
+        }
+    }
+
+    mod output {
+        use ::os::log::Level;
+        use ::traits::LogBackend;
+        use super::MockPorts;
+        use super::super::ItmWriter;
+
+        #[test]
+        fn formats() {
+            let ports = MockPorts::new(1, 0);
+            {
+                let mut w = ItmWriter::new(&ports, 0).unwrap();
+                ::core::fmt::Write::write_fmt(&mut w, format_args!("{}-{:x}", 10, 255)).unwrap();
+            }
+            assert_eq!(b"10-ff".to_vec(), ports.bytes());
+        }
+
+        #[test]
+        fn log_backend() {
+            let ports = MockPorts::new(1, 0);
+            {
+                let mut w = ItmWriter::new(&ports, 0).unwrap();
+                w.log(Level::Warn, format_args!("low battery: {}mV", 3300)).unwrap();
+            }
+            assert_eq!(b"[WARN] low battery: 3300mV\n".to_vec(), ports.bytes());
+        }
+    }
+
+    #[test]
+    fn prescaler() {
+        use ::os::error::ItmError;
+        use super::swo_prescaler;
+
+        assert_eq!(Ok(59), swo_prescaler(120_000_000, 2_000_000));
+        assert_eq!(Ok(0), swo_prescaler(120_000_000, 120_000_000));
+        assert_eq!(Ok(1041), swo_prescaler(120_000_000, 115_200));  // nearest, 115163 baud
+        assert_eq!(Err(ItmError::BadBaudRate), swo_prescaler(120_000_000, 0));
+        assert_eq!(Err(ItmError::BadBaudRate), swo_prescaler(120_000_000, 240_000_000));
+        assert_eq!(Err(ItmError::BadBaudRate), swo_prescaler(120_000_000, 9_600));
+    }
+}
@@ -1,2 +1,7 @@
+pub mod dwt;
 pub mod fpu;
+pub mod itm;
+pub mod mpu;
+pub mod nvic;
+pub mod scb;
 pub mod systick;
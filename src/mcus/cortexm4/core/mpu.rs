@@ -0,0 +1,475 @@
+extern crate core;
+use core::intrinsics::{volatile_load, volatile_store};
+
+use ::libc::memory::IOVec;
+use ::os::error::MpuError;
+use ::os::sync;
+
+/// Smallest region the MPU supports.
+pub const MIN_REGION_SIZE: u32 = 32;
+
+/// Smallest region that supports disabling subregions.
+pub const MIN_SUBREGION_SIZE: u32 = 256;
+
+/// Size of the guard region placed at the bottom of the stack by `guard_stack()`.
+///
+/// Exception entry stacks up to 26 words (104 bytes) before the handler runs, and a function may drop the stack
+/// pointer by more than that at once, so a smaller guard is easily jumped over.
+pub const STACK_GUARD_SIZE: u32 = 256;
+
+// CTRL
+const CTRL_ENABLE: u32 = 1 << 0;
+const CTRL_PRIVDEFENA: u32 = 1 << 2;
+
+// RBAR
+const RBAR_VALID: u32 = 1 << 4;
+
+// RASR
+const RASR_ENABLE: u32 = 1 << 0;
+const RASR_XN: u32 = 1 << 28;
+const RASR_S: u32 = 1 << 18;
+
+/// Core Memory Protection Unit registers.
+///
+/// The alias registers program the region selected by RNR plus 1-3, so up to 4 regions can be written in one
+/// multi-word store.
+ioreg!(
+    name => MPU;
+    doc_srcs => [
+        "http://infocenter.arm.com/help/topic/com.arm.doc.dui0553a/DUI0553A_cortex_m4_dgug.pdf" // section 4.5
+    ];
+
+    0x0000 => mpu_type r32 ro {};
+
+    0x0004 => ctrl r32 rw {
+        0..31 => { write_ctrl => (); }
+    };
+
+    0x0008 => rnr r32 rw {
+        0..31 => { write_rnr => (); }
+    };
+
+    0x000C => rbar r32 rw {
+        0..31 => { write_rbar => (); }
+    };
+
+    0x0010 => rasr r32 rw {
+        0..31 => { write_rasr => (); }
+    };
+
+    //
+    // aliases
+    //
+
+    0x0014 => rbar_a1 r32 rw {
+        0..31 => { write_rbar_a1 => (); }
+    };
+    0x0018 => rasr_a1 r32 rw {
+        0..31 => { write_rasr_a1 => (); }
+    };
+    0x001C => rbar_a2 r32 rw {
+        0..31 => { write_rbar_a2 => (); }
+    };
+    0x0020 => rasr_a2 r32 rw {
+        0..31 => { write_rasr_a2 => (); }
+    };
+    0x0024 => rbar_a3 r32 rw {
+        0..31 => { write_rbar_a3 => (); }
+    };
+    0x0028 => rasr_a3 r32 rw {
+        0..31 => { write_rasr_a3 => (); }
+    };
+);
+
+/// Raw access to the MPU registers, separating the register layout from the logic driving it.
+pub trait MpuRegisters {
+    /// Read TYPE.
+    fn read_type(&self) -> u32;
+    /// Read CTRL.
+    fn control(&self) -> u32;
+    /// Write CTRL. The write must take effect before the next instruction.
+    fn set_control(&self, val: u32);
+    /// Select a region through RNR, then write its RBAR and RASR.
+    fn write_region(&self, region: u8, rbar: u32, rasr: u32);
+}
+
+impl MpuRegisters for MPU {
+    fn read_type(&self) -> u32 { self.read_mpu_type() }
+    fn control(&self) -> u32 { self.read_ctrl() }
+
+    // the new configuration must apply to the next access, and the next instruction fetch
+    fn set_control(&self, val: u32) {
+        self.write_ctrl(val);
+        sync::data_barrier();
+        sync::instruction_barrier();
+    }
+
+    fn write_region(&self, region: u8, rbar: u32, rasr: u32) {
+        self.write_rnr(region as u32);
+        self.write_rbar(rbar);
+        self.write_rasr(rasr);
+    }
+}
+
+
+//
+// regions
+//
+
+/// Access permissions (RASR.AP) for privileged and unprivileged code.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Access {
+    /// Any access faults.
+    NoAccess = 0b000,
+    /// Privileged read/write, unprivileged accesses fault.
+    PrivilegedOnly = 0b001,
+    /// Privileged read/write, unprivileged read only.
+    UnprivilegedReadOnly = 0b010,
+    /// Read/write for everyone.
+    Full = 0b011,
+    /// Privileged read only, unprivileged accesses fault.
+    PrivilegedReadOnly = 0b101,
+    /// Read only for everyone.
+    ReadOnly = 0b110,
+}
+
+/// Memory type and cache policy of a region (RASR.TEX, C and B).
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Attributes {
+    /// Every access is performed in order and completes before the next.
+    StronglyOrdered,
+    /// Peripheral registers.
+    Device,
+    /// Normal memory, write-through without write allocate.
+    WriteThrough,
+    /// Normal memory, write-back without write allocate.
+    WriteBack,
+    /// Normal memory, not cached.
+    NonCacheable,
+}
+impl Attributes {
+    /// Get the TEX, C and B bits as they sit in RASR.
+    fn rasr_bits(&self) -> u32 {
+        let (tex, c, b) = match *self {
+            Attributes::StronglyOrdered => (0b000, 0, 0),
+            Attributes::Device => (0b000, 0, 1),
+            Attributes::WriteThrough => (0b000, 1, 0),
+            Attributes::WriteBack => (0b000, 1, 1),
+            Attributes::NonCacheable => (0b001, 0, 0),
+        };
+        (tex << 19) | (c << 17) | (b << 16)
+    }
+}
+
+/// A protected region of memory.
+///
+/// The size must be a power of two of at least 32 bytes, and the base must be aligned to it. A region is split into
+/// 8 equal subregions, and each set bit of `disabled_subregions` excludes one (bit 0 being the lowest) from the
+/// region.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Region {
+    pub base: u32,
+    pub size: u32,
+    pub access: Access,
+    pub attributes: Attributes,
+    pub shareable: bool,
+    pub execute_never: bool,
+    pub disabled_subregions: u8,
+}
+impl Region {
+    /// Creates an executable region of normal, write-through memory with every subregion enabled.
+    pub fn new(base: u32, size: u32, access: Access) -> Region {
+        Region{
+            base: base,
+            size: size,
+            access: access,
+            attributes: Attributes::WriteThrough,
+            shareable: false,
+            execute_never: false,
+            disabled_subregions: 0,
+        }
+    }
+
+    /// Check the size and alignment of the region.
+    pub fn validate(&self) -> Result<(), MpuError> {
+        if self.size < MIN_REGION_SIZE { return Err(MpuError::TooSmall); }
+        if ! self.size.is_power_of_two() { return Err(MpuError::NotPowerOfTwo); }
+        if (self.base & (self.size - 1)) != 0 { return Err(MpuError::Misaligned); }
+        if self.disabled_subregions != 0 && self.size < MIN_SUBREGION_SIZE {
+            return Err(MpuError::SubregionsUnsupported);
+        }
+        Ok(())
+    }
+
+    /// Encode the region into the RBAR and RASR values for the given region number.
+    ///
+    /// RBAR has VALID set, so writing it also selects the region.
+    pub fn encode(&self, region: u8) -> Result<(u32, u32), MpuError> {
+        if region > 0xF { return Err(MpuError::BadRegion); }
+        try!(self.validate());
+
+        let rbar = self.base | RBAR_VALID | (region as u32);
+        let size_field = self.size.trailing_zeros() - 1;
+        let mut rasr = RASR_ENABLE
+            | (size_field << 1)
+            | ((self.disabled_subregions as u32) << 8)
+            | self.attributes.rasr_bits()
+            | ((self.access as u32) << 24);
+        if self.shareable { rasr |= RASR_S; }
+        if self.execute_never { rasr |= RASR_XN; }
+        Ok((rbar, rasr))
+    }
+}
+
+
+//
+// unit logic
+//
+
+/// Get the number of regions the MPU implements. Zero if there is no MPU.
+pub fn regions<R: MpuRegisters>(regs: &R) -> u8 {
+    ((regs.read_type() >> 8) & 0xFF) as u8
+}
+
+/// Program the given region. Takes effect immediately if the MPU is enabled.
+pub fn configure<R: MpuRegisters>(regs: &R, number: u8, region: &Region) -> Result<(), MpuError> {
+    let implemented = regions(regs);
+    if implemented == 0 { return Err(MpuError::Unsupported); }
+    if number >= implemented { return Err(MpuError::BadRegion); }
+
+    let (rbar, rasr) = try!(region.encode(number));
+    regs.write_region(number, rbar, rasr);
+    Ok(())
+}
+
+/// Disable the given region.
+pub fn disable_region<R: MpuRegisters>(regs: &R, number: u8) -> Result<(), MpuError> {
+    let implemented = regions(regs);
+    if implemented == 0 { return Err(MpuError::Unsupported); }
+    if number >= implemented { return Err(MpuError::BadRegion); }
+    regs.write_region(number, 0, 0);
+    Ok(())
+}
+
+/// Enable the MPU. With `default_map`, privileged accesses outside every region use the default memory map rather
+/// than faulting.
+pub fn enable<R: MpuRegisters>(regs: &R, default_map: bool) {
+    let ctrl = if default_map { CTRL_ENABLE | CTRL_PRIVDEFENA } else { CTRL_ENABLE };
+    regs.set_control(ctrl);
+}
+
+/// Disable the MPU. Every access uses the default memory map.
+pub fn disable<R: MpuRegisters>(regs: &R) {
+    regs.set_control(0);
+}
+
+/// Get a no-access region of `STACK_GUARD_SIZE` bytes at the bottom (lowest address) of the stack.
+///
+/// The stack grows down, so an overflow runs into the guard and faults instead of running into what lies below.
+pub fn stack_guard(stack: IOVec) -> Result<Region, MpuError> {
+    let begin = stack.ptr as usize;
+    let end = begin + stack.size;
+    let base = (begin + (STACK_GUARD_SIZE as usize - 1)) & !(STACK_GUARD_SIZE as usize - 1);
+    if base + (STACK_GUARD_SIZE as usize) > end { return Err(MpuError::NoRoom); }
+
+    let mut guard = Region::new(base as u32, STACK_GUARD_SIZE, Access::NoAccess);
+    guard.execute_never = true;
+    Ok(guard)
+}
+
+/// Place a guard region at the bottom of the MCU's stack in the highest numbered region, and enable the MPU with
+/// the default memory map for everything else.
+///
+/// The highest region takes priority where regions overlap, so the guard holds even inside other regions.
+///
+/// __NOTE:__ this is `Unsupported` on MCUs whose core MPU implements no regions. That includes the K64, which
+/// protects memory with the Kinetis SYSMPU instead: see `kinetis::k64::sysmpu::guard_stack()`.
+pub fn guard_stack<R: MpuRegisters>(regs: &R, mcu: &::traits::MCU) -> Result<Region, MpuError> {
+    let implemented = regions(regs);
+    if implemented == 0 { return Err(MpuError::Unsupported); }
+
+    let guard = try!(stack_guard(mcu.stack_memory()));
+    try!(configure(regs, implemented - 1, &guard));
+    enable(regs, true);
+    Ok(guard)
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate core;
+    use self::core::cell::Cell;
+    use super::MpuRegisters;
+
+    /// Records the last region written, reporting the given number of regions in TYPE.
+    struct MockMpu {
+        dregion: u32,
+        ctrl: Cell<u32>,
+        last: Cell<(u8, u32, u32)>,
+    }
+    impl MockMpu {
+        fn new(dregion: u32) -> MockMpu {
+            MockMpu{dregion: dregion, ctrl: Cell::new(0), last: Cell::new((0xFF, 0, 0))}
+        }
+    }
+    impl MpuRegisters for MockMpu {
+        fn read_type(&self) -> u32 { self.dregion << 8 }
+        fn control(&self) -> u32 { self.ctrl.get() }
+        fn set_control(&self, val: u32) { self.ctrl.set(val); }
+        fn write_region(&self, region: u8, rbar: u32, rasr: u32) { self.last.set((region, rbar, rasr)); }
+    }
+
+    mod region {
+        use ::os::error::MpuError;
+        use super::super::{Access, Attributes, Region};
+
+        #[test]
+        fn validation() {
+            assert_eq!(Ok(()), Region::new(0x2000_0000, 0x1000, Access::Full).validate());
+            assert_eq!(Err(MpuError::TooSmall), Region::new(0, 16, Access::Full).validate());
+            assert_eq!(Err(MpuError::NotPowerOfTwo), Region::new(0, 0x300, Access::Full).validate());
+            assert_eq!(Err(MpuError::Misaligned), Region::new(0x2000_0800, 0x1000, Access::Full).validate());
+
+            let mut small = Region::new(0, 128, Access::Full);
+            small.disabled_subregions = 0x01;
+            assert_eq!(Err(MpuError::SubregionsUnsupported), small.validate());
+            small.size = 256;
+            assert_eq!(Ok(()), small.validate());
+        }
+
+        #[test]
+        fn encoding() {
+            let mut region = Region::new(0x2000_0000, 0x1_0000, Access::PrivilegedReadOnly);
+            region.execute_never = true;
+            region.disabled_subregions = 0x81;
+            region.attributes = Attributes::WriteBack;
+            region.shareable = true;
+
+            let (rbar, rasr) = region.encode(3).unwrap();
+            assert_eq!(0x2000_0000 | (1 << 4) | 3, rbar);
+            assert_eq!(1, rasr & 1);                        // enabled
+            assert_eq!(15, (rasr >> 1) & 0x1F);             // 64KiB = 2^(15 + 1)
+            assert_eq!(0x81, (rasr >> 8) & 0xFF);           // subregions
+            assert_eq!(0b111, (rasr >> 16) & 0b111);        // S, C, B
+            assert_eq!(0, (rasr >> 19) & 0b111);            // TEX
+            assert_eq!(0b101, (rasr >> 24) & 0b111);        // AP
+            assert_eq!(1, rasr >> 28);                      // XN
+        }
+
+        #[test]
+        fn minimum_region() {
+            let (_, rasr) = Region::new(0x20, 32, Access::NoAccess).encode(0).unwrap();
+            assert_eq!(4, (rasr >> 1) & 0x1F);
+            assert_eq!(0, (rasr >> 24) & 0b111);
+        }
+
+        #[test]
+        fn bad_region_number() {
+            assert_eq!(Err(MpuError::BadRegion), Region::new(0, 32, Access::Full).encode(16));
+        }
+    }
+
+    mod unit {
+        use ::os::error::MpuError;
+        use super::MockMpu;
+        use super::super::{Access, Region, regions, configure, disable_region, enable, disable};
+
+        #[test]
+        fn configures_region() {
+            let mpu = MockMpu::new(8);
+            assert_eq!(8, regions(&mpu));
+            configure(&mpu, 2, &Region::new(0x1FFF_0000, 0x1_0000, Access::Full)).unwrap();
+            let (number, rbar, _) = mpu.last.get();
+            assert_eq!(2, number);
+            assert_eq!(0x1FFF_0012, rbar);
+
+            disable_region(&mpu, 2).unwrap();
+            assert_eq!((2, 0, 0), mpu.last.get());
+        }
+
+        #[test]
+        fn checks_region_number() {
+            let mpu = MockMpu::new(8);
+            let region = Region::new(0, 32, Access::Full);
+            assert_eq!(Err(MpuError::BadRegion), configure(&mpu, 8, &region));
+            assert_eq!(Err(MpuError::BadRegion), disable_region(&mpu, 8));
+            assert_eq!(Err(MpuError::Unsupported), configure(&MockMpu::new(0), 0, &region));
+            assert_eq!(Err(MpuError::Unsupported), disable_region(&MockMpu::new(0), 0));
+        }
+
+        #[test]
+        fn invalid_region_not_written() {
+            let mpu = MockMpu::new(8);
+            assert_eq!(Err(MpuError::Misaligned), configure(&mpu, 0, &Region::new(0x10, 32, Access::Full)));
+            assert_eq!(0xFF, mpu.last.get().0);
+        }
+
+        #[test]
+        fn control() {
+            let mpu = MockMpu::new(8);
+            enable(&mpu, true);
+            assert_eq!(0b101, mpu.ctrl.get());
+            enable(&mpu, false);
+            assert_eq!(0b001, mpu.ctrl.get());
+            disable(&mpu);
+            assert_eq!(0, mpu.ctrl.get());
+        }
+    }
+
+    mod guard {
+        use ::libc::memory::IOVec;
+        use ::os::error::MpuError;
+        use ::test_support::FakeMcu;
+        use super::MockMpu;
+        use super::super::{Access, STACK_GUARD_SIZE, stack_guard, guard_stack};
+
+        fn mcu() -> FakeMcu { FakeMcu::new(IOVec{ptr: 0x1FFF_8000 as *const u8, size: 0x4000}) }
+
+        #[test]
+        fn at_stack_bottom() {
+            let guard = stack_guard(IOVec{ptr: 0x1FFF_8000 as *const u8, size: 0x4000}).unwrap();
+            assert_eq!(0x1FFF_8000, guard.base);
+            assert_eq!(STACK_GUARD_SIZE, guard.size);
+            assert_eq!(Access::NoAccess, guard.access);
+            assert!(guard.execute_never);
+        }
+
+        #[test]
+        fn aligned_up() {
+            let guard = stack_guard(IOVec{ptr: 0x1FFF_8004 as *const u8, size: 0x4000}).unwrap();
+            assert_eq!(0x1FFF_8100, guard.base);
+        }
+
+        #[test]
+        fn no_room() {
+            let stack = IOVec{ptr: 0x1FFF_8004 as *const u8, size: 300};
+            assert_eq!(Err(MpuError::NoRoom), stack_guard(stack));
+        }
+
+        #[test]
+        fn uses_last_region_and_enables() {
+            let mpu = MockMpu::new(8);
+            let guard = guard_stack(&mpu, &mcu()).unwrap();
+            let (number, rbar, _) = mpu.last.get();
+            assert_eq!(7, number);
+            assert_eq!(guard.base | (1 << 4) | 7, rbar);
+            assert_eq!(0b101, mpu.ctrl.get());
+        }
+
+        #[test]
+        fn unsupported_without_regions() {
+            let mpu = MockMpu::new(0);
+            assert_eq!(Err(MpuError::Unsupported), guard_stack(&mpu, &mcu()));
+            assert_eq!(0, mpu.ctrl.get());
+        }
+    }
+}
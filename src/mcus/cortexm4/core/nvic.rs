@@ -0,0 +1,719 @@
+extern crate core;
+use core::intrinsics::{volatile_load, volatile_store};
+
+use ::os::sync;
+
+/// Number of IRQs the `NVIC` register block covers. Cortex-M4 allows up to 240, but no supported part uses more
+/// than 128.
+pub const NUM_IRQS: usize = 128;
+
+/// Number of 32 bit words in each of the enable, pending, and active banks.
+const BANK_WORDS: usize = NUM_IRQS / 32;
+
+/// Number of 32 bit words of priority registers (one byte per IRQ).
+const PRIORITY_WORDS: usize = NUM_IRQS / 4;
+
+/// Write key required by AIRCR.
+const AIRCR_VECTKEY: u32 = 0x05FA << 16;
+
+// probed by the first priority access, see `read_priority_bits`. every Cortex-M4 implements at least 3 bits, so 0 is
+// never probed
+static mut PRIORITY_BITS: u8 = 0;
+
+/// Core Nested Vectored Interrupt Controller registers, addressed from the start of the System Control Space.
+///
+/// Only the registers for the first `NUM_IRQS` IRQs are described. Priority grouping lives in the SCB's AIRCR, which
+/// is included so the whole controller can be driven from one block.
+ioreg!(
+    name => NVIC;
+    doc_srcs => [
+        "http://infocenter.arm.com/help/topic/com.arm.doc.dui0553a/DUI0553A_cortex_m4_dgug.pdf" // section 4.2
+    ];
+
+    0x0004 => interrupt_controller_type r32 ro {};
+
+
+    //
+    // set-enable
+    //
+
+    0x0100 => iser0 r32 rw {
+        0..31 => { write_iser0 => (); }
+    };
+    0x0104 => iser1 r32 rw {
+        0..31 => { write_iser1 => (); }
+    };
+    0x0108 => iser2 r32 rw {
+        0..31 => { write_iser2 => (); }
+    };
+    0x010C => iser3 r32 rw {
+        0..31 => { write_iser3 => (); }
+    };
+
+
+    //
+    // clear-enable
+    //
+
+    0x0180 => icer0 r32 rw {
+        0..31 => { write_icer0 => (); }
+    };
+    0x0184 => icer1 r32 rw {
+        0..31 => { write_icer1 => (); }
+    };
+    0x0188 => icer2 r32 rw {
+        0..31 => { write_icer2 => (); }
+    };
+    0x018C => icer3 r32 rw {
+        0..31 => { write_icer3 => (); }
+    };
+
+    //
+    // set-pending
+    //
+
+    0x0200 => ispr0 r32 rw {
+        0..31 => { write_ispr0 => (); }
+    };
+    0x0204 => ispr1 r32 rw {
+        0..31 => { write_ispr1 => (); }
+    };
+    0x0208 => ispr2 r32 rw {
+        0..31 => { write_ispr2 => (); }
+    };
+    0x020C => ispr3 r32 rw {
+        0..31 => { write_ispr3 => (); }
+    };
+
+    //
+    // clear-pending
+    //
+
+    0x0280 => icpr0 r32 rw {
+        0..31 => { write_icpr0 => (); }
+    };
+    0x0284 => icpr1 r32 rw {
+        0..31 => { write_icpr1 => (); }
+    };
+    0x0288 => icpr2 r32 rw {
+        0..31 => { write_icpr2 => (); }
+    };
+    0x028C => icpr3 r32 rw {
+        0..31 => { write_icpr3 => (); }
+    };
+
+    //
+    // active
+    //
+
+    0x0300 => iabr0 r32 ro {};
+    0x0304 => iabr1 r32 ro {};
+    0x0308 => iabr2 r32 ro {};
+    0x030C => iabr3 r32 ro {};
+
+    //
+    // priority (one byte per IRQ)
+    //
+
+    0x0400 => ipr0 r32 rw {
+        0..31 => { write_ipr0 => (); }
+    };
+    0x0404 => ipr1 r32 rw {
+        0..31 => { write_ipr1 => (); }
+    };
+    0x0408 => ipr2 r32 rw {
+        0..31 => { write_ipr2 => (); }
+    };
+    0x040C => ipr3 r32 rw {
+        0..31 => { write_ipr3 => (); }
+    };
+    0x0410 => ipr4 r32 rw {
+        0..31 => { write_ipr4 => (); }
+    };
+    0x0414 => ipr5 r32 rw {
+        0..31 => { write_ipr5 => (); }
+    };
+    0x0418 => ipr6 r32 rw {
+        0..31 => { write_ipr6 => (); }
+    };
+    0x041C => ipr7 r32 rw {
+        0..31 => { write_ipr7 => (); }
+    };
+    0x0420 => ipr8 r32 rw {
+        0..31 => { write_ipr8 => (); }
+    };
+    0x0424 => ipr9 r32 rw {
+        0..31 => { write_ipr9 => (); }
+    };
+    0x0428 => ipr10 r32 rw {
+        0..31 => { write_ipr10 => (); }
+    };
+    0x042C => ipr11 r32 rw {
+        0..31 => { write_ipr11 => (); }
+    };
+    0x0430 => ipr12 r32 rw {
+        0..31 => { write_ipr12 => (); }
+    };
+    0x0434 => ipr13 r32 rw {
+        0..31 => { write_ipr13 => (); }
+    };
+    0x0438 => ipr14 r32 rw {
+        0..31 => { write_ipr14 => (); }
+    };
+    0x043C => ipr15 r32 rw {
+        0..31 => { write_ipr15 => (); }
+    };
+    0x0440 => ipr16 r32 rw {
+        0..31 => { write_ipr16 => (); }
+    };
+    0x0444 => ipr17 r32 rw {
+        0..31 => { write_ipr17 => (); }
+    };
+    0x0448 => ipr18 r32 rw {
+        0..31 => { write_ipr18 => (); }
+    };
+    0x044C => ipr19 r32 rw {
+        0..31 => { write_ipr19 => (); }
+    };
+    0x0450 => ipr20 r32 rw {
+        0..31 => { write_ipr20 => (); }
+    };
+    0x0454 => ipr21 r32 rw {
+        0..31 => { write_ipr21 => (); }
+    };
+    0x0458 => ipr22 r32 rw {
+        0..31 => { write_ipr22 => (); }
+    };
+    0x045C => ipr23 r32 rw {
+        0..31 => { write_ipr23 => (); }
+    };
+    0x0460 => ipr24 r32 rw {
+        0..31 => { write_ipr24 => (); }
+    };
+    0x0464 => ipr25 r32 rw {
+        0..31 => { write_ipr25 => (); }
+    };
+    0x0468 => ipr26 r32 rw {
+        0..31 => { write_ipr26 => (); }
+    };
+    0x046C => ipr27 r32 rw {
+        0..31 => { write_ipr27 => (); }
+    };
+    0x0470 => ipr28 r32 rw {
+        0..31 => { write_ipr28 => (); }
+    };
+    0x0474 => ipr29 r32 rw {
+        0..31 => { write_ipr29 => (); }
+    };
+    0x0478 => ipr30 r32 rw {
+        0..31 => { write_ipr30 => (); }
+    };
+    0x047C => ipr31 r32 rw {
+        0..31 => { write_ipr31 => (); }
+    };
+
+    //
+    // application interrupt and reset control (SCB)
+    //
+
+    0x0D0C => app_int_reset_control r32 rw {
+        0..31 => { write_app_int_reset_control => (); }
+    };
+);
+
+/// A bank of per-IRQ bits, one bit per IRQ across `NUM_IRQS / 32` words.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Bank {
+    /// ISER. Writing 1 enables an IRQ, reading gives the enabled IRQs.
+    SetEnable,
+    /// ICER. Writing 1 disables an IRQ, reading gives the enabled IRQs.
+    ClearEnable,
+    /// ISPR. Writing 1 marks an IRQ pending, reading gives the pending IRQs.
+    SetPending,
+    /// ICPR. Writing 1 clears a pending IRQ, reading gives the pending IRQs.
+    ClearPending,
+    /// IABR. Read only, gives the IRQs being serviced.
+    Active,
+}
+
+/// Raw access to the NVIC registers, separating the register layout from the logic driving it.
+///
+/// Word indexes are always within the bank (`< NUM_IRQS / 32`, or `< NUM_IRQS / 4` for priorities).
+pub trait NvicRegisters {
+    /// Read a word of the given bank.
+    fn read_bank(&self, bank: Bank, word: usize) -> u32;
+    /// Write a word of the given bank. Writes to the read only `Bank::Active` are ignored.
+    fn write_bank(&self, bank: Bank, word: usize, val: u32);
+
+    /// Read a word of the priority registers (IPR), holding the priority of 4 IRQs.
+    fn read_priorities(&self, word: usize) -> u32;
+    /// Write a word of the priority registers (IPR).
+    fn write_priorities(&self, word: usize, val: u32);
+
+    /// Read AIRCR.
+    fn read_aircr(&self) -> u32;
+    /// Write AIRCR. The write key is expected to already be in the value.
+    fn write_aircr(&self, val: u32);
+
+    /// Get the number of priority bits implemented by the controller. Vendors implement between 3 and 8.
+    fn priority_bits(&self) -> u8;
+}
+
+impl NvicRegisters for NVIC {
+    fn read_bank(&self, bank: Bank, word: usize) -> u32 {
+        match (bank, word) {
+            (Bank::SetEnable, 0) => self.read_iser0(),
+            (Bank::SetEnable, 1) => self.read_iser1(),
+            (Bank::SetEnable, 2) => self.read_iser2(),
+            (Bank::SetEnable, 3) => self.read_iser3(),
+            (Bank::ClearEnable, 0) => self.read_icer0(),
+            (Bank::ClearEnable, 1) => self.read_icer1(),
+            (Bank::ClearEnable, 2) => self.read_icer2(),
+            (Bank::ClearEnable, 3) => self.read_icer3(),
+            (Bank::SetPending, 0) => self.read_ispr0(),
+            (Bank::SetPending, 1) => self.read_ispr1(),
+            (Bank::SetPending, 2) => self.read_ispr2(),
+            (Bank::SetPending, 3) => self.read_ispr3(),
+            (Bank::ClearPending, 0) => self.read_icpr0(),
+            (Bank::ClearPending, 1) => self.read_icpr1(),
+            (Bank::ClearPending, 2) => self.read_icpr2(),
+            (Bank::ClearPending, 3) => self.read_icpr3(),
+            (Bank::Active, 0) => self.read_iabr0(),
+            (Bank::Active, 1) => self.read_iabr1(),
+            (Bank::Active, 2) => self.read_iabr2(),
+            (Bank::Active, 3) => self.read_iabr3(),
+            _ => 0,
+        }
+    }
+
+    fn write_bank(&self, bank: Bank, word: usize, val: u32) {
+        match (bank, word) {
+            (Bank::SetEnable, 0) => self.write_iser0(val),
+            (Bank::SetEnable, 1) => self.write_iser1(val),
+            (Bank::SetEnable, 2) => self.write_iser2(val),
+            (Bank::SetEnable, 3) => self.write_iser3(val),
+            (Bank::ClearEnable, 0) => self.write_icer0(val),
+            (Bank::ClearEnable, 1) => self.write_icer1(val),
+            (Bank::ClearEnable, 2) => self.write_icer2(val),
+            (Bank::ClearEnable, 3) => self.write_icer3(val),
+            (Bank::SetPending, 0) => self.write_ispr0(val),
+            (Bank::SetPending, 1) => self.write_ispr1(val),
+            (Bank::SetPending, 2) => self.write_ispr2(val),
+            (Bank::SetPending, 3) => self.write_ispr3(val),
+            (Bank::ClearPending, 0) => self.write_icpr0(val),
+            (Bank::ClearPending, 1) => self.write_icpr1(val),
+            (Bank::ClearPending, 2) => self.write_icpr2(val),
+            (Bank::ClearPending, 3) => self.write_icpr3(val),
+            _ => {}
+        }
+    }
+
+    fn read_priorities(&self, word: usize) -> u32 {
+        match word {
+            0 => self.read_ipr0(),
+            1 => self.read_ipr1(),
+            2 => self.read_ipr2(),
+            3 => self.read_ipr3(),
+            4 => self.read_ipr4(),
+            5 => self.read_ipr5(),
+            6 => self.read_ipr6(),
+            7 => self.read_ipr7(),
+            8 => self.read_ipr8(),
+            9 => self.read_ipr9(),
+            10 => self.read_ipr10(),
+            11 => self.read_ipr11(),
+            12 => self.read_ipr12(),
+            13 => self.read_ipr13(),
+            14 => self.read_ipr14(),
+            15 => self.read_ipr15(),
+            16 => self.read_ipr16(),
+            17 => self.read_ipr17(),
+            18 => self.read_ipr18(),
+            19 => self.read_ipr19(),
+            20 => self.read_ipr20(),
+            21 => self.read_ipr21(),
+            22 => self.read_ipr22(),
+            23 => self.read_ipr23(),
+            24 => self.read_ipr24(),
+            25 => self.read_ipr25(),
+            26 => self.read_ipr26(),
+            27 => self.read_ipr27(),
+            28 => self.read_ipr28(),
+            29 => self.read_ipr29(),
+            30 => self.read_ipr30(),
+            31 => self.read_ipr31(),
+            _ => 0,
+        }
+    }
+
+    fn write_priorities(&self, word: usize, val: u32) {
+        match word {
+            0 => self.write_ipr0(val),
+            1 => self.write_ipr1(val),
+            2 => self.write_ipr2(val),
+            3 => self.write_ipr3(val),
+            4 => self.write_ipr4(val),
+            5 => self.write_ipr5(val),
+            6 => self.write_ipr6(val),
+            7 => self.write_ipr7(val),
+            8 => self.write_ipr8(val),
+            9 => self.write_ipr9(val),
+            10 => self.write_ipr10(val),
+            11 => self.write_ipr11(val),
+            12 => self.write_ipr12(val),
+            13 => self.write_ipr13(val),
+            14 => self.write_ipr14(val),
+            15 => self.write_ipr15(val),
+            16 => self.write_ipr16(val),
+            17 => self.write_ipr17(val),
+            18 => self.write_ipr18(val),
+            19 => self.write_ipr19(val),
+            20 => self.write_ipr20(val),
+            21 => self.write_ipr21(val),
+            22 => self.write_ipr22(val),
+            23 => self.write_ipr23(val),
+            24 => self.write_ipr24(val),
+            25 => self.write_ipr25(val),
+            26 => self.write_ipr26(val),
+            27 => self.write_ipr27(val),
+            28 => self.write_ipr28(val),
+            29 => self.write_ipr29(val),
+            30 => self.write_ipr30(val),
+            31 => self.write_ipr31(val),
+            _ => {}
+        }
+    }
+
+    fn read_aircr(&self) -> u32 { self.read_app_int_reset_control() }
+    fn write_aircr(&self, val: u32) { self.write_app_int_reset_control(val); }
+
+    // a byte read is atomic, so only probing needs interrupts masked
+    fn priority_bits(&self) -> u8 {
+        let bits = unsafe { PRIORITY_BITS };
+        if bits != 0 { return bits; }
+        sync::critical(|| unsafe {
+            if PRIORITY_BITS == 0 { PRIORITY_BITS = read_priority_bits(self); }
+            PRIORITY_BITS
+        })
+    }
+}
+
+impl ::traits::NVIC for NVIC {
+    fn enable_irq(&self, irq: u8) { enable_irq(self, irq); }
+    fn disable_irq(&self, irq: u8) { disable_irq(self, irq); }
+    fn is_enabled(&self, irq: u8) -> bool { is_enabled(self, irq) }
+
+    fn set_pending(&self, irq: u8) { set_pending(self, irq); }
+    fn clear_pending(&self, irq: u8) { clear_pending(self, irq); }
+    fn is_pending(&self, irq: u8) -> bool { is_pending(self, irq) }
+
+    fn is_active(&self, irq: u8) -> bool { is_active(self, irq) }
+
+    fn set_priority(&self, irq: u8, prio: u8) { set_priority(self, irq, prio); }
+    fn get_priority(&self, irq: u8) -> u8 { get_priority(self, irq) }
+}
+
+
+//
+// controller logic
+//
+// every function ignores (or reports false/0 for) IRQs at or beyond `NUM_IRQS`
+//
+
+/// Enables the given IRQ.
+pub fn enable_irq<R: NvicRegisters>(regs: &R, irq: u8) { write_bit(regs, Bank::SetEnable, irq); }
+
+/// Disables the given IRQ.
+pub fn disable_irq<R: NvicRegisters>(regs: &R, irq: u8) { write_bit(regs, Bank::ClearEnable, irq); }
+
+/// Check whether the given IRQ is enabled.
+pub fn is_enabled<R: NvicRegisters>(regs: &R, irq: u8) -> bool { read_bit(regs, Bank::SetEnable, irq) }
+
+/// Marks the given IRQ as pending.
+pub fn set_pending<R: NvicRegisters>(regs: &R, irq: u8) { write_bit(regs, Bank::SetPending, irq); }
+
+/// Removes the given IRQ from the pending list.
+pub fn clear_pending<R: NvicRegisters>(regs: &R, irq: u8) { write_bit(regs, Bank::ClearPending, irq); }
+
+/// Check whether the given IRQ is pending.
+pub fn is_pending<R: NvicRegisters>(regs: &R, irq: u8) -> bool { read_bit(regs, Bank::SetPending, irq) }
+
+/// Check whether the given IRQ is being serviced.
+pub fn is_active<R: NvicRegisters>(regs: &R, irq: u8) -> bool { read_bit(regs, Bank::Active, irq) }
+
+/// Set the priority of the given IRQ, where 0 is the most urgent.
+///
+/// The priority is given in the range `[0, 2^priority_bits)`. It is masked to that range, and shifted into the
+/// implemented (upper) bits of the priority byte.
+///
+/// __NOTE:__ the priority word is shared with 3 other IRQs, and is updated with a read-modify-write.
+pub fn set_priority<R: NvicRegisters>(regs: &R, irq: u8, prio: u8) {
+    if (irq as usize) >= NUM_IRQS { return; }
+
+    let bits = regs.priority_bits();
+    let value = ((prio & low_mask(bits)) as u32) << (8 - bits);
+    let word = (irq as usize) / 4;
+    let shift = ((irq as u32) % 4) * 8;
+
+    let current = regs.read_priorities(word);
+    regs.write_priorities(word, (current & !(0xFF << shift)) | (value << shift));
+}
+
+/// Get the priority of the given IRQ, in the range `[0, 2^priority_bits)`.
+pub fn get_priority<R: NvicRegisters>(regs: &R, irq: u8) -> u8 {
+    if (irq as usize) >= NUM_IRQS { return 0; }
+
+    let bits = regs.priority_bits();
+    let shift = ((irq as u32) % 4) * 8;
+    let byte = ((regs.read_priorities((irq as usize) / 4) >> shift) & 0xFF) as u8;
+    (byte >> (8 - bits)) & low_mask(bits)
+}
+
+/// Find the number of priority bits the controller implements, by writing all ones to IRQ 0's priority and counting
+/// the bits that stick. Unimplemented bits read as zero.
+///
+/// IRQ 0's priority is restored, and interrupts are masked meanwhile so the IRQ is never taken at the wrong one.
+/// `NVIC` only probes once, keeping the result for every later priority access.
+pub fn read_priority_bits<R: NvicRegisters>(regs: &R) -> u8 {
+    sync::critical(|| {
+        let word = regs.read_priorities(0);
+        regs.write_priorities(0, word | 0xFF);
+        let implemented = regs.read_priorities(0) & 0xFF;
+        regs.write_priorities(0, word);
+        implemented.count_ones() as u8
+    })
+}
+
+/// Set how many of the (upper) priority bits select the preemption priority, through AIRCR.PRIGROUP.
+///
+/// An IRQ only preempts another with a lower preemption priority. The remaining bits form the subpriority, which
+/// only orders pending IRQs of the same preemption priority. Values beyond the implemented bits are clamped.
+pub fn set_preemption_bits<R: NvicRegisters>(regs: &R, bits: u8) {
+    let implemented = regs.priority_bits();
+    let bits = if bits > implemented { implemented } else { bits };
+    regs.write_aircr(AIRCR_VECTKEY | ((7 - bits as u32) << 8));
+}
+
+/// Get how many of the priority bits select the preemption priority.
+pub fn preemption_bits<R: NvicRegisters>(regs: &R) -> u8 {
+    let group_bits = 7 - ((regs.read_aircr() >> 8) & 0x7) as u8;
+    let implemented = regs.priority_bits();
+    if group_bits > implemented { implemented } else { group_bits }
+}
+
+/// Combine a preemption priority and subpriority into a priority for `set_priority()`.
+///
+/// `preempt_bits` is the value given to `set_preemption_bits()`, and is clamped to `prio_bits`.
+pub fn encode_priority(prio_bits: u8, preempt_bits: u8, preempt: u8, sub: u8) -> u8 {
+    let preempt_bits = if preempt_bits > prio_bits { prio_bits } else { preempt_bits };
+    let sub_bits = prio_bits - preempt_bits;
+    ((preempt & low_mask(preempt_bits)) << sub_bits) | (sub & low_mask(sub_bits))
+}
+
+/// Split a priority from `get_priority()` into its preemption priority and subpriority.
+pub fn decode_priority(prio_bits: u8, preempt_bits: u8, prio: u8) -> (u8, u8) {
+    let preempt_bits = if preempt_bits > prio_bits { prio_bits } else { preempt_bits };
+    let sub_bits = prio_bits - preempt_bits;
+    ((prio >> sub_bits) & low_mask(preempt_bits), prio & low_mask(sub_bits))
+}
+
+/// Get the word and bit of the IRQ within a bank.
+fn bit_of(irq: u8) -> Option<(usize, u32)> {
+    if (irq as usize) >= NUM_IRQS { return None; }
+    Some(((irq as usize) / 32, 1 << ((irq as u32) % 32)))
+}
+
+/// Write only the IRQ's bit to the bank. The write-one banks ignore zero bits, so no read is needed.
+fn write_bit<R: NvicRegisters>(regs: &R, bank: Bank, irq: u8) {
+    if let Some((word, mask)) = bit_of(irq) { regs.write_bank(bank, word, mask); }
+}
+
+/// Read the IRQ's bit from the bank.
+fn read_bit<R: NvicRegisters>(regs: &R, bank: Bank, irq: u8) -> bool {
+    match bit_of(irq) {
+        Some((word, mask)) => (regs.read_bank(bank, word) & mask) != 0,
+        None => false,
+    }
+}
+
+/// Get a mask of the lowest `bits` bits.
+fn low_mask(bits: u8) -> u8 { ((1u16 << bits) - 1) as u8 }
+
+
+#[cfg(test)]
+mod test {
+    extern crate core;
+    use self::core::cell::Cell;
+    use super::{Bank, NvicRegisters, BANK_WORDS, PRIORITY_WORDS};
+
+    /// Behaves like the hardware: set/clear banks share state, and unimplemented priority bits read as zero.
+    struct MockNvic {
+        enabled: Cell<[u32; BANK_WORDS]>,
+        pending: Cell<[u32; BANK_WORDS]>,
+        active: Cell<[u32; BANK_WORDS]>,
+        priorities: Cell<[u32; PRIORITY_WORDS]>,
+        aircr: Cell<u32>,
+        bits: u8,
+    }
+    impl MockNvic {
+        fn new(bits: u8) -> MockNvic {
+            MockNvic{
+                enabled: Cell::new([0; BANK_WORDS]),
+                pending: Cell::new([0; BANK_WORDS]),
+                active: Cell::new([0; BANK_WORDS]),
+                priorities: Cell::new([0; PRIORITY_WORDS]),
+                aircr: Cell::new(0xFA05_0000),
+                bits: bits,
+            }
+        }
+
+        fn update(cell: &Cell<[u32; BANK_WORDS]>, word: usize, set: u32, clear: u32) {
+            let mut words = cell.get();
+            words[word] = (words[word] | set) & !clear;
+            cell.set(words);
+        }
+    }
+    impl NvicRegisters for MockNvic {
+        fn read_bank(&self, bank: Bank, word: usize) -> u32 {
+            match bank {
+                Bank::SetEnable | Bank::ClearEnable => self.enabled.get()[word],
+                Bank::SetPending | Bank::ClearPending => self.pending.get()[word],
+                Bank::Active => self.active.get()[word],
+            }
+        }
+
+        fn write_bank(&self, bank: Bank, word: usize, val: u32) {
+            match bank {
+                Bank::SetEnable => MockNvic::update(&self.enabled, word, val, 0),
+                Bank::ClearEnable => MockNvic::update(&self.enabled, word, 0, val),
+                Bank::SetPending => MockNvic::update(&self.pending, word, val, 0),
+                Bank::ClearPending => MockNvic::update(&self.pending, word, 0, val),
+                Bank::Active => {}
+            }
+        }
+
+        fn read_priorities(&self, word: usize) -> u32 { self.priorities.get()[word] }
+        fn write_priorities(&self, word: usize, val: u32) {
+            let implemented = ((0xFF00u32 >> self.bits) & 0xFF) * 0x0101_0101;
+            let mut words = self.priorities.get();
+            words[word] = val & implemented;
+            self.priorities.set(words);
+        }
+
+        fn read_aircr(&self) -> u32 { self.aircr.get() }
+        fn write_aircr(&self, val: u32) {
+            assert_eq!(0x05FA, val >> 16, "AIRCR written without the key");
+            self.aircr.set(0xFA05_0000 | (val & 0x0700));
+        }
+
+        fn priority_bits(&self) -> u8 { self.bits }
+    }
+
+    mod bits {
+        use super::MockNvic;
+        use super::super::{enable_irq, disable_irq, is_enabled, set_pending, clear_pending, is_pending, is_active};
+
+        #[test]
+        fn enable_and_disable() {
+            let regs = MockNvic::new(4);
+            for irq in [0u8, 31, 32, 85, 127].iter() {
+                enable_irq(&regs, *irq);
+                assert!(is_enabled(&regs, *irq), "irq {} not enabled", irq);
+            }
+            assert_eq!(false, is_enabled(&regs, 1));
+
+            disable_irq(&regs, 32);
+            assert_eq!(false, is_enabled(&regs, 32));
+            assert!(is_enabled(&regs, 31), "disabling one irq disabled another");
+            assert_eq!(1 << 21, regs.enabled.get()[2]); // 85 = 64 + 21
+        }
+
+        #[test]
+        fn pending() {
+            let regs = MockNvic::new(4);
+            set_pending(&regs, 40);
+            assert!(is_pending(&regs, 40));
+            assert_eq!(false, is_active(&regs, 40));
+            clear_pending(&regs, 40);
+            assert_eq!(false, is_pending(&regs, 40));
+        }
+
+        #[test]
+        fn out_of_range_ignored() {
+            let regs = MockNvic::new(4);
+            enable_irq(&regs, 200);
+            assert_eq!(false, is_enabled(&regs, 200));
+            assert_eq!([0; 4], regs.enabled.get());
+        }
+    }
+
+    mod priority {
+        use super::MockNvic;
+        use super::super::{set_priority, get_priority, set_preemption_bits, preemption_bits};
+        use super::super::{encode_priority, decode_priority, read_priority_bits};
+
+        #[test]
+        fn reads_implemented_bits() {
+            for bits in 3..9 {
+                let regs = MockNvic::new(bits);
+                set_priority(&regs, 0, 0x5);
+                set_priority(&regs, 1, 0x2);
+                assert_eq!(bits, read_priority_bits(&regs));
+                assert_eq!((0x5, 0x2), (get_priority(&regs, 0), get_priority(&regs, 1)), "priorities not restored");
+            }
+        }
+
+        #[test]
+        fn shifted_into_upper_bits() {
+            let regs = MockNvic::new(4);
+            set_priority(&regs, 5, 0x3);
+            assert_eq!(0x30 << 8, regs.priorities.get()[1]); // irq 5 is byte 1 of word 1
+            assert_eq!(0x3, get_priority(&regs, 5));
+        }
+
+        #[test]
+        fn masked_to_priority_bits() {
+            let regs = MockNvic::new(3);
+            set_priority(&regs, 0, 0xFF);
+            assert_eq!(0x7, get_priority(&regs, 0));
+            assert_eq!(0xE0, regs.priorities.get()[0]);
+        }
+
+        #[test]
+        fn neighbours_untouched() {
+            let regs = MockNvic::new(4);
+            for irq in 8..12 { set_priority(&regs, irq, irq - 7); }
+            set_priority(&regs, 9, 0xF);
+            assert_eq!(1, get_priority(&regs, 8));
+            assert_eq!(0xF, get_priority(&regs, 9));
+            assert_eq!(3, get_priority(&regs, 10));
+            assert_eq!(4, get_priority(&regs, 11));
+        }
+
+        #[test]
+        fn grouping() {
+            let regs = MockNvic::new(4);
+            set_preemption_bits(&regs, 2);
+            assert_eq!(5 << 8, regs.aircr.get() & 0x0700);
+            assert_eq!(2, preemption_bits(&regs));
+
+            set_preemption_bits(&regs, 7); // more than implemented
+            assert_eq!(4, preemption_bits(&regs));
+
+            set_preemption_bits(&regs, 0);
+            assert_eq!(0, preemption_bits(&regs));
+        }
+
+        #[test]
+        fn encode_and_decode() {
+            assert_eq!(0b1110, encode_priority(4, 2, 0b11, 0b10));
+            assert_eq!((0b11, 0b10), decode_priority(4, 2, 0b1110));
+            assert_eq!(0b0111, encode_priority(4, 0, 0b11, 0b111));
+            assert_eq!(0b1000, encode_priority(4, 9, 0b1000, 0b1)); // preemption bits clamped
+            assert_eq!((0b1000, 0), decode_priority(4, 4, 0b1000));
+        }
+    }
+}
@@ -0,0 +1,519 @@
+extern crate core;
+use core::intrinsics::{volatile_load, volatile_store};
+
+/// Address of the SCB, which is fixed by the architecture.
+pub const SCB_ADDR: usize = 0xE000_ED00;
+
+// ICSR
+const ICSR_VECTACTIVE: u32 = 0x1FF;
+const ICSR_PENDSTCLR: u32 = 1 << 25;
+const ICSR_PENDSTSET: u32 = 1 << 26;
+
+// AIRCR
+const AIRCR_VECTKEY: u32 = 0x05FA << 16;    // without it, writes are ignored
+const AIRCR_PRIGROUP: u32 = 0x7 << 8;
+const AIRCR_SYSRESETREQ: u32 = 1 << 2;
+
+/// Core System Control Block registers.
+///
+/// AIRCR is also described by the `NVIC` block, which drives the priority grouping. CPACR is driven by
+/// `fpu::Access`.
+ioreg!(
+    name => SCB;
+    doc_srcs => [
+        "http://infocenter.arm.com/help/topic/com.arm.doc.dui0553a/DUI0553A_cortex_m4_dgug.pdf" // section 4.3
+    ];
+
+    0x0000 => cpuid r32 ro {};
+
+    0x0004 => icsr r32 rw {
+        0..31 => { write_icsr => (); }
+    };
+
+    0x0008 => vtor r32 rw {
+        0..31 => { write_vtor => (); }
+    };
+
+    0x000C => aircr r32 rw {
+        0..31 => { write_aircr => (); }
+    };
+
+    0x0010 => scr r32 rw {
+        0..31 => { write_scr => (); }
+    };
+
+    0x0014 => ccr r32 rw {
+        3 => {
+            enable_unaligned_trap => [0x1];
+            disable_unaligned_trap => [0x0];
+        }
+
+        4 => {
+            enable_divide_by_zero_trap => [0x1];
+            disable_divide_by_zero_trap => [0x0];
+        }
+    };
+
+    //
+    // system handler priorities (one byte per handler, exceptions 4-15)
+    //
+
+    0x0018 => shpr1 r32 rw {
+        0..31 => { write_shpr1 => (); }
+    };
+    0x001C => shpr2 r32 rw {
+        0..31 => { write_shpr2 => (); }
+    };
+    0x0020 => shpr3 r32 rw {
+        0..31 => { write_shpr3 => (); }
+    };
+
+    0x0024 => shcsr r32 rw {
+        16 => {
+            enable_mem_manage_fault => [0x1];
+            disable_mem_manage_fault => [0x0];
+        }
+
+        17 => {
+            enable_bus_fault => [0x1];
+            disable_bus_fault => [0x0];
+        }
+
+        18 => {
+            enable_usage_fault => [0x1];
+            disable_usage_fault => [0x0];
+        }
+    };
+
+    //
+    // fault status and address
+    //
+
+    0x0028 => cfsr r32 rw {
+        0..31 => { write_cfsr => (); }  // write 1 to clear
+    };
+    0x002C => hfsr r32 rw {
+        0..31 => { write_hfsr => (); }  // write 1 to clear
+    };
+    0x0034 => mmfar r32 rw {};
+    0x0038 => bfar r32 rw {};
+    0x003C => afsr r32 rw {};
+);
+
+/// Get the SCB, for handlers that are not handed the MCU (such as the fault handler).
+pub fn scb() -> &'static SCB { unsafe { &*(SCB_ADDR as *const SCB) } }
+
+impl SCB {
+    /// Get the number of the active exception (ICSR.VECTACTIVE), 0 in thread mode.
+    pub fn active_vector(&self) -> u32 { self.read_icsr() & ICSR_VECTACTIVE }
+
+    /// Check ICSR.PENDSTSET, set while the SysTick exception waits behind masked interrupts or a higher priority
+    /// handler.
+    pub fn is_systick_pending(&self) -> bool { (self.read_icsr() & ICSR_PENDSTSET) != 0 }
+
+    /// Clear a pending SysTick exception with ICSR.PENDSTCLR.
+    ///
+    /// ICSR is written whole, as writing 0 to the other bits has no effect. A read-modify-write would write back the
+    /// set bits of other pending exceptions, and writing PENDSTSET along with PENDSTCLR is unpredictable.
+    pub fn clear_systick_pending(&self) { self.write_icsr(ICSR_PENDSTCLR); }
+
+    /// Request a system reset through AIRCR.SYSRESETREQ, keeping the priority grouping. The reset is not immediate,
+    /// so the caller should wait for it.
+    pub fn request_reset(&self) { self.write_aircr(reset_request(self.read_aircr())); }
+
+    /// Enables the divide by zero (DIV_0_TRP) and unaligned access (UNALIGN_TRP) usage fault traps.
+    ///
+    /// Without them, a division by zero quietly gives 0 and unaligned word accesses are split by the core.
+    pub fn enable_traps(&self) {
+        self.enable_divide_by_zero_trap();
+        self.enable_unaligned_trap();
+    }
+
+    /// Disables the divide by zero and unaligned access traps, restoring the reset behaviour.
+    pub fn disable_traps(&self) {
+        self.disable_divide_by_zero_trap();
+        self.disable_unaligned_trap();
+    }
+
+    /// Enables the MemManage, BusFault and UsageFault handlers. Until enabled, those faults escalate to HardFault.
+    pub fn enable_fault_handlers(&self) {
+        self.enable_mem_manage_fault();
+        self.enable_bus_fault();
+        self.enable_usage_fault();
+    }
+
+    /// Snapshot the fault status and address registers.
+    pub fn fault_status(&self) -> FaultStatus {
+        FaultStatus{
+            cfsr: self.read_cfsr(),
+            hfsr: self.read_hfsr(),
+            mmfar: self.read_mmfar(),
+            bfar: self.read_bfar(),
+        }
+    }
+
+    /// Clears the status bits recorded in the snapshot, leaving any raised since.
+    pub fn clear_fault_status(&self, status: &FaultStatus) {
+        self.write_cfsr(status.cfsr);
+        self.write_hfsr(status.hfsr);
+    }
+}
+
+/// Get the AIRCR value requesting a reset, keeping the priority grouping of the current value.
+fn reset_request(aircr: u32) -> u32 {
+    AIRCR_VECTKEY | (aircr & AIRCR_PRIGROUP) | AIRCR_SYSRESETREQ
+}
+
+
+//
+// fault decoding
+//
+
+// MemManage status (CFSR[7:0])
+const IACCVIOL: u32 = 1 << 0;
+const DACCVIOL: u32 = 1 << 1;
+const MUNSTKERR: u32 = 1 << 3;
+const MSTKERR: u32 = 1 << 4;
+const MLSPERR: u32 = 1 << 5;
+const MMARVALID: u32 = 1 << 7;
+
+// BusFault status (CFSR[15:8])
+const IBUSERR: u32 = 1 << 8;
+const PRECISERR: u32 = 1 << 9;
+const IMPRECISERR: u32 = 1 << 10;
+const UNSTKERR: u32 = 1 << 11;
+const STKERR: u32 = 1 << 12;
+const LSPERR: u32 = 1 << 13;
+const BFARVALID: u32 = 1 << 15;
+
+// UsageFault status (CFSR[31:16])
+const UNDEFINSTR: u32 = 1 << 16;
+const INVSTATE: u32 = 1 << 17;
+const INVPC: u32 = 1 << 18;
+const NOCP: u32 = 1 << 19;
+const UNALIGNED: u32 = 1 << 24;
+const DIVBYZERO: u32 = 1 << 25;
+
+// HardFault status
+const VECTTBL: u32 = 1 << 1;
+const FORCED: u32 = 1 << 30;
+const DEBUGEVT: u32 = 1 << 31;
+
+/// The fault handler a cause is reported through, when not escalated to HardFault.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum FaultClass {
+    MemManage,
+    Bus,
+    Usage,
+    Hard,
+}
+
+/// A single decoded fault cause.
+///
+/// Causes carrying an address hold `None` when the core did not latch a valid address.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Fault {
+    /// Instruction fetch from a region the MPU forbids, or from an XN region.
+    InstructionAccessViolation,
+    /// Data access to a region the MPU forbids.
+    DataAccessViolation(Option<u32>),
+    /// MPU violation while unstacking on exception return.
+    MemManageUnstacking,
+    /// MPU violation while stacking on exception entry.
+    MemManageStacking,
+    /// MPU violation during lazy floating point state preservation.
+    MemManageLazyFpState,
+
+    /// Bus error on instruction prefetch.
+    InstructionBusError,
+    /// Bus error on a data access, at the given address.
+    PreciseBusError(Option<u32>),
+    /// Bus error on a buffered data write. The faulting instruction has already retired.
+    ImpreciseBusError,
+    /// Bus error while unstacking on exception return.
+    BusUnstacking,
+    /// Bus error while stacking on exception entry.
+    BusStacking,
+    /// Bus error during lazy floating point state preservation.
+    BusLazyFpState,
+
+    /// Undefined instruction.
+    UndefinedInstruction,
+    /// Instruction executed with an invalid EPSR, usually a branch to an address without the thumb bit.
+    InvalidState,
+    /// Exception return with an invalid EXC_RETURN value.
+    InvalidPc,
+    /// Coprocessor instruction with the coprocessor disabled or absent (often the FPU).
+    NoCoprocessor,
+    /// Unaligned access with UNALIGN_TRP set, or an unaligned multiple/exclusive access.
+    UnalignedAccess,
+    /// Integer division by zero with DIV_0_TRP set.
+    DivideByZero,
+
+    /// Bus error while reading the vector table on exception entry.
+    VectorTableRead,
+    /// A fault escalated to HardFault without recording its cause in CFSR.
+    Forced,
+    /// A debug event while the debugger is not attached.
+    DebugEvent,
+}
+
+impl Fault {
+    /// The handler the cause belongs to.
+    pub fn class(&self) -> FaultClass {
+        match *self {
+            Fault::InstructionAccessViolation | Fault::DataAccessViolation(_) | Fault::MemManageUnstacking |
+            Fault::MemManageStacking | Fault::MemManageLazyFpState => FaultClass::MemManage,
+
+            Fault::InstructionBusError | Fault::PreciseBusError(_) | Fault::ImpreciseBusError |
+            Fault::BusUnstacking | Fault::BusStacking | Fault::BusLazyFpState => FaultClass::Bus,
+
+            Fault::UndefinedInstruction | Fault::InvalidState | Fault::InvalidPc | Fault::NoCoprocessor |
+            Fault::UnalignedAccess | Fault::DivideByZero => FaultClass::Usage,
+
+            Fault::VectorTableRead | Fault::Forced | Fault::DebugEvent => FaultClass::Hard,
+        }
+    }
+
+    /// The faulting data address, when the cause has one and it was latched.
+    pub fn address(&self) -> Option<u32> {
+        match *self {
+            Fault::DataAccessViolation(addr) | Fault::PreciseBusError(addr) => addr,
+            _ => None,
+        }
+    }
+
+    /// Short human readable description of the cause.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            Fault::InstructionAccessViolation => "instruction access violation",
+            Fault::DataAccessViolation(_) => "data access violation",
+            Fault::MemManageUnstacking => "memory management fault on unstacking",
+            Fault::MemManageStacking => "memory management fault on stacking",
+            Fault::MemManageLazyFpState => "memory management fault on lazy fp state preservation",
+            Fault::InstructionBusError => "instruction bus error",
+            Fault::PreciseBusError(_) => "precise data bus error",
+            Fault::ImpreciseBusError => "imprecise data bus error",
+            Fault::BusUnstacking => "bus fault on unstacking",
+            Fault::BusStacking => "bus fault on stacking",
+            Fault::BusLazyFpState => "bus fault on lazy fp state preservation",
+            Fault::UndefinedInstruction => "undefined instruction",
+            Fault::InvalidState => "invalid execution state",
+            Fault::InvalidPc => "invalid exception return",
+            Fault::NoCoprocessor => "no coprocessor",
+            Fault::UnalignedAccess => "unaligned access",
+            Fault::DivideByZero => "divide by zero",
+            Fault::VectorTableRead => "vector table read fault",
+            Fault::Forced => "escalated fault",
+            Fault::DebugEvent => "debug event",
+        }
+    }
+}
+
+/// Snapshot of the fault status (CFSR, HFSR) and fault address (MMFAR, BFAR) registers.
+#[repr(C)]
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct FaultStatus {
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+}
+
+/// Number of decoding steps: VECTTBL, each CFSR bit, DEBUGEVT, then FORCED.
+const DECODE_STEPS: u8 = 35;
+
+impl FaultStatus {
+    /// Check whether any fault is recorded.
+    pub fn is_empty(&self) -> bool { self.faults().next().is_none() }
+
+    /// Check whether the HardFault was escalated from a configurable fault (or a fault within its handler).
+    pub fn is_forced(&self) -> bool { (self.hfsr & FORCED) != 0 }
+
+    /// The primary fault cause. A vector table read fault is reported first, then the CFSR causes from MemManage up
+    /// to UsageFault.
+    pub fn reason(&self) -> Option<Fault> { self.faults().next() }
+
+    /// Iterate over every recorded fault cause, in the order given by `reason()`.
+    pub fn faults(&self) -> Faults {
+        Faults{status: self, step: 0}
+    }
+
+    /// Decode the given step, where 0-31 are the CFSR bits.
+    fn decode(&self, step: u8) -> Option<Fault> {
+        let cfsr = self.cfsr;
+        let mmfar = if (cfsr & MMARVALID) != 0 { Some(self.mmfar) } else { None };
+        let bfar = if (cfsr & BFARVALID) != 0 { Some(self.bfar) } else { None };
+
+        if step < 32 {
+            return match cfsr & (1 << step) {
+                IACCVIOL => Some(Fault::InstructionAccessViolation),
+                DACCVIOL => Some(Fault::DataAccessViolation(mmfar)),
+                MUNSTKERR => Some(Fault::MemManageUnstacking),
+                MSTKERR => Some(Fault::MemManageStacking),
+                MLSPERR => Some(Fault::MemManageLazyFpState),
+                IBUSERR => Some(Fault::InstructionBusError),
+                PRECISERR => Some(Fault::PreciseBusError(bfar)),
+                IMPRECISERR => Some(Fault::ImpreciseBusError),
+                UNSTKERR => Some(Fault::BusUnstacking),
+                STKERR => Some(Fault::BusStacking),
+                LSPERR => Some(Fault::BusLazyFpState),
+                UNDEFINSTR => Some(Fault::UndefinedInstruction),
+                INVSTATE => Some(Fault::InvalidState),
+                INVPC => Some(Fault::InvalidPc),
+                NOCP => Some(Fault::NoCoprocessor),
+                UNALIGNED => Some(Fault::UnalignedAccess),
+                DIVBYZERO => Some(Fault::DivideByZero),
+                _ => None, // clear, reserved, or an address valid flag
+            };
+        }
+
+        match step {
+            32 if (self.hfsr & DEBUGEVT) != 0 => Some(Fault::DebugEvent),
+            // only report FORCED on its own, otherwise the escalated cause is already given by CFSR
+            33 if self.is_forced() && (cfsr & !(MMARVALID | BFARVALID)) == 0 => Some(Fault::Forced),
+            _ => None,
+        }
+    }
+}
+
+/// Iterator over the fault causes recorded in a `FaultStatus`.
+pub struct Faults<'a> {
+    status: &'a FaultStatus,
+    step: u8,
+}
+
+impl<'a> Iterator for Faults<'a> {
+    type Item = Fault;
+
+    fn next(&mut self) -> Option<Fault> {
+        // the vector table read is reported before anything else, as the handler never ran
+        if self.step == 0 {
+            self.step = 1;
+            if (self.status.hfsr & VECTTBL) != 0 { return Some(Fault::VectorTableRead); }
+        }
+
+        while self.step < DECODE_STEPS {
+            let step = self.step - 1;
+            self.step += 1;
+            if let Some(f) = self.status.decode(step) { return Some(f); }
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Fault, FaultClass, FaultStatus, reset_request};
+
+    #[test]
+    fn reset_keeps_priority_grouping() {
+        assert_eq!(0x05FA_0004, reset_request(0xFA05_0000));  // the key reads back inverted
+        assert_eq!(0x05FA_0504, reset_request(0xFA05_0503));  // only PRIGROUP is carried over
+    }
+
+    fn status(cfsr: u32, hfsr: u32) -> FaultStatus {
+        FaultStatus{cfsr: cfsr, hfsr: hfsr, mmfar: 0x2000_0100, bfar: 0x4000_0000}
+    }
+
+    mod decode {
+        use super::status;
+        use super::super::{Fault, FaultClass};
+
+        #[test]
+        fn empty() {
+            let s = status(0, 0);
+            assert!(s.is_empty());
+            assert_eq!(None, s.reason());
+        }
+
+        #[test]
+        fn precise_bus_fault_with_address() {
+            let s = status((1 << 9) | (1 << 15), 0);
+            assert_eq!(Some(Fault::PreciseBusError(Some(0x4000_0000))), s.reason());
+            assert_eq!(Some(0x4000_0000), s.reason().unwrap().address());
+            assert_eq!(FaultClass::Bus, s.reason().unwrap().class());
+        }
+
+        #[test]
+        fn address_only_when_valid() {
+            assert_eq!(Some(Fault::PreciseBusError(None)), status(1 << 9, 0).reason());
+            assert_eq!(Some(Fault::DataAccessViolation(None)), status(1 << 1, 0).reason());
+            assert_eq!(Some(Fault::DataAccessViolation(Some(0x2000_0100))), status((1 << 1) | (1 << 7), 0).reason());
+            assert!(status(1 << 15, 0).is_empty()); // a valid address alone is not a fault
+        }
+
+        #[test]
+        fn usage_faults() {
+            assert_eq!(Some(Fault::DivideByZero), status(1 << 25, 0).reason());
+            assert_eq!(Some(Fault::UnalignedAccess), status(1 << 24, 0).reason());
+            assert_eq!(Some(Fault::InvalidState), status(1 << 17, 0).reason());
+            assert_eq!(Some(Fault::NoCoprocessor), status(1 << 19, 0).reason());
+            assert_eq!(FaultClass::Usage, Fault::DivideByZero.class());
+        }
+
+        #[test]
+        fn reserved_bits_ignored() {
+            assert!(status((1 << 2) | (1 << 6) | (1 << 20) | (1 << 31), 0).is_empty());
+        }
+    }
+
+    mod hard_fault {
+        use super::status;
+        use super::super::Fault;
+
+        #[test]
+        fn forced_reports_cfsr_cause() {
+            let s = status(1 << 25, 1 << 30);
+            assert!(s.is_forced());
+            assert_eq!(Some(Fault::DivideByZero), s.reason());
+            assert_eq!(1, s.faults().count());
+        }
+
+        #[test]
+        fn forced_without_cause() {
+            let s = status(0, 1 << 30);
+            assert_eq!(Some(Fault::Forced), s.reason());
+        }
+
+        #[test]
+        fn vector_table_first() {
+            let s = status(1 << 16, (1 << 1) | (1 << 31));
+            let mut faults = s.faults();
+            assert_eq!(Some(Fault::VectorTableRead), faults.next());
+            assert_eq!(Some(Fault::UndefinedInstruction), faults.next());
+            assert_eq!(Some(Fault::DebugEvent), faults.next());
+            assert_eq!(None, faults.next());
+        }
+    }
+
+    #[test]
+    fn every_cause_in_order() {
+        let s = FaultStatus{cfsr: 0xFFFF_FFFF, hfsr: 0xFFFF_FFFF, mmfar: 1, bfar: 2};
+        let expected = [
+            Fault::VectorTableRead,
+            Fault::InstructionAccessViolation, Fault::DataAccessViolation(Some(1)), Fault::MemManageUnstacking,
+            Fault::MemManageStacking, Fault::MemManageLazyFpState,
+            Fault::InstructionBusError, Fault::PreciseBusError(Some(2)), Fault::ImpreciseBusError,
+            Fault::BusUnstacking, Fault::BusStacking, Fault::BusLazyFpState,
+            Fault::UndefinedInstruction, Fault::InvalidState, Fault::InvalidPc, Fault::NoCoprocessor,
+            Fault::UnalignedAccess, Fault::DivideByZero,
+            Fault::DebugEvent,
+        ];
+        assert_eq!(expected.len(), s.faults().count());
+        for (got, want) in s.faults().zip(expected.iter()) {
+            assert_eq!(*want, got);
+        }
+        assert_eq!(FaultClass::Hard, s.reason().unwrap().class());
+    }
+}
@@ -1,6 +1,21 @@
 extern crate core;
 use core::intrinsics::{volatile_load, volatile_store};
 
+use ::os::error::SysTickError;
+use ::traits::ClockSource;
+use super::scb;
+
+/// Largest value the 24 bit reload and current value registers hold.
+pub const MAX_RELOAD: u32 = 0x00FF_FFFF;
+
+// CSR
+const CSR_CLKSOURCE: u32 = 1 << 2;
+const CSR_COUNTFLAG: u32 = 1 << 16;
+
+// CALIB
+const CALIB_SKEW: u32 = 1 << 30;
+const CALIB_NOREF: u32 = 1 << 31;
+
 /// Core SysTick definition.
 ///
 /// On init, the interrupt is disabled, ticking is disabled, and the reload value is set to 0.
@@ -23,16 +38,35 @@ ioreg!(
             unset_exception_enable_bit => [0x0];
         }
 
-        // TODO: clock sourcing
+        2 => {
+            use_processor_clock => [0x1];
+            use_external_clock => [0x0];
+        }
     };
 
     0x0004 => reload r32 rw {
         0..23 => { reload => (); }
     };
 
-    0x0008 => calibration r32 ro {};
+    0x0008 => current r32 rw {
+        0..23 => { clear_current => (); }  // any write clears the count and COUNTFLAG
+    };
+
+    0x000C => calibration r32 ro {};
 );
 
+/// Get the reload value that makes a SysTick counting at `clock_hz` wrap `tick_hz` times a second.
+///
+/// The counter wraps every reload + 1 clocks, and the period is rounded to the nearest clock.
+pub fn reload_value(clock_hz: u32, tick_hz: u32) -> Result<u32, SysTickError> {
+    if tick_hz == 0 { return Err(SysTickError::ZeroRate); }
+
+    let period = ((clock_hz as u64) + (tick_hz as u64) / 2) / (tick_hz as u64);
+    if period < 2 { return Err(SysTickError::RateTooHigh); }  // a reload of 0 stops the counter
+    if period - 1 > (MAX_RELOAD as u64) { return Err(SysTickError::RateTooLow); }
+    Ok((period - 1) as u32)
+}
+
 impl ::traits::SysTick for SysTick {
     /// Enables ticking of the counter
     fn enable(&self) { self.set_enable_bit(); }
@@ -42,7 +76,7 @@ impl ::traits::SysTick for SysTick {
 
     /// Detect if the counter has hit 0 since it was last read. Used if the interrupt is not enabled.
     fn has_reset(&self) -> bool {
-        (self.read_status_and_control() & (0x1 << 15)) as usize > 0 // read 16th bit
+        (self.read_status_and_control() & CSR_COUNTFLAG) != 0
     }
 
     /// Enables the SysTick exception when the counter hits 0.
@@ -51,15 +85,86 @@ impl ::traits::SysTick for SysTick {
     /// Disables the SysTick exception.
     fn disable_interrupt(&self) { self.unset_exception_enable_bit(); }
 
+    /// Check ICSR.PENDSTSET, set while the exception waits behind masked interrupts or a higher priority handler.
+    fn is_exception_pending(&self) -> bool { scb::scb().is_systick_pending() }
+
+    /// Clears the pending exception with ICSR.PENDSTCLR.
+    fn clear_pending_exception(&self) { scb::scb().clear_systick_pending(); }
+
     /// Sets the value the module will reload with when resetting.
     fn set_tick_reload_value(&self, val: usize) { self.reload(val as u32); }
 
     /// Fetch the current value of the countdown.
-    fn current_tick(&self) -> usize { (self.read_reload() & 0xFFFFFF) as usize }
+    fn current_tick(&self) -> usize { (self.read_current() & MAX_RELOAD) as usize }
 
     /// Polls for whether the 10ms calibration value is reliable
-    fn has_calibration_value(&self) -> bool { (self.read_calibration() & (0x1 << 29)) > 0 }
+    fn has_calibration_value(&self) -> bool {
+        let calib = self.read_calibration();
+        (calib & CALIB_SKEW) == 0 && (calib & MAX_RELOAD) != 0
+    }
 
     /// Fetches the 10ms calibration value.
-    fn calibration_value(&self) -> usize { (self.read_calibration() & 0xFFFFFF) as usize }
+    fn calibration_value(&self) -> usize { (self.read_calibration() & MAX_RELOAD) as usize }
+
+    /// Selects the core clock, or the external reference clock.
+    fn set_clock_source(&self, src: ClockSource) {
+        match src {
+            ClockSource::Processor => self.use_processor_clock(),
+            ClockSource::External => self.use_external_clock(),
+        }
+    }
+
+    /// Fetch the selected clock.
+    fn clock_source(&self) -> ClockSource {
+        match self.read_status_and_control() & CSR_CLKSOURCE {
+            0 => ClockSource::External,
+            _ => ClockSource::Processor,
+        }
+    }
+
+    /// Check the calibration register for the external reference clock.
+    fn has_external_clock(&self) -> bool { (self.read_calibration() & CALIB_NOREF) == 0 }
+
+    /// Sets the reload value for the tick rate and clears the current count, so the first period is a full one.
+    fn set_tick_rate(&self, clock_hz: u32, tick_hz: u32) -> Result<u32, SysTickError> {
+        let reload = try!(reload_value(clock_hz, tick_hz));
+        self.reload(reload);
+        self.clear_current(0);
+        Ok(reload)
+    }
+}
+
+/// SysTick exception handler, advancing the system clock when it was started in `os::time::Mode::Interrupt`.
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_systick() { ::os::time::tick(); }
+
+
+#[cfg(test)]
+mod test {
+    use ::os::error::SysTickError;
+    use super::{reload_value, MAX_RELOAD};
+
+    #[test]
+    fn exact_rates() {
+        assert_eq!(Ok(119_999), reload_value(120_000_000, 1_000));      // 1ms at 120MHz
+        assert_eq!(Ok(20_969), reload_value(20_970_000, 1_000));        // FEI default
+        assert_eq!(Ok(1), reload_value(1_000, 500));
+    }
+
+    #[test]
+    fn rounds_to_nearest() {
+        assert_eq!(Ok(39_999), reload_value(120_000_000, 3_000));
+        assert_eq!(Ok(17_142), reload_value(120_000_000, 7_000));       // 17142.86 clocks
+    }
+
+    #[test]
+    fn limits() {
+        assert_eq!(Err(SysTickError::ZeroRate), reload_value(120_000_000, 0));
+        assert_eq!(Err(SysTickError::RateTooHigh), reload_value(120_000_000, 120_000_000));
+        assert_eq!(Err(SysTickError::RateTooHigh), reload_value(1_000, 2_000));
+        assert_eq!(Ok(MAX_RELOAD), reload_value(MAX_RELOAD + 1, 1));
+        assert_eq!(Err(SysTickError::RateTooLow), reload_value(120_000_000, 7));    // 17M clocks
+        assert_eq!(Err(SysTickError::RateTooLow), reload_value(::core::u32::MAX, 1));
+    }
 }
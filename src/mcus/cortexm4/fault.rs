@@ -0,0 +1,509 @@
+extern crate core;
+
+use ::mcus::cortexm4::core::scb::{self, Fault, FaultStatus};
+use ::os::error::RegistryError;
+use ::os::error::panic::{PanicAction, ResetControl, SystemReset};
+use ::os::sync;
+
+
+//------------------------------------------------
+//
+// exception frame
+//
+//------------------------------------------------
+
+/// Number of words the core pushes on exception entry without floating point context.
+pub const BASIC_FRAME_WORDS: usize = 8;
+
+/// Number of words the core pushes on exception entry with floating point context (S0-S15, FPSCR, and a reserved
+/// word follow the basic frame).
+pub const EXTENDED_FRAME_WORDS: usize = 26;
+
+/// EXC_RETURN bit set when returning to the process stack.
+const EXC_RETURN_PSP: u32 = 1 << 2;
+/// EXC_RETURN bit set when returning to thread mode.
+const EXC_RETURN_THREAD: u32 = 1 << 3;
+/// EXC_RETURN bit clear when the frame includes floating point context.
+const EXC_RETURN_NO_FP: u32 = 1 << 4;
+/// Stacked xPSR bit set when the core inserted a padding word to 8 byte align the frame.
+const XPSR_PADDED: u32 = 1 << 9;
+
+/// The stack the core pushed the exception frame to.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum StackPointer {
+    Main,
+    Process,
+}
+impl StackPointer {
+    /// Get the stack the frame is on from the handler's EXC_RETURN (the LR on entry).
+    pub fn from_exc_return(exc_return: u32) -> StackPointer {
+        if (exc_return & EXC_RETURN_PSP) != 0 { StackPointer::Process } else { StackPointer::Main }
+    }
+}
+
+/// Registers pushed by the core on exception entry, in stack order.
+#[repr(C)]
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ExceptionFrame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    /// Link register of the faulting context.
+    pub lr: u32,
+    /// Address of the faulting instruction (or the next one, for imprecise faults).
+    pub pc: u32,
+    pub xpsr: u32,
+}
+impl ExceptionFrame {
+    /// Creates a zeroed frame.
+    pub const fn empty() -> ExceptionFrame {
+        ExceptionFrame{r0: 0, r1: 0, r2: 0, r3: 0, r12: 0, lr: 0, pc: 0, xpsr: 0}
+    }
+
+    /// Parse a frame from the words at the stack pointer. `None` if there are fewer words than a basic frame.
+    pub fn from_words(stack: &[u32]) -> Option<ExceptionFrame> {
+        if stack.len() < BASIC_FRAME_WORDS { return None; }
+        Some(ExceptionFrame{
+            r0: stack[0],
+            r1: stack[1],
+            r2: stack[2],
+            r3: stack[3],
+            r12: stack[4],
+            lr: stack[5],
+            pc: stack[6],
+            xpsr: stack[7],
+        })
+    }
+
+    /// Read the frame from the stack pointer the core pushed it to.
+    pub unsafe fn read(sp: *const u32) -> ExceptionFrame {
+        ExceptionFrame::from_words(core::slice::from_raw_parts(sp, BASIC_FRAME_WORDS)).unwrap()
+    }
+}
+
+/// Get the number of bytes the core pushed on exception entry, including alignment padding.
+///
+/// Adding this to the frame's address gives the stack pointer of the context at the time of the fault.
+pub fn frame_size(exc_return: u32, xpsr: u32) -> usize {
+    let words = if (exc_return & EXC_RETURN_NO_FP) == 0 { EXTENDED_FRAME_WORDS } else { BASIC_FRAME_WORDS };
+    let padding = if (xpsr & XPSR_PADDED) != 0 { 4 } else { 0 };
+    words * 4 + padding
+}
+
+
+//------------------------------------------------
+//
+// fault record
+//
+//------------------------------------------------
+
+/// Value marking a `FaultRecord` as holding a fault. Anything else is treated as leftover RAM contents.
+pub const FAULT_MAGIC: u32 = 0xFA17_C0DE;
+
+/// Exception numbers of the fault handlers.
+#[repr(u8)]
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Exception {
+    HardFault = 3,
+    MemManage = 4,
+    BusFault = 5,
+    UsageFault = 6,
+}
+impl Exception {
+    /// Get the fault exception for the given vector number (ICSR.VECTACTIVE or IPSR).
+    pub fn from_vector(vector: u32) -> Option<Exception> {
+        match vector {
+            3 => Some(Exception::HardFault),
+            4 => Some(Exception::MemManage),
+            5 => Some(Exception::BusFault),
+            6 => Some(Exception::UsageFault),
+            _ => None,
+        }
+    }
+}
+
+/// Everything known about a fault: the handler that took it, the stacked registers, and the SCB fault status.
+///
+/// Like `CrashRecord`, a record is only valid with the magic intact as it may be read back from RAM after a reset.
+#[repr(C)]
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct FaultRecord {
+    magic: u32,
+    vector: u32,
+    exc_return: u32,
+    sp: usize,
+    frame: ExceptionFrame,
+    status: FaultStatus,
+}
+impl FaultRecord {
+    /// Creates an empty (invalid) record.
+    pub const fn empty() -> FaultRecord {
+        FaultRecord{
+            magic: 0,
+            vector: 0,
+            exc_return: 0,
+            sp: 0,
+            frame: ExceptionFrame::empty(),
+            status: FaultStatus{cfsr: 0, hfsr: 0, mmfar: 0, bfar: 0},
+        }
+    }
+
+    /// Creates a record from the frame the core stacked at `sp`, for the handler with the given vector number.
+    ///
+    /// The stack is given as words so frames can be parsed away from the hardware. `None` if it is shorter than a
+    /// basic frame.
+    pub fn from_stack(vector: u32, exc_return: u32, stack: &[u32], status: FaultStatus) -> Option<FaultRecord> {
+        let frame = match ExceptionFrame::from_words(stack) {
+            Some(f) => f,
+            None => return None,
+        };
+        Some(FaultRecord{
+            magic: FAULT_MAGIC,
+            vector: vector,
+            exc_return: exc_return,
+            sp: (stack.as_ptr() as usize) + frame_size(exc_return, frame.xpsr),
+            frame: frame,
+            status: status,
+        })
+    }
+
+    /// Creates a record from the frame at the given stack pointer.
+    pub unsafe fn capture(vector: u32, exc_return: u32, sp: *const u32, status: FaultStatus) -> FaultRecord {
+        let stack = core::slice::from_raw_parts(sp, BASIC_FRAME_WORDS);
+        FaultRecord::from_stack(vector, exc_return, stack, status).unwrap()
+    }
+
+    /// Invalidate the record.
+    pub fn clear(&mut self) { self.magic = 0; }
+
+    /// Check whether the record holds a fault.
+    pub fn is_valid(&self) -> bool { self.magic == FAULT_MAGIC }
+
+    /// Get the handler that took the fault. `None` if the record was captured outside a fault handler.
+    pub fn exception(&self) -> Option<Exception> { Exception::from_vector(self.vector) }
+
+    /// Get the registers stacked on exception entry.
+    pub fn frame(&self) -> &ExceptionFrame { &self.frame }
+
+    /// Get the SCB fault status at the time of the fault.
+    pub fn status(&self) -> &FaultStatus { &self.status }
+
+    /// Get the primary decoded fault cause.
+    pub fn reason(&self) -> Option<Fault> { self.status.reason() }
+
+    /// Get the stack the faulting context was running on.
+    pub fn stack(&self) -> StackPointer { StackPointer::from_exc_return(self.exc_return) }
+
+    /// Check whether the fault was taken from thread mode (rather than from another handler).
+    pub fn in_thread_mode(&self) -> bool { (self.exc_return & EXC_RETURN_THREAD) != 0 }
+
+    /// Check whether the core stacked floating point context.
+    pub fn has_fp_context(&self) -> bool { (self.exc_return & EXC_RETURN_NO_FP) == 0 }
+
+    /// Get the stack pointer of the faulting context, before the core pushed the exception frame.
+    pub fn stack_pointer(&self) -> usize { self.sp }
+}
+
+
+//------------------------------------------------
+//
+// fault handling
+//
+//------------------------------------------------
+
+/// Maximum number of fault hooks that can be registered, including the default `save_fault`.
+pub const MAX_FAULT_HOOKS: usize = 4;
+
+/// Function given the record of a fault. Runs in the fault handler, so it must be short and must not allocate.
+pub type FaultHook = fn(&FaultRecord);
+
+/// Runs the fault sequence: pass the record to each hook, then reset or halt.
+pub struct FaultHandler {
+    hooks: [Option<FaultHook>; MAX_FAULT_HOOKS],
+    num_hooks: usize,
+    action: PanicAction,
+}
+impl FaultHandler {
+    /// Creates a handler with no hooks that finishes with the given action.
+    pub const fn new(action: PanicAction) -> FaultHandler {
+        FaultHandler{
+            hooks: [None; MAX_FAULT_HOOKS],
+            num_hooks: 0,
+            action: action,
+        }
+    }
+
+    /// Register a hook to run on fault. Hooks run in the order they were registered.
+    pub fn register(&mut self, hook: FaultHook) -> Result<(), RegistryError> {
+        if self.num_hooks == MAX_FAULT_HOOKS { return Err(RegistryError::Full); }
+        self.hooks[self.num_hooks] = Some(hook);
+        self.num_hooks += 1;
+        Ok(())
+    }
+
+    /// Get the number of registered hooks.
+    pub fn hooks(&self) -> usize { self.num_hooks }
+
+    /// Set what happens after the hooks have run.
+    pub fn set_action(&mut self, action: PanicAction) { self.action = action; }
+
+    /// Get what happens after the hooks have run.
+    pub fn action(&self) -> PanicAction { self.action }
+
+    /// Handle a fault. This only returns if the `ResetControl` does.
+    pub fn handle(&self, record: &FaultRecord, ctl: &mut ResetControl) {
+        for i in 0..self.num_hooks {
+            if let Some(hook) = self.hooks[i] { hook(record); }
+        }
+
+        match self.action {
+            PanicAction::Halt => ctl.halt(),
+            PanicAction::Reset => ctl.reset(),
+        }
+    }
+}
+
+
+//------------------------------------------------
+//
+// system fault handler
+//
+//------------------------------------------------
+
+// changed with interrupts masked, so a fault taken in an ISR never sees it part way through an update
+static mut FAULT_HANDLER: FaultHandler = FaultHandler{
+    hooks: [Some(save_fault), None, None, None],
+    num_hooks: 1,
+    action: PanicAction::Halt,
+};
+
+#[cfg_attr(not(test), link_section = ".noinit")]
+static mut FAULT_RECORD: FaultRecord = FaultRecord::empty();
+
+/// Default hook, saving the record to the `.noinit` section so it can be read back with `last_fault()` after a
+/// reset.
+pub fn save_fault(record: &FaultRecord) {
+    unsafe { FAULT_RECORD = *record; }
+}
+
+/// Register a hook to run when the system faults, after saving the record. See `FaultHandler::register()`.
+pub fn register_fault_hook(hook: FaultHook) -> Result<(), RegistryError> {
+    sync::critical(|| unsafe { FAULT_HANDLER.register(hook) })
+}
+
+/// Set whether the system halts (the default) or resets after a fault.
+pub fn set_fault_action(action: PanicAction) {
+    sync::critical(|| unsafe { FAULT_HANDLER.set_action(action); });
+}
+
+/// Get the record of the fault that happened before the last reset, if any.
+pub fn last_fault() -> Option<&'static FaultRecord> {
+    let record = unsafe { &FAULT_RECORD };
+    if record.is_valid() { Some(record) } else { None }
+}
+
+/// Forget the last fault, typically once it has been reported.
+pub fn clear_last_fault() {
+    unsafe { FAULT_RECORD.clear(); }
+}
+
+/// Common body of the fault handlers, given the stack pointer the frame is on and the handler's EXC_RETURN.
+#[no_mangle]
+pub unsafe extern "C" fn fault_entry(sp: *const u32, exc_return: u32) -> ! {
+    let scb = scb::scb();
+    let status = scb.fault_status();
+    let vector = scb.active_vector();
+
+    let record = FaultRecord::capture(vector, exc_return, sp, status);
+    FAULT_HANDLER.handle(&record, &mut SystemReset);
+    loop {}
+}
+
+/// Fault trampoline, wired to the HardFault, MemManage, BusFault and UsageFault vectors (3-6). It finds the stack the
+/// core pushed the frame to from EXC_RETURN, and hands it to `fault_entry` without touching the stack itself.
+/// `fault_entry` tells the faults apart from the active vector in ICSR.
+///
+/// MemManage, BusFault and UsageFault are only taken once enabled with `SCB::enable_fault_handlers()`, and escalate
+/// to HardFault until then.
+#[cfg(target_arch = "arm")]
+#[naked]
+#[no_mangle]
+pub unsafe extern "C" fn isr_fault() {
+    asm!("tst lr, #4
+          ite eq
+          mrseq r0, msp
+          mrsne r0, psp
+          mov r1, lr
+          b fault_entry" :::: "volatile");
+}
+
+
+#[cfg(test)]
+mod test {
+    use ::mcus::cortexm4::core::scb::FaultStatus;
+
+    /// Stack image of a basic frame followed by the faulting context's stack.
+    const STACK: [u32; 10] = [
+        0x0000_0001, 0x0000_0002, 0x0000_0003, 0x0000_0004, // r0-r3
+        0x0000_000C,                                        // r12
+        0x0000_1235,                                        // lr
+        0x0000_2000,                                        // pc
+        0x0100_0000,                                        // xpsr (thumb)
+        0xAAAA_AAAA, 0xBBBB_BBBB,
+    ];
+
+    const DIV_BY_ZERO: FaultStatus = FaultStatus{cfsr: 1 << 25, hfsr: 0, mmfar: 0, bfar: 0};
+
+    mod frame {
+        use super::STACK;
+        use super::super::{ExceptionFrame, StackPointer, frame_size};
+
+        #[test]
+        fn parses_stacked_registers() {
+            let frame = ExceptionFrame::from_words(&STACK).unwrap();
+            assert_eq!(1, frame.r0);
+            assert_eq!(4, frame.r3);
+            assert_eq!(0xC, frame.r12);
+            assert_eq!(0x1235, frame.lr);
+            assert_eq!(0x2000, frame.pc);
+            assert_eq!(0x0100_0000, frame.xpsr);
+            assert_eq!(frame, unsafe { ExceptionFrame::read(STACK.as_ptr()) });
+        }
+
+        #[test]
+        fn short_stack() {
+            assert_eq!(None, ExceptionFrame::from_words(&STACK[..7]));
+        }
+
+        #[test]
+        fn sizes() {
+            assert_eq!(32, frame_size(0xFFFF_FFFD, 0));             // thread mode, psp, no fp
+            assert_eq!(36, frame_size(0xFFFF_FFF9, 1 << 9));        // padded
+            assert_eq!(104, frame_size(0xFFFF_FFE9, 0));            // fp context
+            assert_eq!(108, frame_size(0xFFFF_FFE1, 1 << 9));
+        }
+
+        #[test]
+        fn stack_selection() {
+            assert_eq!(StackPointer::Process, StackPointer::from_exc_return(0xFFFF_FFFD));
+            assert_eq!(StackPointer::Main, StackPointer::from_exc_return(0xFFFF_FFF9));
+            assert_eq!(StackPointer::Main, StackPointer::from_exc_return(0xFFFF_FFF1));
+        }
+    }
+
+    mod record {
+        use ::mcus::cortexm4::core::scb::Fault;
+        use super::{STACK, DIV_BY_ZERO};
+        use super::super::{Exception, FaultRecord, StackPointer};
+
+        #[test]
+        fn from_synthetic_stack() {
+            let stack = STACK;
+            let record = FaultRecord::from_stack(6, 0xFFFF_FFFD, &stack, DIV_BY_ZERO).unwrap();
+            assert!(record.is_valid());
+            assert_eq!(Some(Exception::UsageFault), record.exception());
+            assert_eq!(Some(Fault::DivideByZero), record.reason());
+            assert_eq!(0x2000, record.frame().pc);
+            assert_eq!(StackPointer::Process, record.stack());
+            assert!(record.in_thread_mode());
+            assert_eq!(false, record.has_fp_context());
+            assert_eq!(stack[8..].as_ptr() as usize, record.stack_pointer());
+        }
+
+        #[test]
+        fn handler_mode_fault() {
+            let record = FaultRecord::from_stack(3, 0xFFFF_FFF1, &STACK, DIV_BY_ZERO).unwrap();
+            assert_eq!(Some(Exception::HardFault), record.exception());
+            assert_eq!(StackPointer::Main, record.stack());
+            assert_eq!(false, record.in_thread_mode());
+        }
+
+        #[test]
+        fn empty_and_cleared() {
+            assert_eq!(false, FaultRecord::empty().is_valid());
+            assert!(FaultRecord::from_stack(6, 0xFFFF_FFFD, &STACK[..4], DIV_BY_ZERO).is_none());
+
+            let mut record = FaultRecord::from_stack(6, 0xFFFF_FFFD, &STACK, DIV_BY_ZERO).unwrap();
+            record.clear();
+            assert_eq!(false, record.is_valid());
+        }
+
+        #[test]
+        fn vectors_share_the_trampoline() {
+            // isr_fault takes vectors 3-6, and the record tells them apart by the active vector alone
+            for vector in 3..7 {
+                let record = FaultRecord::from_stack(vector, 0xFFFF_FFFD, &STACK, DIV_BY_ZERO).unwrap();
+                assert_eq!(Some(vector as u8), record.exception().map(|e| e as u8));
+            }
+        }
+
+        #[test]
+        fn not_a_fault_handler() {
+            let record = FaultRecord::from_stack(15, 0xFFFF_FFFD, &STACK, DIV_BY_ZERO).unwrap();
+            assert_eq!(None, record.exception());
+        }
+    }
+
+    mod handler {
+        extern crate std;
+        use self::std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+        use ::os::error::RegistryError;
+        use ::os::error::panic::PanicAction;
+        use ::test_support::MockReset;
+        use super::{STACK, DIV_BY_ZERO};
+        use super::super::{FaultHandler, FaultRecord, MAX_FAULT_HOOKS};
+
+        static SEEN_PC: AtomicUsize = ATOMIC_USIZE_INIT;
+        fn pc_hook(record: &FaultRecord) { SEEN_PC.store(record.frame().pc as usize, Ordering::SeqCst); }
+
+        #[test]
+        fn runs_hooks_then_action() {
+            let mut handler = FaultHandler::new(PanicAction::Reset);
+            handler.register(pc_hook).expect("could not register");
+
+            let record = FaultRecord::from_stack(6, 0xFFFF_FFFD, &STACK, DIV_BY_ZERO).unwrap();
+            let mut ctl = MockReset::new();
+            handler.handle(&record, &mut ctl);
+
+            assert_eq!(0x2000, SEEN_PC.load(Ordering::SeqCst));
+            assert_eq!(1, ctl.resets);
+            assert_eq!(0, ctl.halts);
+        }
+
+        fn noop_hook(_: &FaultRecord) {}
+
+        #[test]
+        fn registry_full() {
+            let mut handler = FaultHandler::new(PanicAction::Halt);
+            for _ in 0..MAX_FAULT_HOOKS {
+                handler.register(noop_hook).expect("could not register");
+            }
+            assert_eq!(Err(RegistryError::Full), handler.register(noop_hook));
+            assert_eq!(MAX_FAULT_HOOKS, handler.hooks());
+        }
+    }
+
+    #[test]
+    fn default_hook_saves_record() {
+        use super::{FaultRecord, save_fault, last_fault, clear_last_fault};
+
+        let record = FaultRecord::from_stack(5, 0xFFFF_FFF9, &STACK, DIV_BY_ZERO).unwrap();
+        save_fault(&record);
+        assert_eq!(0x1235, last_fault().expect("no saved fault").frame().lr);
+        clear_last_fault();
+        assert!(last_fault().is_none());
+    }
+}
@@ -0,0 +1,340 @@
+use ::os::sync;
+use ::traits::Clock;
+use super::mcg::{MCG, OSC, Dividers, PllConfig, FRDIV_LOW_RANGE, FRDIV_HIGH_RANGE};
+use super::sim::SIM;
+
+const SLOW_IRC_HZ: u32 = 32_768;
+const FAST_IRC_HZ: u32 = 4_000_000;
+const IRC48M_HZ: u32 = 48_000_000;
+const LPO_HZ: u32 = 1_000;
+const MAX_32K_OSC_HZ: u32 = 40_000;
+
+// the FLL output is its reference times these, by DMX32 then DRST_DRS
+const FLL_FACTORS: [[u32; 4]; 2] = [[640, 1280, 1920, 2560], [732, 1464, 2197, 2929]];
+
+
+//------------------------------------------------
+//
+// external clocks
+//
+//------------------------------------------------
+
+/// Frequencies of the clocks coming into the chip, which cannot be read back from any register. Zero when absent.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ExternalClocks {
+    /// Crystal or clock on EXTAL0.
+    pub osc_hz: u32,
+    /// Crystal on EXTAL32, for the RTC.
+    pub rtc_hz: u32,
+}
+
+// read and written with interrupts masked, so a reader never sees half of an update
+static mut EXTERNAL_CLOCKS: ExternalClocks = ExternalClocks{osc_hz: 0, rtc_hz: 0};
+
+/// Record the board's external clocks. `mcg::MCG::use_pll()` records the oscillator it was given, once the PLL runs
+/// from it.
+pub fn set_external_clocks(external: ExternalClocks) {
+    sync::critical(|| unsafe { EXTERNAL_CLOCKS = external; });
+}
+
+/// Record the frequency on EXTAL0.
+pub fn set_oscillator_hz(hz: u32) {
+    sync::critical(|| unsafe { EXTERNAL_CLOCKS.osc_hz = hz; });
+}
+
+/// Get the recorded external clocks.
+pub fn external_clocks() -> ExternalClocks {
+    sync::critical(|| unsafe { EXTERNAL_CLOCKS })
+}
+
+
+//------------------------------------------------
+//
+// clock tree
+//
+//------------------------------------------------
+
+/// The registers selecting and dividing every clock, as read at one time.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ClockSettings {
+    pub mcg_c1: u8,
+    pub mcg_c2: u8,
+    pub mcg_c4: u8,
+    pub mcg_c5: u8,
+    pub mcg_c6: u8,
+    pub mcg_c7: u8,
+    pub mcg_s: u8,
+    pub mcg_sc: u8,
+    pub osc_cr: u8,
+    pub osc_div: u8,
+    pub sim_sopt1: u32,
+    pub sim_sopt2: u32,
+    pub sim_clkdiv1: u32,
+}
+impl ClockSettings {
+    /// Register values out of reset.
+    pub fn reset() -> ClockSettings {
+        ClockSettings{
+            mcg_c1: 0x04,
+            mcg_c2: 0x80,
+            mcg_c4: 0x00,
+            mcg_c5: 0x00,
+            mcg_c6: 0x00,
+            mcg_c7: 0x00,
+            mcg_s: 0x10,
+            mcg_sc: 0x02,
+            osc_cr: 0x00,
+            osc_div: 0x00,
+            sim_sopt1: 0x0000_0000,
+            sim_sopt2: 0x0000_1000,
+            sim_clkdiv1: 0x0001_0000,
+        }
+    }
+
+    /// Read the current settings.
+    pub fn read(mcg: &MCG, osc: &OSC, sim: &SIM) -> ClockSettings {
+        ClockSettings{
+            mcg_c1: mcg.read_control_1(),
+            mcg_c2: mcg.read_control_2(),
+            mcg_c4: mcg.read_control_4(),
+            mcg_c5: mcg.read_control_5(),
+            mcg_c6: mcg.read_control_6(),
+            mcg_c7: mcg.read_control_7(),
+            mcg_s: mcg.read_status(),
+            mcg_sc: mcg.read_status_control(),
+            osc_cr: osc.read_control(),
+            osc_div: osc.read_divider(),
+            sim_sopt1: sim.read_options_1(),
+            sim_sopt2: sim.read_options_2(),
+            sim_clkdiv1: sim.read_clock_divider_1(),
+        }
+    }
+}
+
+/// Frequency of every clock in the K64 clock tree, in Hz. Clocks that are stopped, or fed by an external clock
+/// that was not recorded, are None.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ClockTree {
+    pub mcgout: Option<u32>,
+    pub core: Option<u32>,
+    pub bus: Option<u32>,
+    pub flexbus: Option<u32>,
+    pub flash: Option<u32>,
+    pub oscer: Option<u32>,
+    pub mcgir: Option<u32>,
+    pub lpo: Option<u32>,
+    pub erclk32k: Option<u32>,
+    pub pllfll: Option<u32>,
+}
+impl ClockTree {
+    /// Work out every clock from the register settings and the external clocks.
+    pub fn compute(settings: &ClockSettings, external: &ExternalClocks) -> ClockTree {
+        let c1 = settings.mcg_c1;
+        let c2 = settings.mcg_c2;
+        let oscsel = settings.mcg_c7 & 0x3;
+        let range = (c2 >> 4) & 0x3;
+
+        let osc = if external.osc_hz != 0 { Some(external.osc_hz) } else { None };
+        let rtc = if external.rtc_hz != 0 { Some(external.rtc_hz) } else { None };
+
+        // MCG external reference, by OSCSEL
+        let external_ref = match oscsel {
+            0 => osc,
+            1 => rtc,
+            2 => Some(IRC48M_HZ),
+            _ => None,
+        };
+
+        // internal reference, by IRCS. the fast one is divided by FCRDIV
+        let internal_ref = if (c2 & 0x1) != 0 { FAST_IRC_HZ >> ((settings.mcg_sc >> 1) & 0x7) } else { SLOW_IRC_HZ };
+
+        // FLL, referenced to the slow internal reference, or the divided external one
+        let fll_ref = if (c1 & 0x4) != 0 {
+            Some(SLOW_IRC_HZ)
+        } else {
+            let factors = if range == 0 || oscsel == 1 { &FRDIV_LOW_RANGE } else { &FRDIV_HIGH_RANGE };
+            external_ref.map(|hz| hz / factors[((c1 >> 3) & 0x7) as usize])
+        };
+        let dmx32 = ((settings.mcg_c4 >> 7) & 0x1) as usize;
+        let drst_drs = ((settings.mcg_c4 >> 5) & 0x3) as usize;
+        let fll = fll_ref.and_then(|hz| hz.checked_mul(FLL_FACTORS[dmx32][drst_drs]));
+
+        // PLL, running when selected by PLLS or enabled on its own by PLLCLKEN0
+        let pll_running = (settings.mcg_c6 & 0x40) != 0 || (settings.mcg_c5 & 0x40) != 0;
+        let pll = if pll_running {
+            let config = PllConfig{divider: (settings.mcg_c5 & 0x1F) + 1, multiplier: (settings.mcg_c6 & 0x1F) + 24};
+            external_ref.map(|hz| config.frequency(hz))
+        } else {
+            None
+        };
+
+        let mcgout = match c1 >> 6 {
+            0 => if (settings.mcg_c6 & 0x40) != 0 { pll } else { fll },
+            1 => Some(internal_ref),
+            2 => external_ref,
+            _ => None,
+        };
+
+        // domains divided from MCGOUTCLK by SIM_CLKDIV1
+        let dividers = Dividers::from_clkdiv1(settings.sim_clkdiv1);
+        let domains = mcgout.map(|hz| dividers.frequencies(hz));
+
+        // OSCERCLK, gated by ERCLKEN and divided by ERPS
+        let oscer = if (settings.osc_cr & 0x80) != 0 { osc.map(|hz| hz >> (settings.osc_div >> 6)) } else { None };
+
+        // MCGIRCLK, gated by IRCLKEN
+        let mcgir = if (c1 & 0x2) != 0 { Some(internal_ref) } else { None };
+
+        // ERCLK32K, by SOPT1 OSC32KSEL. the system oscillator only counts if it is a 32kHz crystal
+        let erclk32k = match (settings.sim_sopt1 >> 18) & 0x3 {
+            0 => match osc {
+                Some(hz) if hz <= MAX_32K_OSC_HZ => osc,
+                _ => None,
+            },
+            2 => rtc,
+            3 => Some(LPO_HZ),
+            _ => None,
+        };
+
+        // peripheral clock, by SOPT2 PLLFLLSEL
+        let pllfll = match (settings.sim_sopt2 >> 16) & 0x3 {
+            0 => fll,
+            1 => pll,
+            3 => Some(IRC48M_HZ),
+            _ => None,
+        };
+
+        ClockTree{
+            mcgout: mcgout,
+            core: domains.map(|f| f.core),
+            bus: domains.map(|f| f.bus),
+            flexbus: domains.map(|f| f.flexbus),
+            flash: domains.map(|f| f.flash),
+            oscer: oscer,
+            mcgir: mcgir,
+            lpo: Some(LPO_HZ),
+            erclk32k: erclk32k,
+            pllfll: pllfll,
+        }
+    }
+
+    /// Read the registers and work out every clock.
+    pub fn read(mcg: &MCG, osc: &OSC, sim: &SIM) -> ClockTree {
+        ClockTree::compute(&ClockSettings::read(mcg, osc, sim), &external_clocks())
+    }
+
+    /// Get the frequency of a clock.
+    pub fn frequency(&self, clock: Clock) -> Option<u32> {
+        match clock {
+            Clock::McgOut => self.mcgout,
+            Clock::Core => self.core,
+            Clock::Bus => self.bus,
+            Clock::FlexBus => self.flexbus,
+            Clock::Flash => self.flash,
+            Clock::OscEr => self.oscer,
+            Clock::McgIr => self.mcgir,
+            Clock::Lpo => self.lpo,
+            Clock::Erclk32k => self.erclk32k,
+            Clock::PllFll => self.pllfll,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use ::traits::Clock;
+    use super::{ClockSettings, ClockTree, ExternalClocks};
+
+    const BOARD: ExternalClocks = ExternalClocks{osc_hz: 50_000_000, rtc_hz: 32_768};
+
+    #[test]
+    fn reset() {
+        let tree = ClockTree::compute(&ClockSettings::reset(), &BOARD);
+        assert_eq!(Some(20_971_520), tree.mcgout);
+        assert_eq!(Some(20_971_520), tree.frequency(Clock::Core));
+        assert_eq!(Some(20_971_520), tree.frequency(Clock::Bus));
+        assert_eq!(Some(10_485_760), tree.frequency(Clock::Flash));
+        assert_eq!(Some(20_971_520), tree.frequency(Clock::PllFll));
+        assert_eq!(Some(1_000), tree.frequency(Clock::Lpo));
+        assert_eq!(None, tree.frequency(Clock::OscEr));
+        assert_eq!(None, tree.frequency(Clock::McgIr));
+        assert_eq!(None, tree.frequency(Clock::Erclk32k));   // the system oscillator is not a 32kHz one
+    }
+
+    #[test]
+    fn pee_120mhz() {
+        let settings = ClockSettings{
+            mcg_c1: 0x30,           // CLKS = FLL/PLL, FRDIV = 6, IREFS = 0
+            mcg_c2: 0xA0,           // RANGE0 = 2, external clock
+            mcg_c5: 0x13,           // divide by 20
+            mcg_c6: 0x58,           // PLLS, multiply by 48
+            mcg_s: 0x6C,
+            osc_cr: 0x80,
+            sim_sopt1: 2 << 18,     // ERCLK32K from the RTC
+            sim_sopt2: 1 << 16,     // PLLFLLSEL = PLL
+            sim_clkdiv1: 0x0124_0000,
+            .. ClockSettings::reset()
+        };
+        let tree = ClockTree::compute(&settings, &BOARD);
+        assert_eq!(Some(120_000_000), tree.frequency(Clock::McgOut));
+        assert_eq!(Some(120_000_000), tree.frequency(Clock::Core));
+        assert_eq!(Some(60_000_000), tree.frequency(Clock::Bus));
+        assert_eq!(Some(40_000_000), tree.frequency(Clock::FlexBus));
+        assert_eq!(Some(24_000_000), tree.frequency(Clock::Flash));
+        assert_eq!(Some(50_000_000), tree.frequency(Clock::OscEr));
+        assert_eq!(Some(120_000_000), tree.frequency(Clock::PllFll));
+        assert_eq!(Some(32_768), tree.frequency(Clock::Erclk32k));
+
+        // without knowing the oscillator, nothing derived from it is known
+        let unknown = ClockTree::compute(&settings, &ExternalClocks{osc_hz: 0, rtc_hz: 0});
+        assert_eq!(None, unknown.core);
+        assert_eq!(None, unknown.oscer);
+        assert_eq!(None, unknown.erclk32k);
+    }
+
+    #[test]
+    fn internal_references() {
+        let fbi = ClockSettings{
+            mcg_c1: 0x46,           // CLKS = internal, IRCLKEN, IREFS
+            mcg_c2: 0x81,           // fast IRC
+            mcg_sc: 0x00,           // FCRDIV = 1
+            mcg_s: 0x15,
+            sim_sopt1: 3 << 18,     // ERCLK32K from the LPO
+            sim_sopt2: 3 << 16,     // PLLFLLSEL = IRC48M
+            .. ClockSettings::reset()
+        };
+        let tree = ClockTree::compute(&fbi, &BOARD);
+        assert_eq!(Some(4_000_000), tree.core);
+        assert_eq!(Some(4_000_000), tree.mcgir);
+        assert_eq!(Some(1_000), tree.erclk32k);
+        assert_eq!(Some(48_000_000), tree.pllfll);
+
+        let slow = ClockSettings{mcg_c2: 0x80, .. fbi};
+        assert_eq!(Some(32_768), ClockTree::compute(&slow, &BOARD).mcgir);
+        let divided = ClockSettings{mcg_sc: 0x02, .. fbi};
+        assert_eq!(Some(2_000_000), ClockTree::compute(&divided, &BOARD).core);
+    }
+
+    #[test]
+    fn fll_from_external() {
+        let fee = ClockSettings{
+            mcg_c1: 0x38,           // FRDIV = 7, IREFS = 0
+            mcg_c2: 0xA0,
+            mcg_c4: 0xE0,           // DMX32, DRST_DRS = 3
+            mcg_s: 0x00,
+            .. ClockSettings::reset()
+        };
+        // 50MHz / 1536 * 2929
+        assert_eq!(Some(32_552 * 2_929), ClockTree::compute(&fee, &BOARD).mcgout);
+    }
+}
@@ -0,0 +1,259 @@
+extern crate core;
+use core::fmt;
+
+use super::sim::SIM;
+
+
+/// Kinetis series, from SDID SERIESID.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Series {
+    K,
+    L,
+    W,
+    V,
+    Unknown(u8),
+}
+impl Series {
+    fn from_bits(bits: u8) -> Series {
+        match bits {
+            0b0000 => Series::K,
+            0b0001 => Series::L,
+            0b0101 => Series::W,
+            0b0110 => Series::V,
+            other => Series::Unknown(other),
+        }
+    }
+
+    fn letter(&self) -> &'static str {
+        match *self {
+            Series::K => "K",
+            Series::L => "L",
+            Series::W => "W",
+            Series::V => "V",
+            Series::Unknown(_) => "?",
+        }
+    }
+}
+
+/// 128 bit unique identification number, most significant word (UIDH) first.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct UniqueId(pub [u32; 4]);
+impl fmt::Display for UniqueId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08X}-{:08X}-{:08X}-{:08X}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+/// A part firmware is built for, to compare against the `DeviceInfo` of the chip it runs on.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Part {
+    pub series: Series,
+    pub family: u8,
+    pub subfamily: u8,
+    pub pins: u16,
+    pub program_flash_kb: u32,
+    pub ram_kb: u32,
+}
+
+/// The MK64FN1M0VLL12: 1MB of program flash, 256KB of RAM, in a 100 pin LQFP.
+pub const MK64FN1M0VLL12: Part = Part{
+    series: Series::K,
+    family: 6,
+    subfamily: 4,
+    pins: 100,
+    program_flash_kb: 1024,
+    ram_kb: 256,
+};
+
+/// Identification of the chip, decoded from SIM_SDID, SIM_SOPT1, SIM_FCFG1/2 and SIM_UIDx.
+///
+/// Sizes the registers give no encoding for are None.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct DeviceInfo {
+    pub series: Series,
+    /// Family digit, such as 6 for the K6x.
+    pub family: u8,
+    /// Subfamily digit, such as 4 for the Kx4.
+    pub subfamily: u8,
+    /// Silicon revision.
+    pub revision: u8,
+    pub die: u8,
+    pub pins: Option<u16>,
+    pub program_flash_kb: Option<u32>,
+    pub flex_nvm_kb: Option<u32>,
+    pub ram_kb: Option<u32>,
+    /// Raw SIM_FCFG2, giving the flash block boundaries.
+    pub flash_config_2: u32,
+    pub unique_id: UniqueId,
+}
+impl DeviceInfo {
+    /// Decode the identification registers.
+    pub fn decode(sdid: u32, sopt1: u32, fcfg1: u32, fcfg2: u32, uid: [u32; 4]) -> DeviceInfo {
+        DeviceInfo{
+            series: Series::from_bits(((sdid >> 20) & 0xF) as u8),
+            family: ((sdid >> 28) & 0xF) as u8,
+            subfamily: ((sdid >> 24) & 0xF) as u8,
+            revision: ((sdid >> 12) & 0xF) as u8,
+            die: ((sdid >> 7) & 0x1F) as u8,
+            pins: pin_count(sdid & 0xF),
+            program_flash_kb: program_flash_kb((fcfg1 >> 24) & 0xF),
+            flex_nvm_kb: flex_nvm_kb((fcfg1 >> 28) & 0xF),
+            ram_kb: ram_kb((sopt1 >> 12) & 0xF),
+            flash_config_2: fcfg2,
+            unique_id: UniqueId(uid),
+        }
+    }
+
+    /// Read the identification registers.
+    pub fn read(sim: &SIM) -> DeviceInfo {
+        DeviceInfo::decode(
+            sim.read_system_id(),
+            sim.read_options_1(),
+            sim.read_flash_configuration_1(),
+            sim.read_flash_configuration_2(),
+            [sim.read_unique_id_high(), sim.read_unique_id_mid_high(), sim.read_unique_id_mid_low(),
+             sim.read_unique_id_low()])
+    }
+
+    /// Check whether the chip is the given part. Sizes the chip does not report never match.
+    pub fn is(&self, part: &Part) -> bool {
+        self.series == part.series
+            && self.family == part.family
+            && self.subfamily == part.subfamily
+            && self.pins == Some(part.pins)
+            && self.program_flash_kb == Some(part.program_flash_kb)
+            && self.ram_kb == Some(part.ram_kb)
+    }
+}
+impl fmt::Display for DeviceInfo {
+    /// Formats a one line summary for boot banners, such as `K64 rev 1, 100 pins, 1024KB flash, 256KB RAM`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}{}{} rev {}", self.series.letter(), self.family, self.subfamily, self.revision));
+        if let Some(pins) = self.pins { try!(write!(f, ", {} pins", pins)); }
+        if let Some(kb) = self.program_flash_kb { try!(write!(f, ", {}KB flash", kb)); }
+        if let Some(kb) = self.ram_kb { try!(write!(f, ", {}KB RAM", kb)); }
+        Ok(())
+    }
+}
+
+// SDID PINID
+fn pin_count(bits: u32) -> Option<u16> {
+    match bits {
+        0b0010 => Some(32),
+        0b0100 => Some(48),
+        0b0101 => Some(64),
+        0b0110 => Some(80),
+        0b0111 => Some(81),     // or 121
+        0b1000 => Some(100),
+        0b1001 => Some(121),
+        0b1010 => Some(144),
+        0b1100 => Some(169),
+        0b1110 => Some(256),
+        _ => None,              // custom (WLCSP) or reserved
+    }
+}
+
+// FCFG1 PFSIZE
+fn program_flash_kb(bits: u32) -> Option<u32> {
+    match bits {
+        0b0111 => Some(128),
+        0b1001 => Some(256),
+        0b1011 => Some(512),
+        0b1100 => Some(768),
+        0b1101 | 0b1111 => Some(1024),
+        _ => None,
+    }
+}
+
+// FCFG1 NVMSIZE
+fn flex_nvm_kb(bits: u32) -> Option<u32> {
+    match bits {
+        0b0000 => Some(0),
+        0b0011 => Some(32),
+        0b0101 => Some(64),
+        0b0111 => Some(128),
+        0b1001 => Some(256),
+        0b1111 => Some(0),      // program flash only part
+        _ => None,
+    }
+}
+
+// SOPT1 RAMSIZE
+fn ram_kb(bits: u32) -> Option<u32> {
+    match bits {
+        0b0001 => Some(8),
+        0b0011 => Some(16),
+        0b0100 => Some(24),
+        0b0101 => Some(32),
+        0b0110 => Some(48),
+        0b0111 => Some(64),
+        0b1000 => Some(96),
+        0b1001 => Some(128),
+        0b1011 => Some(256),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use self::std::string::ToString;
+    use super::{DeviceInfo, Series, UniqueId, MK64FN1M0VLL12};
+
+    const UID: [u32; 4] = [0x0000_0013, 0x0002_4E4A, 0x4E45_0001, 0x4000_2005];
+
+    fn k64() -> DeviceInfo {
+        // SDID of a rev 1 MK64FN1M0VLL12, RAMSIZE = 256KB, PFSIZE = 1MB with no FlexNVM
+        DeviceInfo::decode(0x6400_1008, 0xB000, 0xFF00_0000, 0x7FFF_0000, UID)
+    }
+
+    #[test]
+    fn decodes_k64() {
+        let info = k64();
+        assert_eq!(Series::K, info.series);
+        assert_eq!((6, 4), (info.family, info.subfamily));
+        assert_eq!(1, info.revision);
+        assert_eq!(Some(100), info.pins);
+        assert_eq!(Some(1024), info.program_flash_kb);
+        assert_eq!(Some(0), info.flex_nvm_kb);
+        assert_eq!(Some(256), info.ram_kb);
+        assert_eq!(0x7FFF_0000, info.flash_config_2);
+        assert!(info.is(&MK64FN1M0VLL12));
+    }
+
+    #[test]
+    fn other_parts() {
+        let smaller = DeviceInfo::decode(0x6400_1008, 0xB000, 0xFB00_0000, 0, UID);
+        assert_eq!(Some(512), smaller.program_flash_kb);
+        assert_eq!(false, smaller.is(&MK64FN1M0VLL12));
+
+        let l_series = DeviceInfo::decode(0x2110_0005, 0x5000, 0x0700_0000, 0, UID);
+        assert_eq!(Series::L, l_series.series);
+        assert_eq!(Some(64), l_series.pins);
+        assert_eq!(Some(32), l_series.ram_kb);
+        assert_eq!(false, l_series.is(&MK64FN1M0VLL12));
+
+        let custom = DeviceInfo::decode(0x6400_100B, 0xB000, 0xFF00_0000, 0, UID);
+        assert_eq!(None, custom.pins);
+        assert_eq!(false, custom.is(&MK64FN1M0VLL12));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!("K64 rev 1, 100 pins, 1024KB flash, 256KB RAM", k64().to_string());
+        assert_eq!("00000013-00024E4A-4E450001-40002005", UniqueId(UID).to_string());
+    }
+}
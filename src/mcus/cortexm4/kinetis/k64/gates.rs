@@ -0,0 +1,355 @@
+extern crate core;
+use core::cell::Cell;
+
+use ::os::sync;
+use super::K64;
+use super::sim::SIM;
+
+/// Number of `Gate`s.
+pub const NUM_GATES: usize = 45;
+
+/// A peripheral clock gate in SIM_SCGC1-7.
+///
+/// The flash memory gate is left out, as gating it off stops the core fetching instructions.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Gate {
+    // SCGC1
+    I2c2 = 0,
+    Uart4,
+    Uart5,
+    // SCGC2
+    Ethernet,
+    Dac0,
+    Dac1,
+    // SCGC3
+    Rnga,
+    Spi2,
+    Sdhc,
+    Ftm2,
+    Ftm3,
+    Adc1,
+    // SCGC4
+    Ewm,
+    Cmt,
+    I2c0,
+    I2c1,
+    Uart0,
+    Uart1,
+    Uart2,
+    Uart3,
+    Usb,
+    Cmp,
+    Vref,
+    // SCGC5
+    LowPowerTimer,
+    PortA,
+    PortB,
+    PortC,
+    PortD,
+    PortE,
+    // SCGC6
+    DmaMux,
+    Can0,
+    Spi0,
+    Spi1,
+    I2s,
+    Crc,
+    UsbDcd,
+    Pdb,
+    Pit,
+    Ftm0,
+    Ftm1,
+    Adc0,
+    Rtc,
+    // SCGC7
+    FlexBus,
+    Dma,
+    Mpu,
+}
+impl Gate {
+    /// Get the SCGC register number (1-7) and bit of the gate.
+    pub fn location(&self) -> (u8, u8) {
+        match *self {
+            Gate::I2c2 => (1, 6),
+            Gate::Uart4 => (1, 10),
+            Gate::Uart5 => (1, 11),
+            Gate::Ethernet => (2, 0),
+            Gate::Dac0 => (2, 12),
+            Gate::Dac1 => (2, 13),
+            Gate::Rnga => (3, 0),
+            Gate::Spi2 => (3, 12),
+            Gate::Sdhc => (3, 17),
+            Gate::Ftm2 => (3, 24),
+            Gate::Ftm3 => (3, 25),
+            Gate::Adc1 => (3, 27),
+            Gate::Ewm => (4, 1),
+            Gate::Cmt => (4, 2),
+            Gate::I2c0 => (4, 6),
+            Gate::I2c1 => (4, 7),
+            Gate::Uart0 => (4, 10),
+            Gate::Uart1 => (4, 11),
+            Gate::Uart2 => (4, 12),
+            Gate::Uart3 => (4, 13),
+            Gate::Usb => (4, 18),
+            Gate::Cmp => (4, 19),
+            Gate::Vref => (4, 20),
+            Gate::LowPowerTimer => (5, 0),
+            Gate::PortA => (5, 9),
+            Gate::PortB => (5, 10),
+            Gate::PortC => (5, 11),
+            Gate::PortD => (5, 12),
+            Gate::PortE => (5, 13),
+            Gate::DmaMux => (6, 1),
+            Gate::Can0 => (6, 4),
+            Gate::Spi0 => (6, 12),
+            Gate::Spi1 => (6, 13),
+            Gate::I2s => (6, 15),
+            Gate::Crc => (6, 18),
+            Gate::UsbDcd => (6, 21),
+            Gate::Pdb => (6, 22),
+            Gate::Pit => (6, 23),
+            Gate::Ftm0 => (6, 24),
+            Gate::Ftm1 => (6, 25),
+            Gate::Adc0 => (6, 27),
+            Gate::Rtc => (6, 29),
+            Gate::FlexBus => (7, 0),
+            Gate::Dma => (7, 1),
+            Gate::Mpu => (7, 2),
+        }
+    }
+}
+
+/// Raw access to the clock gates, so the reference counting can be tested off target.
+pub trait GateRegisters {
+    /// Open (`true`) or close the gate.
+    fn set_gate(&self, gate: Gate, enabled: bool);
+    /// Check whether the gate is open.
+    fn is_gate_enabled(&self, gate: Gate) -> bool;
+}
+
+impl GateRegisters for SIM {
+    // the other gates in the register are shared with ISRs, so the read-modify-write is masked from them
+    fn set_gate(&self, gate: Gate, enabled: bool) {
+        let (register, bit) = gate.location();
+        sync::critical(|| {
+            let value = read_scgc(self, register);
+            write_scgc(self, register, if enabled { value | (1 << bit) } else { value & !(1 << bit) });
+        });
+    }
+
+    fn is_gate_enabled(&self, gate: Gate) -> bool {
+        let (register, bit) = gate.location();
+        (read_scgc(self, register) & (1 << bit)) != 0
+    }
+}
+
+fn read_scgc(sim: &SIM, register: u8) -> u32 {
+    match register {
+        1 => sim.read_clock_gating_1(),
+        2 => sim.read_clock_gating_2(),
+        3 => sim.read_clock_gating_3(),
+        4 => sim.read_clock_gating_4(),
+        5 => sim.read_clock_gating_5(),
+        6 => sim.read_clock_gating_6(),
+        _ => sim.read_clock_gating_7(),
+    }
+}
+
+// whole-register writes of the gates, which only set_gate makes
+fn write_scgc(sim: &SIM, register: u8, value: u32) {
+    match register {
+        1 => sim.write_clock_gating_1(value),
+        2 => sim.write_clock_gating_2(value),
+        3 => sim.write_clock_gating_3(value),
+        4 => sim.write_clock_gating_4(value),
+        5 => sim.write_clock_gating_5(value),
+        6 => sim.write_clock_gating_6(value),
+        _ => sim.write_clock_gating_7(value),
+    }
+}
+
+
+//------------------------------------------------
+//
+// ownership
+//
+//------------------------------------------------
+
+/// Reference counts the users of each clock gate.
+///
+/// Drivers take a `GateToken` for their peripheral when constructed, so the peripheral cannot be touched with its
+/// clock gated off (which faults). The gate is opened with the first token, and closed when the last is dropped.
+///
+/// There is one set, got with `K64::clock_gates()`.
+pub struct ClockGates<'a> {
+    regs: &'a GateRegisters,
+    users: Cell<[u16; NUM_GATES]>,
+}
+impl<'a> ClockGates<'a> {
+    /// Creates the counts with no users. Gates open out of reset stay open until they have had users.
+    fn new(regs: &'a GateRegisters) -> ClockGates<'a> {
+        ClockGates{regs: regs, users: Cell::new([0; NUM_GATES])}
+    }
+
+    /// Creates counts over mock registers, for the drivers' tests.
+    #[cfg(test)]
+    pub fn with_registers(regs: &'a GateRegisters) -> ClockGates<'a> { ClockGates::new(regs) }
+
+    /// Open the gate, if it is not already, and get a token for it.
+    pub fn enable(&self, gate: Gate) -> GateToken {
+        self.acquire(gate);
+        GateToken{gates: self, gate: gate}
+    }
+
+    /// Get the number of tokens held for the gate.
+    pub fn users(&self, gate: Gate) -> u16 { self.users.get()[gate as usize] }
+
+    /// Check whether the gate is open.
+    pub fn is_enabled(&self, gate: Gate) -> bool { self.regs.is_gate_enabled(gate) }
+
+    // tokens may be taken and dropped in ISRs, so the counts and the gate they guard change together
+    fn acquire(&self, gate: Gate) {
+        sync::critical(|| {
+            let mut users = self.users.get();
+            users[gate as usize] += 1;
+            self.users.set(users);
+            if users[gate as usize] == 1 { self.regs.set_gate(gate, true); }
+        });
+    }
+
+    fn release(&self, gate: Gate) {
+        sync::critical(|| {
+            let mut users = self.users.get();
+            users[gate as usize] -= 1;
+            self.users.set(users);
+            if users[gate as usize] == 0 { self.regs.set_gate(gate, false); }
+        });
+    }
+}
+
+// the MCU's one set of counts, created by `K64::clock_gates`
+static mut GATES: Option<ClockGates<'static>> = None;
+
+impl K64 {
+    /// Get the clock gates. There is one set for the MCU, so every driver's tokens for a gate are counted together.
+    pub fn clock_gates(&self) -> &'static ClockGates<'static> {
+        // the SIM is memory mapped, so outlives any borrow of the MCU
+        let sim: &SIM = &self.sim;
+        let sim: &'static SIM = unsafe { &*(sim as *const SIM) };
+        sync::critical(|| unsafe {
+            if GATES.is_none() { GATES = Some(ClockGates::new(sim)); }
+            GATES.as_ref().unwrap()
+        })
+    }
+}
+
+/// Proof that a peripheral's clock gate is open. Cloning adds a user, and dropping removes one.
+pub struct GateToken<'a> {
+    gates: &'a ClockGates<'a>,
+    gate: Gate,
+}
+impl<'a> GateToken<'a> {
+    /// Get the gate the token holds open.
+    pub fn gate(&self) -> Gate { self.gate }
+}
+impl<'a> Clone for GateToken<'a> {
+    fn clone(&self) -> GateToken<'a> {
+        self.gates.acquire(self.gate);
+        GateToken{gates: self.gates, gate: self.gate}
+    }
+}
+impl<'a> Drop for GateToken<'a> {
+    fn drop(&mut self) { self.gates.release(self.gate); }
+}
+
+
+#[cfg(test)]
+mod test {
+    use core::cell::Cell;
+    use super::{Gate, GateRegisters, ClockGates, NUM_GATES};
+
+    struct MockSim {
+        scgc: Cell<[u32; 7]>,
+        writes: Cell<usize>,
+    }
+    impl MockSim {
+        fn new() -> MockSim { MockSim{scgc: Cell::new([0; 7]), writes: Cell::new(0)} }
+    }
+    impl GateRegisters for MockSim {
+        fn set_gate(&self, gate: Gate, enabled: bool) {
+            let (register, bit) = gate.location();
+            let mut scgc = self.scgc.get();
+            if enabled {
+                scgc[register as usize - 1] |= 1 << bit;
+            } else {
+                scgc[register as usize - 1] &= !(1 << bit);
+            }
+            self.scgc.set(scgc);
+            self.writes.set(self.writes.get() + 1);
+        }
+
+        fn is_gate_enabled(&self, gate: Gate) -> bool {
+            let (register, bit) = gate.location();
+            (self.scgc.get()[register as usize - 1] & (1 << bit)) != 0
+        }
+    }
+
+    #[test]
+    fn locations_are_unique() {
+        assert_eq!(Gate::Mpu as usize + 1, NUM_GATES);
+        assert_eq!((4, 10), Gate::Uart0.location());
+        assert_eq!((5, 9), Gate::PortA.location());
+        assert_eq!((7, 2), Gate::Mpu.location());
+        assert!(Gate::Ftm2.location() != Gate::Ftm1.location());
+    }
+
+    #[test]
+    fn opens_and_closes() {
+        let sim = MockSim::new();
+        let gates = ClockGates::new(&sim);
+        {
+            let token = gates.enable(Gate::Uart0);
+            assert_eq!(Gate::Uart0, token.gate());
+            assert!(gates.is_enabled(Gate::Uart0));
+            assert_eq!(1 << 10, sim.scgc.get()[3]);
+            assert_eq!(1, gates.users(Gate::Uart0));
+        }
+        assert_eq!(false, gates.is_enabled(Gate::Uart0));
+        assert_eq!(0, gates.users(Gate::Uart0));
+        assert_eq!(2, sim.writes.get());
+    }
+
+    #[test]
+    fn counts_users() {
+        let sim = MockSim::new();
+        let gates = ClockGates::new(&sim);
+        let first = gates.enable(Gate::PortC);
+        let second = gates.enable(Gate::PortC);
+        let third = first.clone();
+        assert_eq!(3, gates.users(Gate::PortC));
+        assert_eq!(1, sim.writes.get());    // only the first opens the gate
+
+        drop(first);
+        drop(third);
+        assert!(gates.is_enabled(Gate::PortC));
+        drop(second);
+        assert_eq!(false, gates.is_enabled(Gate::PortC));
+        assert_eq!(2, sim.writes.get());
+    }
+
+    #[test]
+    fn gates_are_independent() {
+        let sim = MockSim::new();
+        let gates = ClockGates::new(&sim);
+        let dma = gates.enable(Gate::Dma);
+        {
+            let _mpu = gates.enable(Gate::Mpu);
+            assert_eq!(0b110, sim.scgc.get()[6]);
+        }
+        assert_eq!(0b010, sim.scgc.get()[6]);
+        assert_eq!(1, gates.users(dma.gate()));
+    }
+}
@@ -0,0 +1,488 @@
+extern crate core;
+use core::cell::Cell;
+use core::intrinsics::{volatile_load, volatile_store};
+use core::marker::PhantomData;
+use core::mem;
+
+use ::os::error::GpioError;
+use ::os::sync;
+
+use super::gates::GateToken;
+use super::interrupts;
+use super::port::{self, PortRegisters, PortId, Mux, Pull, Drive, Trigger, PINS_PER_PORT};
+
+
+ioreg!(
+    name => GPIO;
+    doc_srcs => [
+        "http://www.nxp.com/files/microcontrollers/doc/ref_manual/K64P144M120SF5RM.pdf" // section 55
+    ];
+
+    constants => {
+        enabled         = 1;
+        disabled        = 0;
+    };
+
+    0x0000 => data_output r32 rw {     // PDOR
+        0..31 => {  write_data_output => ();  }
+    };
+
+    0x0004 => set_output r32 wo {      // PSOR
+        0..31 => {  set_pins => ();  }
+    };
+
+    0x0008 => clear_output r32 wo {    // PCOR
+        0..31 => {  clear_pins => ();  }
+    };
+
+    0x000C => toggle_output r32 wo {   // PTOR
+        0..31 => {  toggle_pins => ();  }
+    };
+
+    0x0010 => data_input r32 ro {};    // PDIR
+
+    0x0014 => direction r32 rw {       // PDDR, 1 is an output
+        0..31 => {  write_direction => ();  }
+    };
+);
+
+/// Raw access to the GPIO registers, so pins can be tested off target. Masks have one bit per pin.
+pub trait GpioRegisters {
+    /// Get the levels the pins drive when outputs.
+    fn output_levels(&self) -> u32;
+    /// Drive the pins in `mask` high.
+    fn set_outputs(&self, mask: u32);
+    /// Drive the pins in `mask` low.
+    fn clear_outputs(&self, mask: u32);
+    /// Invert the pins in `mask`.
+    fn toggle_outputs(&self, mask: u32);
+    /// Read the levels on the pins.
+    fn input_levels(&self) -> u32;
+    /// Get the pins that are outputs.
+    fn directions(&self) -> u32;
+    /// Make the pins set in `outputs` outputs, and the rest inputs.
+    fn set_directions(&self, outputs: u32);
+}
+
+impl GpioRegisters for GPIO {
+    fn output_levels(&self) -> u32 { self.read_data_output() }
+    fn set_outputs(&self, mask: u32) { self.set_pins(mask) }
+    fn clear_outputs(&self, mask: u32) { self.clear_pins(mask) }
+    fn toggle_outputs(&self, mask: u32) { self.toggle_pins(mask) }
+    fn input_levels(&self) -> u32 { self.read_data_input() }
+    fn directions(&self) -> u32 { self.read_direction() }
+    fn set_directions(&self, outputs: u32) { self.write_direction(outputs) }
+}
+
+
+//------------------------------------------------
+//
+// ports
+//
+//------------------------------------------------
+
+/// A port's PORT and GPIO registers, handing out each pin once.
+///
+/// Holds the port's `GateToken`, so the clock gate stays open while any pin of the port is in use.
+pub struct Port<'a> {
+    id: PortId,
+    port: &'a PortRegisters,
+    gpio: &'a GpioRegisters,
+    _gate: GateToken<'a>,
+    claimed: Cell<u32>,
+}
+impl<'a> Port<'a> {
+    /// Wrap the registers of the port. The token must be for the port's gate.
+    pub fn new(id: PortId, port: &'a PortRegisters, gpio: &'a GpioRegisters, gate: GateToken<'a>)
+            -> Result<Port<'a>, GpioError> {
+        if gate.gate() != id.gate() { return Err(GpioError::WrongGate); }
+        Ok(Port{id: id, port: port, gpio: gpio, _gate: gate, claimed: Cell::new(0)})
+    }
+
+    /// Get which port this is.
+    pub fn id(&self) -> PortId { self.id }
+
+    /// Claim the pin. It is left as configured until converted, and can be claimed again once dropped.
+    pub fn pin(&self, pin: u8) -> Result<Pin<Unconfigured>, GpioError> {
+        if pin >= PINS_PER_PORT { return Err(GpioError::BadPin); }
+        let claimed = self.claimed.get();
+        if claimed & (1 << pin) != 0 { return Err(GpioError::PinTaken); }
+        self.claimed.set(claimed | (1 << pin));
+        Ok(Pin{port: self, pin: pin, mode: PhantomData})
+    }
+
+    /// Check whether the pin has been claimed.
+    pub fn is_claimed(&self, pin: u8) -> bool { pin < PINS_PER_PORT && self.claimed.get() & (1 << pin) != 0 }
+
+    /// Read and clear the pending pin interrupts, one bit per pin.
+    pub fn take_interrupts(&self) -> u32 { take_interrupts(self.port) }
+}
+
+
+//------------------------------------------------
+//
+// pins
+//
+//------------------------------------------------
+
+/// Pin mode of a freshly claimed pin, which can only be converted.
+pub struct Unconfigured;
+/// Pin mode of a GPIO input.
+pub struct Input;
+/// Pin mode of a GPIO output.
+pub struct Output;
+/// Pin mode of a pin muxed to a peripheral, such as a UART.
+pub struct Alternate;
+
+/// A claimed pin of a port, typed by its mode so only inputs can be read and only outputs driven.
+pub struct Pin<'a, Mode> {
+    port: &'a Port<'a>,
+    pin: u8,
+    mode: PhantomData<Mode>,
+}
+impl<'a, Mode> Pin<'a, Mode> {
+    /// Get the port of the pin.
+    pub fn port(&self) -> PortId { self.port.id }
+
+    /// Get the pin number within the port.
+    pub fn number(&self) -> u8 { self.pin }
+
+    /// Make the pin a GPIO input.
+    pub fn into_input(self, pull: Pull) -> Pin<'a, Input> {
+        self.set_direction(false);
+        self.configure(|c| {
+            c.mux = Mux::Gpio;
+            c.pull = pull;
+            c.open_drain = false;
+        });
+        self.into_mode()
+    }
+
+    /// Make the pin a GPIO output, driving `high` from the moment it becomes one.
+    pub fn into_output(self, high: bool) -> Pin<'a, Output> {
+        if high { self.port.gpio.set_outputs(self.mask()) } else { self.port.gpio.clear_outputs(self.mask()) }
+        self.configure(|c| {
+            c.mux = Mux::Gpio;
+            c.pull = Pull::None;
+            c.trigger = Trigger::Disabled;
+        });
+        self.set_direction(true);
+        self.into_mode()
+    }
+
+    /// Hand the pin to a peripheral. The GPIO registers have no effect on it until converted back.
+    pub fn into_alternate(self, mux: Mux) -> Pin<'a, Alternate> {
+        self.configure(|c| {
+            c.mux = mux;
+            c.trigger = Trigger::Disabled;
+        });
+        self.into_mode()
+    }
+
+    fn mask(&self) -> u32 { 1 << self.pin }
+
+    // PDDR has no set and clear registers, so the read-modify-write is masked from ISRs converting other pins
+    fn set_direction(&self, output: bool) {
+        let gpio = self.port.gpio;
+        sync::critical(|| {
+            let directions = gpio.directions();
+            gpio.set_directions(if output { directions | self.mask() } else { directions & !self.mask() });
+        });
+    }
+
+    fn configure<F: FnOnce(&mut port::PinConfig)>(&self, f: F) { port::modify_pin_config(self.port.port, self.pin, f) }
+
+    fn into_mode<New>(self) -> Pin<'a, New> {
+        let pin = Pin{port: self.port, pin: self.pin, mode: PhantomData};
+        mem::forget(self);  // the claim moves to the new pin
+        pin
+    }
+}
+impl<'a, Mode> Drop for Pin<'a, Mode> {
+    fn drop(&mut self) { self.port.claimed.set(self.port.claimed.get() & !self.mask()); }
+}
+
+impl<'a> Pin<'a, Input> {
+    /// Change the pull resistor.
+    pub fn set_pull(&self, pull: Pull) { self.configure(|c| c.pull = pull) }
+
+    /// Enable the passive input filter, for slow or noisy signals.
+    pub fn set_filter(&self, enabled: bool) { self.configure(|c| c.passive_filter = enabled) }
+
+    /// Raise the port's interrupt on `trigger`, and enable it in the NVIC.
+    ///
+    /// Stale flags are cleared first. The interrupt is shared by the whole port, see `dispatch`, so the pin's
+    /// handler must already be set with `set_handler`: the port's interrupt would panic as unhandled otherwise.
+    pub fn enable_interrupt(&self, trigger: Trigger, nvic: &::traits::NVIC) -> Result<(), GpioError> {
+        if !has_handler(self.port.id, self.pin) { return Err(GpioError::NoHandler); }
+        self.configure(|c| c.trigger = Trigger::Disabled);
+        self.clear_interrupt();
+        self.configure(|c| c.trigger = trigger);
+        nvic.enable_irq(self.port.id.irq().into());
+        Ok(())
+    }
+
+    /// Stop the pin raising interrupts. The port's interrupt stays enabled in the NVIC for its other pins.
+    pub fn disable_interrupt(&self) { self.configure(|c| c.trigger = Trigger::Disabled) }
+
+    /// Check whether the pin has a pending interrupt.
+    pub fn is_interrupt_pending(&self) -> bool { self.port.port.pending_interrupts() & self.mask() != 0 }
+
+    /// Clear the pin's pending interrupt.
+    pub fn clear_interrupt(&self) { self.port.port.clear_pending_interrupts(self.mask()) }
+}
+impl<'a> ::traits::InputPin for Pin<'a, Input> {
+    fn is_high(&self) -> bool { self.port.gpio.input_levels() & self.mask() != 0 }
+}
+
+impl<'a> Pin<'a, Output> {
+    /// Change the drive strength.
+    pub fn set_drive(&self, drive: Drive) { self.configure(|c| c.drive = drive) }
+
+    /// Only drive the pin low, leaving it floating when high.
+    pub fn set_open_drain(&self, enabled: bool) { self.configure(|c| c.open_drain = enabled) }
+}
+impl<'a> ::traits::OutputPin for Pin<'a, Output> {
+    fn set_high(&self) { self.port.gpio.set_outputs(self.mask()) }
+    fn set_low(&self) { self.port.gpio.clear_outputs(self.mask()) }
+    fn toggle(&self) { self.port.gpio.toggle_outputs(self.mask()) }
+    fn is_set_high(&self) -> bool { self.port.gpio.output_levels() & self.mask() != 0 }
+}
+
+
+//------------------------------------------------
+//
+// interrupts
+//
+//------------------------------------------------
+
+/// Called with the port and pin that raised an interrupt.
+pub type PinHandler = fn(port: PortId, pin: u8);
+
+struct PortHandlers {
+    regs: Option<&'static PortRegisters>,
+    handlers: [Option<PinHandler>; 32],
+}
+
+// written with interrupts masked, so an ISR never sees a port's registers without its handlers
+static mut HANDLERS: [PortHandlers; 5] = [
+    PortHandlers{regs: None, handlers: [None; 32]},
+    PortHandlers{regs: None, handlers: [None; 32]},
+    PortHandlers{regs: None, handlers: [None; 32]},
+    PortHandlers{regs: None, handlers: [None; 32]},
+    PortHandlers{regs: None, handlers: [None; 32]},
+];
+
+/// Call `handler` from the port's interrupt when the pin triggers. The pin's interrupt is enabled afterwards,
+/// with `Pin::enable_interrupt`.
+pub fn set_handler(port: &Port<'static>, pin: u8, handler: PinHandler) -> Result<(), GpioError> {
+    if pin >= PINS_PER_PORT { return Err(GpioError::BadPin); }
+    sync::critical(|| unsafe {
+        let entry = &mut HANDLERS[port.id as usize];
+        entry.regs = Some(port.port);
+        entry.handlers[pin as usize] = Some(handler);
+    });
+    Ok(())
+}
+
+/// Stop calling the pin's handler.
+pub fn clear_handler(port: PortId, pin: u8) {
+    if pin < PINS_PER_PORT {
+        sync::critical(|| unsafe { HANDLERS[port as usize].handlers[pin as usize] = None; });
+    }
+}
+
+// a handler is only set along with the port's registers, so dispatch can always read which pins triggered
+fn has_handler(port: PortId, pin: u8) -> bool {
+    unsafe { HANDLERS[port as usize].handlers[pin as usize].is_some() }
+}
+
+/// Handle the port's interrupt, calling the handlers of the pins that triggered. This is the default
+/// `isr_port_x`, and panics as unhandled if a pin triggered without a handler, or no handler was ever set on the
+/// port (when which pins triggered is unknown, so all are reported).
+pub fn dispatch(port: PortId) {
+    let entry = unsafe { &HANDLERS[port as usize] };
+    let unhandled = match entry.regs {
+        Some(regs) => dispatch_to(port, regs, &entry.handlers),
+        None => !0,
+    };
+    if unhandled != 0 { interrupts::unhandled_sources(port.irq(), unhandled) }
+}
+
+/// Read and clear the pending pin interrupts, one bit per pin.
+pub fn take_interrupts(regs: &PortRegisters) -> u32 {
+    let pending = regs.pending_interrupts();
+    regs.clear_pending_interrupts(pending);
+    pending
+}
+
+// clears the pending pins and calls their handlers, returning the pins that had none
+fn dispatch_to(port: PortId, regs: &PortRegisters, handlers: &[Option<PinHandler>; 32]) -> u32 {
+    let mut pending = take_interrupts(regs);
+    let mut unhandled = 0;
+    while pending != 0 {
+        let pin = pending.trailing_zeros() as u8;
+        match handlers[pin as usize] {
+            Some(handler) => handler(port, pin),
+            None => unhandled |= 1 << pin,
+        }
+        pending &= pending - 1;
+    }
+    unhandled
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use core::cell::Cell;
+    use self::std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+    use ::traits::{InputPin, OutputPin};
+    use ::os::error::GpioError;
+    use super::{GpioRegisters, Port, PinHandler, HANDLERS, dispatch_to};
+    use ::test_support::{OpenGates, MockNvic};
+    use super::super::gates::{Gate, ClockGates};
+    use super::super::port::{PortRegisters, PortId, PinConfig, Mux, Pull, Trigger};
+
+    struct MockPort {
+        pcr: Cell<[u32; 32]>,
+        isfr: Cell<u32>,
+    }
+    impl PortRegisters for MockPort {
+        fn read_pin_control(&self, pin: u8) -> u32 { self.pcr.get()[pin as usize] }
+        fn write_pin_control(&self, pin: u8, value: u32) {
+            let mut pcr = self.pcr.get();
+            pcr[pin as usize] = value;
+            self.pcr.set(pcr);
+        }
+        fn pending_interrupts(&self) -> u32 { self.isfr.get() }
+        fn clear_pending_interrupts(&self, mask: u32) { self.isfr.set(self.isfr.get() & !mask) }
+    }
+
+    struct MockGpio {
+        pdor: Cell<u32>,
+        pdir: Cell<u32>,
+        pddr: Cell<u32>,
+    }
+    impl GpioRegisters for MockGpio {
+        fn output_levels(&self) -> u32 { self.pdor.get() }
+        fn set_outputs(&self, mask: u32) { self.pdor.set(self.pdor.get() | mask) }
+        fn clear_outputs(&self, mask: u32) { self.pdor.set(self.pdor.get() & !mask) }
+        fn toggle_outputs(&self, mask: u32) { self.pdor.set(self.pdor.get() ^ mask) }
+        fn input_levels(&self) -> u32 { self.pdir.get() }
+        fn directions(&self) -> u32 { self.pddr.get() }
+        fn set_directions(&self, outputs: u32) { self.pddr.set(outputs) }
+    }
+
+    fn mocks() -> (MockPort, MockGpio) {
+        (MockPort{pcr: Cell::new([0; 32]), isfr: Cell::new(0)},
+         MockGpio{pdor: Cell::new(0), pdir: Cell::new(0), pddr: Cell::new(0)})
+    }
+
+    #[test]
+    fn claims_pins() {
+        let sim = OpenGates;
+        let gates = ClockGates::with_registers(&sim);
+        let (port_regs, gpio_regs) = mocks();
+        assert_eq!(Err(GpioError::WrongGate),
+                   Port::new(PortId::B, &port_regs, &gpio_regs, gates.enable(Gate::PortA)).map(|_| ()));
+
+        let port = Port::new(PortId::B, &port_regs, &gpio_regs, gates.enable(Gate::PortB)).unwrap();
+        assert_eq!(1, gates.users(Gate::PortB));
+        assert_eq!(Err(GpioError::BadPin), port.pin(32).map(|_| ()));
+        {
+            let pin = port.pin(3).unwrap().into_input(Pull::Up);
+            assert_eq!(Err(GpioError::PinTaken), port.pin(3).map(|_| ()));
+            assert!(port.is_claimed(pin.number()));
+        }
+        assert_eq!(false, port.is_claimed(3));
+        assert!(port.pin(3).is_ok());
+    }
+
+    #[test]
+    fn outputs() {
+        let sim = OpenGates;
+        let gates = ClockGates::with_registers(&sim);
+        let (port_regs, gpio_regs) = mocks();
+        let port = Port::new(PortId::C, &port_regs, &gpio_regs, gates.enable(Gate::PortC)).unwrap();
+
+        let led = port.pin(5).unwrap().into_output(true);
+        assert_eq!(1 << 5, gpio_regs.pddr.get());
+        assert_eq!(Mux::Gpio, PinConfig::from_pcr(port_regs.pcr.get()[5]).mux);
+        assert!(led.is_set_high());
+        led.toggle();
+        assert_eq!(false, led.is_set_high());
+        led.set_high();
+        assert_eq!(1 << 5, gpio_regs.pdor.get());
+
+        let input = led.into_input(Pull::Down);
+        assert_eq!(0, gpio_regs.pddr.get());
+        assert!(input.is_low());
+        gpio_regs.pdir.set(1 << 5);
+        assert!(input.is_high());
+    }
+
+    #[test]
+    fn alternate() {
+        let sim = OpenGates;
+        let gates = ClockGates::with_registers(&sim);
+        let (port_regs, gpio_regs) = mocks();
+        let port = Port::new(PortId::B, &port_regs, &gpio_regs, gates.enable(Gate::PortB)).unwrap();
+
+        let rx = port.pin(16).unwrap().into_alternate(Mux::Alt3);
+        assert_eq!(0x0300, port_regs.pcr.get()[16]);
+        assert_eq!(0, gpio_regs.pddr.get());
+        assert_eq!((PortId::B, 16), (rx.port(), rx.number()));
+    }
+
+    #[test]
+    fn interrupts() {
+        let sim = OpenGates;
+        let gates = ClockGates::with_registers(&sim);
+        let nvic = MockNvic::new();
+        let (port_regs, gpio_regs) = mocks();
+        let port = Port::new(PortId::D, &port_regs, &gpio_regs, gates.enable(Gate::PortD)).unwrap();
+
+        let button = port.pin(0).unwrap().into_input(Pull::Up);
+        assert_eq!(Err(GpioError::NoHandler), button.enable_interrupt(Trigger::Falling, &nvic));
+        assert_eq!(None, nvic.enabled.get());
+
+        // no other test uses port D, and set_handler needs a port that lives forever
+        unsafe { HANDLERS[PortId::D as usize].handlers[0] = Some(ignore); }
+        port_regs.isfr.set(1);     // stale
+        button.enable_interrupt(Trigger::Falling, &nvic).unwrap();
+        assert_eq!(Trigger::Falling, PinConfig::from_pcr(port_regs.pcr.get()[0]).trigger);
+        assert_eq!(Some(62), nvic.enabled.get());
+        assert_eq!(false, button.is_interrupt_pending());
+
+        port_regs.isfr.set(0b11);
+        assert!(button.is_interrupt_pending());
+        assert_eq!(0b11, port.take_interrupts());
+        assert_eq!(0, port_regs.isfr.get());
+
+        button.disable_interrupt();
+        assert_eq!(Trigger::Disabled, PinConfig::from_pcr(port_regs.pcr.get()[0]).trigger);
+    }
+
+    fn ignore(_: PortId, _: u8) {}
+
+    static CALLS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn count(port: PortId, pin: u8) {
+        assert_eq!(PortId::A, port);
+        CALLS.fetch_add(1 << pin, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn dispatches() {
+        let (port_regs, _) = mocks();
+        let mut handlers: [Option<PinHandler>; 32] = [None; 32];
+        handlers[1] = Some(count);
+        handlers[4] = Some(count);
+
+        port_regs.isfr.set(0b1_0110);
+        assert_eq!(0b100, dispatch_to(PortId::A, &port_regs, &handlers));
+        assert_eq!(0b1_0010, CALLS.load(Ordering::SeqCst));
+        assert_eq!(0, port_regs.isfr.get());
+    }
+}
@@ -0,0 +1,583 @@
+use super::port::PortId;
+use super::uart::UartId;
+
+/// Number of peripheral IRQs on the K64.
+pub const NUM_IRQS: usize = 86;
+
+/// Peripheral interrupt requests of the K64, numbered as the NVIC sees them (vector number - 16).
+///
+/// IRQ 55 is reserved and has no variant.
+///
+/// # Examples
+/// ```ignore
+/// mcu.get_nvic().enable_irq(Irq::Pit0.into());
+/// ```
+#[repr(u8)]
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Irq {
+    /// DMA channel 0 transfer complete
+    Dma0 = 0,
+    /// DMA channel 1 transfer complete
+    Dma1 = 1,
+    /// DMA channel 2 transfer complete
+    Dma2 = 2,
+    /// DMA channel 3 transfer complete
+    Dma3 = 3,
+    /// DMA channel 4 transfer complete
+    Dma4 = 4,
+    /// DMA channel 5 transfer complete
+    Dma5 = 5,
+    /// DMA channel 6 transfer complete
+    Dma6 = 6,
+    /// DMA channel 7 transfer complete
+    Dma7 = 7,
+    /// DMA channel 8 transfer complete
+    Dma8 = 8,
+    /// DMA channel 9 transfer complete
+    Dma9 = 9,
+    /// DMA channel 10 transfer complete
+    Dma10 = 10,
+    /// DMA channel 11 transfer complete
+    Dma11 = 11,
+    /// DMA channel 12 transfer complete
+    Dma12 = 12,
+    /// DMA channel 13 transfer complete
+    Dma13 = 13,
+    /// DMA channel 14 transfer complete
+    Dma14 = 14,
+    /// DMA channel 15 transfer complete
+    Dma15 = 15,
+    /// DMA error (any channel)
+    DmaError = 16,
+    /// Miscellaneous control module
+    Mcm = 17,
+    /// Flash memory command complete
+    FlashCommand = 18,
+    /// Flash memory read collision
+    FlashReadCollision = 19,
+    /// Low voltage detect and warning
+    LowVoltage = 20,
+    /// Low leakage wakeup unit
+    Llwu = 21,
+    /// Watchdog or external watchdog monitor
+    WatchdogEwm = 22,
+    /// Random number generator
+    Rng = 23,
+    /// I2C0
+    I2c0 = 24,
+    /// I2C1
+    I2c1 = 25,
+    /// SPI0
+    Spi0 = 26,
+    /// SPI1
+    Spi1 = 27,
+    /// I2S0 transmit
+    I2s0Tx = 28,
+    /// I2S0 receive
+    I2s0Rx = 29,
+    /// UART0 LON
+    Uart0Lon = 30,
+    /// UART0 receive and transmit status
+    Uart0RxTx = 31,
+    /// UART0 error
+    Uart0Error = 32,
+    /// UART1 receive and transmit status
+    Uart1RxTx = 33,
+    /// UART1 error
+    Uart1Error = 34,
+    /// UART2 receive and transmit status
+    Uart2RxTx = 35,
+    /// UART2 error
+    Uart2Error = 36,
+    /// UART3 receive and transmit status
+    Uart3RxTx = 37,
+    /// UART3 error
+    Uart3Error = 38,
+    /// ADC0
+    Adc0 = 39,
+    /// Comparator 0
+    Cmp0 = 40,
+    /// Comparator 1
+    Cmp1 = 41,
+    /// FlexTimer 0
+    Ftm0 = 42,
+    /// FlexTimer 1
+    Ftm1 = 43,
+    /// FlexTimer 2
+    Ftm2 = 44,
+    /// Carrier modulator transmitter
+    Cmt = 45,
+    /// RTC alarm
+    RtcAlarm = 46,
+    /// RTC seconds
+    RtcSeconds = 47,
+    /// Periodic interrupt timer channel 0
+    Pit0 = 48,
+    /// Periodic interrupt timer channel 1
+    Pit1 = 49,
+    /// Periodic interrupt timer channel 2
+    Pit2 = 50,
+    /// Periodic interrupt timer channel 3
+    Pit3 = 51,
+    /// Programmable delay block
+    Pdb0 = 52,
+    /// USB OTG
+    Usb0 = 53,
+    /// USB charger detect
+    UsbDcd = 54,
+    // 55 is reserved
+    /// DAC0
+    Dac0 = 56,
+    /// Multipurpose clock generator
+    Mcg = 57,
+    /// Low power timer
+    LpTimer = 58,
+    /// Port A pin detect
+    PortA = 59,
+    /// Port B pin detect
+    PortB = 60,
+    /// Port C pin detect
+    PortC = 61,
+    /// Port D pin detect
+    PortD = 62,
+    /// Port E pin detect
+    PortE = 63,
+    /// Software interrupt
+    Software = 64,
+    /// SPI2
+    Spi2 = 65,
+    /// UART4 receive and transmit status
+    Uart4RxTx = 66,
+    /// UART4 error
+    Uart4Error = 67,
+    /// UART5 receive and transmit status
+    Uart5RxTx = 68,
+    /// UART5 error
+    Uart5Error = 69,
+    /// Comparator 2
+    Cmp2 = 70,
+    /// FlexTimer 3
+    Ftm3 = 71,
+    /// DAC1
+    Dac1 = 72,
+    /// ADC1
+    Adc1 = 73,
+    /// I2C2
+    I2c2 = 74,
+    /// CAN0 message buffers (OR'ed)
+    Can0MessageBuffer = 75,
+    /// CAN0 bus off
+    Can0BusOff = 76,
+    /// CAN0 error
+    Can0Error = 77,
+    /// CAN0 transmit warning
+    Can0TxWarning = 78,
+    /// CAN0 receive warning
+    Can0RxWarning = 79,
+    /// CAN0 wake up
+    Can0WakeUp = 80,
+    /// SD host controller
+    Sdhc = 81,
+    /// Ethernet IEEE 1588 timer
+    Enet1588Timer = 82,
+    /// Ethernet transmit
+    EnetTransmit = 83,
+    /// Ethernet receive
+    EnetReceive = 84,
+    /// Ethernet error
+    EnetError = 85,
+}
+
+impl From<Irq> for u8 {
+    fn from(irq: Irq) -> u8 { irq as u8 }
+}
+
+
+//------------------------------------------------
+//
+// default handlers
+//
+//------------------------------------------------
+
+// Every handler is weakly linked so an application overrides it by defining a function of the same name:
+//
+//     #[no_mangle]
+//     pub extern "C" fn isr_pit0() { ... }
+//
+// The port handlers instead default to `gpio::dispatch`, which calls the pin handlers set with `gpio::set_handler`.
+// The UART receive/transmit handlers default to `uart::dispatch`, which calls the handler set with `uart::set_handler`.
+
+/// Called by every handler the application has not overridden. An enabled IRQ without a handler is a bug, so it
+/// panics with the IRQ rather than returning into an interrupt that will fire again.
+#[inline(never)]
+pub fn unhandled(irq: Irq) -> ! {
+    panic!("unhandled interrupt: {:?}", irq)
+}
+
+/// Like `unhandled`, for an interrupt shared by several sources (one bit each), naming the sources nothing handled.
+#[inline(never)]
+pub fn unhandled_sources(irq: Irq, sources: u32) -> ! {
+    panic!("unhandled interrupt: {:?} (sources {:#x})", irq, sources)
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma0() { unhandled(Irq::Dma0) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma1() { unhandled(Irq::Dma1) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma2() { unhandled(Irq::Dma2) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma3() { unhandled(Irq::Dma3) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma4() { unhandled(Irq::Dma4) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma5() { unhandled(Irq::Dma5) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma6() { unhandled(Irq::Dma6) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma7() { unhandled(Irq::Dma7) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma8() { unhandled(Irq::Dma8) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma9() { unhandled(Irq::Dma9) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma10() { unhandled(Irq::Dma10) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma11() { unhandled(Irq::Dma11) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma12() { unhandled(Irq::Dma12) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma13() { unhandled(Irq::Dma13) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma14() { unhandled(Irq::Dma14) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma15() { unhandled(Irq::Dma15) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dma_error() { unhandled(Irq::DmaError) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_mcm() { unhandled(Irq::Mcm) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_flash_command() { unhandled(Irq::FlashCommand) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_flash_read_collision() { unhandled(Irq::FlashReadCollision) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_low_voltage() { unhandled(Irq::LowVoltage) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_llwu() { unhandled(Irq::Llwu) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_watchdog_ewm() { unhandled(Irq::WatchdogEwm) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_rng() { unhandled(Irq::Rng) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_i2c0() { unhandled(Irq::I2c0) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_i2c1() { unhandled(Irq::I2c1) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_spi0() { unhandled(Irq::Spi0) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_spi1() { unhandled(Irq::Spi1) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_i2s0_tx() { unhandled(Irq::I2s0Tx) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_i2s0_rx() { unhandled(Irq::I2s0Rx) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_uart0_lon() { unhandled(Irq::Uart0Lon) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_uart0_rx_tx() { super::uart::dispatch(UartId::Uart0) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_uart0_error() { unhandled(Irq::Uart0Error) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_uart1_rx_tx() { super::uart::dispatch(UartId::Uart1) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_uart1_error() { unhandled(Irq::Uart1Error) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_uart2_rx_tx() { super::uart::dispatch(UartId::Uart2) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_uart2_error() { unhandled(Irq::Uart2Error) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_uart3_rx_tx() { super::uart::dispatch(UartId::Uart3) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_uart3_error() { unhandled(Irq::Uart3Error) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_adc0() { unhandled(Irq::Adc0) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_cmp0() { unhandled(Irq::Cmp0) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_cmp1() { unhandled(Irq::Cmp1) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_ftm0() { unhandled(Irq::Ftm0) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_ftm1() { unhandled(Irq::Ftm1) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_ftm2() { unhandled(Irq::Ftm2) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_cmt() { unhandled(Irq::Cmt) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_rtc_alarm() { unhandled(Irq::RtcAlarm) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_rtc_seconds() { unhandled(Irq::RtcSeconds) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_pit0() { unhandled(Irq::Pit0) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_pit1() { unhandled(Irq::Pit1) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_pit2() { unhandled(Irq::Pit2) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_pit3() { unhandled(Irq::Pit3) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_pdb0() { unhandled(Irq::Pdb0) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_usb0() { unhandled(Irq::Usb0) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_usb_dcd() { unhandled(Irq::UsbDcd) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dac0() { unhandled(Irq::Dac0) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_mcg() { unhandled(Irq::Mcg) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_lp_timer() { unhandled(Irq::LpTimer) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_port_a() { super::gpio::dispatch(PortId::A) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_port_b() { super::gpio::dispatch(PortId::B) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_port_c() { super::gpio::dispatch(PortId::C) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_port_d() { super::gpio::dispatch(PortId::D) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_port_e() { super::gpio::dispatch(PortId::E) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_software() { unhandled(Irq::Software) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_spi2() { unhandled(Irq::Spi2) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_uart4_rx_tx() { super::uart::dispatch(UartId::Uart4) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_uart4_error() { unhandled(Irq::Uart4Error) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_uart5_rx_tx() { super::uart::dispatch(UartId::Uart5) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_uart5_error() { unhandled(Irq::Uart5Error) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_cmp2() { unhandled(Irq::Cmp2) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_ftm3() { unhandled(Irq::Ftm3) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_dac1() { unhandled(Irq::Dac1) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_adc1() { unhandled(Irq::Adc1) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_i2c2() { unhandled(Irq::I2c2) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_can0_message_buffer() { unhandled(Irq::Can0MessageBuffer) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_can0_bus_off() { unhandled(Irq::Can0BusOff) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_can0_error() { unhandled(Irq::Can0Error) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_can0_tx_warning() { unhandled(Irq::Can0TxWarning) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_can0_rx_warning() { unhandled(Irq::Can0RxWarning) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_can0_wake_up() { unhandled(Irq::Can0WakeUp) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_sdhc() { unhandled(Irq::Sdhc) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_enet1588_timer() { unhandled(Irq::Enet1588Timer) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_enet_transmit() { unhandled(Irq::EnetTransmit) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_enet_receive() { unhandled(Irq::EnetReceive) }
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn isr_enet_error() { unhandled(Irq::EnetError) }
+
+
+#[cfg(test)]
+mod test {
+    use super::{Irq, NUM_IRQS};
+
+    #[test]
+    fn numbers_match_the_vector_table() {
+        assert_eq!(0, u8::from(Irq::Dma0));
+        assert_eq!(16, u8::from(Irq::DmaError));
+        assert_eq!(31, u8::from(Irq::Uart0RxTx));
+        assert_eq!(48, u8::from(Irq::Pit0));
+        assert_eq!(54, u8::from(Irq::UsbDcd));
+        assert_eq!(56, u8::from(Irq::Dac0));    // 55 is reserved
+        assert_eq!(59, u8::from(Irq::PortA));
+        assert_eq!(66, u8::from(Irq::Uart4RxTx));
+        assert_eq!(NUM_IRQS - 1, u8::from(Irq::EnetError) as usize);
+    }
+}
@@ -0,0 +1,547 @@
+extern crate core;
+use core::intrinsics::{volatile_load, volatile_store};
+
+use ::os::error::ClockError;
+use super::clocks;
+use super::sim::SIM;
+
+
+ioreg!(
+    name => MCG;
+    doc_srcs => [
+        "http://www.nxp.com/files/microcontrollers/doc/ref_manual/K64P144M120SF5RM.pdf" // section 25
+    ];
+
+    0x0000 => control_1 r8 rw {
+        0 => { // IREFSTEN
+            enable_internal_reference_in_stop   => [1];
+            disable_internal_reference_in_stop  => [0];
+        }
+
+        1 => { // IRCLKEN
+            enable_internal_reference_clock     => [1];
+            disable_internal_reference_clock    => [0];
+        }
+
+        2 => { // IREFS
+            use_external_fll_reference          => [0];
+            use_internal_fll_reference          => [1];
+        }
+
+        3..5 => { // FRDIV
+            set_fll_external_divider            => ();
+        }
+
+        6..7 => { // CLKS
+            use_fll_pll_clock                   => [0b00];
+            use_internal_reference_clock        => [0b01];
+            use_external_reference_clock        => [0b10];
+        }
+    };
+
+    0x0001 => control_2 r8 rw {
+        0 => { // IRCS
+            use_slow_internal_reference         => [0];
+            use_fast_internal_reference         => [1];
+        }
+
+        1 => { // LP
+            enable_fll_pll_in_bypass            => [0];
+            disable_fll_pll_in_bypass           => [1];
+        }
+
+        2 => { // EREFS0
+            use_external_clock                  => [0];
+            use_oscillator                      => [1];
+        }
+
+        3 => { // HGO0
+            use_low_power_oscillator            => [0];
+            use_high_gain_oscillator            => [1];
+        }
+
+        4..5 => { // RANGE0
+            set_oscillator_range                => ();
+        }
+    };
+
+    0x0002 => control_3 r8 rw {
+        0..7 => { set_slow_internal_reference_trim => (); }
+    };
+
+    0x0003 => control_4 r8 rw {
+        // ... fast internal reference and dco trims ...
+
+        5..6 => { // DRST_DRS
+            set_fll_range                       => ();
+        }
+
+        7 => { // DMX32
+            use_default_fll_range               => [0];
+            use_32khz_fll_range                 => [1];
+        }
+    };
+
+    0x0004 => control_5 r8 rw {
+        0..4 => { // PRDIV0
+            set_pll_divider                     => ();  // divide by value + 1
+        }
+
+        5 => { // PLLSTEN0
+            enable_pll_in_stop                  => [1];
+            disable_pll_in_stop                 => [0];
+        }
+
+        6 => { // PLLCLKEN0
+            enable_pll_clock                    => [1];
+            disable_pll_clock                   => [0];
+        }
+    };
+
+    0x0005 => control_6 r8 rw {
+        0..4 => { // VDIV0
+            set_pll_multiplier                  => ();  // multiply by value + 24
+        }
+
+        5 => { // CME0
+            enable_oscillator_monitor           => [1];
+            disable_oscillator_monitor          => [0];
+        }
+
+        6 => { // PLLS
+            select_fll                          => [0];
+            select_pll                          => [1];
+        }
+
+        7 => { // LOLIE0
+            enable_loss_of_lock_interrupt       => [1];
+            disable_loss_of_lock_interrupt      => [0];
+        }
+    };
+
+    0x0006 => status r8 ro {};
+
+    0x0008 => status_control r8 rw {
+        0 => { clear_oscillator_loss => [1]; }  // write 1 to clear
+    };
+
+    0x000C => control_7 r8 rw {
+        0..1 => { // OSCSEL
+            use_oscillator_for_external_reference   => [0b00];
+            use_rtc_for_external_reference          => [0b01];
+            use_irc48m_for_external_reference       => [0b10];
+        }
+    };
+
+    0x000D => control_8 r8 rw {
+        // ... rtc loss of clock ...
+    };
+);
+
+/// System oscillator (OSC0) control.
+ioreg!(
+    name => OSC;
+    doc_srcs => [
+        "http://www.nxp.com/files/microcontrollers/doc/ref_manual/K64P144M120SF5RM.pdf" // section 26
+    ];
+
+    0x0000 => control r8 rw {
+        0..3 => { // SC16P, SC8P, SC4P, SC2P
+            set_load_capacitors                 => ();
+        }
+
+        5 => { // EREFSTEN
+            enable_external_reference_in_stop   => [1];
+            disable_external_reference_in_stop  => [0];
+        }
+
+        7 => { // ERCLKEN
+            enable_external_reference           => [1];
+            disable_external_reference          => [0];
+        }
+    };
+
+    0x0002 => divider r8 rw {
+        6..7 => { set_external_reference_divider => (); }
+    };
+);
+
+
+//------------------------------------------------
+//
+// limits
+//
+//------------------------------------------------
+
+/// Highest rated core and system clock.
+pub const MAX_CORE_HZ: u32 = 120_000_000;
+/// Highest rated bus clock.
+pub const MAX_BUS_HZ: u32 = 60_000_000;
+/// Highest rated FlexBus clock.
+pub const MAX_FLEXBUS_HZ: u32 = 50_000_000;
+/// Highest rated flash clock.
+pub const MAX_FLASH_HZ: u32 = 25_000_000;
+
+/// The PLL reference (oscillator / PRDIV0) must be within 2-4MHz.
+const PLL_REF_MIN_HZ: u32 = 2_000_000;
+const PLL_REF_MAX_HZ: u32 = 4_000_000;
+const PLL_MIN_HZ: u32 = 48_000_000;
+const MIN_PLL_DIVIDER: u32 = 1;
+const MAX_PLL_DIVIDER: u32 = 25;
+const MIN_PLL_MULTIPLIER: u32 = 24;
+const MAX_PLL_MULTIPLIER: u32 = 55;
+
+/// The FLL reference (external reference / FRDIV) must be within 31.25-39.0625kHz. Doubled to stay an integer.
+const FLL_REF_MAX_2HZ: u64 = 78_125;
+
+/// Highest frequency of a clock driven into EXTAL0, rather than a crystal.
+const MAX_EXTERNAL_CLOCK_HZ: u32 = 50_000_000;
+
+// status polls before giving up. nothing can be timed before the clocks are up
+const TIMEOUT: u32 = 1_000_000;
+
+
+//------------------------------------------------
+//
+// configuration
+//
+//------------------------------------------------
+
+// S
+const S_OSCINIT0: u8 = 1 << 1;
+const S_CLKST_MASK: u8 = 0b11 << 2;
+const S_CLKST_FLL: u8 = 0b00 << 2;
+const S_CLKST_INTERNAL: u8 = 0b01 << 2;
+const S_CLKST_EXTERNAL: u8 = 0b10 << 2;
+const S_CLKST_PLL: u8 = 0b11 << 2;
+const S_IREFST: u8 = 1 << 4;
+const S_PLLST: u8 = 1 << 5;
+const S_LOCK0: u8 = 1 << 6;
+
+/// Clock mode of the MCG. The low power bypass modes are reported as their FLL/PLL-enabled counterparts.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Mode {
+    /// FLL engaged internal, the mode out of reset.
+    Fei,
+    /// FLL engaged external.
+    Fee,
+    /// FLL bypassed internal.
+    Fbi,
+    /// FLL bypassed external.
+    Fbe,
+    /// PLL bypassed external.
+    Pbe,
+    /// PLL engaged external.
+    Pee,
+}
+impl Mode {
+    /// Decode the mode from the MCG status register.
+    pub fn from_status(status: u8) -> Mode {
+        match status & S_CLKST_MASK {
+            S_CLKST_FLL => if (status & S_IREFST) != 0 { Mode::Fei } else { Mode::Fee },
+            S_CLKST_INTERNAL => Mode::Fbi,
+            S_CLKST_EXTERNAL => if (status & S_PLLST) != 0 { Mode::Pbe } else { Mode::Fbe },
+            _ => Mode::Pee,
+        }
+    }
+}
+
+/// The external reference on EXTAL0 (and XTAL0 for a crystal).
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Oscillator {
+    pub hz: u32,
+    /// A crystal driven by OSC0, rather than a clock signal.
+    pub crystal: bool,
+    /// Internal load capacitance for the crystal, in pF. Even, up to 30pF.
+    pub load_pf: u8,
+}
+impl Oscillator {
+    /// Get the MCG RANGE0 setting for the oscillator.
+    pub fn range(&self) -> Result<u8, ClockError> {
+        match self.hz {
+            32_000...40_000 => Ok(0),
+            3_000_000...8_000_000 => Ok(1),
+            8_000_001...32_000_000 => Ok(2),
+            32_000_001...MAX_EXTERNAL_CLOCK_HZ if !self.crystal => Ok(2),
+            _ => Err(ClockError::BadOscillator),
+        }
+    }
+
+    /// Get the OSC SCxP bits for the load capacitance.
+    pub fn load_capacitors(&self) -> Result<u8, ClockError> {
+        if self.load_pf > 30 || self.load_pf % 2 != 0 { return Err(ClockError::BadOscillator); }
+        // SC2P is the highest bit and SC16P the lowest
+        let units = self.load_pf / 2;
+        Ok(((units & 0x1) << 3) | ((units & 0x2) << 1) | ((units & 0x4) >> 1) | ((units & 0x8) >> 3))
+    }
+}
+
+/// FLL external reference divide factors by FRDIV, with RANGE0 = 0 (or the RTC oscillator selected).
+pub const FRDIV_LOW_RANGE: [u32; 8] = [1, 2, 4, 8, 16, 32, 64, 128];
+/// FLL external reference divide factors by FRDIV, otherwise.
+pub const FRDIV_HIGH_RANGE: [u32; 8] = [32, 64, 128, 256, 512, 1024, 1280, 1536];
+
+/// Get the FRDIV setting dividing the external reference to within the FLL's reference range.
+pub fn fll_external_divider(range: u8, osc_hz: u32) -> Result<u8, ClockError> {
+    let factors = if range == 0 { &FRDIV_LOW_RANGE } else { &FRDIV_HIGH_RANGE };
+    for (frdiv, factor) in factors.iter().enumerate() {
+        if 2 * (osc_hz as u64) <= FLL_REF_MAX_2HZ * (*factor as u64) { return Ok(frdiv as u8); }
+    }
+    Err(ClockError::BadOscillator)
+}
+
+/// PLL settings, as the divider and multiplier rather than their register encodings.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct PllConfig {
+    /// Oscillator divider to the PLL reference, 1-25.
+    pub divider: u8,
+    /// PLL reference multiplier, 24-55.
+    pub multiplier: u8,
+}
+impl PllConfig {
+    /// Find the settings making exactly `target_hz` from the oscillator, preferring the highest PLL reference.
+    pub fn for_frequency(osc_hz: u32, target_hz: u32) -> Result<PllConfig, ClockError> {
+        if target_hz > MAX_CORE_HZ { return Err(ClockError::TooFast); }
+        if target_hz < PLL_MIN_HZ { return Err(ClockError::NoPllSolution); }
+
+        for divider in MIN_PLL_DIVIDER..(MAX_PLL_DIVIDER + 1) {
+            if osc_hz % divider != 0 { continue; }
+            let reference = osc_hz / divider;
+            if reference > PLL_REF_MAX_HZ { continue; }
+            if reference < PLL_REF_MIN_HZ { break; }
+
+            if target_hz % reference != 0 { continue; }
+            let multiplier = target_hz / reference;
+            if multiplier >= MIN_PLL_MULTIPLIER && multiplier <= MAX_PLL_MULTIPLIER {
+                return Ok(PllConfig{divider: divider as u8, multiplier: multiplier as u8});
+            }
+        }
+        Err(ClockError::NoPllSolution)
+    }
+
+    /// Get the PLL output frequency from the oscillator.
+    pub fn frequency(&self, osc_hz: u32) -> u32 {
+        (osc_hz / self.divider as u32) * self.multiplier as u32
+    }
+}
+
+/// The SIM_CLKDIV1 divisors (1-16) from MCGOUTCLK to each clock domain.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Dividers {
+    pub core: u8,
+    pub bus: u8,
+    pub flexbus: u8,
+    pub flash: u8,
+}
+impl Dividers {
+    /// Get the smallest divisors keeping each clock within its rating. The bus, FlexBus and flash divisors are
+    /// multiples of the core divisor, so the core clock is an integer multiple of each.
+    pub fn for_clock(mcgout_hz: u32) -> Result<Dividers, ClockError> {
+        let core = try!(Dividers::divisor(mcgout_hz, MAX_CORE_HZ, 1));
+        Ok(Dividers{
+            core: core,
+            bus: try!(Dividers::divisor(mcgout_hz, MAX_BUS_HZ, core)),
+            flexbus: try!(Dividers::divisor(mcgout_hz, MAX_FLEXBUS_HZ, core)),
+            flash: try!(Dividers::divisor(mcgout_hz, MAX_FLASH_HZ, core)),
+        })
+    }
+
+    /// Decode the divisors from a SIM_CLKDIV1 value.
+    pub fn from_clkdiv1(clkdiv1: u32) -> Dividers {
+        Dividers{
+            core: ((clkdiv1 >> 28) & 0xF) as u8 + 1,
+            bus: ((clkdiv1 >> 24) & 0xF) as u8 + 1,
+            flexbus: ((clkdiv1 >> 20) & 0xF) as u8 + 1,
+            flash: ((clkdiv1 >> 16) & 0xF) as u8 + 1,
+        }
+    }
+
+    /// Get the clocks the divisors make from MCGOUTCLK.
+    pub fn frequencies(&self, mcgout_hz: u32) -> Frequencies {
+        Frequencies{
+            core: mcgout_hz / self.core as u32,
+            bus: mcgout_hz / self.bus as u32,
+            flexbus: mcgout_hz / self.flexbus as u32,
+            flash: mcgout_hz / self.flash as u32,
+        }
+    }
+
+    fn divisor(clock_hz: u32, max_hz: u32, step: u8) -> Result<u8, ClockError> {
+        let mut divisor = step;
+        while (clock_hz as u64) > (max_hz as u64) * (divisor as u64) {
+            divisor += step;
+            if divisor > 16 { return Err(ClockError::TooFast); }
+        }
+        Ok(divisor)
+    }
+}
+
+/// Frequency of each clock domain, in Hz.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Frequencies {
+    /// Core and system clock, which also drives UART0 and UART1.
+    pub core: u32,
+    /// Bus clock, driving most peripherals.
+    pub bus: u32,
+    pub flexbus: u32,
+    pub flash: u32,
+}
+
+impl MCG {
+    /// Get the current clock mode.
+    pub fn mode(&self) -> Mode { Mode::from_status(self.read_status()) }
+
+    /// Run the core from the PLL at `target_hz`, walking FEI -> FBE -> PBE -> PEE, and set the SIM dividers to keep
+    /// every clock within its rating. The new frequencies are returned. Afterwards, `clocks::ClockTree` reads them back
+    /// from the registers.
+    ///
+    /// Everything is validated before any register is touched, so on an error returned before the walk starts the
+    /// clocks are unchanged. On a timeout, the MCG is left in the mode it was waiting to leave.
+    pub fn use_pll(&self, osc: &OSC, sim: &SIM, oscillator: &Oscillator, target_hz: u32)
+        -> Result<Frequencies, ClockError>
+    {
+        if self.mode() != Mode::Fei { return Err(ClockError::BadMode); }
+        let range = try!(oscillator.range());
+        let frdiv = try!(fll_external_divider(range, oscillator.hz));
+        let pll = try!(PllConfig::for_frequency(oscillator.hz, target_hz));
+        let pll_hz = pll.frequency(oscillator.hz);
+        let dividers = try!(Dividers::for_clock(pll_hz));
+
+        // FEI -> FBE: MCGOUTCLK from the external reference, the FLL (unused) referenced to it as well
+        if oscillator.crystal {
+            osc.set_load_capacitors(try!(oscillator.load_capacitors()));
+            self.use_oscillator();
+        } else {
+            self.use_external_clock();
+        }
+        osc.enable_external_reference();
+        self.set_oscillator_range(range);
+        self.set_fll_external_divider(frdiv);
+        self.use_external_fll_reference();
+        self.use_external_reference_clock();
+
+        if oscillator.crystal { try!(self.wait_for(S_OSCINIT0, S_OSCINIT0, ClockError::OscillatorTimeout)); }
+        try!(self.wait_for(S_IREFST, 0, ClockError::SwitchTimeout));
+        try!(self.wait_for(S_CLKST_MASK, S_CLKST_EXTERNAL, ClockError::SwitchTimeout));
+
+        // FBE -> PBE: start the PLL while still running from the external reference
+        self.set_pll_divider(pll.divider - 1);
+        self.set_pll_multiplier(pll.multiplier - MIN_PLL_MULTIPLIER as u8);
+        self.select_pll();
+        try!(self.wait_for(S_PLLST, S_PLLST, ClockError::SwitchTimeout));
+        try!(self.wait_for(S_LOCK0, S_LOCK0, ClockError::PllLockTimeout));
+
+        // the external reference is at most 50MHz, so these are safe for it as well as the PLL
+        sim.set_system_clock_divider(dividers.core as u32 - 1);
+        sim.set_bus_clock_divider(dividers.bus as u32 - 1);
+        sim.set_flexbus_clock_divider(dividers.flexbus as u32 - 1);
+        sim.set_flash_clock_divider(dividers.flash as u32 - 1);
+
+        // PBE -> PEE
+        self.use_fll_pll_clock();
+        try!(self.wait_for(S_CLKST_MASK, S_CLKST_PLL, ClockError::SwitchTimeout));
+
+        // only recorded once the PLL runs from it, so ClockTree never reports a reference that failed to start
+        clocks::set_oscillator_hz(oscillator.hz);
+        Ok(dividers.frequencies(pll_hz))
+    }
+
+    fn wait_for(&self, mask: u8, value: u8, err: ClockError) -> Result<(), ClockError> {
+        for _ in 0..TIMEOUT {
+            if (self.read_status() & mask) == value { return Ok(()); }
+        }
+        Err(err)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use ::os::error::ClockError;
+    use super::{Mode, Oscillator, PllConfig, Dividers, Frequencies, fll_external_divider};
+
+    #[test]
+    fn modes() {
+        assert_eq!(Mode::Fei, Mode::from_status(0x10));     // reset value
+        assert_eq!(Mode::Fee, Mode::from_status(0x00));
+        assert_eq!(Mode::Fbi, Mode::from_status(0x14));
+        assert_eq!(Mode::Fbe, Mode::from_status(0x0A));
+        assert_eq!(Mode::Pbe, Mode::from_status(0x6A));
+        assert_eq!(Mode::Pee, Mode::from_status(0x6E));
+    }
+
+    #[test]
+    fn oscillator() {
+        let crystal = Oscillator{hz: 16_000_000, crystal: true, load_pf: 10};
+        assert_eq!(Ok(2), crystal.range());
+        assert_eq!(Ok(0b1010), crystal.load_capacitors());   // SC8P + SC2P
+        assert_eq!(Ok(1), Oscillator{hz: 8_000_000, crystal: true, load_pf: 0}.range());
+        assert_eq!(Ok(0), Oscillator{hz: 32_768, crystal: true, load_pf: 0}.range());
+
+        let clock = Oscillator{hz: 50_000_000, crystal: false, load_pf: 0};
+        assert_eq!(Ok(2), clock.range());
+        assert_eq!(Err(ClockError::BadOscillator), Oscillator{crystal: true, .. clock}.range());
+        assert_eq!(Err(ClockError::BadOscillator), Oscillator{hz: 1_000_000, .. clock}.range());
+        assert_eq!(Err(ClockError::BadOscillator), Oscillator{load_pf: 3, .. crystal}.load_capacitors());
+        assert_eq!(Ok(0b1111), Oscillator{load_pf: 30, .. crystal}.load_capacitors());
+        assert_eq!(Ok(0b1000), Oscillator{load_pf: 2, .. crystal}.load_capacitors());
+    }
+
+    #[test]
+    fn fll_divider() {
+        assert_eq!(Ok(4), fll_external_divider(2, 16_000_000));     // /512 = 31.25kHz
+        assert_eq!(Ok(6), fll_external_divider(2, 50_000_000));     // /1280 = 39.0625kHz, the limit
+        assert_eq!(Ok(7), fll_external_divider(2, 50_000_001));
+        assert_eq!(Ok(0), fll_external_divider(0, 32_768));
+        assert_eq!(Err(ClockError::BadOscillator), fll_external_divider(2, 64_000_000));
+    }
+
+    #[test]
+    fn pll() {
+        let from_50 = PllConfig::for_frequency(50_000_000, 120_000_000).unwrap();
+        assert_eq!(PllConfig{divider: 20, multiplier: 48}, from_50);    // 2.5MHz reference
+        assert_eq!(120_000_000, from_50.frequency(50_000_000));
+
+        let from_16 = PllConfig::for_frequency(16_000_000, 120_000_000).unwrap();
+        assert_eq!(PllConfig{divider: 4, multiplier: 30}, from_16);     // 4MHz reference
+        assert_eq!(120_000_000, from_16.frequency(16_000_000));
+
+        assert_eq!(PllConfig{divider: 4, multiplier: 24}, PllConfig::for_frequency(8_000_000, 48_000_000).unwrap());
+        assert_eq!(Err(ClockError::TooFast), PllConfig::for_frequency(50_000_000, 150_000_000));
+        assert_eq!(Err(ClockError::NoPllSolution), PllConfig::for_frequency(50_000_000, 24_000_000));
+        assert_eq!(Err(ClockError::NoPllSolution), PllConfig::for_frequency(16_000_000, 100_000_001));
+    }
+
+    #[test]
+    fn dividers() {
+        let full = Dividers::for_clock(120_000_000).unwrap();
+        assert_eq!(Dividers{core: 1, bus: 2, flexbus: 3, flash: 5}, full);
+        assert_eq!(Frequencies{core: 120_000_000, bus: 60_000_000, flexbus: 40_000_000, flash: 24_000_000},
+                   full.frequencies(120_000_000));
+
+        assert_eq!(Dividers{core: 1, bus: 1, flexbus: 1, flash: 2}, Dividers::for_clock(50_000_000).unwrap());
+        assert_eq!(Dividers{core: 1, bus: 2, flexbus: 2, flash: 4}, Dividers::for_clock(96_000_000).unwrap());
+        assert_eq!(Dividers{core: 2, bus: 4, flexbus: 4, flash: 8}, Dividers::for_clock(180_000_000).unwrap());
+    }
+
+    #[test]
+    fn clkdiv1() {
+        assert_eq!(Dividers{core: 1, bus: 1, flexbus: 1, flash: 2}, Dividers::from_clkdiv1(0x0001_0000));  // reset
+        assert_eq!(Dividers{core: 1, bus: 2, flexbus: 3, flash: 5}, Dividers::from_clkdiv1(0x0124_0000));
+    }
+}
@@ -3,21 +3,43 @@ use core::intrinsics::{volatile_copy_nonoverlapping_memory, volatile_set_memory}
 
 use ::mcus::cortexm4;
 
+pub mod interrupts;
 pub mod wdog;
 pub mod sim;
+pub mod sysmpu;
+pub mod gates;
+pub mod port;
+pub mod gpio;
+pub mod uart;
+pub mod mcg;
+pub mod clocks;
+pub mod device;
 
 extern {
     fn entry(mcu: K64) -> !;
 }
 
+/// Last step of the reset path. Copies `.data` out of flash and zeroes `.bss` so statics are valid, enables the
+/// configurable fault handlers (see `cortexm4::fault`), then hands the MCU to the application's `entry`.
+fn reset_exit(mcu: K64) -> ! {
+    unsafe {
+        // the externs are the linker symbols themselves, so their addresses mark the sections
+        cortexm4::startup::init_data(&__data_load as *const u32, &__data_begin as *const u32 as *mut u32,
+                                     &__data_end as *const u32);
+        cortexm4::startup::zero_bss(&__bss_begin as *const u32 as *mut u32, &__bss_end as *const u32);
+        mcu.scb.enable_fault_handlers();
+        entry(mcu)
+    }
+}
+
 mcu!(
     name => K64;
     doc_srcs => [
         "http://www.nxp.com/files/microcontrollers/doc/ref_manual/K64P144M120SF5RM.pdf"
     ];
 
-    link_script => "MK64FN1M0VLL12.ld"; // TODO
-    bootloader_exit => entry;
+    link_script => "MK64FN1M0VLL12.ld"; // generated by build.rs
+    bootloader_exit => reset_exit;
 
     constants => {
     };
@@ -29,17 +51,111 @@ mcu!(
         __heap_begin:   u32;
         __heap_end:     u32;
 
+        __data_load:        u32;
+        __data_begin:       u32;
+        __data_end:         u32;
+
         __bss_begin:    u32;
         __bss_end:      u32;
     };
 
-    nvic => ::traits::NVIC {
-        addr => 0xE000_E000;
-        prio_bits => 4;
+    // indexed by exception number, ahead of the IRQs. the faults share a trampoline, see cortexm4::fault
+    exceptions => [16] @ .interrupt_table {
+        3  => cortexm4::fault::isr_fault;           // HardFault
+        4  => cortexm4::fault::isr_fault;           // MemManage
+        5  => cortexm4::fault::isr_fault;           // BusFault
+        6  => cortexm4::fault::isr_fault;           // UsageFault
+        15 => cortexm4::core::systick::isr_systick;
     };
 
+    // indexed by IRQ number. every handler is weak, see the interrupts module
     interrupts => [128] @ .interrupt_table {
-        // TODO
+        0  => interrupts::isr_dma0;
+        1  => interrupts::isr_dma1;
+        2  => interrupts::isr_dma2;
+        3  => interrupts::isr_dma3;
+        4  => interrupts::isr_dma4;
+        5  => interrupts::isr_dma5;
+        6  => interrupts::isr_dma6;
+        7  => interrupts::isr_dma7;
+        8  => interrupts::isr_dma8;
+        9  => interrupts::isr_dma9;
+        10 => interrupts::isr_dma10;
+        11 => interrupts::isr_dma11;
+        12 => interrupts::isr_dma12;
+        13 => interrupts::isr_dma13;
+        14 => interrupts::isr_dma14;
+        15 => interrupts::isr_dma15;
+        16 => interrupts::isr_dma_error;
+        17 => interrupts::isr_mcm;
+        18 => interrupts::isr_flash_command;
+        19 => interrupts::isr_flash_read_collision;
+        20 => interrupts::isr_low_voltage;
+        21 => interrupts::isr_llwu;
+        22 => interrupts::isr_watchdog_ewm;
+        23 => interrupts::isr_rng;
+        24 => interrupts::isr_i2c0;
+        25 => interrupts::isr_i2c1;
+        26 => interrupts::isr_spi0;
+        27 => interrupts::isr_spi1;
+        28 => interrupts::isr_i2s0_tx;
+        29 => interrupts::isr_i2s0_rx;
+        30 => interrupts::isr_uart0_lon;
+        31 => interrupts::isr_uart0_rx_tx;
+        32 => interrupts::isr_uart0_error;
+        33 => interrupts::isr_uart1_rx_tx;
+        34 => interrupts::isr_uart1_error;
+        35 => interrupts::isr_uart2_rx_tx;
+        36 => interrupts::isr_uart2_error;
+        37 => interrupts::isr_uart3_rx_tx;
+        38 => interrupts::isr_uart3_error;
+        39 => interrupts::isr_adc0;
+        40 => interrupts::isr_cmp0;
+        41 => interrupts::isr_cmp1;
+        42 => interrupts::isr_ftm0;
+        43 => interrupts::isr_ftm1;
+        44 => interrupts::isr_ftm2;
+        45 => interrupts::isr_cmt;
+        46 => interrupts::isr_rtc_alarm;
+        47 => interrupts::isr_rtc_seconds;
+        48 => interrupts::isr_pit0;
+        49 => interrupts::isr_pit1;
+        50 => interrupts::isr_pit2;
+        51 => interrupts::isr_pit3;
+        52 => interrupts::isr_pdb0;
+        53 => interrupts::isr_usb0;
+        54 => interrupts::isr_usb_dcd;
+        // 55 is reserved
+        56 => interrupts::isr_dac0;
+        57 => interrupts::isr_mcg;
+        58 => interrupts::isr_lp_timer;
+        59 => interrupts::isr_port_a;
+        60 => interrupts::isr_port_b;
+        61 => interrupts::isr_port_c;
+        62 => interrupts::isr_port_d;
+        63 => interrupts::isr_port_e;
+        64 => interrupts::isr_software;
+        65 => interrupts::isr_spi2;
+        66 => interrupts::isr_uart4_rx_tx;
+        67 => interrupts::isr_uart4_error;
+        68 => interrupts::isr_uart5_rx_tx;
+        69 => interrupts::isr_uart5_error;
+        70 => interrupts::isr_cmp2;
+        71 => interrupts::isr_ftm3;
+        72 => interrupts::isr_dac1;
+        73 => interrupts::isr_adc1;
+        74 => interrupts::isr_i2c2;
+        75 => interrupts::isr_can0_message_buffer;
+        76 => interrupts::isr_can0_bus_off;
+        77 => interrupts::isr_can0_error;
+        78 => interrupts::isr_can0_tx_warning;
+        79 => interrupts::isr_can0_rx_warning;
+        80 => interrupts::isr_can0_wake_up;
+        81 => interrupts::isr_sdhc;
+        82 => interrupts::isr_enet1588_timer;
+        83 => interrupts::isr_enet_transmit;
+        84 => interrupts::isr_enet_receive;
+        85 => interrupts::isr_enet_error;
     };
 
 
@@ -54,16 +170,7 @@ mcu!(
             limit       => __heap_end;
         };
 
-        data => {
-            src_begin   => 0x1000; // TODO
-            src_end     => 0x1000; // TODO
-            dest        => 0x1000; // TODO
-        };
-
-        bss => {
-            base        => __bss_begin;
-            limit       => __bss_end;
-        };
+        // .data and .bss are set up once, by reset_exit
     };
 
 
@@ -72,10 +179,36 @@ mcu!(
         wdog        => wdog::Watchdog                       @ 0x4005_2000;
 
         // core modules
+        core_nvic   => cortexm4::core::nvic::NVIC           @ 0xE000_E000;
+        scb         => cortexm4::core::scb::SCB             @ 0xE000_ED00;
+        mpu         => cortexm4::core::mpu::MPU             @ 0xE000_ED90;  // reports no regions, see sysmpu
         systick     => cortexm4::core::systick::SysTick     @ 0xE000_E010;
+        dwt         => cortexm4::core::dwt::DWT             @ 0xE000_1000;
+        core_debug  => cortexm4::core::dwt::CoreDebug       @ 0xE000_EDF0;
+        itm         => cortexm4::core::itm::ITM             @ 0xE000_0000;
+        tpiu        => cortexm4::core::itm::TPIU            @ 0xE004_0000;
         fpu_coproc  => cortexm4::core::fpu::Access          @ 0xE000_ED88;  // enables full access
         fpu         => cortexm4::core::fpu::Unit            @ 0xE000_EF34;
+        sysmpu      => sysmpu::SYSMPU                       @ 0x4000_D000;  // guards the stack, see `guard_stack`
         sim         => sim::SIM                             @ 0x4004_7000;
+        mcg         => mcg::MCG                             @ 0x4006_4000;  // FEI until configured, see `use_pll`
+        osc         => mcg::OSC                             @ 0x4006_5000;
+        port_a      => port::PORT                           @ 0x4004_9000;  // gate with port::PortId::A.gate()
+        port_b      => port::PORT                           @ 0x4004_A000;
+        port_c      => port::PORT                           @ 0x4004_B000;
+        port_d      => port::PORT                           @ 0x4004_C000;
+        port_e      => port::PORT                           @ 0x4004_D000;
+        gpio_a      => gpio::GPIO                           @ 0x400F_F000;
+        gpio_b      => gpio::GPIO                           @ 0x400F_F040;
+        gpio_c      => gpio::GPIO                           @ 0x400F_F080;
+        gpio_d      => gpio::GPIO                           @ 0x400F_F0C0;
+        gpio_e      => gpio::GPIO                           @ 0x400F_F100;
+        uart0       => uart::UART                           @ 0x4006_A000;  // core clock
+        uart1       => uart::UART                           @ 0x4006_B000;  // core clock
+        uart2       => uart::UART                           @ 0x4006_C000;
+        uart3       => uart::UART                           @ 0x4006_D000;
+        uart4       => uart::UART                           @ 0x400E_A000;
+        uart5       => uart::UART                           @ 0x400E_B000;
     };
 );
 
@@ -84,8 +217,8 @@ impl ::traits::MCU for K64 {
     // ARM core
     //
 
-    /// Fetches the NVIC for this specific MCU.
-    fn get_nvic(&self) -> &::traits::NVIC { &self.nvic }
+    /// Fetches the NVIC for this specific MCU, driven through the Cortex-M4 core NVIC module.
+    fn get_nvic(&self) -> &::traits::NVIC { &self.core_nvic }
 
 
     //
@@ -94,11 +227,25 @@ impl ::traits::MCU for K64 {
 
     /// Get stack region information.
     fn stack_memory(&self) -> ::libc::memory::IOVec {
-        unsafe { ::libc::memory::IOVec{ptr: __stack_begin as *const u8, size: (__stack_end-__stack_begin) as usize} }
+        region(unsafe { &__stack_begin }, unsafe { &__stack_end })
     }
 
     /// Get heap region information.
     fn heap_memory(&self) -> ::libc::memory::IOVec {
-        unsafe { ::libc::memory::IOVec{ptr: __heap_begin as *const u8, size: (__heap_end-__heap_begin) as usize} }
+        region(unsafe { &__heap_begin }, unsafe { &__heap_end })
+    }
+}
+
+// the memory between two linker symbols, which mark it by their addresses rather than their values
+fn region(begin: &u32, end: &u32) -> ::libc::memory::IOVec {
+    let begin = begin as *const u32 as usize;
+    ::libc::memory::IOVec{ptr: begin as *const u8, size: (end as *const u32 as usize) - begin}
+}
+
+impl ::traits::Clocks for K64 {
+    /// Works the clock out from the current MCG, OSC and SIM settings, and the external clocks recorded in the
+    /// clocks module.
+    fn frequency(&self, clock: ::traits::Clock) -> Option<u32> {
+        clocks::ClockTree::read(&self.mcg, &self.osc, &self.sim).frequency(clock)
     }
 }
@@ -0,0 +1,464 @@
+extern crate core;
+use core::intrinsics::{volatile_load, volatile_store};
+
+use super::gates::Gate;
+use super::interrupts::Irq;
+
+
+ioreg!(
+    name => PORT;
+    doc_srcs => [
+        "http://www.nxp.com/files/microcontrollers/doc/ref_manual/K64P144M120SF5RM.pdf" // section 11
+    ];
+
+    constants => {
+        enabled         = 1;
+        disabled        = 0;
+    };
+
+    //
+    // pin control, one register per pin. the fields are encoded by `PinConfig`
+    //
+
+    0x0000 => pcr0 r32 rw {  0..31 => {  write_pcr0 => ();  }  };
+    0x0004 => pcr1 r32 rw {  0..31 => {  write_pcr1 => ();  }  };
+    0x0008 => pcr2 r32 rw {  0..31 => {  write_pcr2 => ();  }  };
+    0x000C => pcr3 r32 rw {  0..31 => {  write_pcr3 => ();  }  };
+    0x0010 => pcr4 r32 rw {  0..31 => {  write_pcr4 => ();  }  };
+    0x0014 => pcr5 r32 rw {  0..31 => {  write_pcr5 => ();  }  };
+    0x0018 => pcr6 r32 rw {  0..31 => {  write_pcr6 => ();  }  };
+    0x001C => pcr7 r32 rw {  0..31 => {  write_pcr7 => ();  }  };
+    0x0020 => pcr8 r32 rw {  0..31 => {  write_pcr8 => ();  }  };
+    0x0024 => pcr9 r32 rw {  0..31 => {  write_pcr9 => ();  }  };
+    0x0028 => pcr10 r32 rw {  0..31 => {  write_pcr10 => ();  }  };
+    0x002C => pcr11 r32 rw {  0..31 => {  write_pcr11 => ();  }  };
+    0x0030 => pcr12 r32 rw {  0..31 => {  write_pcr12 => ();  }  };
+    0x0034 => pcr13 r32 rw {  0..31 => {  write_pcr13 => ();  }  };
+    0x0038 => pcr14 r32 rw {  0..31 => {  write_pcr14 => ();  }  };
+    0x003C => pcr15 r32 rw {  0..31 => {  write_pcr15 => ();  }  };
+    0x0040 => pcr16 r32 rw {  0..31 => {  write_pcr16 => ();  }  };
+    0x0044 => pcr17 r32 rw {  0..31 => {  write_pcr17 => ();  }  };
+    0x0048 => pcr18 r32 rw {  0..31 => {  write_pcr18 => ();  }  };
+    0x004C => pcr19 r32 rw {  0..31 => {  write_pcr19 => ();  }  };
+    0x0050 => pcr20 r32 rw {  0..31 => {  write_pcr20 => ();  }  };
+    0x0054 => pcr21 r32 rw {  0..31 => {  write_pcr21 => ();  }  };
+    0x0058 => pcr22 r32 rw {  0..31 => {  write_pcr22 => ();  }  };
+    0x005C => pcr23 r32 rw {  0..31 => {  write_pcr23 => ();  }  };
+    0x0060 => pcr24 r32 rw {  0..31 => {  write_pcr24 => ();  }  };
+    0x0064 => pcr25 r32 rw {  0..31 => {  write_pcr25 => ();  }  };
+    0x0068 => pcr26 r32 rw {  0..31 => {  write_pcr26 => ();  }  };
+    0x006C => pcr27 r32 rw {  0..31 => {  write_pcr27 => ();  }  };
+    0x0070 => pcr28 r32 rw {  0..31 => {  write_pcr28 => ();  }  };
+    0x0074 => pcr29 r32 rw {  0..31 => {  write_pcr29 => ();  }  };
+    0x0078 => pcr30 r32 rw {  0..31 => {  write_pcr30 => ();  }  };
+    0x007C => pcr31 r32 rw {  0..31 => {  write_pcr31 => ();  }  };
+
+    //
+    // global pin control. the upper half selects the pins (0-15 or 16-31) to write the lower half into
+    //
+
+    0x0080 => global_control_low r32 wo {
+        0..31 => {  write_global_control_low => ();  }
+    };
+
+    0x0084 => global_control_high r32 wo {
+        0..31 => {  write_global_control_high => ();  }
+    };
+
+    //
+    // interrupt status, write 1 to clear
+    //
+
+    0x00A0 => interrupt_status r32 rw {
+        0..31 => {  clear_interrupts => ();  }
+    };
+
+    //
+    // digital filter
+    //
+
+    0x00C0 => digital_filter_enable r32 rw {
+        0..31 => {  set_digital_filters => ();  }
+    };
+
+    0x00C4 => digital_filter_clock r32 rw {
+        0 => { // CS
+            use_bus_clock_filter => [disabled];
+            use_lpo_clock_filter => [enabled];
+        }
+    };
+
+    0x00C8 => digital_filter_width r32 rw {
+        0..4 => {  set_digital_filter_width => ();  }
+    };
+);
+
+
+/// Number of pins (and PCRs) in a port. Not every pin is bonded out on every package.
+pub const PINS_PER_PORT: u8 = 32;
+
+/// One of the five ports.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum PortId {
+    A = 0,
+    B,
+    C,
+    D,
+    E,
+}
+impl PortId {
+    /// Get the pin detect interrupt of the port.
+    pub fn irq(&self) -> Irq {
+        match *self {
+            PortId::A => Irq::PortA,
+            PortId::B => Irq::PortB,
+            PortId::C => Irq::PortC,
+            PortId::D => Irq::PortD,
+            PortId::E => Irq::PortE,
+        }
+    }
+
+    /// Get the clock gate of the port. The PORT and GPIO registers both fault with it closed.
+    pub fn gate(&self) -> Gate {
+        match *self {
+            PortId::A => Gate::PortA,
+            PortId::B => Gate::PortB,
+            PortId::C => Gate::PortC,
+            PortId::D => Gate::PortD,
+            PortId::E => Gate::PortE,
+        }
+    }
+}
+
+
+//------------------------------------------------
+//
+// pin control
+//
+//------------------------------------------------
+
+// PCR fields
+const PS: u32 = 1 << 0;
+const PE: u32 = 1 << 1;
+const SRE: u32 = 1 << 2;
+const PFE: u32 = 1 << 4;
+const ODE: u32 = 1 << 5;
+const DSE: u32 = 1 << 6;
+const MUX_SHIFT: u32 = 8;
+const MUX_MASK: u32 = 0x7 << MUX_SHIFT;
+const LK: u32 = 1 << 15;
+const IRQC_SHIFT: u32 = 16;
+const IRQC_MASK: u32 = 0xF << IRQC_SHIFT;
+const ISF: u32 = 1 << 24;
+
+/// Pin function, from the signal multiplexing table (section 10.3.1 of the reference manual).
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Mux {
+    /// Pin disabled, or analog where the pin has one. The reset state of most pins.
+    Analog = 0,
+    Gpio = 1,
+    Alt2 = 2,
+    Alt3 = 3,
+    Alt4 = 4,
+    Alt5 = 5,
+    Alt6 = 6,
+    Alt7 = 7,
+}
+impl Mux {
+    fn from_bits(bits: u32) -> Mux {
+        match bits & 0x7 {
+            0 => Mux::Analog,
+            1 => Mux::Gpio,
+            2 => Mux::Alt2,
+            3 => Mux::Alt3,
+            4 => Mux::Alt4,
+            5 => Mux::Alt5,
+            6 => Mux::Alt6,
+            _ => Mux::Alt7,
+        }
+    }
+}
+
+/// Internal pull resistor.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+/// Output drive strength.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Drive {
+    Low,
+    High,
+}
+
+/// Pin detect condition, raising a DMA request or the port's interrupt.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Trigger {
+    Disabled = 0,
+    DmaRising = 1,
+    DmaFalling = 2,
+    DmaEither = 3,
+    /// Interrupt while the pin is low.
+    Low = 8,
+    Rising = 9,
+    Falling = 10,
+    Either = 11,
+    /// Interrupt while the pin is high.
+    High = 12,
+}
+impl Trigger {
+    fn from_bits(bits: u32) -> Trigger {
+        match bits & 0xF {
+            1 => Trigger::DmaRising,
+            2 => Trigger::DmaFalling,
+            3 => Trigger::DmaEither,
+            8 => Trigger::Low,
+            9 => Trigger::Rising,
+            10 => Trigger::Falling,
+            11 => Trigger::Either,
+            12 => Trigger::High,
+            _ => Trigger::Disabled,
+        }
+    }
+}
+
+/// Decoded pin control register.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct PinConfig {
+    pub mux: Mux,
+    pub pull: Pull,
+    pub drive: Drive,
+    pub slow_slew: bool,
+    pub passive_filter: bool,
+    pub open_drain: bool,
+    pub trigger: Trigger,
+    /// Fields are locked until the next reset.
+    pub locked: bool,
+}
+impl PinConfig {
+    /// Decode a PCR value. The interrupt flag is not part of the configuration, and is dropped.
+    pub fn from_pcr(pcr: u32) -> PinConfig {
+        PinConfig{
+            mux: Mux::from_bits((pcr & MUX_MASK) >> MUX_SHIFT),
+            pull: match (pcr & PE != 0, pcr & PS != 0) {
+                (false, _) => Pull::None,
+                (true, true) => Pull::Up,
+                (true, false) => Pull::Down,
+            },
+            drive: if pcr & DSE != 0 { Drive::High } else { Drive::Low },
+            slow_slew: pcr & SRE != 0,
+            passive_filter: pcr & PFE != 0,
+            open_drain: pcr & ODE != 0,
+            trigger: Trigger::from_bits((pcr & IRQC_MASK) >> IRQC_SHIFT),
+            locked: pcr & LK != 0,
+        }
+    }
+
+    /// Encode the configuration as a PCR value. The interrupt flag is left clear, so writing it does not clear a
+    /// pending pin.
+    pub fn to_pcr(&self) -> u32 {
+        let pull = match self.pull {
+            Pull::None => 0,
+            Pull::Up => PE | PS,
+            Pull::Down => PE,
+        };
+        ((self.mux as u32) << MUX_SHIFT)
+            | pull
+            | if self.drive == Drive::High { DSE } else { 0 }
+            | if self.slow_slew { SRE } else { 0 }
+            | if self.passive_filter { PFE } else { 0 }
+            | if self.open_drain { ODE } else { 0 }
+            | ((self.trigger as u32) << IRQC_SHIFT)
+            | if self.locked { LK } else { 0 }
+    }
+}
+
+/// Raw access to the pin control and interrupt status registers, so pins can be tested off target.
+pub trait PortRegisters {
+    /// Read the PCR of the pin.
+    fn read_pin_control(&self, pin: u8) -> u32;
+    /// Write the PCR of the pin.
+    fn write_pin_control(&self, pin: u8, value: u32);
+    /// Read the pending pin interrupts, one bit per pin.
+    fn pending_interrupts(&self) -> u32;
+    /// Clear the pending interrupts of the pins set in `mask`.
+    fn clear_pending_interrupts(&self, mask: u32);
+}
+
+impl PortRegisters for PORT {
+    fn read_pin_control(&self, pin: u8) -> u32 {
+        match pin {
+            0 => self.read_pcr0(),
+            1 => self.read_pcr1(),
+            2 => self.read_pcr2(),
+            3 => self.read_pcr3(),
+            4 => self.read_pcr4(),
+            5 => self.read_pcr5(),
+            6 => self.read_pcr6(),
+            7 => self.read_pcr7(),
+            8 => self.read_pcr8(),
+            9 => self.read_pcr9(),
+            10 => self.read_pcr10(),
+            11 => self.read_pcr11(),
+            12 => self.read_pcr12(),
+            13 => self.read_pcr13(),
+            14 => self.read_pcr14(),
+            15 => self.read_pcr15(),
+            16 => self.read_pcr16(),
+            17 => self.read_pcr17(),
+            18 => self.read_pcr18(),
+            19 => self.read_pcr19(),
+            20 => self.read_pcr20(),
+            21 => self.read_pcr21(),
+            22 => self.read_pcr22(),
+            23 => self.read_pcr23(),
+            24 => self.read_pcr24(),
+            25 => self.read_pcr25(),
+            26 => self.read_pcr26(),
+            27 => self.read_pcr27(),
+            28 => self.read_pcr28(),
+            29 => self.read_pcr29(),
+            30 => self.read_pcr30(),
+            31 => self.read_pcr31(),
+            _ => panic!("no pin {} in a port", pin),
+        }
+    }
+
+    fn write_pin_control(&self, pin: u8, value: u32) {
+        match pin {
+            0 => self.write_pcr0(value),
+            1 => self.write_pcr1(value),
+            2 => self.write_pcr2(value),
+            3 => self.write_pcr3(value),
+            4 => self.write_pcr4(value),
+            5 => self.write_pcr5(value),
+            6 => self.write_pcr6(value),
+            7 => self.write_pcr7(value),
+            8 => self.write_pcr8(value),
+            9 => self.write_pcr9(value),
+            10 => self.write_pcr10(value),
+            11 => self.write_pcr11(value),
+            12 => self.write_pcr12(value),
+            13 => self.write_pcr13(value),
+            14 => self.write_pcr14(value),
+            15 => self.write_pcr15(value),
+            16 => self.write_pcr16(value),
+            17 => self.write_pcr17(value),
+            18 => self.write_pcr18(value),
+            19 => self.write_pcr19(value),
+            20 => self.write_pcr20(value),
+            21 => self.write_pcr21(value),
+            22 => self.write_pcr22(value),
+            23 => self.write_pcr23(value),
+            24 => self.write_pcr24(value),
+            25 => self.write_pcr25(value),
+            26 => self.write_pcr26(value),
+            27 => self.write_pcr27(value),
+            28 => self.write_pcr28(value),
+            29 => self.write_pcr29(value),
+            30 => self.write_pcr30(value),
+            31 => self.write_pcr31(value),
+            _ => panic!("no pin {} in a port", pin),
+        }
+    }
+
+    fn pending_interrupts(&self) -> u32 { self.read_interrupt_status() }
+
+    fn clear_pending_interrupts(&self, mask: u32) { self.clear_interrupts(mask) }
+}
+
+/// Read the configuration of the pin.
+pub fn pin_config(regs: &PortRegisters, pin: u8) -> PinConfig {
+    PinConfig::from_pcr(regs.read_pin_control(pin))
+}
+
+/// Change the configuration of the pin with `f`, leaving its interrupt flag pending.
+pub fn modify_pin_config<F: FnOnce(&mut PinConfig)>(regs: &PortRegisters, pin: u8, f: F) {
+    let mut config = pin_config(regs, pin);
+    f(&mut config);
+    regs.write_pin_control(pin, config.to_pcr());
+}
+
+
+#[cfg(test)]
+mod test {
+    use core::cell::Cell;
+    use super::{PortRegisters, PinConfig, Mux, Pull, Drive, Trigger, PortId, modify_pin_config, ISF};
+    use super::super::gates::Gate;
+    use super::super::interrupts::Irq;
+
+    struct MockPort {
+        pcr: Cell<[u32; 32]>,
+    }
+    impl PortRegisters for MockPort {
+        fn read_pin_control(&self, pin: u8) -> u32 { self.pcr.get()[pin as usize] }
+
+        fn write_pin_control(&self, pin: u8, value: u32) {
+            let mut pcr = self.pcr.get();
+            pcr[pin as usize] = (pcr[pin as usize] & ISF & !(value & ISF)) | (value & !ISF);   // ISF is w1c
+            self.pcr.set(pcr);
+        }
+
+        fn pending_interrupts(&self) -> u32 { 0 }
+
+        fn clear_pending_interrupts(&self, _: u32) {}
+    }
+
+    #[test]
+    fn encodes_pcr() {
+        let config = PinConfig{
+            mux: Mux::Alt3,
+            pull: Pull::Up,
+            drive: Drive::High,
+            slow_slew: false,
+            passive_filter: true,
+            open_drain: true,
+            trigger: Trigger::Falling,
+            locked: false,
+        };
+        assert_eq!(0x000A_0373, config.to_pcr());
+        assert_eq!(config, PinConfig::from_pcr(0x000A_0373 | ISF));
+
+        let reset = PinConfig::from_pcr(0);
+        assert_eq!((Mux::Analog, Pull::None, Trigger::Disabled), (reset.mux, reset.pull, reset.trigger));
+        assert_eq!(Pull::Down, PinConfig::from_pcr(0b10).pull);
+        assert_eq!(Pull::None, PinConfig::from_pcr(0b01).pull);    // PS alone does nothing
+    }
+
+    #[test]
+    fn modify_keeps_flag() {
+        let port = MockPort{pcr: Cell::new([0; 32])};
+        port.pcr.set({ let mut pcr = [0; 32]; pcr[5] = ISF | 0x0100; pcr });
+
+        modify_pin_config(&port, 5, |c| c.trigger = Trigger::Either);
+        assert_eq!(ISF | 0x000B_0100, port.pcr.get()[5]);
+        assert_eq!(0, port.pcr.get()[4]);
+    }
+
+    #[test]
+    fn port_ids() {
+        assert_eq!(Irq::PortC, PortId::C.irq());
+        assert_eq!(Gate::PortE, PortId::E.gate());
+    }
+}
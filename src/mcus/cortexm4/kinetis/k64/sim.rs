@@ -381,322 +381,36 @@ ioreg!(
     // system identification
     //
 
-    0x1024 => system_id r32 ro { /* TODO: read portions of the register to get this info */ };
+    0x1024 => system_id r32 ro {};  // decoded by device::DeviceInfo
 
 
     //
     // clock gating
     //
+    // only written by gates::ClockGates, which counts the users of each gate. see gates::Gate::location for the
+    // bits.
+    //
 
     0x1028 => clock_gating_1 r32 rw {
-        // ... reserved ...
-
-        6 => {
-            i2c_2_disable_clock                 => [0];
-            i2c_2_enable_clock                  => [1];
-        }
-
-        // ... reserved ...
-
-        10 => {
-            uart_4_disable_clock                => [0];
-            uart_4_enable_clock                 => [1];
-        }
-
-        11 => {
-            uart_5_disable_clock                => [0];
-            uart_5_enable_clock                 => [1];
-        }
-
-        // ... reserved ...
+        0..31 => { write_clock_gating_1 => (); }
     };
-
     0x102C => clock_gating_2 r32 rw {
-        0 => {
-            ethernet_disable_clock              => [0];
-            ethernet_enable_clock               => [1];
-        }
-
-        // ... reserved ...
-
-        12 => {
-            dac_0_disable_clock                 => [0];
-            dac_0_enable_clock                  => [1];
-        }
-
-        13 => {
-            dac_1_disable_clock                 => [0];
-            dac_1_enable_clock                  => [1];
-        }
+        0..31 => { write_clock_gating_2 => (); }
     };
-
     0x1030 => clock_gating_3 r32 rw {
-        0 => {
-            rnga_disable_clock                  => [0];
-            rnga_enable_clock                   => [1];
-        }
-
-        // ... reserved ...
-
-        12 => {
-            spi_2_disable_clock                 => [0];
-            spi_2_enable_clock                  => [1];
-        }
-
-        // ... reserved ...
-
-        17 => {
-            sdhc_disable_clock                  => [0];
-            sdhc_enable_clock                   => [1];
-        }
-
-        // ... reserved ...
-
-        24 => {
-            ftm_2_disable_clock                 => [0];
-            ftm_2_enable_clock                  => [1];
-        }
-
-        25 => {
-            ftm_3_disable_clock                 => [0];
-            ftm_3_enable_clock                  => [1];
-        }
-
-        // ... reserved ...
-
-        27 => {
-            adc_1_disable_clock                 => [0];
-            adc_1_enable_clock                  => [1];
-        }
-
-        // ... reserved ...
+        0..31 => { write_clock_gating_3 => (); }
     };
-
     0x1034 => clock_gating_4 r32 rw {
-        // ... reserved ...
-
-        1 => {
-            ewm_disable_clock                   => [0];
-            ewm_enable_clock                    => [1];
-        }
-
-        2 => {
-            cmt_disable_clock                   => [0];
-            cmt_enable_clock                    => [1];
-        }
-
-        // ... reserved ...
-
-        6 => {
-            i2c_0_disable_clock                 => [0];
-            i2c_0_enable_clock                  => [1];
-        }
-
-        7 => {
-            i2c_1_disable_clock                 => [0];
-            i2c_1_enable_clock                  => [1];
-        }
-
-        // ... reserved ...
-
-        10 => {
-            uart_0_disable_clock                => [0];
-            uart_0_enable_clock                 => [1];
-        }
-
-        11 => {
-            uart_1_disable_clock                => [0];
-            uart_1_enable_clock                 => [1];
-        }
-
-        12 => {
-            uart_2_disable_clock                => [0];
-            uart_2_enable_clock                 => [1];
-        }
-
-        13 => {
-            uart_3_disable_clock                => [0];
-            uart_3_enable_clock                 => [1];
-        }
-
-        // ... reserved ...
-
-        18 => {
-            usb_disable_clock                   => [0];
-            usb_enable_clock                    => [1];
-        }
-
-        19 => {
-            cmp_disable_clock                   => [0];
-            cmp_enable_clock                    => [1];
-        }
-
-        20 => {
-            vref_disable_clock                  => [0];
-            vref_enable_clock                   => [1];
-        }
-
-        // ... reserved ...
+        0..31 => { write_clock_gating_4 => (); }
     };
-
     0x1038 => clock_gating_5 r32 rw {
-        0 => {
-            disable_low_power_timer_access      => [0];
-            enable_low_power_timer_access       => [1];
-        }
-
-        // ... reserved ...
-
-        9 => {
-            port_a_disable_clock                => [0];
-            port_a_enable_clock                 => [1];
-        }
-
-        10 => {
-            port_b_disable_clock                => [0];
-            port_b_enable_clock                 => [1];
-        }
-
-        11 => {
-            port_c_disable_clock                => [0];
-            port_c_enable_clock                 => [1];
-        }
-
-        12 => {
-            port_d_disable_clock                => [0];
-            port_d_enable_clock                 => [1];
-        }
-
-        13 => {
-            port_e_disable_clock                => [0];
-            port_e_enable_clock                 => [1];
-        }
-
-        // ... reserved ...
+        0..31 => { write_clock_gating_5 => (); }
     };
-
     0x103C => clock_gating_6 r32 rw {
-        0 => {
-            flash_disable_clock                 => [0];
-            flash_enable_clock                  => [1];
-        }
-
-        1 => {
-            dma_mux_disable_clock               => [0];
-            dma_mux_enable_clock                => [1];
-        }
-
-        // ... reserved ...
-
-        4 => {
-            can_0_disable_clock                 => [0];
-            can_0_enable_clock                  => [1];
-        }
-
-        // ... reserved ...
-
-        // TODO: bit 9 claims for RNGA, but we already have one of those.... and the table block is emtpy?
-        //       error in the data sheet?
-
-        // ... reserved ...
-
-        12 => {
-            spi_0_disable_clock                 => [0];
-            spi_0_enable_clock                  => [1];
-        }
-
-        13 => {
-            spi_1_disable_clock                 => [0];
-            spi_1_enable_clock                  => [1];
-        }
-
-        // ... reserved ...
-
-        15 => {
-            i2s_disable_clock                   => [0];
-            i2s_enable_clock                    => [1];
-        }
-
-        // ... reserved ...
-
-        18 => {
-            crc_disable_clock                   => [0];
-            crc_enable_clock                    => [1];
-        }
-
-        // ... reserved ...
-
-        21 => {
-            usb_dcd_disable_clock               => [0];
-            usb_dcd_enable_clock                => [1];
-        }
-
-        22 => {
-            pdb_disable_clock                   => [0];
-            pdb_enable_clock                    => [1];
-        }
-
-        23 => {
-            pit_disable_clock                   => [0];
-            pit_enable_clock                    => [1];
-        }
-
-        24 => {
-            ftm_0_disable_clock                 => [0];
-            ftm_0_enable_clock                  => [1];
-        }
-
-        25 => {
-            ftm_1_disable_clock                 => [0];
-            ftm_1_enable_clock                  => [1];
-        }
-
-        /*  TODO: yet another duplicated clock bit -- needs both? datasheet errata?
-        26 => {
-            ftm_2_disable_clock                 => [0];
-            ftm_2_enable_clock                  => [1];
-        }
-        */
-
-        27 => {
-            adc_0_disable_clock                 => [0];
-            adc_0_enable_clock                  => [1];
-        }
-
-        // ... reserved ...
-
-        29 => {
-            rtc_disable_access                  => [0];
-            rtc_enable_access                   => [1];
-        }
-
-        // ... reserved ...
-
-        /*  TODO: another duplicate clock gate... already in clock_gating_2. needs both!?
-        31 => {
-            dac_0_disable_clock                 => [0];
-            dac_0_enable_clock                  => [1];
-        }
-        */
+        0..31 => { write_clock_gating_6 => (); }
     };
-
     0x1040 => clock_gating_7 r32 rw {
-        0 => {
-            flexbus_disable_clock               => [0];
-            flexbus_enable_clock                => [0];
-        }
-
-        1 => {
-            dma_disable_clock                   => [0];
-            dma_enable_clock                    => [0];
-        }
-
-        2 => {
-            mpu_disable_clock                   => [0];
-            mpu_enable_clock                    => [0];
-        }
-
-        // ... reserved ...
+        0..31 => { write_clock_gating_7 => (); }
     };
 
 
@@ -704,7 +418,7 @@ ioreg!(
     // clock division
     //
 
-    0x1040 => clock_divider_1 r32 rw {
+    0x1044 => clock_divider_1 r32 rw {
         // ... reserved ...
 
         16..19 => { // OUTDIV4
@@ -724,6 +438,7 @@ ioreg!(
             set_flash_clock_divide_14           => [0b1101];
             set_flash_clock_divide_15           => [0b1110];
             set_flash_clock_divide_16           => [0b1111];
+            set_flash_clock_divider             => ();  // divide by value + 1
         }
 
         20..23 => { // OUTDIV3
@@ -743,6 +458,7 @@ ioreg!(
             set_flexbus_clock_divide_14         => [0b1101];
             set_flexbus_clock_divide_15         => [0b1110];
             set_flexbus_clock_divide_16         => [0b1111];
+            set_flexbus_clock_divider           => ();  // divide by value + 1
         }
 
         24..27 => { // OUTDIV2
@@ -762,6 +478,7 @@ ioreg!(
             set_bus_clock_divide_14             => [0b1101];
             set_bus_clock_divide_15             => [0b1110];
             set_bus_clock_divide_16             => [0b1111];
+            set_bus_clock_divider               => ();  // divide by value + 1
         }
 
         28..31 => { // OUTDIV1
@@ -781,6 +498,7 @@ ioreg!(
             set_system_clock_divide_14          => [0b1101];
             set_system_clock_divide_15          => [0b1110];
             set_system_clock_divide_16          => [0b1111];
+            set_system_clock_divider            => ();  // divide by value + 1
         }
     };
 
@@ -0,0 +1,224 @@
+extern crate core;
+use core::intrinsics::{volatile_load, volatile_store};
+
+use ::mcus::cortexm4::core::mpu::{self, Region};
+use ::os::error::MpuError;
+use ::os::sync;
+
+// CESR
+const CESR_VLD: u32 = 1 << 0;
+
+// RGDn_WORD2 and RGDAACn, bus master 0 (the core)
+const M0_MASK: u32 = 0x3F;
+const M0_FULL: u32 = 0b00_111;      // supervisor r/w/x, user r/w/x
+const M0_NONE: u32 = 0b11_000;      // supervisor as user, user none
+
+// RGDn_WORD3
+const WORD3_VLD: u32 = 1 << 0;
+
+/// Descriptors the stack guard programs. RGD0 always spans the whole address space.
+const BELOW_GUARD: u8 = 1;
+const ABOVE_GUARD: u8 = 2;
+
+/// Kinetis system memory protection unit registers.
+///
+/// The SYSMPU checks every bus master's accesses against region descriptors, and unlike the core MPU an access is
+/// allowed if any region covering it allows it. Only the descriptors used by `guard_stack()` are described.
+ioreg!(
+    name => SYSMPU;
+    doc_srcs => [
+        "http://www.nxp.com/files/microcontrollers/doc/ref_manual/K64P144M120SF5RM.pdf" // section 18
+    ];
+
+    0x0000 => cesr r32 rw {
+        0..31 => { write_cesr => (); }  // the error bits are write 1 to clear
+    };
+
+    //
+    // region descriptors
+    //
+
+    0x0410 => rgd1_word0 r32 rw {
+        0..31 => { write_rgd1_word0 => (); }
+    };
+    0x0414 => rgd1_word1 r32 rw {
+        0..31 => { write_rgd1_word1 => (); }
+    };
+    0x0418 => rgd1_word2 r32 rw {
+        0..31 => { write_rgd1_word2 => (); }
+    };
+    0x041C => rgd1_word3 r32 rw {
+        0..31 => { write_rgd1_word3 => (); }
+    };
+
+    0x0420 => rgd2_word0 r32 rw {
+        0..31 => { write_rgd2_word0 => (); }
+    };
+    0x0424 => rgd2_word1 r32 rw {
+        0..31 => { write_rgd2_word1 => (); }
+    };
+    0x0428 => rgd2_word2 r32 rw {
+        0..31 => { write_rgd2_word2 => (); }
+    };
+    0x042C => rgd2_word3 r32 rw {
+        0..31 => { write_rgd2_word3 => (); }
+    };
+
+    //
+    // alternate access control, writing WORD2 without invalidating the region
+    //
+
+    0x0800 => rgdaac0 r32 rw {
+        0..31 => { write_rgdaac0 => (); }
+    };
+);
+
+/// Raw access to the SYSMPU registers, so the stack guard can be tested off target.
+pub trait SysMpuRegisters {
+    /// Read CESR.
+    fn status(&self) -> u32;
+    /// Set or clear CESR.VLD, enabling or disabling every region check.
+    fn set_enabled(&self, enabled: bool);
+    /// Write a descriptor's start and end addresses (both inclusive, at 32 byte granularity) and access rights, then
+    /// mark it valid.
+    fn write_descriptor(&self, n: u8, start: u32, end: u32, rights: u32);
+    /// Read the access rights of RGD0, which spans the whole address space.
+    fn default_rights(&self) -> u32;
+    /// Write the access rights of RGD0.
+    fn set_default_rights(&self, rights: u32);
+}
+
+impl SysMpuRegisters for SYSMPU {
+    fn status(&self) -> u32 { self.read_cesr() }
+
+    fn set_enabled(&self, enabled: bool) {
+        self.write_cesr(if enabled { CESR_VLD } else { 0 });
+        sync::data_barrier();
+    }
+
+    // writing WORD0-2 invalidates the descriptor until WORD3 is written
+    fn write_descriptor(&self, n: u8, start: u32, end: u32, rights: u32) {
+        match n {
+            BELOW_GUARD => {
+                self.write_rgd1_word0(start);
+                self.write_rgd1_word1(end);
+                self.write_rgd1_word2(rights);
+                self.write_rgd1_word3(WORD3_VLD);
+            }
+            ABOVE_GUARD => {
+                self.write_rgd2_word0(start);
+                self.write_rgd2_word1(end);
+                self.write_rgd2_word2(rights);
+                self.write_rgd2_word3(WORD3_VLD);
+            }
+            _ => {}
+        }
+    }
+
+    fn default_rights(&self) -> u32 { self.read_rgdaac0() }
+
+    fn set_default_rights(&self, rights: u32) {
+        self.write_rgdaac0(rights);
+        sync::data_barrier();
+    }
+}
+
+/// Get the number of region descriptors the SYSMPU implements.
+pub fn descriptors<R: SysMpuRegisters>(regs: &R) -> u8 {
+    match (regs.status() >> 8) & 0xF {
+        0 => 8,
+        1 => 12,
+        _ => 16,
+    }
+}
+
+/// Place a no-access guard of `mpu::STACK_GUARD_SIZE` bytes at the bottom of the MCU's stack, and enable the SYSMPU.
+///
+/// An access is allowed by any region that allows it, so a no-access region cannot guard anything by itself.
+/// Instead the core's rights are taken out of RGD0 and given back by two descriptors (1 and 2) covering everything
+/// below and above the guard. Other bus masters, such as DMA, keep their rights through RGD0. Accesses to the guard
+/// fault as a precise BusFault.
+///
+/// __NOTE:__ the SYSMPU's clock gate (`gates::Gate::Mpu`) is open out of reset, and must stay open.
+pub fn guard_stack<R: SysMpuRegisters>(regs: &R, mcu: &::traits::MCU) -> Result<Region, MpuError> {
+    if descriptors(regs) <= ABOVE_GUARD { return Err(MpuError::Unsupported); }
+
+    let guard = try!(mpu::stack_guard(mcu.stack_memory()));
+    if guard.base == 0 { return Err(MpuError::NoRoom); }
+    let above = guard.base.wrapping_add(guard.size);
+    if above == 0 { return Err(MpuError::NoRoom); }
+
+    // the core keeps its access throughout: the new descriptors are valid before RGD0 stops covering it
+    sync::critical(|| {
+        regs.write_descriptor(BELOW_GUARD, 0, guard.base - 1, M0_FULL);
+        regs.write_descriptor(ABOVE_GUARD, above, 0xFFFF_FFFF, M0_FULL);
+        regs.set_default_rights((regs.default_rights() & !M0_MASK) | M0_NONE);
+        regs.set_enabled(true);
+    });
+    Ok(guard)
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate core;
+    use self::core::cell::Cell;
+    use ::libc::memory::IOVec;
+    use ::os::error::MpuError;
+    use ::test_support::FakeMcu;
+    use super::{SysMpuRegisters, descriptors, guard_stack};
+
+    /// Records the descriptors written, reporting `nrgd` in CESR.
+    struct MockSysMpu {
+        nrgd: u32,
+        enabled: Cell<bool>,
+        rgd0: Cell<u32>,
+        written: Cell<[(u32, u32, u32); 3]>,
+    }
+    impl MockSysMpu {
+        fn new(nrgd: u32) -> MockSysMpu {
+            MockSysMpu{nrgd: nrgd, enabled: Cell::new(false), rgd0: Cell::new(0x0061_F7DF),
+                       written: Cell::new([(0, 0, 0); 3])}
+        }
+    }
+    impl SysMpuRegisters for MockSysMpu {
+        fn status(&self) -> u32 { (self.nrgd << 8) | (self.enabled.get() as u32) }
+        fn set_enabled(&self, enabled: bool) { self.enabled.set(enabled); }
+        fn write_descriptor(&self, n: u8, start: u32, end: u32, rights: u32) {
+            let mut written = self.written.get();
+            written[n as usize] = (start, end, rights);
+            self.written.set(written);
+        }
+        fn default_rights(&self) -> u32 { self.rgd0.get() }
+        fn set_default_rights(&self, rights: u32) { self.rgd0.set(rights); }
+    }
+
+    #[test]
+    fn counts_descriptors() {
+        assert_eq!(8, descriptors(&MockSysMpu::new(0)));
+        assert_eq!(12, descriptors(&MockSysMpu::new(1)));
+        assert_eq!(16, descriptors(&MockSysMpu::new(2)));
+    }
+
+    #[test]
+    fn core_is_kept_out_of_the_guard() {
+        let sysmpu = MockSysMpu::new(1);
+        let guard = guard_stack(&sysmpu, &FakeMcu::new(IOVec{ptr: 0x1FFF_8004 as *const u8, size: 0x4000})).unwrap();
+        assert_eq!(0x1FFF_8100, guard.base);
+
+        let written = sysmpu.written.get();
+        assert_eq!((0, 0x1FFF_80FF, 0b00_111), written[1]);
+        assert_eq!((0x1FFF_8200, 0xFFFF_FFFF, 0b00_111), written[2]);
+        assert_eq!(0x0061_F7D8, sysmpu.rgd0.get(), "only the core's rights in RGD0 change");
+        assert!(sysmpu.enabled.get());
+    }
+
+    #[test]
+    fn no_room() {
+        let sysmpu = MockSysMpu::new(1);
+        let stack = IOVec{ptr: 0 as *const u8, size: 0x4000};
+        assert_eq!(Err(MpuError::NoRoom), guard_stack(&sysmpu, &FakeMcu::new(stack)));
+        assert_eq!(false, sysmpu.enabled.get());
+        assert_eq!(0x0061_F7DF, sysmpu.rgd0.get());
+    }
+}
@@ -0,0 +1,798 @@
+extern crate core;
+use core::cell::{Cell, UnsafeCell};
+use core::fmt;
+use core::intrinsics::volatile_store;
+
+use ::libc::structures::RingBuffer;
+use ::os::error::SerialError;
+use ::os::sync;
+use ::traits::{Serial, SerialMode};
+
+use super::gates::{Gate, GateToken};
+use super::interrupts::{self, Irq};
+
+
+ioreg!(
+    name => UART;
+    doc_srcs => [
+        "http://www.nxp.com/files/microcontrollers/doc/ref_manual/K64P144M120SF5RM.pdf" // section 52
+    ];
+
+    constants => {
+        enabled         = 1;
+        disabled        = 0;
+    };
+
+    //
+    // baud rate. SBR is only updated once BDL is written, so BDH goes first
+    //
+
+    0x0000 => baud_rate_high r8 rw {
+        0..4 => {  set_baud_high => ();  }
+
+        5 => { // SBNS
+            use_one_stop_bit => [disabled];
+            use_two_stop_bits => [enabled];
+        }
+    };
+
+    0x0001 => baud_rate_low r8 rw {
+        0..7 => {  set_baud_low => ();  }
+    };
+
+    //
+    // control
+    //
+
+    0x0002 => control_1 r8 rw {
+        0 => { // PT
+            use_even_parity => [disabled];
+            use_odd_parity => [enabled];
+        }
+
+        1 => { // PE
+            disable_parity => [disabled];
+            enable_parity => [enabled];
+        }
+
+        4 => { // M
+            use_8_bit_data => [disabled];
+            use_9_bit_data => [enabled];      // 8 data bits and the parity bit
+        }
+    };
+
+    0x0003 => control_2 r8 rw {
+        // TIE 7, TCIE 6, RIE 5, ILIE 4, TE 3, RE 2, RWU 1, SBK 0. written whole, see the C2_ constants below
+        0..7 => {  write_control_2 => ();  }
+    };
+
+    0x0004 => status_1 r8 ro {};    // reading S1 then D clears the flags
+
+    0x0005 => status_2 r8 rw {};
+
+    0x0006 => control_3 r8 rw {};   // error interrupts, unused as the rx/tx interrupt sees the same flags
+
+    // the data register is written directly, see write_byte. a read-modify-write would pop the receive FIFO
+    0x0007 => data r8 ro {};
+
+    // ... match address and DMA select ...
+
+    0x000A => control_4 r8 rw {
+        0..4 => {  set_baud_fine_adjust => ();  }   // BRFA, in 32nds of SBR
+    };
+
+    0x000C => extended_data r8 ro {};   // noise and parity flags of the byte at the head of the receive FIFO
+
+    //
+    // FIFOs
+    //
+
+    0x0010 => fifo_parameters r8 rw {
+        3 => { // RXFE
+            disable_rx_fifo => [disabled];
+            enable_rx_fifo => [enabled];
+        }
+
+        7 => { // TXFE
+            disable_tx_fifo => [disabled];
+            enable_tx_fifo => [enabled];
+        }
+    };
+
+    0x0011 => fifo_control r8 rw {
+        6 => {  flush_rx_fifo => [enabled];  }
+        7 => {  flush_tx_fifo => [enabled];  }
+    };
+
+    0x0012 => fifo_status r8 rw {};
+
+    0x0013 => tx_watermark r8 rw {
+        0..7 => {  set_tx_watermark => ();  }
+    };
+
+    0x0014 => tx_count r8 ro {};
+
+    0x0015 => rx_watermark r8 rw {
+        0..7 => {  set_rx_watermark => ();  }
+    };
+
+    0x0016 => rx_count r8 ro {};
+);
+
+
+/// One of the six UARTs.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum UartId {
+    Uart0 = 0,
+    Uart1,
+    Uart2,
+    Uart3,
+    Uart4,
+    Uart5,
+}
+impl UartId {
+    /// Get the clock the UART's baud rate is divided from. UART0 and UART1 run from the core clock, the rest from
+    /// the bus clock.
+    pub fn clock(&self) -> ::traits::Clock {
+        match *self {
+            UartId::Uart0 | UartId::Uart1 => ::traits::Clock::Core,
+            _ => ::traits::Clock::Bus,
+        }
+    }
+
+    /// Get the clock gate of the UART.
+    pub fn gate(&self) -> Gate {
+        match *self {
+            UartId::Uart0 => Gate::Uart0,
+            UartId::Uart1 => Gate::Uart1,
+            UartId::Uart2 => Gate::Uart2,
+            UartId::Uart3 => Gate::Uart3,
+            UartId::Uart4 => Gate::Uart4,
+            UartId::Uart5 => Gate::Uart5,
+        }
+    }
+
+    /// Get the receive/transmit interrupt of the UART.
+    pub fn irq(&self) -> Irq {
+        match *self {
+            UartId::Uart0 => Irq::Uart0RxTx,
+            UartId::Uart1 => Irq::Uart1RxTx,
+            UartId::Uart2 => Irq::Uart2RxTx,
+            UartId::Uart3 => Irq::Uart3RxTx,
+            UartId::Uart4 => Irq::Uart4RxTx,
+            UartId::Uart5 => Irq::Uart5RxTx,
+        }
+    }
+}
+
+
+//------------------------------------------------
+//
+// line settings
+//
+//------------------------------------------------
+
+/// Largest value of the 13 bit SBR.
+pub const MAX_SBR: u16 = 0x1FFF;
+
+/// Baud rate divisor: the clock is divided by `16 * (sbr + brfa/32)`.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct BaudDivisor {
+    pub sbr: u16,
+    /// Fine adjust, in 32nds.
+    pub brfa: u8,
+}
+impl BaudDivisor {
+    /// Get the divisor closest to `baud` from a `clock_hz` clock.
+    pub fn for_baud(clock_hz: u32, baud: u32) -> Result<BaudDivisor, SerialError> {
+        if baud == 0 { return Err(SerialError::BadBaudRate); }
+
+        // in 32nds: clock / (16 * baud) * 32, rounded
+        let baud = baud as u64;
+        let divisor = (clock_hz as u64 * 2 + baud / 2) / baud;
+        let sbr = divisor / 32;
+        if sbr == 0 || sbr > MAX_SBR as u64 { return Err(SerialError::BadBaudRate); }
+        Ok(BaudDivisor{sbr: sbr as u16, brfa: (divisor % 32) as u8})
+    }
+
+    /// Get the baud rate the divisor gives from a `clock_hz` clock.
+    pub fn baud(&self, clock_hz: u32) -> u32 {
+        ((clock_hz as u64 * 2) / (self.sbr as u64 * 32 + self.brfa as u64)) as u32
+    }
+}
+
+/// Parity bit sent after the 8 data bits.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// Number of stop bits.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// Line settings. Data is always 8 bits.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Config {
+    pub baud: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+impl Config {
+    /// 8N1 at the given baud rate.
+    pub fn new(baud: u32) -> Config {
+        Config{baud: baud, parity: Parity::None, stop_bits: StopBits::One}
+    }
+}
+
+// C2
+const C2_TIE: u8 = 1 << 7;
+const C2_RIE: u8 = 1 << 5;
+const C2_TE: u8 = 1 << 3;
+const C2_RE: u8 = 1 << 2;
+
+// S1
+const S1_TC: u8 = 1 << 6;
+const S1_OR: u8 = 1 << 3;
+const S1_FE: u8 = 1 << 1;
+
+// ED
+const ED_NOISY: u8 = 1 << 7;
+const ED_PARITYE: u8 = 1 << 6;
+
+// S1 also has NF and PF, but with the FIFO enabled they may belong to any queued byte. ED has them per byte
+fn line_error(status: u8) -> Option<SerialError> {
+    if status & S1_OR != 0 {
+        Some(SerialError::Overrun)
+    } else if status & S1_FE != 0 {
+        Some(SerialError::Framing)
+    } else {
+        None
+    }
+}
+
+// most severe first, as one byte can raise both
+fn byte_error(flags: u8) -> Option<SerialError> {
+    if flags & ED_PARITYE != 0 {
+        Some(SerialError::Parity)
+    } else if flags & ED_NOISY != 0 {
+        Some(SerialError::Noise)
+    } else {
+        None
+    }
+}
+
+// PFIFO TXFIFOSIZE/RXFIFOSIZE
+fn fifo_depth(bits: u8) -> u8 {
+    match bits & 0x7 {
+        1 => 4,
+        2 => 8,
+        3 => 16,
+        4 => 32,
+        5 => 64,
+        6 => 128,
+        _ => 1,
+    }
+}
+
+/// Raw access to a UART, so the driver can be tested off target.
+pub trait UartRegisters {
+    /// Write the baud rate divisor and frame format. The transmitter and receiver must be disabled.
+    fn set_line(&self, divisor: BaudDivisor, parity: Parity, stop_bits: StopBits);
+    /// Enable and flush the FIFOs, returning their depths as (transmit, receive).
+    fn enable_fifos(&self) -> (u8, u8);
+    /// Read C2.
+    fn control(&self) -> u8;
+    /// Write C2.
+    fn set_control(&self, value: u8);
+    /// Read S1. Reading the data register next clears its flags.
+    fn status(&self) -> u8;
+    /// Read ED, the noise and parity flags of the byte at the head of the receive FIFO.
+    fn byte_errors(&self) -> u8;
+    /// Get the number of bytes waiting in the transmit FIFO.
+    fn tx_count(&self) -> u8;
+    /// Get the number of bytes waiting in the receive FIFO.
+    fn rx_count(&self) -> u8;
+    /// Pop a byte from the receive FIFO.
+    fn read_byte(&self) -> u8;
+    /// Push a byte into the transmit FIFO.
+    fn write_byte(&self, byte: u8);
+}
+
+impl UartRegisters for UART {
+    fn set_line(&self, divisor: BaudDivisor, parity: Parity, stop_bits: StopBits) {
+        match stop_bits {
+            StopBits::One => self.use_one_stop_bit(),
+            StopBits::Two => self.use_two_stop_bits(),
+        }
+        self.set_baud_high((divisor.sbr >> 8) as u8);
+        self.set_baud_low(divisor.sbr as u8);
+        self.set_baud_fine_adjust(divisor.brfa);
+
+        match parity {
+            Parity::None => {
+                self.use_8_bit_data();
+                self.disable_parity();
+            },
+            Parity::Even => {
+                self.use_9_bit_data();
+                self.enable_parity();
+                self.use_even_parity();
+            },
+            Parity::Odd => {
+                self.use_9_bit_data();
+                self.enable_parity();
+                self.use_odd_parity();
+            },
+        }
+    }
+
+    fn enable_fifos(&self) -> (u8, u8) {
+        self.enable_tx_fifo();
+        self.enable_rx_fifo();
+        self.flush_tx_fifo();
+        self.flush_rx_fifo();
+        self.set_rx_watermark(1);
+        let params = self.read_fifo_parameters();
+        (fifo_depth(params >> 4), fifo_depth(params))
+    }
+
+    fn control(&self) -> u8 { self.read_control_2() }
+    fn set_control(&self, value: u8) { self.write_control_2(value) }
+    fn status(&self) -> u8 { self.read_status_1() }
+    fn byte_errors(&self) -> u8 { self.read_extended_data() }
+    fn tx_count(&self) -> u8 { self.read_tx_count() }
+    fn rx_count(&self) -> u8 { self.read_rx_count() }
+    fn read_byte(&self) -> u8 { self.read_data() }
+
+    fn write_byte(&self, byte: u8) {
+        unsafe { volatile_store((self as *const UART as *mut u8).offset(0x7), byte); }
+    }
+}
+
+
+//------------------------------------------------
+//
+// driver
+//
+//------------------------------------------------
+
+/// A UART configured for 8 data bits, moving bytes in `SerialMode::Blocking` until given queues with
+/// `use_interrupts`.
+///
+/// Holds the UART's `GateToken`, so the clock gate stays open while the driver exists.
+pub struct Uart<'a> {
+    id: UartId,
+    regs: &'a UartRegisters,
+    _gate: GateToken<'a>,
+    baud: u32,
+    tx_depth: u8,
+    mode: Cell<SerialMode>,
+    tx: UnsafeCell<Option<RingBuffer<u8>>>,
+    rx: UnsafeCell<Option<RingBuffer<u8>>>,
+    error: Cell<Option<SerialError>>,
+}
+impl<'a> Uart<'a> {
+    /// Configure the UART and enable its transmitter and receiver, with the baud rate divided from the UART's
+    /// clock as `clocks` reports it. The token must be for the UART's gate.
+    pub fn new(id: UartId, regs: &'a UartRegisters, gate: GateToken<'a>, clocks: &::traits::Clocks, config: Config)
+            -> Result<Uart<'a>, SerialError> {
+        if gate.gate() != id.gate() { return Err(SerialError::WrongGate); }
+        let clock_hz = match clocks.frequency(id.clock()) {
+            Some(hz) if hz > 0 => hz,
+            _ => return Err(SerialError::NoClock),
+        };
+        let divisor = try!(BaudDivisor::for_baud(clock_hz, config.baud));
+
+        regs.set_control(0);
+        regs.set_line(divisor, config.parity, config.stop_bits);
+        let (tx_depth, _) = regs.enable_fifos();
+        regs.set_control(C2_TE | C2_RE);
+
+        Ok(Uart{
+            id: id,
+            regs: regs,
+            _gate: gate,
+            baud: divisor.baud(clock_hz),
+            tx_depth: tx_depth,
+            mode: Cell::new(SerialMode::Blocking),
+            tx: UnsafeCell::new(None),
+            rx: UnsafeCell::new(None),
+            error: Cell::new(None),
+        })
+    }
+
+    /// Get which UART this is.
+    pub fn id(&self) -> UartId { self.id }
+
+    /// Get the baud rate actually reached, which is off the one asked for by the rounding of the divisor.
+    pub fn baud(&self) -> u32 { self.baud }
+
+    /// Switch to `SerialMode::Interrupt`, queueing bytes in `tx` and `rx`, and enable the UART's interrupt.
+    ///
+    /// The interrupt must reach `handle_interrupt`: either `set_handler` for the default handler, or call it from an
+    /// `isr_uartN_rx_tx` of your own.
+    pub fn use_interrupts(&self, tx: RingBuffer<u8>, rx: RingBuffer<u8>, nvic: &::traits::NVIC) {
+        self.regs.set_control(C2_TE | C2_RE);
+        unsafe {
+            *self.tx.get() = Some(tx);
+            *self.rx.get() = Some(rx);
+        }
+        self.mode.set(SerialMode::Interrupt);
+        self.regs.set_control(C2_TE | C2_RE | C2_RIE);
+        nvic.enable_irq(self.id.irq().into());
+    }
+
+    /// Move bytes between the FIFOs and the queues. Called from the UART's receive/transmit interrupt.
+    pub fn handle_interrupt(&self) {
+        let (tx, rx) = match self.queues() {
+            Some(queues) => queues,
+            None => return,
+        };
+
+        while self.regs.rx_count() > 0 {
+            match self.receive() {
+                Ok(byte) => if rx.is_full() { self.latch(SerialError::Overrun); } else { rx.push(byte); },
+                Err(err) => self.latch(err),
+            }
+        }
+
+        if self.regs.control() & C2_TIE != 0 {
+            while self.regs.tx_count() < self.tx_depth {
+                match tx.pop() {
+                    Ok(byte) => self.regs.write_byte(byte),
+                    Err(_) => {
+                        self.regs.set_control(self.regs.control() & !C2_TIE);
+                        break;
+                    },
+                }
+            }
+        }
+    }
+
+    // pops the byte at the head of the receive FIFO, which is dropped if ED flags it. overrun and framing errors
+    // are latched and the byte kept, as S1 cannot say which byte they belong to. reading S1 then D clears them
+    fn receive(&self) -> Result<u8, SerialError> {
+        let flags = self.regs.byte_errors();
+        let status = self.regs.status();
+        let byte = self.regs.read_byte();
+        if let Some(err) = line_error(status) { self.latch(err); }
+        match byte_error(flags) {
+            Some(err) => Err(err),
+            None => Ok(byte),
+        }
+    }
+
+    // keeps the first error until it is read
+    fn latch(&self, err: SerialError) {
+        if self.error.get().is_none() { self.error.set(Some(err)); }
+    }
+
+    fn take_error(&self) -> Option<SerialError> {
+        let err = self.error.get();
+        self.error.set(None);
+        err
+    }
+
+    fn queues(&self) -> Option<(&mut RingBuffer<u8>, &mut RingBuffer<u8>)> {
+        unsafe {
+            match (&mut *self.tx.get(), &mut *self.rx.get()) {
+                (&mut Some(ref mut tx), &mut Some(ref mut rx)) => Some((tx, rx)),
+                _ => None,
+            }
+        }
+    }
+
+    // runs `f` with the UART's interrupt masked, so it can touch the queues. the interrupt enables are restored,
+    // plus any `f` returns
+    fn masked<R, F: FnOnce(&mut RingBuffer<u8>, &mut RingBuffer<u8>) -> (R, u8)>(&self, f: F) -> R {
+        let control = self.regs.control();
+        self.regs.set_control(control & !(C2_TIE | C2_RIE));
+        let (result, enable) = match self.queues() {
+            Some((tx, rx)) => f(tx, rx),
+            None => unreachable!(),     // only called in SerialMode::Interrupt
+        };
+        self.regs.set_control(control | enable);
+        result
+    }
+}
+
+impl<'a> Serial for Uart<'a> {
+    fn mode(&self) -> SerialMode { self.mode.get() }
+
+    fn try_write(&self, byte: u8) -> bool {
+        match self.mode.get() {
+            SerialMode::Blocking => {
+                if self.regs.tx_count() >= self.tx_depth { return false; }
+                self.regs.status();     // TDRE is cleared by reading S1 before writing D
+                self.regs.write_byte(byte);
+                true
+            },
+            SerialMode::Interrupt => self.masked(|tx, _| {
+                if tx.is_full() { return (false, 0); }
+                tx.push(byte);
+                (true, C2_TIE)
+            }),
+        }
+    }
+
+    fn try_read(&self) -> Result<Option<u8>, SerialError> {
+        match self.mode.get() {
+            SerialMode::Blocking => {
+                if let Some(err) = self.take_error() { return Err(err); }
+                if self.regs.rx_count() > 0 { self.receive().map(Some) } else { Ok(None) }
+            },
+            SerialMode::Interrupt => {
+                self.masked(|_, rx| {
+                    match self.take_error() {
+                        Some(err) => (Err(err), 0),
+                        None => (Ok(rx.pop().ok()), 0),
+                    }
+                })
+            },
+        }
+    }
+
+    fn flush(&self) {
+        if self.mode.get() == SerialMode::Interrupt {
+            while !self.masked(|tx, _| (tx.is_empty(), 0)) {}
+        }
+        while self.regs.status() & S1_TC == 0 {}
+    }
+}
+
+impl<'a> fmt::Write for Uart<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes());
+        Ok(())
+    }
+}
+
+
+//------------------------------------------------
+//
+// interrupts
+//
+//------------------------------------------------
+
+/// Handler called from a UART's receive/transmit interrupt, typically calling `Uart::handle_interrupt` on the
+/// application's driver.
+pub type UartHandler = fn(UartId);
+
+// written with interrupts masked, so an ISR never sees a handler half set
+static mut HANDLERS: [Option<UartHandler>; 6] = [None; 6];
+
+/// Call `handler` from the UART's default interrupt handler.
+pub fn set_handler(id: UartId, handler: UartHandler) {
+    sync::critical(|| unsafe { HANDLERS[id as usize] = Some(handler); });
+}
+
+/// Stop calling the UART's handler.
+pub fn clear_handler(id: UartId) {
+    sync::critical(|| unsafe { HANDLERS[id as usize] = None; });
+}
+
+/// Handle the UART's receive/transmit interrupt. This is the default `isr_uartN_rx_tx`, and panics as unhandled if
+/// no handler was set for the UART.
+pub fn dispatch(id: UartId) {
+    match unsafe { HANDLERS[id as usize] } {
+        Some(handler) => handler(id),
+        None => interrupts::unhandled(id.irq()),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use core::cell::{Cell, RefCell};
+    use self::std::collections::VecDeque;
+    use self::std::vec::Vec;
+    use ::libc::structures::RingBuffer;
+    use ::os::error::SerialError;
+    use ::traits::{Serial, SerialMode};
+    use ::test_support::{OpenGates, MockNvic};
+    use super::super::gates::{Gate, ClockGates};
+    use super::{BaudDivisor, Config, Parity, StopBits, Uart, UartId, UartRegisters, fifo_depth};
+    use super::{C2_TIE, C2_RIE, C2_TE, C2_RE, S1_TC, S1_OR, ED_PARITYE};
+
+    struct MockClocks(Option<u32>);
+    impl ::traits::Clocks for MockClocks {
+        fn frequency(&self, _: ::traits::Clock) -> Option<u32> { self.0 }
+    }
+
+    // the transmit FIFO is drained by the test, as if sent
+    struct MockUart {
+        line: Cell<Option<(BaudDivisor, Parity, StopBits)>>,
+        control: Cell<u8>,
+        status: Cell<u8>,
+        sent: RefCell<Vec<u8>>,
+        tx_fifo: Cell<u8>,
+        received: RefCell<VecDeque<(u8, u8)>>,     // each byte with its ED flags
+    }
+    impl MockUart {
+        fn new() -> MockUart {
+            MockUart{
+                line: Cell::new(None),
+                control: Cell::new(0),
+                status: Cell::new(S1_TC),
+                sent: RefCell::new(Vec::new()),
+                tx_fifo: Cell::new(0),
+                received: RefCell::new(VecDeque::new()),
+            }
+        }
+
+        fn drain(&self) { self.tx_fifo.set(0); }
+
+        fn receive(&self, bytes: &[u8]) {
+            self.received.borrow_mut().extend(bytes.iter().map(|b| (*b, 0)));
+        }
+    }
+    impl UartRegisters for MockUart {
+        fn set_line(&self, divisor: BaudDivisor, parity: Parity, stop_bits: StopBits) {
+            assert_eq!(0, self.control.get() & (C2_TE | C2_RE));
+            self.line.set(Some((divisor, parity, stop_bits)));
+        }
+        fn enable_fifos(&self) -> (u8, u8) { (8, 8) }
+        fn control(&self) -> u8 { self.control.get() }
+        fn set_control(&self, value: u8) { self.control.set(value) }
+        fn status(&self) -> u8 { self.status.get() }
+        fn byte_errors(&self) -> u8 { self.received.borrow().front().map_or(0, |r| r.1) }
+        fn tx_count(&self) -> u8 { self.tx_fifo.get() }
+        fn rx_count(&self) -> u8 { self.received.borrow().len() as u8 }
+        fn read_byte(&self) -> u8 {
+            self.status.set(self.status.get() & S1_TC);
+            self.received.borrow_mut().pop_front().map_or(0, |r| r.0)
+        }
+        fn write_byte(&self, byte: u8) {
+            assert!(self.tx_fifo.get() < 8);
+            self.tx_fifo.set(self.tx_fifo.get() + 1);
+            self.sent.borrow_mut().push(byte);
+        }
+    }
+
+    #[test]
+    fn baud_divisors() {
+        // 120MHz core clock
+        let divisor = BaudDivisor::for_baud(120_000_000, 115_200).unwrap();
+        assert_eq!(BaudDivisor{sbr: 65, brfa: 3}, divisor);
+        assert_eq!(115_218, divisor.baud(120_000_000));
+
+        // 60MHz bus clock
+        assert_eq!(BaudDivisor{sbr: 390, brfa: 20}, BaudDivisor::for_baud(60_000_000, 9_600).unwrap());
+
+        assert_eq!(Err(SerialError::BadBaudRate), BaudDivisor::for_baud(60_000_000, 0));
+        assert_eq!(Err(SerialError::BadBaudRate), BaudDivisor::for_baud(60_000_000, 300));      // SBR too big
+        assert_eq!(Err(SerialError::BadBaudRate), BaudDivisor::for_baud(20_000_000, 2_000_000));
+
+        assert_eq!((1, 8, 1), (fifo_depth(0), fifo_depth(0b1010), fifo_depth(0b111)));
+    }
+
+    #[test]
+    fn configures() {
+        let sim = OpenGates;
+        let gates = ClockGates::with_registers(&sim);
+        let regs = MockUart::new();
+        let config = Config{baud: 115_200, parity: Parity::Even, stop_bits: StopBits::Two};
+
+        assert_eq!(Err(SerialError::WrongGate),
+                   Uart::new(UartId::Uart0, &regs, gates.enable(Gate::Uart1), &MockClocks(Some(1)), config)
+                       .map(|_| ()));
+        assert_eq!(Err(SerialError::NoClock),
+                   Uart::new(UartId::Uart0, &regs, gates.enable(Gate::Uart0), &MockClocks(None), config).map(|_| ()));
+
+        let uart = Uart::new(UartId::Uart0, &regs, gates.enable(Gate::Uart0), &MockClocks(Some(120_000_000)), config)
+            .unwrap();
+        assert_eq!(Some((BaudDivisor{sbr: 65, brfa: 3}, Parity::Even, StopBits::Two)), regs.line.get());
+        assert_eq!(C2_TE | C2_RE, regs.control.get());
+        assert_eq!(115_218, uart.baud());
+        assert_eq!(SerialMode::Blocking, uart.mode());
+        assert_eq!(1, gates.users(Gate::Uart0));
+    }
+
+    #[test]
+    fn blocking() {
+        let sim = OpenGates;
+        let gates = ClockGates::with_registers(&sim);
+        let regs = MockUart::new();
+        let uart = Uart::new(UartId::Uart2, &regs, gates.enable(Gate::Uart2), &MockClocks(Some(60_000_000)),
+                             Config::new(9_600)).unwrap();
+
+        uart.write_all(b"hello");
+        assert_eq!(b"hello", &regs.sent.borrow()[..]);
+        for _ in 0..3 { assert!(uart.try_write(b'!')); }
+        assert_eq!(false, uart.try_write(b'!'));     // FIFO full
+        regs.drain();
+        assert!(uart.try_write(b'!'));
+
+        assert_eq!(Ok(None), uart.try_read());
+        regs.receive(b"ok");
+        assert_eq!(Ok(b'o'), uart.read());
+        assert_eq!(Ok(Some(b'k')), uart.try_read());
+
+        // ED flags the byte at the head of the FIFO, and only that byte is dropped
+        regs.receive(b"a");
+        regs.received.borrow_mut().push_back((0xFF, ED_PARITYE));
+        regs.receive(b"b");
+        assert_eq!(Ok(Some(b'a')), uart.try_read());
+        assert_eq!(Err(SerialError::Parity), uart.try_read());
+        assert_eq!(Ok(Some(b'b')), uart.try_read());
+        assert_eq!(Ok(None), uart.try_read());
+    }
+
+    #[test]
+    fn overrun_keeps_queued_bytes() {
+        let sim = OpenGates;
+        let gates = ClockGates::with_registers(&sim);
+        let regs = MockUart::new();
+        let uart = Uart::new(UartId::Uart2, &regs, gates.enable(Gate::Uart2), &MockClocks(Some(60_000_000)),
+                             Config::new(9_600)).unwrap();
+
+        // the bytes lost to an overrun came after those in the FIFO
+        regs.receive(b"xy");
+        regs.status.set(S1_TC | S1_OR);
+        assert_eq!(Ok(Some(b'x')), uart.try_read());
+        assert_eq!(0, regs.status.get() & S1_OR, "OR was not cleared");
+        assert_eq!(Err(SerialError::Overrun), uart.try_read());
+        assert_eq!(Ok(Some(b'y')), uart.try_read());
+    }
+
+    #[test]
+    fn interrupts() {
+        let sim = OpenGates;
+        let gates = ClockGates::with_registers(&sim);
+        let nvic = MockNvic::new();
+        let regs = MockUart::new();
+        let uart = Uart::new(UartId::Uart4, &regs, gates.enable(Gate::Uart4), &MockClocks(Some(60_000_000)),
+                             Config::new(115_200)).unwrap();
+
+        let tx_mem = [0u8; 16];
+        let rx_mem = [0u8; 4];
+        uart.use_interrupts(RingBuffer::from(&tx_mem), RingBuffer::from(&rx_mem), &nvic);
+        assert_eq!(SerialMode::Interrupt, uart.mode());
+        assert_eq!(Some(66), nvic.enabled.get());
+        assert_eq!(C2_TE | C2_RE | C2_RIE, regs.control.get());
+
+        // writes are queued until the interrupt moves them
+        uart.write_all(b"0123456789");
+        assert_eq!(C2_TE | C2_RE | C2_RIE | C2_TIE, regs.control.get());
+        assert!(regs.sent.borrow().is_empty());
+        uart.handle_interrupt();
+        assert_eq!(b"01234567", &regs.sent.borrow()[..]);
+        regs.drain();
+        uart.handle_interrupt();
+        assert_eq!(b"0123456789", &regs.sent.borrow()[..]);
+        assert_eq!(0, regs.control.get() & C2_TIE);     // queue empty
+        for _ in 0..16 { assert!(uart.try_write(b'.')); }
+        assert_eq!(false, uart.try_write(b'.'));
+
+        // reads come from the queue, and bytes past its size are lost
+        regs.receive(b"abcdef");
+        uart.handle_interrupt();
+        assert_eq!(0, regs.rx_count());
+        assert_eq!(Err(SerialError::Overrun), uart.try_read());
+        assert_eq!(Ok(b'a'), uart.read());
+        for b in b"bcd" { assert_eq!(Ok(Some(*b)), uart.try_read()); }
+        assert_eq!(Ok(None), uart.try_read());
+    }
+}
@@ -3,6 +3,8 @@
 //------------------------------------------------
 
 pub mod core;
+pub mod fault;
+pub mod startup;
 
 
 //------------------------------------------------
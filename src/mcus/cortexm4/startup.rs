@@ -0,0 +1,62 @@
+extern crate core;
+use core::intrinsics::{volatile_load, volatile_store};
+
+/// Copies initialized statics (`.data`) from their load address in flash to their home in RAM.
+///
+/// Words are copied from `load` into `[begin, end)`. The linker script must keep all three word aligned.
+/// The accesses are volatile so the loop is not turned into a call to `memcpy`, which may itself rely on statics.
+pub unsafe fn init_data(load: *const u32, begin: *mut u32, end: *const u32) {
+    let mut src = load;
+    let mut dest = begin;
+    while (dest as usize) < (end as usize) {
+        volatile_store(dest, volatile_load(src));
+        src = src.offset(1);
+        dest = dest.offset(1);
+    }
+}
+
+/// Zeroes the uninitialized statics (`.bss`) in `[begin, end)`. Both must be word aligned.
+pub unsafe fn zero_bss(begin: *mut u32, end: *const u32) {
+    let mut dest = begin;
+    while (dest as usize) < (end as usize) {
+        volatile_store(dest, 0);
+        dest = dest.offset(1);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn copies_data() {
+        let load = [1u32, 2, 3, 4];
+        let mut ram = [0xFFu32; 6];
+        unsafe {
+            let ptr = ram.as_mut_ptr();
+            super::init_data(load.as_ptr(), ptr.offset(1), ptr.offset(5));
+        }
+        assert_eq!([0xFF, 1, 2, 3, 4, 0xFF], ram);
+    }
+
+    #[test]
+    fn zeroes_bss() {
+        let mut ram = [0xFFu32; 6];
+        unsafe {
+            let ptr = ram.as_mut_ptr();
+            super::zero_bss(ptr.offset(1), ptr.offset(4));
+        }
+        assert_eq!([0xFF, 0, 0, 0, 0xFF, 0xFF], ram);
+    }
+
+    #[test]
+    fn empty_sections() {
+        let load = [1u32];
+        let mut ram = [0xFFu32; 2];
+        unsafe {
+            let ptr = ram.as_mut_ptr();
+            super::init_data(load.as_ptr(), ptr, ptr);
+            super::zero_bss(ptr.offset(1), ptr.offset(1));
+        }
+        assert_eq!([0xFF, 0xFF], ram);
+    }
+}
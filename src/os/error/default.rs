@@ -23,9 +23,11 @@ pub mod unwind {
 
     #[no_mangle]
     #[lang="panic_fmt"]
-    pub extern "C" fn rust_begin_unwind(_fmt: &Arguments,
-                                        _file_line: &(&'static str, usize))
-                                        -> ! { loop {} }
+    pub extern "C" fn rust_begin_unwind(fmt: &Arguments,
+                                        file_line: &(&'static str, usize))
+                                        -> ! {
+        ::os::error::panic::begin_panic(*fmt, file_line.0, file_line.1 as u32)
+    }
 
     #[allow(non_snake_case)]
     #[no_mangle]
@@ -45,3 +47,4 @@ pub mod personality {
     #[lang="eh_personality"]
     extern "C" fn eh_personality() {}
 }
+
@@ -0,0 +1,642 @@
+extern crate core;
+use core::fmt;
+
+
+/// Common interface to every error type in the crate.
+///
+/// Each error has a compact numeric code suitable for telemetry and crash records. The high byte identifies the
+/// subsystem the error came from, and the low byte the kind of error within it. Zero is never a valid code. Any data
+/// carried by the error (such as a block index) is not part of the code.
+pub trait Error: fmt::Debug {
+    /// Get the numeric code of the error.
+    fn code(&self) -> u16;
+    /// Get a human readable description of the error.
+    fn description(&self) -> &'static str;
+}
+
+/// Subsystem byte of `BitmapError` codes.
+pub const BITMAP_SUBSYSTEM: u8 = 0x01;
+/// Subsystem byte of `RingBufferError` codes.
+pub const RING_BUFFER_SUBSYSTEM: u8 = 0x02;
+/// Subsystem byte of `LockError` codes.
+pub const LOCK_SUBSYSTEM: u8 = 0x03;
+/// Subsystem byte of `AllocError` codes.
+pub const ALLOC_SUBSYSTEM: u8 = 0x04;
+/// Subsystem byte of `RegistryError` codes.
+pub const REGISTRY_SUBSYSTEM: u8 = 0x05;
+/// Subsystem byte of `MpuError` codes.
+pub const MPU_SUBSYSTEM: u8 = 0x06;
+/// Subsystem byte of `DwtError` codes.
+pub const DWT_SUBSYSTEM: u8 = 0x07;
+/// Subsystem byte of `ItmError` codes.
+pub const ITM_SUBSYSTEM: u8 = 0x08;
+/// Subsystem byte of `SysTickError` codes.
+pub const SYSTICK_SUBSYSTEM: u8 = 0x09;
+/// Subsystem byte of `ClockError` codes.
+pub const CLOCK_SUBSYSTEM: u8 = 0x0A;
+/// Subsystem byte of `GpioError` codes.
+pub const GPIO_SUBSYSTEM: u8 = 0x0B;
+/// Subsystem byte of `SerialError` codes.
+pub const SERIAL_SUBSYSTEM: u8 = 0x0C;
+
+/// Build an error code from its subsystem and kind.
+fn make_code(subsystem: u8, kind: u8) -> u16 {
+    ((subsystem as u16) << 8) | (kind as u16)
+}
+
+
+//------------------------------------------------
+//
+// libc::structures
+//
+//------------------------------------------------
+
+/// Errors from `libc::structures::Bitmap`.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum BitmapError {
+    /// The index is not in the bounds of the bitmap.
+    OutOfBounds,
+    /// The bit was already set.
+    AlreadySet,
+    /// The bit was already clear.
+    AlreadyClear,
+    /// There are not enough contiguous unset bits.
+    NoSpace,
+    /// The search bound is beyond the number of bits in the bitmap.
+    BoundTooLarge,
+    /// There are not enough contiguous unset bits within the search bound.
+    NoSpaceInBounds,
+}
+impl Error for BitmapError {
+    fn code(&self) -> u16 {
+        make_code(BITMAP_SUBSYSTEM, match *self {
+            BitmapError::OutOfBounds => 1,
+            BitmapError::AlreadySet => 2,
+            BitmapError::AlreadyClear => 3,
+            BitmapError::NoSpace => 4,
+            BitmapError::BoundTooLarge => 5,
+            BitmapError::NoSpaceInBounds => 6,
+        })
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            BitmapError::OutOfBounds => "requested index is not in the bounds of the bitmap",
+            BitmapError::AlreadySet => "bit was already set",
+            BitmapError::AlreadyClear => "bit was already clear",
+            BitmapError::NoSpace => "could not find enough contiguous bits",
+            BitmapError::BoundTooLarge => "bound is beyond the number of bits in the bitmap",
+            BitmapError::NoSpaceInBounds => "no empty bits within the bounds",
+        }
+    }
+}
+
+/// Errors from `libc::structures::RingBuffer`.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum RingBufferError {
+    /// The index is beyond the size of the buffer.
+    OutOfBounds,
+    /// The index is beyond the number of items pushed so far (including an empty buffer).
+    NotPushed,
+    /// The computed element pointer was null.
+    NullPointer,
+}
+impl Error for RingBufferError {
+    fn code(&self) -> u16 {
+        make_code(RING_BUFFER_SUBSYSTEM, match *self {
+            RingBufferError::OutOfBounds => 1,
+            RingBufferError::NotPushed => 2,
+            RingBufferError::NullPointer => 3,
+        })
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            RingBufferError::OutOfBounds => "index is out of bounds",
+            RingBufferError::NotPushed => "index is beyond the items pushed so far",
+            RingBufferError::NullPointer => "received nullptr from ring buffer lookup",
+        }
+    }
+}
+
+/// Errors from `libc::structures::SpinLock`.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum LockError {
+    /// The lock was released without being held.
+    NotHeld,
+}
+impl Error for LockError {
+    fn code(&self) -> u16 {
+        make_code(LOCK_SUBSYSTEM, match *self {
+            LockError::NotHeld => 1,
+        })
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            LockError::NotHeld => "attempt to release unlocked lock",
+        }
+    }
+}
+
+
+//------------------------------------------------
+//
+// os::mman
+//
+//------------------------------------------------
+
+/// Errors from the `os::mman` allocators and the `traits::Allocator` interface.
+///
+/// Errors detected on a single block carry the index of that block within the allocator.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum AllocError {
+    /// A zero block allocation was requested.
+    ZeroBlocks,
+    /// The request is larger than anything the heap can hold.
+    TooLarge,
+    /// There are not enough free blocks, regardless of continuity.
+    OutOfMemory,
+    /// There are enough free blocks, but not enough contiguous ones.
+    Fragmented,
+    /// The requested alignment is not a power of two.
+    BadAlignment,
+    /// The heap itself is not aligned well enough for the requested alignment.
+    UnsupportedAlignment,
+    /// The region does not belong to this heap.
+    NotOwned,
+    /// The region does not start or end on a block boundary.
+    Misaligned,
+    /// The region's size does not match any allocation the heap could have made.
+    BadSize,
+    /// The block was not allocated (double free?).
+    DoubleFree(usize),
+    /// The canary after the allocation, in the given block, was overwritten. Only detected with `heap_debug`.
+    Overrun(usize),
+    /// The canary before the allocation, in the given block, was overwritten. Only detected with `heap_debug`.
+    Underrun(usize),
+    /// The free block was written to. Only detected with `heap_debug`.
+    UseAfterFree(usize),
+    /// The allocator was given an invalid configuration.
+    InvalidConfig,
+    /// The region is too small for the requested configuration.
+    RegionTooSmall,
+    /// The heap was never given memory.
+    Uninitialized,
+    /// The allocator's bookkeeping failed. This points to corruption of the allocator itself.
+    Bitmap(BitmapError),
+}
+impl AllocError {
+    /// Get the index of the block the error was detected on, if any.
+    pub fn block(&self) -> Option<usize> {
+        match *self {
+            AllocError::DoubleFree(b) |
+            AllocError::Overrun(b) |
+            AllocError::Underrun(b) |
+            AllocError::UseAfterFree(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+impl Error for AllocError {
+    /// Get the numeric code of the error. Bookkeeping failures report the code of the underlying `BitmapError`.
+    fn code(&self) -> u16 {
+        let kind = match *self {
+            AllocError::ZeroBlocks => 1,
+            AllocError::TooLarge => 2,
+            AllocError::OutOfMemory => 3,
+            AllocError::Fragmented => 4,
+            AllocError::BadAlignment => 5,
+            AllocError::UnsupportedAlignment => 6,
+            AllocError::NotOwned => 7,
+            AllocError::Misaligned => 8,
+            AllocError::BadSize => 9,
+            AllocError::DoubleFree(_) => 10,
+            AllocError::Overrun(_) => 11,
+            AllocError::Underrun(_) => 12,
+            AllocError::UseAfterFree(_) => 13,
+            AllocError::InvalidConfig => 14,
+            AllocError::RegionTooSmall => 15,
+            AllocError::Uninitialized => 16,
+            AllocError::Bitmap(e) => { return e.code(); }
+        };
+        make_code(ALLOC_SUBSYSTEM, kind)
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            AllocError::ZeroBlocks => "cannot allocate zero blocks",
+            AllocError::TooLarge => "request is larger than the heap can hold",
+            AllocError::OutOfMemory => "not enough free blocks (without checking continuity)",
+            AllocError::Fragmented => "no free region is large enough (heap is fragmented)",
+            AllocError::BadAlignment => "requested alignment is not a power of two",
+            AllocError::UnsupportedAlignment => "heap is not aligned well enough for the requested alignment",
+            AllocError::NotOwned => "region is not within this heap",
+            AllocError::Misaligned => "region is not aligned to a block",
+            AllocError::BadSize => "region size does not describe an allocation",
+            AllocError::DoubleFree(_) => "block is not allocated (double free?)",
+            AllocError::Overrun(_) => "back canary was overwritten (buffer overrun)",
+            AllocError::Underrun(_) => "front canary was overwritten",
+            AllocError::UseAfterFree(_) => "free block was written to (use after free)",
+            AllocError::InvalidConfig => "invalid allocator configuration",
+            AllocError::RegionTooSmall => "region is too small for the configuration",
+            AllocError::Uninitialized => "heap was never initialized",
+            AllocError::Bitmap(e) => e.description(),
+        }
+    }
+}
+
+/// Failing to find room in the tracking bitmap means the heap is fragmented. Anything else is a bookkeeping failure.
+impl From<BitmapError> for AllocError {
+    fn from(e: BitmapError) -> AllocError {
+        match e {
+            BitmapError::NoSpace | BitmapError::NoSpaceInBounds => AllocError::Fragmented,
+            _ => AllocError::Bitmap(e),
+        }
+    }
+}
+
+
+
+//------------------------------------------------
+//
+// os
+//
+//------------------------------------------------
+
+/// Errors from the fixed size registries of hooks and handlers.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum RegistryError {
+    /// Every slot in the registry is taken.
+    Full,
+}
+impl Error for RegistryError {
+    fn code(&self) -> u16 {
+        make_code(REGISTRY_SUBSYSTEM, match *self {
+            RegistryError::Full => 1,
+        })
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            RegistryError::Full => "registry is full",
+        }
+    }
+}
+
+
+
+//------------------------------------------------
+//
+// mcus
+//
+//------------------------------------------------
+
+/// Errors from configuring the Cortex-M4 memory protection unit.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum MpuError {
+    /// The MPU is not implemented.
+    Unsupported,
+    /// The region number is beyond the regions implemented.
+    BadRegion,
+    /// The region is smaller than the 32 byte minimum.
+    TooSmall,
+    /// The region size is not a power of two.
+    NotPowerOfTwo,
+    /// The region base is not aligned to its size.
+    Misaligned,
+    /// Subregions were disabled in a region smaller than 256 bytes.
+    SubregionsUnsupported,
+    /// The memory is too small to hold the region.
+    NoRoom,
+}
+impl Error for MpuError {
+    fn code(&self) -> u16 {
+        make_code(MPU_SUBSYSTEM, match *self {
+            MpuError::Unsupported => 1,
+            MpuError::BadRegion => 2,
+            MpuError::TooSmall => 3,
+            MpuError::NotPowerOfTwo => 4,
+            MpuError::Misaligned => 5,
+            MpuError::SubregionsUnsupported => 6,
+            MpuError::NoRoom => 7,
+        })
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            MpuError::Unsupported => "the mpu is not implemented",
+            MpuError::BadRegion => "region number is beyond the implemented regions",
+            MpuError::TooSmall => "region is smaller than 32 bytes",
+            MpuError::NotPowerOfTwo => "region size is not a power of two",
+            MpuError::Misaligned => "region base is not aligned to its size",
+            MpuError::SubregionsUnsupported => "subregions require a region of at least 256 bytes",
+            MpuError::NoRoom => "memory is too small to hold the region",
+        }
+    }
+}
+
+/// Errors from configuring the Cortex-M4 data watchpoint and trace unit.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum DwtError {
+    /// The comparator number is beyond the comparators implemented.
+    BadComparator,
+    /// The watched size is not a power of two.
+    NotPowerOfTwo,
+    /// The watched address is not aligned to the watched size.
+    Misaligned,
+    /// The watched size is beyond what a comparator mask can cover.
+    TooLarge,
+    /// The core clock frequency given is zero, so cycles cannot be converted to time.
+    NoCoreClock,
+}
+impl Error for DwtError {
+    fn code(&self) -> u16 {
+        make_code(DWT_SUBSYSTEM, match *self {
+            DwtError::BadComparator => 1,
+            DwtError::NotPowerOfTwo => 2,
+            DwtError::Misaligned => 3,
+            DwtError::TooLarge => 4,
+            DwtError::NoCoreClock => 5,
+        })
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            DwtError::BadComparator => "comparator number is beyond the implemented comparators",
+            DwtError::NotPowerOfTwo => "watched size is not a power of two",
+            DwtError::Misaligned => "watched address is not aligned to its size",
+            DwtError::TooLarge => "watched size is too large for a comparator",
+            DwtError::NoCoreClock => "core clock frequency is zero",
+        }
+    }
+}
+
+/// Errors from the Cortex-M4 instrumentation trace macrocell.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum ItmError {
+    /// The stimulus port number is beyond the 32 ports.
+    BadPort,
+    /// The ITM or the stimulus port is disabled, usually because no debugger has enabled tracing.
+    Disabled,
+    /// The stimulus port FIFO did not drain in time. The data was dropped.
+    Timeout,
+    /// The SWO baud rate cannot be derived from the trace clock.
+    BadBaudRate,
+}
+impl Error for ItmError {
+    fn code(&self) -> u16 {
+        make_code(ITM_SUBSYSTEM, match *self {
+            ItmError::BadPort => 1,
+            ItmError::Disabled => 2,
+            ItmError::Timeout => 3,
+            ItmError::BadBaudRate => 4,
+        })
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            ItmError::BadPort => "stimulus port number is beyond the implemented ports",
+            ItmError::Disabled => "the itm or stimulus port is disabled",
+            ItmError::Timeout => "timed out waiting for the stimulus port",
+            ItmError::BadBaudRate => "swo baud rate cannot be derived from the trace clock",
+        }
+    }
+}
+
+/// Errors from configuring a SysTick timer.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum SysTickError {
+    /// A tick rate of zero was requested.
+    ZeroRate,
+    /// The tick rate is too close to (or above) the clock rate to count.
+    RateTooHigh,
+    /// The tick rate is so low the reload value does not fit in 24 bits.
+    RateTooLow,
+}
+impl Error for SysTickError {
+    fn code(&self) -> u16 {
+        make_code(SYSTICK_SUBSYSTEM, match *self {
+            SysTickError::ZeroRate => 1,
+            SysTickError::RateTooHigh => 2,
+            SysTickError::RateTooLow => 3,
+        })
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            SysTickError::ZeroRate => "tick rate must not be zero",
+            SysTickError::RateTooHigh => "tick rate is too high for the clock",
+            SysTickError::RateTooLow => "tick rate is too low, the reload value does not fit in 24 bits",
+        }
+    }
+}
+
+/// Errors from configuring the system clocks.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum ClockError {
+    /// The oscillator frequency is outside the ranges the clock generator accepts.
+    BadOscillator,
+    /// The requested frequency is above what the chip is rated for.
+    TooFast,
+    /// No PLL divider and multiplier give the requested frequency from the oscillator.
+    NoPllSolution,
+    /// The clock generator is not in the mode the transition starts from.
+    BadMode,
+    /// The oscillator did not start in time.
+    OscillatorTimeout,
+    /// The PLL did not lock in time.
+    PllLockTimeout,
+    /// The clock generator did not report switching to the new clock in time.
+    SwitchTimeout,
+}
+impl Error for ClockError {
+    fn code(&self) -> u16 {
+        make_code(CLOCK_SUBSYSTEM, match *self {
+            ClockError::BadOscillator => 1,
+            ClockError::TooFast => 2,
+            ClockError::NoPllSolution => 3,
+            ClockError::BadMode => 4,
+            ClockError::OscillatorTimeout => 5,
+            ClockError::PllLockTimeout => 6,
+            ClockError::SwitchTimeout => 7,
+        })
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            ClockError::BadOscillator => "oscillator frequency is not supported",
+            ClockError::TooFast => "requested frequency is above the rated maximum",
+            ClockError::NoPllSolution => "requested frequency cannot be made by the pll",
+            ClockError::BadMode => "clock generator is not in the expected mode",
+            ClockError::OscillatorTimeout => "timed out waiting for the oscillator to start",
+            ClockError::PllLockTimeout => "timed out waiting for the pll to lock",
+            ClockError::SwitchTimeout => "timed out waiting for the clock switch",
+        }
+    }
+}
+
+/// Errors from claiming and configuring GPIO pins.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum GpioError {
+    /// The pin number is beyond the pins of the port.
+    BadPin,
+    /// The pin has already been claimed.
+    PinTaken,
+    /// The clock gate token given is not the one for the port.
+    WrongGate,
+    /// The pin's interrupt was enabled before a handler was set for it.
+    NoHandler,
+}
+impl Error for GpioError {
+    fn code(&self) -> u16 {
+        make_code(GPIO_SUBSYSTEM, match *self {
+            GpioError::BadPin => 1,
+            GpioError::PinTaken => 2,
+            GpioError::WrongGate => 3,
+            GpioError::NoHandler => 4,
+        })
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            GpioError::BadPin => "pin number is beyond the port",
+            GpioError::PinTaken => "pin has already been claimed",
+            GpioError::WrongGate => "clock gate token is not for the port",
+            GpioError::NoHandler => "pin interrupt has no handler",
+        }
+    }
+}
+
+/// Errors from configuring serial ports, and line errors on received bytes.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum SerialError {
+    /// The baud rate cannot be reached from the port's clock.
+    BadBaudRate,
+    /// The port's clock is not running, or its frequency is unknown.
+    NoClock,
+    /// The clock gate token given is not the one for the port.
+    WrongGate,
+    /// Received bytes were lost because the hardware or the receive queue was full.
+    Overrun,
+    /// A byte was received without a valid stop bit.
+    Framing,
+    /// A byte was received with the wrong parity.
+    Parity,
+    /// Noise was detected on the line while receiving a byte.
+    Noise,
+}
+impl Error for SerialError {
+    fn code(&self) -> u16 {
+        make_code(SERIAL_SUBSYSTEM, match *self {
+            SerialError::BadBaudRate => 1,
+            SerialError::NoClock => 2,
+            SerialError::WrongGate => 3,
+            SerialError::Overrun => 4,
+            SerialError::Framing => 5,
+            SerialError::Parity => 6,
+            SerialError::Noise => 7,
+        })
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            SerialError::BadBaudRate => "baud rate cannot be reached from the clock",
+            SerialError::NoClock => "serial port clock is not running",
+            SerialError::WrongGate => "clock gate token is not for the port",
+            SerialError::Overrun => "received bytes were lost",
+            SerialError::Framing => "received a byte without a stop bit",
+            SerialError::Parity => "received a byte with bad parity",
+            SerialError::Noise => "noise on the line while receiving",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Error, BitmapError, RingBufferError, LockError, AllocError, MpuError, DwtError};
+
+    #[test]
+    fn codes_carry_subsystem() {
+        assert_eq!(0x0101, BitmapError::OutOfBounds.code());
+        assert_eq!(0x0202, RingBufferError::NotPushed.code());
+        assert_eq!(0x0301, LockError::NotHeld.code());
+        assert_eq!(0x0404, AllocError::Fragmented.code());
+        assert_eq!(0x0605, MpuError::Misaligned.code());
+        assert_eq!(0x0705, DwtError::NoCoreClock.code());
+    }
+
+    #[test]
+    fn codes_ignore_block() {
+        assert_eq!(AllocError::DoubleFree(1).code(), AllocError::DoubleFree(7).code());
+        assert!(AllocError::DoubleFree(1).code() != AllocError::Overrun(1).code());
+    }
+
+    #[test]
+    fn alloc_codes_are_unique() {
+        let errors = [
+            AllocError::ZeroBlocks, AllocError::TooLarge, AllocError::OutOfMemory, AllocError::Fragmented,
+            AllocError::BadAlignment, AllocError::UnsupportedAlignment, AllocError::NotOwned, AllocError::Misaligned,
+            AllocError::BadSize, AllocError::DoubleFree(0), AllocError::Overrun(0), AllocError::Underrun(0),
+            AllocError::UseAfterFree(0), AllocError::InvalidConfig, AllocError::RegionTooSmall,
+            AllocError::Uninitialized,
+        ];
+        for i in 0..errors.len() {
+            for j in (i+1)..errors.len() {
+                assert!(errors[i].code() != errors[j].code(), "{:?} and {:?} share a code", errors[i], errors[j]);
+            }
+        }
+    }
+
+    #[test]
+    fn block_index() {
+        assert_eq!(Some(3), AllocError::UseAfterFree(3).block());
+        assert_eq!(None, AllocError::Fragmented.block());
+    }
+
+    #[test]
+    fn from_bitmap() {
+        assert_eq!(AllocError::Fragmented, AllocError::from(BitmapError::NoSpace));
+        assert_eq!(AllocError::Fragmented, AllocError::from(BitmapError::NoSpaceInBounds));
+
+        let internal = AllocError::from(BitmapError::AlreadyClear);
+        assert_eq!(AllocError::Bitmap(BitmapError::AlreadyClear), internal);
+        assert_eq!(BitmapError::AlreadyClear.code(), internal.code());
+    }
+}
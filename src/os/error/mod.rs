@@ -1 +1,33 @@
 pub mod default;
+pub mod panic;
+
+mod kinds;
+pub use self::kinds::{Error, BitmapError, RingBufferError, LockError, AllocError, RegistryError};
+pub use self::kinds::{MpuError, DwtError, ItmError, SysTickError, ClockError, GpioError, SerialError};
+pub use self::kinds::{BITMAP_SUBSYSTEM, RING_BUFFER_SUBSYSTEM, LOCK_SUBSYSTEM, ALLOC_SUBSYSTEM, REGISTRY_SUBSYSTEM};
+pub use self::kinds::{MPU_SUBSYSTEM, DWT_SUBSYSTEM, ITM_SUBSYSTEM, SYSTICK_SUBSYSTEM, CLOCK_SUBSYSTEM};
+pub use self::kinds::{GPIO_SUBSYSTEM, SERIAL_SUBSYSTEM};
+
+
+//------------------------------------------------
+//
+// allocation failure
+//
+//------------------------------------------------
+
+/// Handles a global heap allocation that could not be satisfied.
+///
+/// The `alloc::` collections have no way to recover from a failed allocation, so the failed request is reported
+/// through a panic and the configured panic handling takes over from there. Called by the allocator crate wrapping
+/// the `GlobalHeap` (see `os::mman::GlobalHeap`).
+pub fn alloc_error(size: usize, align: usize) -> ! {
+    panic!("could not allocate {} bytes aligned to {} from the global heap", size, align)
+}
+
+/// Handles memory that could not be returned to the global heap.
+///
+/// Freeing memory the heap did not hand out, or freeing it twice, is a bug in the caller that leaves the heap in an
+/// unknown state, so it is reported through a panic like `alloc_error`. Called by `GlobalHeap::deallocate`.
+pub fn dealloc_error(err: AllocError) -> ! {
+    panic!("could not return memory to the global heap: {} (error {:#06x})", err.description(), err.code())
+}
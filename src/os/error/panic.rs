@@ -0,0 +1,387 @@
+extern crate core;
+use core::fmt;
+
+use ::mcus::cortexm4::core::scb;
+use ::os::sync;
+use super::RegistryError;
+
+
+//------------------------------------------------
+//
+// crash record
+//
+//------------------------------------------------
+
+/// Value marking a `CrashRecord` as holding a panic. Anything else is treated as leftover RAM contents.
+pub const CRASH_MAGIC: u32 = 0xDEAD_C0DE;
+
+/// Maximum number of bytes of the panic message kept in a `CrashRecord`.
+pub const CRASH_MESSAGE_LEN: usize = 128;
+
+/// Maximum number of bytes of the source file name kept in a `CrashRecord`.
+pub const CRASH_FILE_LEN: usize = 64;
+
+/// Description of the last panic, kept in RAM that is not initialized at startup so it survives a reset.
+///
+/// The message and file name are truncated (on a character boundary) to fit. A record is only considered valid if
+/// the magic is intact and the lengths fit, as the contents after a power cycle are random.
+#[repr(C)]
+pub struct CrashRecord {
+    magic: u32,
+    line: u32,
+    message_len: u16,
+    file_len: u16,
+    message: [u8; CRASH_MESSAGE_LEN],
+    file: [u8; CRASH_FILE_LEN],
+}
+impl CrashRecord {
+    /// Creates an empty (invalid) record.
+    pub const fn empty() -> CrashRecord {
+        CrashRecord{
+            magic: 0,
+            line: 0,
+            message_len: 0,
+            file_len: 0,
+            message: [0; CRASH_MESSAGE_LEN],
+            file: [0; CRASH_FILE_LEN],
+        }
+    }
+
+    /// Fill the record with the given panic.
+    pub fn record(&mut self, msg: fmt::Arguments, file: &str, line: u32) {
+        self.magic = 0;  // invalid while half written
+
+        self.message_len = {
+            let mut w = Truncating{buf: &mut self.message, len: 0};
+            let _ = fmt::write(&mut w, msg); // truncation is not an error
+            w.len as u16
+        };
+        self.file_len = {
+            let mut w = Truncating{buf: &mut self.file, len: 0};
+            let _ = fmt::Write::write_str(&mut w, file);
+            w.len as u16
+        };
+        self.line = line;
+
+        self.magic = CRASH_MAGIC;
+    }
+
+    /// Invalidate the record.
+    pub fn clear(&mut self) { self.magic = 0; }
+
+    /// Check whether the record holds a panic.
+    pub fn is_valid(&self) -> bool {
+        self.magic == CRASH_MAGIC
+            && (self.message_len as usize) <= CRASH_MESSAGE_LEN
+            && (self.file_len as usize) <= CRASH_FILE_LEN
+            && core::str::from_utf8(&self.message[..self.message_len as usize]).is_ok()
+            && core::str::from_utf8(&self.file[..self.file_len as usize]).is_ok()
+    }
+
+    /// Get the (possibly truncated) panic message. Empty if the record is not valid.
+    pub fn message(&self) -> &str {
+        if ! self.is_valid() { return ""; }
+        unsafe { core::str::from_utf8_unchecked(&self.message[..self.message_len as usize]) }
+    }
+
+    /// Get the (possibly truncated) file the panic came from. Empty if the record is not valid.
+    pub fn file(&self) -> &str {
+        if ! self.is_valid() { return ""; }
+        unsafe { core::str::from_utf8_unchecked(&self.file[..self.file_len as usize]) }
+    }
+
+    /// Get the line the panic came from. Zero if the record is not valid.
+    pub fn line(&self) -> u32 {
+        if ! self.is_valid() { return 0; }
+        self.line
+    }
+}
+
+/// `fmt::Write` into a fixed buffer, silently dropping whatever does not fit.
+struct Truncating<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+impl<'a> fmt::Write for Truncating<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let n = c.len_utf8();
+            if self.len + n > self.buf.len() { break; }
+            c.encode_utf8(&mut self.buf[self.len..]);
+            self.len += n;
+        }
+        Ok(())
+    }
+}
+
+
+//------------------------------------------------
+//
+// panic handling
+//
+//------------------------------------------------
+
+/// Maximum number of shutdown hooks that can be registered.
+pub const MAX_SHUTDOWN_HOOKS: usize = 8;
+
+/// Function run on panic to put a module into a safe state (motors off, valves closed, ...).
+///
+/// Hooks run with the system in an unknown state, so they should be short and must not allocate.
+pub type ShutdownHook = fn();
+
+/// What to do once a panic has been recorded and the shutdown hooks have run.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum PanicAction {
+    /// Stop in place, leaving the system for a debugger (or watchdog) to deal with.
+    Halt,
+    /// Request a system reset.
+    Reset,
+}
+
+/// The final step of a panic. Abstracted so the panic sequence can be exercised on the host.
+pub trait ResetControl {
+    /// Reset the system. Hardware implementations never return.
+    fn reset(&mut self);
+    /// Stop the system. Hardware implementations never return.
+    fn halt(&mut self);
+}
+
+/// Resets through the core's `AIRCR.SYSRESETREQ`, and halts by spinning.
+pub struct SystemReset;
+impl ResetControl for SystemReset {
+    fn reset(&mut self) {
+        sync::data_barrier();   // let outstanding writes (the crash record, say) complete first
+        scb::scb().request_reset();
+        sync::data_barrier();
+        loop {}
+    }
+
+    fn halt(&mut self) {
+        loop {}
+    }
+}
+
+/// Runs the panic sequence: record the panic, run the shutdown hooks, then reset or halt.
+///
+/// A panic raised while already panicking (such as from a shutdown hook) keeps the original record, and skips
+/// straight to the final action.
+pub struct PanicHandler {
+    hooks: [Option<ShutdownHook>; MAX_SHUTDOWN_HOOKS],
+    num_hooks: usize,
+    action: PanicAction,
+    panicking: bool,
+}
+impl PanicHandler {
+    /// Creates a handler with no hooks that finishes with the given action.
+    pub const fn new(action: PanicAction) -> PanicHandler {
+        PanicHandler{
+            hooks: [None; MAX_SHUTDOWN_HOOKS],
+            num_hooks: 0,
+            action: action,
+            panicking: false,
+        }
+    }
+
+    /// Register a hook to run on panic. Hooks run in the order they were registered.
+    pub fn register(&mut self, hook: ShutdownHook) -> Result<(), RegistryError> {
+        if self.num_hooks == MAX_SHUTDOWN_HOOKS { return Err(RegistryError::Full); }
+        self.hooks[self.num_hooks] = Some(hook);
+        self.num_hooks += 1;
+        Ok(())
+    }
+
+    /// Get the number of registered hooks.
+    pub fn hooks(&self) -> usize { self.num_hooks }
+
+    /// Set what happens after the hooks have run.
+    pub fn set_action(&mut self, action: PanicAction) { self.action = action; }
+
+    /// Get what happens after the hooks have run.
+    pub fn action(&self) -> PanicAction { self.action }
+
+    /// Handle a panic. This only returns if the `ResetControl` does.
+    pub fn handle(&mut self, record: &mut CrashRecord, msg: fmt::Arguments, file: &str, line: u32,
+                  ctl: &mut ResetControl)
+    {
+        if ! self.panicking {
+            self.panicking = true;
+            record.record(msg, file, line);
+            for i in 0..self.num_hooks {
+                if let Some(hook) = self.hooks[i] { hook(); }
+            }
+        }
+
+        match self.action {
+            PanicAction::Halt => ctl.halt(),
+            PanicAction::Reset => ctl.reset(),
+        }
+    }
+}
+
+
+//------------------------------------------------
+//
+// system panic handler
+//
+//------------------------------------------------
+
+// changed with interrupts masked, and a panic masks them for good, so no ISR sees it part way through an update
+static mut PANIC_HANDLER: PanicHandler = PanicHandler::new(PanicAction::Halt);
+
+#[cfg_attr(not(test), link_section = ".noinit")]
+static mut CRASH_RECORD: CrashRecord = CrashRecord::empty();
+
+/// Register a hook to run when the system panics. See `PanicHandler::register()`.
+pub fn register_shutdown_hook(hook: ShutdownHook) -> Result<(), RegistryError> {
+    sync::critical(|| unsafe { PANIC_HANDLER.register(hook) })
+}
+
+/// Set whether the system halts (the default) or resets after a panic.
+pub fn set_panic_action(action: PanicAction) {
+    sync::critical(|| unsafe { PANIC_HANDLER.set_action(action); });
+}
+
+/// Get the record of the panic that happened before the last reset, if any.
+///
+/// The record lives in the `.noinit` section, so the linker script must place it in RAM that is neither zeroed nor
+/// loaded at startup.
+pub fn last_crash() -> Option<&'static CrashRecord> {
+    let record = unsafe { &CRASH_RECORD };
+    if record.is_valid() { Some(record) } else { None }
+}
+
+/// Forget the last crash, typically once it has been reported.
+pub fn clear_last_crash() {
+    unsafe { CRASH_RECORD.clear(); }
+}
+
+/// Run the system panic sequence. Used as the panic handler with the `panic_default` feature.
+///
+/// Interrupts are masked first, and never unmasked, so no ISR can preempt the record or the hooks.
+pub fn begin_panic(msg: fmt::Arguments, file: &str, line: u32) -> ! {
+    sync::mask_interrupts();
+    unsafe { PANIC_HANDLER.handle(&mut CRASH_RECORD, msg, file, line, &mut SystemReset); }
+    loop {}
+}
+
+
+#[cfg(test)]
+mod test {
+    mod record {
+        use super::super::{CrashRecord, CRASH_MESSAGE_LEN, CRASH_FILE_LEN};
+
+        #[test]
+        fn empty_is_invalid() {
+            let record = CrashRecord::empty();
+            assert_eq!(false, record.is_valid());
+            assert_eq!("", record.message());
+            assert_eq!(0, record.line());
+        }
+
+        #[test]
+        fn records_panic() {
+            let mut record = CrashRecord::empty();
+            record.record(format_args!("value was {}", 42), "src/main.rs", 17);
+            assert!(record.is_valid());
+            assert_eq!("value was 42", record.message());
+            assert_eq!("src/main.rs", record.file());
+            assert_eq!(17, record.line());
+
+            record.clear();
+            assert_eq!(false, record.is_valid());
+        }
+
+        #[test]
+        fn truncates_long_strings() {
+            let long = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef\
+                        0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef\
+                        overflow";
+            let mut record = CrashRecord::empty();
+            record.record(format_args!("{}", long), long, 1);
+            assert_eq!(&long[..CRASH_MESSAGE_LEN], record.message());
+            assert_eq!(&long[..CRASH_FILE_LEN], record.file());
+        }
+
+        #[test]
+        fn truncates_on_char_boundary() {
+            let mut record = CrashRecord::empty();
+            let mut msg = [b'a'; CRASH_MESSAGE_LEN - 1];
+            msg[0] = b'b';
+            let msg = ::core::str::from_utf8(&msg).unwrap();
+            record.record(format_args!("{}\u{e9}", msg), "f", 1); // the two byte char does not fit
+            assert_eq!(msg, record.message());
+        }
+
+        #[test]
+        fn garbage_is_invalid() {
+            let mut record = CrashRecord::empty();
+            record.record(format_args!("boom"), "f", 1);
+            record.message_len = (CRASH_MESSAGE_LEN + 1) as u16;
+            assert_eq!(false, record.is_valid());
+        }
+    }
+
+    mod handler {
+        extern crate std;
+        use self::std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+        use ::os::error::RegistryError;
+        use ::test_support::MockReset;
+        use super::super::{CrashRecord, PanicAction, PanicHandler, MAX_SHUTDOWN_HOOKS};
+
+        static ORDER: AtomicUsize = ATOMIC_USIZE_INIT;
+        static FIRST_RAN_AT: AtomicUsize = ATOMIC_USIZE_INIT;
+        static SECOND_RAN_AT: AtomicUsize = ATOMIC_USIZE_INIT;
+        fn first_hook() { FIRST_RAN_AT.store(ORDER.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst); }
+        fn second_hook() { SECOND_RAN_AT.store(ORDER.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst); }
+
+        #[test]
+        fn records_runs_hooks_and_resets() {
+            let mut handler = PanicHandler::new(PanicAction::Reset);
+            handler.register(first_hook).expect("could not register");
+            handler.register(second_hook).expect("could not register");
+
+            let mut record = CrashRecord::empty();
+            let mut ctl = MockReset::new();
+            handler.handle(&mut record, format_args!("boom"), "lib.rs", 3, &mut ctl);
+
+            assert_eq!("boom", record.message());
+            assert_eq!(1, FIRST_RAN_AT.load(Ordering::SeqCst), "first hook did not run first");
+            assert_eq!(2, SECOND_RAN_AT.load(Ordering::SeqCst), "second hook did not run second");
+            assert_eq!(1, ctl.resets);
+            assert_eq!(0, ctl.halts);
+        }
+
+        static NESTED_RUNS: AtomicUsize = ATOMIC_USIZE_INIT;
+        fn nested_hook() { NESTED_RUNS.fetch_add(1, Ordering::SeqCst); }
+
+        #[test]
+        fn nested_panic_keeps_first_record() {
+            let mut handler = PanicHandler::new(PanicAction::Halt);
+            handler.register(nested_hook).expect("could not register");
+
+            let mut record = CrashRecord::empty();
+            let mut ctl = MockReset::new();
+            handler.handle(&mut record, format_args!("first"), "a.rs", 1, &mut ctl);
+            handler.handle(&mut record, format_args!("second"), "b.rs", 2, &mut ctl);
+
+            assert_eq!("first", record.message());
+            assert_eq!(1, NESTED_RUNS.load(Ordering::SeqCst), "hooks ran twice");
+            assert_eq!(2, ctl.halts);
+        }
+
+        fn noop_hook() {}
+
+        #[test]
+        fn registry_full() {
+            let mut handler = PanicHandler::new(PanicAction::Halt);
+            for _ in 0..MAX_SHUTDOWN_HOOKS {
+                handler.register(noop_hook).expect("could not register");
+            }
+            assert_eq!(Err(RegistryError::Full), handler.register(noop_hook));
+            assert_eq!(MAX_SHUTDOWN_HOOKS, handler.hooks());
+        }
+    }
+}
@@ -0,0 +1,170 @@
+extern crate core;
+use core::fmt;
+
+use ::os::sync;
+use ::traits::LogBackend;
+
+
+/// Severity of a log message, from most to least severe.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(PartialOrd)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+impl Level {
+    /// Get the short upper case name of the level, for message prefixes.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// Routes messages at or above a severity to a `LogBackend`.
+///
+/// Messages are dropped while there is no backend. Failed writes are only counted, as there is nowhere to report
+/// them.
+pub struct Logger<'a> {
+    backend: Option<&'a mut LogBackend>,
+    level: Level,
+    dropped: usize,
+}
+impl<'a> Logger<'a> {
+    /// Creates a logger without a backend, passing messages of `level` and above.
+    pub const fn new(level: Level) -> Logger<'a> {
+        Logger{backend: None, level: level, dropped: 0}
+    }
+
+    /// Send messages to the given backend, replacing the previous one.
+    pub fn set_backend(&mut self, backend: &'a mut LogBackend) { self.backend = Some(backend); }
+
+    /// Set the least severe level that is passed on.
+    pub fn set_level(&mut self, level: Level) { self.level = level; }
+
+    /// Get the least severe level that is passed on.
+    pub fn level(&self) -> Level { self.level }
+
+    /// Check whether messages of the given level are passed on. Useful to skip building expensive messages.
+    pub fn enabled(&self, level: Level) -> bool { self.backend.is_some() && level <= self.level }
+
+    /// Get the number of messages the backend failed to write.
+    pub fn dropped(&self) -> usize { self.dropped }
+
+    /// Log a message.
+    pub fn log(&mut self, level: Level, args: fmt::Arguments) {
+        if level > self.level { return; }
+        if let Some(ref mut backend) = self.backend {
+            if backend.log(level, args).is_err() { self.dropped += 1; }
+        }
+    }
+}
+
+
+//------------------------------------------------
+//
+// system logger
+//
+//------------------------------------------------
+
+// used with interrupts masked, so a message logged from an ISR never sees a half-written backend. messages are
+// written to the backend masked too, and stay in order.
+static mut LOGGER: Logger<'static> = Logger::new(Level::Info);
+
+/// Send the system log to the given backend.
+pub fn set_backend(backend: &'static mut LogBackend) {
+    sync::critical(move || unsafe { LOGGER.set_backend(backend); });
+}
+
+/// Set the least severe level of the system log. Defaults to `Level::Info`.
+pub fn set_level(level: Level) {
+    sync::critical(|| unsafe { LOGGER.set_level(level); });
+}
+
+/// Check whether the system log passes on messages of the given level.
+pub fn enabled(level: Level) -> bool {
+    sync::critical(|| unsafe { LOGGER.enabled(level) })
+}
+
+/// Log a message to the system log, such as `log::log(Level::Info, format_args!("booted in {}ms", ms))`.
+pub fn log(level: Level, args: fmt::Arguments) {
+    sync::critical(|| unsafe { LOGGER.log(level, args); });
+}
+
+/// Log an error to the system log.
+pub fn error(args: fmt::Arguments) { log(Level::Error, args); }
+
+/// Log a warning to the system log.
+pub fn warn(args: fmt::Arguments) { log(Level::Warn, args); }
+
+/// Log an informational message to the system log.
+pub fn info(args: fmt::Arguments) { log(Level::Info, args); }
+
+/// Log a debug message to the system log.
+pub fn debug(args: fmt::Arguments) { log(Level::Debug, args); }
+
+/// Log a trace message to the system log.
+pub fn trace(args: fmt::Arguments) { log(Level::Trace, args); }
+
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use self::std::string::String;
+    use self::std::fmt::{self, Write};
+    use ::traits::LogBackend;
+    use super::{Level, Logger};
+
+    struct Collect { out: String, fail: bool }
+    impl LogBackend for Collect {
+        fn log(&mut self, level: Level, args: fmt::Arguments) -> fmt::Result {
+            if self.fail { return Err(fmt::Error); }
+            try!(write!(self.out, "[{}] ", level.name()));
+            try!(self.out.write_fmt(args));
+            self.out.write_str("\n")
+        }
+    }
+
+    #[test]
+    fn filters_by_level() {
+        let mut backend = Collect{out: String::new(), fail: false};
+        {
+            let mut logger = Logger::new(Level::Info);
+            logger.set_backend(&mut backend);
+            logger.log(Level::Error, format_args!("bad {}", 1));
+            logger.log(Level::Debug, format_args!("hidden"));
+            logger.set_level(Level::Trace);
+            logger.log(Level::Trace, format_args!("shown"));
+        }
+        assert_eq!("[ERROR] bad 1\n[TRACE] shown\n", backend.out);
+    }
+
+    #[test]
+    fn without_backend() {
+        let mut logger = Logger::new(Level::Trace);
+        assert_eq!(false, logger.enabled(Level::Error));
+        logger.log(Level::Error, format_args!("dropped"));
+        assert_eq!(0, logger.dropped());
+    }
+
+    #[test]
+    fn counts_failures() {
+        let mut backend = Collect{out: String::new(), fail: true};
+        let mut logger = Logger::new(Level::Info);
+        logger.set_backend(&mut backend);
+        assert!(logger.enabled(Level::Warn));
+        assert_eq!(false, logger.enabled(Level::Debug));
+        logger.log(Level::Warn, format_args!("lost"));
+        assert_eq!(1, logger.dropped());
+    }
+}
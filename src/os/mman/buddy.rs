@@ -0,0 +1,542 @@
+use ::libc::math::uceil;
+use ::libc::memory::IOVec;
+use ::libc::structures::Bitmap;
+use ::os::error::AllocError;
+
+/// Power-of-two block allocator that splits larger blocks when allocating, and merges "buddies" when freeing.
+///
+/// The region is divided into units of `block_size` bytes. A block of order `N` spans `2^N` units, and is always
+/// aligned (relative to the first unit) to its own size. Free blocks of every order are tracked with one bit per
+/// block, all packed into a single `Bitmap` that lives inside the managed region.
+pub struct BuddyAllocator {
+    blocks: IOVec,
+    free_map: Bitmap,
+    block_size: usize,
+    num_blocks: usize,
+    num_orders: usize,
+    used_blocks: usize,
+}
+impl BuddyAllocator {
+    /// Instantiates a new buddy allocator managing the region defined by the `IOVec`.
+    ///
+    /// The `block_size` is the smallest unit handed out and is rounded up to a power of two. The free-block bitmap
+    /// for all orders is placed at the front of the region, taking as many blocks as it needs. If the trailing space
+    /// that does not fit a whole block is large enough to hold the bitmap, that dead space is used instead.
+    pub fn new(iov: IOVec, block_size: usize) -> BuddyAllocator {
+        let block_size = block_size.next_power_of_two();
+        let total_blocks = iov.size / block_size;
+        let num_map_bytes = uceil(map_bits(total_blocks), 8);
+
+        let (num_blocks, blks, fmap) = if num_map_bytes <= (iov.size % block_size) {
+            (
+                total_blocks,
+                IOVec{
+                    ptr: iov.ptr,
+                    size: iov.size - (iov.size % block_size)    // trim the "fat" we use for the map
+                },
+                IOVec{
+                    ptr: unsafe { iov.ptr.offset( (iov.size - (iov.size % block_size)) as isize) },
+                    size: iov.size % block_size
+                }
+            )
+        } else {
+            let num_map_blocks = uceil(num_map_bytes, block_size);
+            let num_blocks = if num_map_blocks > total_blocks { 0 } else { total_blocks - num_map_blocks };
+            (
+                num_blocks,
+                IOVec{
+                    ptr: unsafe { iov.ptr.offset((num_map_blocks*block_size) as isize) },
+                    size: num_blocks * block_size,
+                },
+                IOVec{
+                    ptr: iov.ptr,
+                    size: num_map_bytes // the map was sized for every block, so it always covers the ones left over
+                }
+            )
+        };
+
+        unsafe { ::libc::memory::memset(iov.ptr as *mut u8, 0, iov.size); }
+        let mut result = BuddyAllocator{
+            blocks: blks,
+            free_map: Bitmap::from_iov(fmap),
+            block_size: block_size,
+            num_blocks: num_blocks,
+            num_orders: num_orders(num_blocks),
+            used_blocks: 0,
+        };
+
+        // carve the region into the largest aligned blocks that fit, and mark them free
+        let mut unit = 0;
+        while unit < result.num_blocks {
+            let mut order = result.num_orders - 1;
+            while (unit % (1 << order)) != 0 || (unit + (1 << order)) > result.num_blocks {
+                order -= 1;
+            }
+            let bit = result.map_index(order, unit >> order);
+            result.free_map.set(bit).expect("free map was sized too small");
+            unit += 1 << order;
+        }
+
+        result
+    }
+
+    /// Allocate the given number of blocks, rounded up to the next power of two. The returned IOVec should be "given
+    /// back" to the allocator when freed.
+    ///
+    /// Errors occur from bounds checking, internal failures, or unavailability of a large enough block.
+    pub fn alloc(&mut self, block_count: usize) -> Result<IOVec, AllocError> {
+        if block_count == 0 { return Err(AllocError::ZeroBlocks); }
+
+        let rounded = try!(block_count.checked_next_power_of_two().ok_or(AllocError::OutOfMemory));
+        let order = rounded.trailing_zeros() as usize;
+        if order >= self.num_orders { return Err(AllocError::TooLarge); }
+        if (1 << order) > self.free_blocks() { return Err(AllocError::OutOfMemory); }
+
+        // find the smallest free block that can satisfy the request
+        let mut found = None;
+        for o in order..self.num_orders {
+            let free = self.find_free(o);
+            if free.is_some() {
+                found = Some((o, free.unwrap()));
+                break;
+            }
+        }
+        if found.is_none() { return Err(AllocError::Fragmented); }
+
+        // split it down to the requested order, freeing the upper half at each step
+        let (mut o, mut index) = found.unwrap();
+        let bit = self.map_index(o, index);
+        try!(self.free_map.checked_clear(bit));
+        while o > order {
+            o -= 1;
+            index *= 2;
+            let buddy = self.map_index(o, index + 1);
+            try!(self.free_map.checked_set(buddy));
+        }
+
+        self.used_blocks += 1 << order;
+        Ok(IOVec{
+            ptr: unsafe { self.blocks.ptr.offset( ((index << order) * self.block_size) as isize) },
+            size: (1 << order) * self.block_size,
+        })
+    }
+
+    /// Allocate the given number of blocks such that the region begins on an `align` byte boundary.
+    ///
+    /// `align` must be a power of two. Blocks are aligned to their own size, so this simply raises the block count
+    /// until the block is at least `align` bytes. An error is returned if the heap itself is not aligned well enough.
+    pub fn alloc_aligned(&mut self, block_count: usize, align: usize) -> Result<IOVec, AllocError> {
+        let count = try!(self.aligned_count(block_count, align));
+        self.alloc(count)
+    }
+
+    /// Free the given region of allocated memory. The input ideally should come from a previous `::alloc()`.
+    ///
+    /// Freed blocks are merged with their buddy for as long as the buddy is also free.
+    ///
+    /// Errors occur from bounds checking, freeing unallocated/unmanaged memory, or a `IOVec` that does not describe a
+    /// whole block. All checks happen before anything is modified.
+    pub fn free(&mut self, iov: IOVec) -> Result<(), AllocError> {
+        if (iov.ptr as usize) < (self.blocks.ptr as usize) {
+            return Err(AllocError::NotOwned);
+        }
+        let offset = (iov.ptr as usize) - (self.blocks.ptr as usize);
+        if offset % self.block_size != 0 {
+            return Err(AllocError::Misaligned);
+        }
+        if iov.size == 0 || iov.size % self.block_size != 0 {
+            return Err(AllocError::Misaligned);
+        }
+
+        let count = iov.size / self.block_size;
+        if ! count.is_power_of_two() {
+            return Err(AllocError::BadSize);
+        }
+        let order = count.trailing_zeros() as usize;
+        let first_block = offset / self.block_size;
+        if first_block % count != 0 {
+            return Err(AllocError::Misaligned);
+        }
+        if order >= self.num_orders || (first_block + count) > self.num_blocks {
+            return Err(AllocError::NotOwned);
+        }
+        if count > self.used_blocks {
+            return Err(AllocError::BadSize);
+        }
+
+        // the block must not be free, nor be part of a free block, nor contain a free block
+        let index = first_block >> order;
+        for o in order..self.num_orders {
+            let containing = index >> (o - order);
+            if containing >= (self.num_blocks >> o) { break; }    // past the last whole block of the order
+            if self.free_map.is_set(self.map_index(o, containing)) {
+                return Err(AllocError::DoubleFree(first_block));
+            }
+        }
+        for o in 0..order {
+            for i in (index << (order - o))..((index + 1) << (order - o)) {
+                if self.free_map.is_set(self.map_index(o, i)) {
+                    return Err(AllocError::DoubleFree(i << o));
+                }
+            }
+        }
+
+        // merge upwards for as long as our buddy is free
+        let (mut o, mut index) = (order, index);
+        while (o + 1) < self.num_orders {
+            let buddy = index ^ 1;
+            if ((buddy + 1) << o) > self.num_blocks { break; }
+
+            let buddy_bit = self.map_index(o, buddy);
+            if ! self.free_map.is_set(buddy_bit) { break; }
+
+            try!(self.free_map.checked_clear(buddy_bit));
+            index >>= 1;
+            o += 1;
+        }
+        let bit = self.map_index(o, index);
+        try!(self.free_map.checked_set(bit));
+
+        self.used_blocks -= count;
+        Ok(())
+    }
+
+    /// Get the total number of managed blocks.
+    pub fn blocks(&self) -> usize { self.num_blocks }
+    /// Get the number of blocks available to the manager.
+    pub fn free_blocks(&self) -> usize { self.num_blocks - self.used_blocks }
+    /// Get the number of blocks currently allocated.
+    pub fn used_blocks(&self) -> usize { self.used_blocks }
+    /// Get the size, in bytes, of the smallest block.
+    pub fn block_size(&self) -> usize { self.block_size }
+
+    /// Get the number of blocks in the largest contiguous free block. This is the largest request that will succeed.
+    pub fn largest_free_blocks(&self) -> usize {
+        for o in (0..self.num_orders).rev() {
+            if self.find_free(o).is_some() { return 1 << o; }
+        }
+        0
+    }
+
+
+    //
+    // internal helpers
+    //
+
+    /// Get the block count needed for `block_count` blocks to land on an `align` byte boundary.
+    fn aligned_count(&self, block_count: usize, align: usize) -> Result<usize, AllocError> {
+        if ! align.is_power_of_two() { return Err(AllocError::BadAlignment); }
+
+        let natural = 1 << ((self.blocks.ptr as usize) | self.block_size).trailing_zeros();
+        if align <= natural { return Ok(block_count); }
+        if (self.blocks.ptr as usize) % align != 0 {
+            return Err(AllocError::UnsupportedAlignment);
+        }
+
+        let min_count = align / self.block_size;
+        Ok(if block_count < min_count { min_count } else { block_count })
+    }
+
+    /// Get the number of blocks needed to hold `size` bytes. Zero bytes still take a block.
+    fn size_to_blocks(&self, size: usize) -> usize {
+        if size == 0 { 1 } else { uceil(size, self.block_size) }
+    }
+
+    /// Get the bit in the free map describing the `index`th block of the given order.
+    fn map_index(&self, order: usize, index: usize) -> usize {
+        let mut base = 0;
+        for o in 0..order { base += self.num_blocks >> o; }
+        base + index
+    }
+
+    /// Find the first free block of the given order.
+    fn find_free(&self, order: usize) -> Option<usize> {
+        for i in 0..(self.num_blocks >> order) {
+            if self.free_map.is_set(self.map_index(order, i)) { return Some(i); }
+        }
+        None
+    }
+}
+
+impl ::traits::Allocator for BuddyAllocator {
+    /// Allocates the smallest power-of-two number of blocks holding `size` bytes at the given alignment.
+    fn allocate(&mut self, size: usize, align: usize) -> Result<IOVec, AllocError> {
+        let count = self.size_to_blocks(size);
+        self.alloc_aligned(count, align)
+    }
+
+    /// Frees the block backing the region. The alignment is needed to know how far the block was rounded up.
+    fn deallocate(&mut self, iov: IOVec, align: usize) -> Result<(), AllocError> {
+        let count = try!(self.aligned_count(self.size_to_blocks(iov.size), align));
+        let count = try!(count.checked_next_power_of_two().ok_or(AllocError::OutOfMemory));
+        self.free(IOVec{ptr: iov.ptr, size: count * self.block_size})
+    }
+
+    fn capacity(&self) -> usize { self.num_blocks * self.block_size }
+    fn used_bytes(&self) -> usize { self.used_blocks * self.block_size }
+}
+
+/// Get the number of orders needed to track `num_blocks` blocks. Zero blocks need zero orders.
+fn num_orders(num_blocks: usize) -> usize {
+    let mut orders = 0;
+    while (num_blocks >> orders) > 0 { orders += 1; }
+    orders
+}
+
+/// Get the number of bits needed to track every block of every order.
+fn map_bits(num_blocks: usize) -> usize {
+    let mut bits = 0;
+    for o in 0..num_orders(num_blocks) { bits += num_blocks >> o; }
+    bits
+}
+
+
+#[cfg(test)]
+mod test {
+    mod sanity {
+        use ::libc::memory::IOVec;
+        use ::os::error::AllocError;
+
+        #[test]
+        pub fn block_counts() {
+            let buff = [0u8; 4096]; // 64 blocks of 64 bytes == 127 bits of map == 1 block
+            let mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+            assert_eq!(63, mman.blocks());
+            assert_eq!(63, mman.free_blocks());
+            assert_eq!(0, mman.used_blocks());
+            assert_eq!(32, mman.largest_free_blocks());
+        }
+
+        #[test]
+        pub fn block_size_rounded_up() {
+            let buff = [0u8; 4096];
+            let mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 48);
+            assert_eq!(64, mman.block_size());
+        }
+
+        #[test]
+        pub fn dead_space_map() {
+            let buff = [0u8; 4096 + 16]; // 16 trailing bytes hold the 127 bit map
+            let mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+            assert_eq!(64, mman.blocks());
+            assert_eq!(64, mman.largest_free_blocks());
+        }
+
+        #[test]
+        pub fn unaligned_free_errors() {
+            let buff = [0u8; 4096 + 16];
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+            mman.alloc(4).expect("could not allocate");
+
+            // unaligned ptr
+            assert!(mman.free(IOVec{ptr:&buff[3], size:64}).is_err());
+
+            // unaligned size
+            assert!(mman.free(IOVec{ptr:&buff[0], size:7}).is_err());
+
+            // not a power of two
+            assert!(mman.free(IOVec{ptr:&buff[0], size:3*64}).is_err());
+
+            // ptr not aligned to its order
+            assert!(mman.free(IOVec{ptr:&buff[64], size:2*64}).is_err());
+        }
+
+        #[test]
+        pub fn free_unused_error() {
+            let buff = [0u8; 4096 + 16];
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+            assert!(mman.free(IOVec{ptr:&buff[0], size:64}).is_err());
+        }
+
+        #[test]
+        pub fn double_free_error() {
+            let buff = [0u8; 4096 + 16];
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+            let keep = mman.alloc(1).expect("could not allocate");
+            let blk = mman.alloc(1).expect("could not allocate");
+            mman.free(blk).expect("could not free");
+            assert_eq!(Err(::os::error::AllocError::DoubleFree(1)), mman.free(blk));
+            assert_eq!(1, mman.used_blocks());
+            mman.free(keep).expect("could not free");
+        }
+
+        #[test]
+        pub fn free_last_partial_block() {
+            let buff = [0u8; 4096]; // 63 blocks, so the last block is in no whole block of a higher order
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+            let mut blks = [IOVec{ptr:&buff[0], size:0}; 63];
+            for b in blks.iter_mut() { *b = mman.alloc(1).expect("could not allocate"); }
+            let last = *blks.iter().max_by_key(|b| b.ptr as usize).unwrap();
+            assert_eq!(&buff[4096 - 64] as *const u8, last.ptr);
+
+            // a free block at the start of a higher order used to be mistaken for one holding the last block
+            for b in blks.iter().filter(|b| (b.ptr as usize) < (&buff[5*64] as *const u8 as usize)) {
+                mman.free(*b).expect("could not free");
+            }
+            assert_eq!(Ok(()), mman.free(last));
+            assert_eq!(58, mman.used_blocks());
+        }
+
+        #[test]
+        pub fn free_partially_free_error() {
+            let buff = [0u8; 4096 + 16];
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+            let blk = mman.alloc(1).expect("could not allocate");
+            mman.alloc(1).expect("could not allocate");
+            assert!(mman.free(IOVec{ptr:blk.ptr, size:4*64}).is_err()); // blocks 2 and 3 are still free
+        }
+
+        #[test]
+        pub fn alloc_too_many_error() {
+            let buff = [0u8; 4096];
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+            assert!(mman.alloc(64).is_err());
+            assert!(mman.alloc(0).is_err());
+            assert_eq!(Err(AllocError::OutOfMemory), mman.alloc(usize::max_value()));  // no power of two above it
+        }
+    }
+
+
+    mod split_and_merge {
+        use ::libc::memory::IOVec;
+
+        #[test]
+        fn rounds_up_to_power_of_two() {
+            let buff = [0u8; 4096 + 16];
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+            let blk = mman.alloc(3).expect("could not allocate");
+            assert_eq!(4*64, blk.size);
+            assert_eq!(4, mman.used_blocks());
+        }
+
+        #[test]
+        fn split_leaves_buddies_free() {
+            let buff = [0u8; 4096 + 16];
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+            let blk = mman.alloc(1).expect("could not allocate");
+            assert_eq!(&buff[0] as *const u8, blk.ptr);
+            assert_eq!(63, mman.free_blocks());
+            assert_eq!(32, mman.largest_free_blocks());
+
+            // the buddy of the first block is the next allocation
+            let next = mman.alloc(1).expect("could not allocate");
+            assert_eq!(&buff[64] as *const u8, next.ptr);
+        }
+
+        #[test]
+        fn merge_on_free() {
+            let buff = [0u8; 4096 + 16];
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+            let a = mman.alloc(1).expect("could not allocate");
+            let b = mman.alloc(2).expect("could not allocate");
+            let c = mman.alloc(1).expect("could not allocate");
+            assert_eq!(32, mman.largest_free_blocks());
+
+            mman.free(c).expect("could not free");
+            mman.free(a).expect("could not free");
+            mman.free(b).expect("could not free");
+            assert_eq!(64, mman.largest_free_blocks(), "blocks were not merged back together");
+            mman.alloc(64).expect("could not allocate entire heap after merging");
+        }
+
+        #[test]
+        fn alloc_all_and_free() {
+            let buff = [0u8; 1024 + 2]; // 64 blocks of 16 bytes, 2 trailing bytes are not enough for the map
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 16);
+            assert_eq!(63, mman.blocks());
+
+            let mut blocks = [IOVec{ptr:0 as *const u8, size:0}; 63];
+            for i in 0..mman.blocks() {
+                let blk = mman.alloc(1).expect("could not allocate one block");
+                unsafe { ::libc::memory::memset(blk.as_mut(), 0xA0 + (i as u8), blk.size); }
+                assert_eq!(i+1, mman.used_blocks(), "incorrect used block count after allocation");
+                blocks[i] = blk;
+            }
+
+            assert_eq!(0, mman.free_blocks(), "incorrect free block count after allocating all");
+            assert!(mman.alloc(1).is_err());
+
+            for i in 0..blocks.len() {
+                for b in 0..blocks[i].size {
+                    assert_eq!(0xA0 + (i as u8), unsafe { *blocks[i].ptr.offset(b as isize) }, "block {} was overwritten", i);
+                }
+            }
+
+            for i in 0..blocks.len() {
+                mman.free(blocks[i].clone()).expect("could not free block");
+            }
+            assert_eq!(0, mman.used_blocks(), "incorrect used block count after freeing all");
+            assert_eq!(32, mman.largest_free_blocks(), "blocks were not merged back together");
+        }
+
+        #[test]
+        fn mixed_sizes() {
+            let buff = [0u8; 4096 + 16];
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+
+            let sizes = [1, 7, 2, 16, 3, 1, 8, 4];
+            let mut blocks = [IOVec{ptr:0 as *const u8, size:0}; 8];
+            for i in 0..sizes.len() {
+                blocks[i] = mman.alloc(sizes[i]).expect("could not allocate");
+            }
+            assert_eq!(1+8+2+16+4+1+8+4, mman.used_blocks());
+
+            // free in an order that forces merging across several orders
+            for i in [3, 0, 5, 2, 7, 1, 4, 6].iter() {
+                mman.free(blocks[*i]).expect("could not free");
+            }
+            assert_eq!(0, mman.used_blocks());
+            assert_eq!(64, mman.largest_free_blocks());
+        }
+    }
+
+
+    mod allocator {
+        use ::libc::memory::IOVec;
+        use ::os::error::AllocError;
+        use ::traits::Allocator;
+
+        #[test]
+        fn bytes_round_to_blocks() {
+            let buff = [0u8; 4096 + 16];
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+            assert_eq!(4096, mman.capacity());
+
+            let heap: &mut Allocator = &mut mman;
+            let iov = heap.allocate(150, 1).expect("could not allocate");
+            assert_eq!(4*64, iov.size);
+            assert_eq!(4*64, heap.used_bytes());
+
+            // the requested size is enough to free the whole allocation
+            heap.deallocate(IOVec{ptr: iov.ptr, size: 150}, 1).expect("could not free");
+            assert_eq!(0, heap.used_bytes());
+        }
+
+        #[test]
+        fn large_alignment() {
+            let buff = [0u8; 8192];
+            let offset = (256 - ((&buff[0] as *const u8 as usize) % 256)) % 256;
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[offset], size:4096 + 16}, 64);
+
+            let heap: &mut Allocator = &mut mman;
+            let iov = heap.allocate(8, 256).expect("could not allocate");
+            assert_eq!(0, (iov.ptr as usize) % 256, "allocation is not aligned");
+            assert_eq!(256, iov.size);
+
+            heap.deallocate(IOVec{ptr: iov.ptr, size: 8}, 256).expect("could not free");
+            assert_eq!(0, heap.used_bytes());
+        }
+
+        #[test]
+        fn oversized_free_errors() {
+            let buff = [0u8; 256];
+            let mut mman = super::super::BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 1);
+
+            let heap: &mut Allocator = &mut mman;
+            let iov = heap.allocate(1, 1).expect("could not allocate");
+            let size = usize::max_value();  // as many blocks, which no power of two covers
+            assert_eq!(Err(AllocError::OutOfMemory), heap.deallocate(IOVec{ptr: iov.ptr, size: size}, 1));
+            assert_eq!(1, heap.used_bytes());
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use ::libc::memory::IOVec;
+use ::os::error::AllocError;
+
+use super::SlabAllocator;
+
+/// The maximum number of size classes a `SlabCache` can manage.
+pub const MAX_SIZE_CLASSES: usize = 8;
+
+/// Describes one size class of a `SlabCache`.
+///
+/// The `weight` is the share of the parent region given to the class, relative to the weights of the other classes.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct SizeClass {
+    pub block_size: usize,
+    pub weight: usize,
+}
+
+/// Utilisation of a single size class.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ClassStats {
+    /// Size, in bytes, of every object in the class.
+    pub block_size: usize,
+    /// Total number of objects the class can hold.
+    pub blocks: usize,
+    /// Number of objects currently allocated.
+    pub used_blocks: usize,
+    /// Number of objects still available.
+    pub free_blocks: usize,
+    /// Number of allocations this class served because a smaller class was full.
+    pub fallbacks: usize,
+    /// Number of allocations that fit this class but could not be served by it, or any class above it, for lack of
+    /// room. Requests failing only on their alignment are not counted.
+    pub failures: usize,
+}
+
+/// Object cache that splits a parent region into several `SlabAllocator`s, one per size class.
+///
+/// Each allocation is a single object sent to the smallest class that fits it. When that class is full, larger
+/// classes are tried in order (unless fallback is disabled). Per-class statistics are kept so the split between the
+/// classes can be tuned.
+pub struct SlabCache {
+    classes: [Option<SlabAllocator>; MAX_SIZE_CLASSES],
+    fallbacks: [usize; MAX_SIZE_CLASSES],
+    failures: [usize; MAX_SIZE_CLASSES],
+    num_classes: usize,
+    fallback: bool,
+}
+impl SlabCache {
+    /// Instantiates a new cache, carving the region defined by the `IOVec` between the given size classes.
+    ///
+    /// Classes must be given smallest first, with strictly increasing block sizes and non-zero weights. Each class
+    /// gets `weight / total_weight` of the region, with the last class also receiving whatever is left by rounding.
+    ///
+    /// Errors occur from an invalid class list, or a region too small to give every class at least one block.
+    pub fn new(iov: IOVec, classes: &[SizeClass]) -> Result<SlabCache, AllocError> {
+        if classes.len() == 0 { return Err(AllocError::InvalidConfig); }
+        if classes.len() > MAX_SIZE_CLASSES { return Err(AllocError::InvalidConfig); }
+
+        let mut total_weight = 0;
+        for i in 0..classes.len() {
+            if classes[i].block_size == 0 || classes[i].weight == 0 {
+                return Err(AllocError::InvalidConfig);
+            }
+            if i > 0 && classes[i].block_size <= classes[i-1].block_size {
+                return Err(AllocError::InvalidConfig);
+            }
+            total_weight += classes[i].weight;
+        }
+
+        let mut result = SlabCache{
+            classes: [None, None, None, None, None, None, None, None],
+            fallbacks: [0; MAX_SIZE_CLASSES],
+            failures: [0; MAX_SIZE_CLASSES],
+            num_classes: classes.len(),
+            fallback: true,
+        };
+
+        let unit = iov.size / total_weight;
+        let mut offset = 0;
+        for i in 0..classes.len() {
+            let size = if i == classes.len() - 1 { iov.size - offset } else { unit * classes[i].weight };
+            if size < (classes[i].block_size * 2) {
+                return Err(AllocError::RegionTooSmall);
+            }
+
+            let slab = SlabAllocator::new(
+                IOVec{ ptr: unsafe { iov.ptr.offset(offset as isize) }, size: size },
+                classes[i].block_size
+            );
+            if slab.blocks() == 0 {
+                return Err(AllocError::RegionTooSmall);
+            }
+
+            result.classes[i] = Some(slab);
+            offset += size;
+        }
+
+        Ok(result)
+    }
+
+    /// Enable or disable falling back to larger classes when the best fitting class is full. Enabled by default.
+    pub fn set_fallback(&mut self, enabled: bool) { self.fallback = enabled; }
+
+    /// Allocate a single object of at least `size` bytes.
+    ///
+    /// Errors occur when the request is larger than the largest class, or when no class that fits it has room.
+    pub fn alloc(&mut self, size: usize) -> Result<IOVec, AllocError> {
+        self.alloc_aligned(size, 1)
+    }
+
+    /// Allocate a single object of at least `size` bytes, starting on an `align` byte boundary.
+    ///
+    /// Errors occur when the request is larger than the largest class, or when no class that fits it has room. If a
+    /// class had room but no object on an `align` boundary, the error is `UnsupportedAlignment` rather than
+    /// `OutOfMemory`, and is not counted in the class' `failures`.
+    pub fn alloc_aligned(&mut self, size: usize, align: usize) -> Result<IOVec, AllocError> {
+        if ! align.is_power_of_two() { return Err(AllocError::BadAlignment); }
+        let first = match self.class_for(size) {
+            Some(c) => c,
+            None => { return Err(AllocError::TooLarge); }
+        };
+        let last = if self.fallback { self.num_classes } else { first + 1 };
+
+        let mut misaligned = false;
+        for i in first..last {
+            // beyond the blocks' own alignment, only the blocks on an `align` boundary are searched
+            let searched_aligned = align > self.class(i).block_alignment();
+            match self.class_mut(i).alloc_aligned(1, align) {
+                Ok(iov) => {
+                    if i != first { self.fallbacks[i] += 1; }
+                    return Ok(iov);
+                }
+                Err(AllocError::Fragmented) => { misaligned = misaligned || searched_aligned; }
+                Err(_) => {}
+            }
+        }
+
+        if misaligned { return Err(AllocError::UnsupportedAlignment); }
+        self.failures[first] += 1;
+        Err(AllocError::OutOfMemory)
+    }
+
+    /// Free an object allocated from this cache. The owning class is found from the pointer.
+    ///
+    /// Errors occur when the pointer does not belong to any class, the size is larger than the owning class'
+    /// objects, or the owning `SlabAllocator` rejects the free.
+    pub fn free(&mut self, iov: IOVec) -> Result<(), AllocError> {
+        for i in 0..self.num_classes {
+            if self.class(i).contains(iov.ptr) {
+                let block_size = self.class(i).block_size();
+                if iov.size > block_size {
+                    return Err(AllocError::BadSize);
+                }
+                return self.class_mut(i).free(IOVec{ptr: iov.ptr, size: block_size});
+            }
+        }
+        Err(AllocError::NotOwned)
+    }
+
+    /// Get the number of size classes.
+    pub fn classes(&self) -> usize { self.num_classes }
+
+    /// Get the index of the smallest class that fits `size` bytes, if any.
+    pub fn class_for(&self, size: usize) -> Option<usize> {
+        for i in 0..self.num_classes {
+            if self.class(i).block_size() >= size { return Some(i); }
+        }
+        None
+    }
+
+    /// Get the utilisation of the given class, or `None` if there is no such class.
+    pub fn stats(&self, class: usize) -> Option<ClassStats> {
+        if class >= self.num_classes { return None; }
+
+        let slab = self.class(class);
+        Some(ClassStats{
+            block_size: slab.block_size(),
+            blocks: slab.blocks(),
+            used_blocks: slab.used_blocks(),
+            free_blocks: slab.free_blocks(),
+            fallbacks: self.fallbacks[class],
+            failures: self.failures[class],
+        })
+    }
+
+    /// Get the class at the given index. Only valid for `index < num_classes`.
+    fn class(&self, index: usize) -> &SlabAllocator {
+        self.classes[index].as_ref().expect("size class was never created")
+    }
+
+    /// Get the class at the given index. Only valid for `index < num_classes`.
+    fn class_mut(&mut self, index: usize) -> &mut SlabAllocator {
+        self.classes[index].as_mut().expect("size class was never created")
+    }
+}
+
+impl ::traits::Allocator for SlabCache {
+    /// Allocates a single object from the smallest class fitting `size` bytes.
+    fn allocate(&mut self, size: usize, align: usize) -> Result<IOVec, AllocError> {
+        self.alloc_aligned(size, align)
+    }
+
+    /// Frees the object from whichever class owns it.
+    fn deallocate(&mut self, iov: IOVec, _align: usize) -> Result<(), AllocError> {
+        self.free(iov)
+    }
+
+    fn capacity(&self) -> usize {
+        let mut total = 0;
+        for i in 0..self.num_classes { total += self.class(i).blocks() * self.class(i).block_size(); }
+        total
+    }
+
+    fn used_bytes(&self) -> usize {
+        let mut total = 0;
+        for i in 0..self.num_classes { total += self.class(i).used_blocks() * self.class(i).block_size(); }
+        total
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use ::libc::memory::IOVec;
+    use ::os::error::AllocError;
+    use ::test_support::guarded_blocks;
+    use super::{SlabCache, SizeClass};
+
+    const CLASSES: [SizeClass; 3] = [
+        SizeClass{block_size: 16, weight: 1},
+        SizeClass{block_size: 64, weight: 1},
+        SizeClass{block_size: 256, weight: 2},
+    ];
+
+    mod config {
+        use ::libc::memory::IOVec;
+        use super::super::{SlabCache, SizeClass};
+
+        #[test]
+        fn no_classes_errors() {
+            let buff = [0u8; 1024];
+            assert!(SlabCache::new(IOVec{ptr:&buff[0], size:buff.len()}, &[]).is_err());
+        }
+
+        #[test]
+        fn unsorted_classes_errors() {
+            let buff = [0u8; 1024];
+            let classes = [SizeClass{block_size: 64, weight: 1}, SizeClass{block_size: 16, weight: 1}];
+            assert!(SlabCache::new(IOVec{ptr:&buff[0], size:buff.len()}, &classes).is_err());
+        }
+
+        #[test]
+        fn zero_weight_errors() {
+            let buff = [0u8; 1024];
+            let classes = [SizeClass{block_size: 16, weight: 0}, SizeClass{block_size: 64, weight: 1}];
+            assert!(SlabCache::new(IOVec{ptr:&buff[0], size:buff.len()}, &classes).is_err());
+        }
+
+        #[test]
+        fn region_too_small_errors() {
+            let buff = [0u8; 1024];
+            let classes = [SizeClass{block_size: 16, weight: 7}, SizeClass{block_size: 512, weight: 1}];
+            assert!(SlabCache::new(IOVec{ptr:&buff[0], size:buff.len()}, &classes).is_err());
+        }
+
+        #[test]
+        fn split_by_weight() {
+            let buff = [0u8; 4096];
+            let cache = SlabCache::new(IOVec{ptr:&buff[0], size:buff.len()}, &super::CLASSES).expect("could not create");
+            assert_eq!(3, cache.classes());
+
+            // 1024 bytes for 16 and 64 byte objects, 2048 bytes for 256 byte objects (minus bitmaps)
+            assert_eq!(63, cache.stats(0).unwrap().blocks);
+            assert_eq!(15, cache.stats(1).unwrap().blocks);
+            assert_eq!(7, cache.stats(2).unwrap().blocks);
+            assert!(cache.stats(3).is_none());
+        }
+    }
+
+    #[test]
+    fn routes_to_smallest_fit() {
+        let buff = [0u8; 4096];
+        let mut cache = SlabCache::new(IOVec{ptr:&buff[0], size:buff.len()}, &CLASSES).expect("could not create");
+
+        assert_eq!(16, cache.alloc(1).expect("could not allocate").size);
+        assert_eq!(16, cache.alloc(16).expect("could not allocate").size);
+        assert_eq!(64, cache.alloc(17).expect("could not allocate").size);
+        assert_eq!(256, cache.alloc(200).expect("could not allocate").size);
+
+        assert_eq!(2 * guarded_blocks(1, 16), cache.stats(0).unwrap().used_blocks);
+        assert_eq!(guarded_blocks(1, 64), cache.stats(1).unwrap().used_blocks);
+        assert_eq!(guarded_blocks(1, 256), cache.stats(2).unwrap().used_blocks);
+    }
+
+    #[test]
+    fn alignment_is_not_a_failure() {
+        let buff = [0u8; 4096];
+        let mut cache = SlabCache::new(IOVec{ptr:&buff[0], size:buff.len()}, &CLASSES).expect("could not create");
+        cache.set_fallback(false);
+
+        // no 16 byte object can start on a boundary beyond the buffer
+        assert_eq!(Err(AllocError::UnsupportedAlignment), cache.alloc_aligned(16, 8192));
+        assert_eq!(Err(AllocError::BadAlignment), cache.alloc_aligned(16, 24));
+        assert_eq!(0, cache.stats(0).unwrap().failures);
+        assert_eq!(0, cache.stats(0).unwrap().used_blocks);
+    }
+
+    #[test]
+    fn too_large_errors() {
+        let buff = [0u8; 4096];
+        let mut cache = SlabCache::new(IOVec{ptr:&buff[0], size:buff.len()}, &CLASSES).expect("could not create");
+        assert!(cache.alloc(257).is_err());
+    }
+
+    #[test]
+    fn falls_back_when_full() {
+        let buff = [0u8; 4096];
+        let mut cache = SlabCache::new(IOVec{ptr:&buff[0], size:buff.len()}, &CLASSES).expect("could not create");
+
+        for _ in 0..(cache.stats(1).unwrap().blocks / guarded_blocks(1, 64)) {
+            cache.alloc(64).expect("could not fill class");
+        }
+
+        let spilled = cache.alloc(64).expect("did not fall back to a larger class");
+        assert_eq!(256, spilled.size);
+        assert_eq!(1, cache.stats(2).unwrap().fallbacks);
+        assert_eq!(0, cache.stats(1).unwrap().fallbacks);
+    }
+
+    #[test]
+    fn no_fallback_errors_when_full() {
+        let buff = [0u8; 4096];
+        let mut cache = SlabCache::new(IOVec{ptr:&buff[0], size:buff.len()}, &CLASSES).expect("could not create");
+        cache.set_fallback(false);
+
+        for _ in 0..(cache.stats(1).unwrap().blocks / guarded_blocks(1, 64)) {
+            cache.alloc(64).expect("could not fill class");
+        }
+        assert!(cache.alloc(64).is_err());
+        assert_eq!(1, cache.stats(1).unwrap().failures);
+        assert_eq!(0, cache.stats(2).unwrap().used_blocks);
+    }
+
+    #[test]
+    fn free_returns_to_owning_class() {
+        let buff = [0u8; 4096];
+        let mut cache = SlabCache::new(IOVec{ptr:&buff[0], size:buff.len()}, &CLASSES).expect("could not create");
+
+        let small = cache.alloc(8).expect("could not allocate");
+        let large = cache.alloc(128).expect("could not allocate");
+
+        cache.free(large).expect("could not free");
+        assert_eq!(0, cache.stats(2).unwrap().used_blocks);
+        assert_eq!(guarded_blocks(1, 16), cache.stats(0).unwrap().used_blocks);
+
+        // the requested size is enough to free the object
+        cache.free(IOVec{ptr: small.ptr, size: 8}).expect("could not free");
+        assert_eq!(0, cache.stats(0).unwrap().used_blocks);
+
+        // not ours
+        let other = [0u8; 16];
+        assert!(cache.free(IOVec{ptr:&other[0], size:16}).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+extern crate core;
+use core::cell::UnsafeCell;
+use core::ptr;
+
+use ::libc::memory::IOVec;
+use ::libc::structures::SpinLock;
+use ::os::error::AllocError;
+use ::os::sync;
+use ::traits::Allocator;
+
+use super::SlabAllocator;
+
+/// Lock-protected adapter allowing any `Allocator` (a `SlabAllocator` by default) to back the `alloc::` collections
+/// (`Vec`, `Box`, ...).
+///
+/// The heap starts out empty so it can live in a `static` of the application's allocator crate, and must be given its
+/// memory with `::init()` (or `::init_with()`) before the first allocation. Until then every allocation fails.
+///
+/// Requests are handed to `Allocator::allocate(...)` as a byte count and alignment, leaving the conversion to blocks
+/// to the wrapped allocator.
+///
+/// The lock is taken with interrupts masked, so ISRs may allocate and free without deadlocking against the code they
+/// interrupted.
+///
+/// # Examples
+/// The allocator crate linked into the firmware forwards to a static heap, reporting failures through
+/// `os::error::alloc_error`:
+///
+/// ```ignore
+/// #![allocator]
+/// #![no_std]
+/// #![feature(allocator, const_fn)]
+/// extern crate peregrine;
+/// use peregrine::os::mman::GlobalHeap;
+///
+/// pub static HEAP: GlobalHeap = GlobalHeap::empty();
+///
+/// #[no_mangle]
+/// pub extern fn __rust_allocate(size: usize, align: usize) -> *mut u8 {
+///     let ptr = unsafe { HEAP.allocate(size, align) };
+///     if ptr.is_null() { peregrine::os::error::alloc_error(size, align) }
+///     ptr
+/// }
+///
+/// #[no_mangle]
+/// pub extern fn __rust_deallocate(ptr: *mut u8, size: usize, align: usize) {
+///     unsafe { HEAP.deallocate(ptr, size, align) }
+/// }
+///
+/// // ... and likewise __rust_reallocate, __rust_reallocate_inplace and __rust_usable_size
+/// ```
+///
+/// after which the firmware gives it the heap region:
+///
+/// ```ignore
+/// #[no_mangle]
+/// pub extern "C" fn entry(mcu: K64) -> ! {
+///     heap::HEAP.init(&mcu, 32);
+///     let v = vec![1, 2, 3];
+///     // ...
+/// }
+/// ```
+pub struct GlobalHeap<A = SlabAllocator> {
+    lock: UnsafeCell<SpinLock>,
+    heap: UnsafeCell<Option<A>>,
+}
+unsafe impl<A> Sync for GlobalHeap<A> {}
+
+impl<A> GlobalHeap<A> {
+    /// Creates a heap with no memory behind it.
+    pub const fn empty() -> GlobalHeap<A> {
+        GlobalHeap{
+            lock: UnsafeCell::new(SpinLock::new()),
+            heap: UnsafeCell::new(None),
+        }
+    }
+}
+
+impl GlobalHeap<SlabAllocator> {
+
+    /// Gives the heap the MCU's heap region (`MCU::heap_memory()`), split into blocks of `block_size` bytes.
+    ///
+    /// __NOTE:__ any previous allocations are forgotten. This should be called once, before anything is allocated.
+    pub fn init(&self, mcu: &::traits::MCU, block_size: usize) {
+        self.init_from_iov(mcu.heap_memory(), block_size);
+    }
+
+    /// Gives the heap the region described by the `IOVec`, split into blocks of `block_size` bytes.
+    ///
+    /// The start of the region is trimmed so every block is aligned to the largest power of two dividing the block
+    /// size. Otherwise, alignments beyond that of the region itself could never be satisfied.
+    ///
+    /// __NOTE:__ any previous allocations are forgotten. This should be called once, before anything is allocated.
+    pub fn init_from_iov(&self, iov: IOVec, block_size: usize) {
+        let align = 1 << block_size.trailing_zeros();
+        let skip = (align - ((iov.ptr as usize) % align)) % align;
+        let aligned = if skip >= iov.size {
+            IOVec{ptr: iov.ptr, size: 0}
+        } else {
+            IOVec{ptr: unsafe { iov.ptr.offset(skip as isize) }, size: iov.size - skip}
+        };
+
+        self.init_with(SlabAllocator::new(aligned, block_size));
+    }
+}
+
+impl<A: Allocator> GlobalHeap<A> {
+    /// Gives the heap an already constructed allocator.
+    ///
+    /// __NOTE:__ any previous allocations are forgotten. This should be called once, before anything is allocated.
+    pub fn init_with(&self, allocator: A) {
+        self.locked(|heap| { *heap = Some(allocator); });
+    }
+
+    /// Check whether the heap has been given memory.
+    pub fn is_initialized(&self) -> bool {
+        self.locked(|heap| heap.is_some())
+    }
+
+    /// Get the number of bytes currently allocated, including rounding.
+    pub fn used_bytes(&self) -> usize {
+        self.locked(|heap| heap.as_ref().map_or(0, |h| h.used_bytes()))
+    }
+
+    /// Get the number of bytes available for allocation, regardless of fragmentation.
+    pub fn free_bytes(&self) -> usize {
+        self.locked(|heap| heap.as_ref().map_or(0, |h| h.free_bytes()))
+    }
+
+    /// Allocates a region of `size` bytes aligned to `align`. A null pointer is returned if the heap is not
+    /// initialized or cannot satisfy the request, leaving the caller to report it (see `os::error::alloc_error`).
+    pub unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+        self.locked(|heap| {
+            match heap.as_mut() {
+                None => ptr::null_mut(),
+                Some(h) => h.allocate(size, align).map(|iov| iov.as_mut()).unwrap_or(ptr::null_mut()),
+            }
+        })
+    }
+
+    /// Returns the region backing the pointer to the heap.
+    ///
+    /// Freeing memory the heap does not own is a bug in the caller, and is reported through
+    /// `os::error::dealloc_error` (after the lock is released).
+    pub unsafe fn deallocate(&self, ptr: *mut u8, size: usize, align: usize) {
+        let result = self.locked(|heap| {
+            match heap.as_mut() {
+                None => Err(AllocError::Uninitialized),
+                Some(h) => h.deallocate(IOVec{ptr: ptr, size: size}, align),
+            }
+        });
+        if let Err(err) = result { ::os::error::dealloc_error(err) }
+    }
+
+    /// Resizes the region backing the pointer to `size` bytes, keeping its contents up to the smaller of the two
+    /// sizes. A null pointer is returned if it cannot be, in which case the original region is left allocated.
+    pub unsafe fn reallocate(&self, ptr: *mut u8, old_size: usize, size: usize, align: usize) -> *mut u8 {
+        self.locked(|heap| {
+            match heap.as_mut() {
+                None => ptr::null_mut(),
+                Some(h) => h.reallocate(IOVec{ptr: ptr, size: old_size}, size, align)
+                    .map(|iov| iov.as_mut()).unwrap_or(ptr::null_mut()),
+            }
+        })
+    }
+
+    /// Run the given function on the wrapped allocator while holding the lock, with interrupts masked so an ISR
+    /// cannot spin on a lock held by the code it interrupted.
+    fn locked<F, R>(&self, f: F) -> R where F: FnOnce(&mut Option<A>) -> R {
+        sync::critical(|| {
+            let lock = unsafe { &mut *self.lock.get() };
+            lock.acquire();
+            let result = f(unsafe { &mut *self.heap.get() });
+            lock.release().expect("global heap lock was released while held");
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ::libc::memory::IOVec;
+    use ::os::mman::BuddyAllocator;
+    use ::test_support::guarded_blocks;
+    use super::GlobalHeap;
+
+    #[test]
+    fn uninitialized_returns_null() {
+        let heap: GlobalHeap = GlobalHeap::empty();
+        assert_eq!(false, heap.is_initialized());
+        let ptr = unsafe { heap.allocate(16, 4) };
+        assert!(ptr.is_null());
+    }
+
+    #[test]
+    fn alloc_and_dealloc() {
+        let buff = [0u8; 4096];
+        let heap: GlobalHeap = GlobalHeap::empty();
+        heap.init_from_iov(IOVec{ptr:&buff[0], size:buff.len()}, 32);
+        assert!(heap.is_initialized());
+
+        let ptr = unsafe { heap.allocate(100, 4) }; // 4 blocks
+        assert!(!ptr.is_null());
+        assert_eq!(guarded_blocks(4, 32) * 32, heap.used_bytes());
+
+        unsafe { heap.deallocate(ptr, 100, 4); }
+        assert_eq!(0, heap.used_bytes());
+    }
+
+    #[test]
+    fn zero_sized_takes_a_block() {
+        let buff = [0u8; 4096];
+        let heap: GlobalHeap = GlobalHeap::empty();
+        heap.init_from_iov(IOVec{ptr:&buff[0], size:buff.len()}, 32);
+
+        let a = unsafe { heap.allocate(0, 1) };
+        let b = unsafe { heap.allocate(0, 1) };
+        assert!(!a.is_null() && !b.is_null());
+        assert!(a != b, "zero sized allocations share an address");
+        assert_eq!(2 * guarded_blocks(1, 32) * 32, heap.used_bytes());
+    }
+
+    #[test]
+    #[should_panic(expected = "could not return memory to the global heap")]
+    fn double_free_is_reported() {
+        let buff = [0u8; 4096];
+        let heap: GlobalHeap = GlobalHeap::empty();
+        heap.init_from_iov(IOVec{ptr:&buff[0], size:buff.len()}, 32);
+
+        let ptr = unsafe { heap.allocate(32, 4) };
+        unsafe { heap.deallocate(ptr, 32, 4); }
+        unsafe { heap.deallocate(ptr, 32, 4); }
+    }
+
+    #[test]
+    fn large_alignment() {
+        let buff = [0u8; 4096];
+        let heap: GlobalHeap = GlobalHeap::empty();
+        heap.init_from_iov(IOVec{ptr:&buff[0], size:buff.len()}, 16);
+
+        for align in [8, 64, 256, 512].iter() {
+            let ptr = unsafe { heap.allocate(24, *align) };
+            assert!(!ptr.is_null(), "could not allocate with alignment {}", align);
+            assert_eq!(0, (ptr as usize) % align, "pointer is not aligned to {}", align);
+            unsafe { heap.deallocate(ptr, 24, *align); }
+        }
+        assert_eq!(0, heap.used_bytes());
+    }
+
+    #[test]
+    fn exhaustion_returns_null() {
+        let buff = [0u8; 1024];
+        let heap: GlobalHeap = GlobalHeap::empty();
+        heap.init_from_iov(IOVec{ptr:&buff[0], size:buff.len()}, 64);
+
+        let free = heap.free_bytes() / (guarded_blocks(1, 64) * 64);
+        for _ in 0..free {
+            assert!(!unsafe { heap.allocate(64, 4) }.is_null());
+        }
+        assert!(unsafe { heap.allocate(64, 4) }.is_null());
+    }
+
+    #[test]
+    fn reallocate_keeps_contents() {
+        let buff = [0u8; 4096];
+        let heap: GlobalHeap = GlobalHeap::empty();
+        heap.init_from_iov(IOVec{ptr:&buff[0], size:buff.len()}, 32);
+
+        let ptr = unsafe { heap.allocate(16, 4) };
+        for i in 0..16 { unsafe { *ptr.offset(i) = i as u8; } }
+        let grown = unsafe { heap.reallocate(ptr, 16, 200, 4) };
+        assert!(!grown.is_null());
+        for i in 0..16 { assert_eq!(i as u8, unsafe { *grown.offset(i) }); }
+        unsafe { heap.deallocate(grown, 200, 4); }
+        assert_eq!(0, heap.used_bytes());
+    }
+
+    #[test]
+    fn wraps_other_allocators() {
+        let buff = [0u8; 4096 + 16];
+        let heap: GlobalHeap<BuddyAllocator> = GlobalHeap::empty();
+        heap.init_with(BuddyAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 64));
+
+        let ptr = unsafe { heap.allocate(150, 4) }; // rounds to 4 blocks
+        assert!(!ptr.is_null());
+        assert_eq!(4*64, heap.used_bytes());
+
+        unsafe { heap.deallocate(ptr, 150, 4); }
+        assert_eq!(0, heap.used_bytes());
+    }
+}
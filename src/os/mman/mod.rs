@@ -1,2 +1,11 @@
 mod slab;
-pub use self::slab::SlabAllocator;
+pub use self::slab::{SlabAllocator, GUARD_SIZE, POISON_BYTE, CANARY_BYTE};
+
+mod buddy;
+pub use self::buddy::BuddyAllocator;
+
+mod cache;
+pub use self::cache::{SlabCache, SizeClass, ClassStats, MAX_SIZE_CLASSES};
+
+mod global;
+pub use self::global::GlobalHeap;
@@ -1,6 +1,25 @@
 use ::libc::math::uceil;
 use ::libc::memory::IOVec;
-
+use ::os::error::AllocError;
+
+/// Byte written over every free block when the `heap_debug` feature is enabled.
+pub const POISON_BYTE: u8 = 0xA5;
+
+/// Byte written around every allocation when the `heap_debug` feature is enabled.
+pub const CANARY_BYTE: u8 = 0xC3;
+
+/// Minimum number of canary bytes guarding each end of an allocation. Guards are rounded up to whole blocks.
+#[cfg(feature = "heap_debug")]
+pub const GUARD_SIZE: usize = 8;
+/// Minimum number of canary bytes guarding each end of an allocation. Guards are rounded up to whole blocks.
+#[cfg(not(feature = "heap_debug"))]
+pub const GUARD_SIZE: usize = 0;
+
+/// Fixed block size allocator tracking used blocks with a `Bitmap`.
+///
+/// With the `heap_debug` feature enabled, every allocation is surrounded by guard blocks filled with `CANARY_BYTE`, and
+/// free blocks are filled with `POISON_BYTE`. Overwritten canaries are reported on free, and writes to free blocks are
+/// reported when the block is next allocated. Guards are whole blocks so allocations keep the blocks' alignment.
 pub struct SlabAllocator {
     blocks: IOVec,
     bitmap: ::libc::structures::Bitmap,
@@ -53,6 +72,9 @@ impl SlabAllocator {
 
 
         unsafe { ::libc::memory::memset(iov.ptr as *mut u8, 0, iov.size); }
+        if cfg!(feature = "heap_debug") {
+            unsafe { ::libc::memory::memset(blks.as_mut(), POISON_BYTE, blks.size); }
+        }
         SlabAllocator{
             blocks: blks,
             bitmap: ::libc::structures::Bitmap::from_iov(bmap),
@@ -63,52 +85,104 @@ impl SlabAllocator {
 
     /// Allocate the given number of blocks. The returned IOVec should be "given back" to the allocator when freed.
     ///
-    /// Errors occur from bounds checking, internal failures, or unavailability of memory.
-    pub fn alloc(&mut self, block_count: usize) -> Result<IOVec, &'static str> {
-        if block_count > self.bitmap.free() { return Err("not enough free blocks (without checking continuity)"); }
-        if block_count > self.num_blocks { return Err("requested block count is more than exist in this heap"); }
+    /// Errors occur from bounds checking, internal failures, or unavailability of memory. With `heap_debug`, a free
+    /// block that was written to is reported with its index, and the allocation fails.
+    pub fn alloc(&mut self, block_count: usize) -> Result<IOVec, AllocError> {
+        let count = self.reserved_blocks(block_count);
+        if count > self.bitmap.free() { return Err(AllocError::OutOfMemory); }
+        if count > self.num_blocks { return Err(AllocError::TooLarge); }
+
+        let first = try!(self.bitmap.bounded_find_and_set(count, self.num_blocks));
+        self.claim(first, block_count)
+    }
 
-        let blocks = self.bitmap.bounded_find_and_set(block_count, self.num_blocks);
-        if blocks.is_err() { return Err(blocks.err().unwrap()); }
+    /// Allocate the given number of blocks such that the returned pointer lands on an `align` byte boundary.
+    ///
+    /// `align` must be a power of two. If every allocation already satisfies the alignment this is the same as
+    /// `::alloc()`, otherwise only the blocks starting on a suitable address are considered.
+    pub fn alloc_aligned(&mut self, block_count: usize, align: usize) -> Result<IOVec, AllocError> {
+        if ! align.is_power_of_two() { return Err(AllocError::BadAlignment); }
+        if align <= self.block_alignment() { return self.alloc(block_count); }
+
+        let count = self.reserved_blocks(block_count);
+        if count > self.bitmap.free() { return Err(AllocError::OutOfMemory); }
+        if count > self.num_blocks { return Err(AllocError::TooLarge); }
+
+        for first in 0..(self.num_blocks - count + 1) {
+            if ((self.block_ptr(first) as usize) + self.guard_bytes()) % align != 0 { continue; }
+
+            let mut is_free = true;
+            for i in first..(first + count) {
+                if self.bitmap.is_set(i) { is_free = false; break; }
+            }
+            if ! is_free { continue; }
 
-        Ok(IOVec{
-            ptr: unsafe { self.blocks.ptr.offset( (blocks.ok().unwrap() * self.block_size) as isize) },
-            size: block_count*self.block_size,
-        })
+            try!(self.bitmap.set_range(first, count));
+            return self.claim(first, block_count);
+        }
+
+        Err(AllocError::Fragmented)
     }
 
     /// Free the given region of allocated memory. The input ideally should come from a previous `::alloc()`.
     ///
     /// Errors occur from bounds checking, freeing unallocated/unmanaged memory, or unaligned `IOVec` pointer and/or size.
-    ///
-    /// __NOTE:__ if `Bitmap::checked_clear(...)` returns an error, the error is immediately returned. This is being worked on
-    /// but can lead to things not being freed. The solution will likely be returning the index with the error -- but we cannot
-    /// return a `Vec` of errors for instance.
-    pub fn free(&mut self, iov: IOVec) -> Result<(), &'static str> {
-        let offset = (iov.ptr as usize) - (self.blocks.ptr as usize);
+    /// Every block is checked before any is freed, so on error nothing has been freed. A block that is not allocated
+    /// (such as on a double free) or, with `heap_debug`, whose canaries were overwritten is reported with its index.
+    pub fn free(&mut self, iov: IOVec) -> Result<(), AllocError> {
+        if (iov.ptr as usize) < (self.blocks.ptr as usize) + self.guard_bytes() {
+            return Err(AllocError::NotOwned);
+        }
+        let offset = (iov.ptr as usize) - self.guard_bytes() - (self.blocks.ptr as usize);
         if offset % self.block_size != 0 {
-            return Err("iov ptr is not aligned to a block");
+            return Err(AllocError::Misaligned);
         }
         let first_block = offset / self.block_size;
 
         if iov.size % self.block_size != 0 {
-            return Err("iov size is not aligned to a block");
+            return Err(AllocError::Misaligned);
         }
-        let num_blocks = iov.size / self.block_size;
+        let num_blocks = self.reserved_blocks(iov.size / self.block_size);
         if num_blocks > self.bitmap.used() {
-            return Err("attempting to free more blocks than are used");
+            return Err(AllocError::BadSize);
         }
         if num_blocks > self.num_blocks {
-            return Err("attempting to free more blocks than are in this heap"); // TODO: kind of a duplicate
+            return Err(AllocError::BadSize); // TODO: kind of a duplicate
+        }
+
+        // check every block before touching any of them, so a bad free leaves the heap as it was
+        for i in first_block..(first_block + num_blocks) {
+            if i >= self.num_blocks {
+                return Err(AllocError::NotOwned);
+            }
+            if ! self.bitmap.is_set(i) {
+                return Err(AllocError::DoubleFree(i));
+            }
         }
 
-        for i in 0..num_blocks {
-            let check = self.bitmap.checked_clear(first_block+i); // we "naturally" limit the "unaligned" bitmap here
-            if check.is_err() {
-                return Err(check.err().unwrap()); // TODO: what to do with the things we skip on early exit?
+        let start = self.block_ptr(first_block);
+        let reserved = num_blocks * self.block_size;
+        if cfg!(feature = "heap_debug") {
+            let front = find_mismatch(start, self.guard_bytes(), CANARY_BYTE);
+            if front.is_some() {
+                return Err(AllocError::Underrun(first_block + (front.unwrap() / self.block_size)));
+            }
+
+            let back_offset = self.guard_bytes() + iov.size;
+            let back = find_mismatch(unsafe { start.offset(back_offset as isize) }, reserved - back_offset, CANARY_BYTE);
+            if back.is_some() {
+                let index = (back_offset + back.unwrap()) / self.block_size;
+                return Err(AllocError::Overrun(first_block + index));
             }
         }
 
+        for i in first_block..(first_block + num_blocks) {
+            try!(self.bitmap.checked_clear(i));
+        }
+        if cfg!(feature = "heap_debug") {
+            unsafe { ::libc::memory::memset(start as *mut u8, POISON_BYTE, reserved); }
+        }
+
         Ok(())
     }
 
@@ -118,6 +192,80 @@ impl SlabAllocator {
     pub fn free_blocks(&self) -> usize { self.bitmap.free() - (self.bitmap.count() - self.num_blocks) }
     /// Get the number of free blocks in the manager.
     pub fn used_blocks(&self) -> usize { self.bitmap.used() }
+    /// Get the size, in bytes, of each block.
+    pub fn block_size(&self) -> usize { self.block_size }
+    /// Check whether the pointer falls within the blocks managed by this allocator.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        (ptr as usize) >= (self.blocks.ptr as usize) && (ptr as usize) < (self.blocks.ptr as usize) + self.blocks.size
+    }
+    /// Get the alignment every block is guaranteed to have. This is the largest power of two dividing both the
+    /// address of the first block and the block size.
+    pub fn block_alignment(&self) -> usize { 1 << ((self.blocks.ptr as usize) | self.block_size).trailing_zeros() }
+
+    /// Get the number of blocks needed to hold `size` bytes. Zero bytes still take a block.
+    fn size_to_blocks(&self, size: usize) -> usize {
+        if size == 0 { 1 } else { uceil(size, self.block_size) }
+    }
+
+    /// Get the number of bytes in the guard at each end of an allocation. Always zero without `heap_debug`.
+    fn guard_bytes(&self) -> usize { uceil(GUARD_SIZE, self.block_size) * self.block_size }
+
+    /// Get the number of blocks actually reserved for a `block_count` allocation, including the guards.
+    fn reserved_blocks(&self, block_count: usize) -> usize { block_count + (2 * self.guard_bytes() / self.block_size) }
+
+    /// Get a pointer to the start of the given block.
+    fn block_ptr(&self, block: usize) -> *const u8 {
+        unsafe { self.blocks.ptr.offset( (block * self.block_size) as isize) }
+    }
+
+    /// Finish an allocation whose blocks (starting at `first`) are already marked used.
+    ///
+    /// With `heap_debug`, the blocks are checked to still hold the poison pattern and are then filled with canaries. If
+    /// the poison was disturbed, the blocks are released and re-poisoned so the error is only reported once.
+    fn claim(&mut self, first: usize, block_count: usize) -> Result<IOVec, AllocError> {
+        let start = self.block_ptr(first);
+        let reserved = self.reserved_blocks(block_count) * self.block_size;
+
+        if cfg!(feature = "heap_debug") {
+            let poisoned = find_mismatch(start, reserved, POISON_BYTE);
+            if poisoned.is_some() {
+                unsafe { ::libc::memory::memset(start as *mut u8, POISON_BYTE, reserved); }
+                try!(self.bitmap.clear_range(first, reserved / self.block_size));
+                return Err(AllocError::UseAfterFree(first + (poisoned.unwrap() / self.block_size)));
+            }
+            unsafe { ::libc::memory::memset(start as *mut u8, CANARY_BYTE, reserved); }
+        }
+
+        Ok(IOVec{
+            ptr: unsafe { start.offset(self.guard_bytes() as isize) },
+            size: block_count*self.block_size,
+        })
+    }
+}
+
+/// Get the offset of the first byte in the region that is not `expected`, if any.
+fn find_mismatch(ptr: *const u8, len: usize, expected: u8) -> Option<usize> {
+    for i in 0..len {
+        if unsafe { *ptr.offset(i as isize) } != expected { return Some(i); }
+    }
+    None
+}
+
+impl ::traits::Allocator for SlabAllocator {
+    /// Allocates enough blocks to hold `size` bytes. Zero sized requests still take a block.
+    fn allocate(&mut self, size: usize, align: usize) -> Result<IOVec, AllocError> {
+        let count = self.size_to_blocks(size);
+        self.alloc_aligned(count, align)
+    }
+
+    /// Frees the blocks backing the region. The alignment is not needed to find them.
+    fn deallocate(&mut self, iov: IOVec, _align: usize) -> Result<(), AllocError> {
+        let count = self.size_to_blocks(iov.size);
+        self.free(IOVec{ptr: iov.ptr, size: count * self.block_size})
+    }
+
+    fn capacity(&self) -> usize { self.num_blocks * self.block_size }
+    fn used_bytes(&self) -> usize { self.used_blocks() * self.block_size }
 }
 
 
@@ -125,6 +273,7 @@ impl SlabAllocator {
 mod test {
     mod sanity {
         use ::libc::memory::IOVec;
+        use ::test_support::guarded_blocks;
 
         #[test]
         pub fn unaligned_free_errors() {
@@ -159,8 +308,9 @@ mod test {
         pub fn alloc_bounded() {
             let buff = [0u8; 5125]; // enough for bitmap but not 8-byte aligned
             let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 1024);
+            assert_eq!(5, mman.blocks());
 
-            for i in 0..5 {
+            for i in 0..(5 / guarded_blocks(1, 1024)) {
                 let result = mman.alloc(1);
                 assert!(result.is_ok(), "could not allocate block {} out of {}: {:?}", i, mman.blocks(), result);
             }
@@ -171,6 +321,9 @@ mod test {
 
     mod dead_bitmap {
         use ::libc::memory::IOVec;
+        use ::libc::math::uceil;
+        use ::test_support::guarded_blocks;
+        use super::super::GUARD_SIZE;
 
         #[test]
         fn no_blocks_removed() {
@@ -185,22 +338,28 @@ mod test {
         fn alloc_all_and_free() {
             let buff = [0u8; 4200];
             let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 1024);
+            let reserved = guarded_blocks(1, 1024);
+            let count = mman.blocks() / reserved;
 
             let mut blocks = [IOVec{ptr:0 as *const u8, size:0}; 4];
-            for i in 0..mman.blocks() {
+            for i in 0..count {
                 let blk = mman.alloc(1).expect("could not allocate one block");
                 unsafe { ::libc::memory::memset(blk.as_mut(), 0xA0 + (i as u8), blk.size); }
-                assert_eq!(i+1, mman.used_blocks(), "incorrect used block count after allocation");
+                assert_eq!((i+1) * reserved, mman.used_blocks(), "incorrect used block count after allocation");
                 blocks[i] = blk;
             }
 
-            assert_eq!(4, mman.used_blocks(), "incorrect used block count after allocating all");
-            assert_eq!(0, mman.free_blocks(), "incorrect free block count after allocating all");
+            assert_eq!(count * reserved, mman.used_blocks(), "incorrect used block count after allocating all");
+            assert_eq!(4 - count * reserved, mman.free_blocks(), "incorrect free block count after allocating all");
 
-            // TODO: add a block size getter
-            for i in 0..(mman.blocks() * 1024) { assert_eq!(0xA0 + ((i / 1024) as u8), buff[i]); }
+            // each allocation's data block follows its front guard
+            let guard = uceil(GUARD_SIZE, 1024);
+            for i in 0..count {
+                let data = (i * reserved + guard) * 1024;
+                for j in data..(data + 1024) { assert_eq!(0xA0 + (i as u8), buff[j]); }
+            }
 
-            for i in 0..blocks.len() {
+            for i in 0..count {
                 mman.free(blocks[i].clone()).expect("could not free block");
             }
             assert_eq!(0, mman.used_blocks(), "incorrect used block count after freeing all");
@@ -211,6 +370,9 @@ mod test {
 
     mod block_bitmap {
         use ::libc::memory::IOVec;
+        use ::libc::math::uceil;
+        use ::test_support::guarded_blocks;
+        use super::super::GUARD_SIZE;
 
         #[test]
         fn round_bitmap_up() {
@@ -235,29 +397,221 @@ mod test {
         fn alloc_all_and_free() {
             let buff = [0u8; 512]; // 512 bytes @ 4byte blocks == 128 blocks == 16 bitmap bytes
             let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 4);
+            let reserved = guarded_blocks(1, 4);
+            let count = mman.blocks() / reserved;
 
             let mut blocks = [IOVec{ptr:0 as *const u8, size:0}; 124]; // only 124 data blocks
-            for i in 0..mman.blocks() {
+            for i in 0..count {
                 let blk = mman.alloc(1).expect("could not allocate one block");
                 unsafe { ::libc::memory::memset(blk.as_mut(), 0x30 + (i as u8), blk.size); }
-                assert_eq!(i+1, mman.used_blocks(), "incorrect used block count after allocation");
+                assert_eq!((i+1) * reserved, mman.used_blocks(), "incorrect used block count after allocation");
                 blocks[i] = blk;
             }
 
-            assert_eq!(124, mman.used_blocks(), "incorrect used block count after allocating all");
-            assert_eq!(0, mman.free_blocks(), "incorrect free block count after allocating all");
+            assert_eq!(count * reserved, mman.used_blocks(), "incorrect used block count after allocating all");
+            assert_eq!(124 - count * reserved, mman.free_blocks(), "incorrect free block count after allocating all");
 
-            // TODO: add a block size getter
-            for i in 16..(mman.blocks() * 4) { // start at 16 to skip the bitmap
-                assert_eq!(0x30 + (((i-16) / 4) as u8), buff[i],    // -16 due to bitmap [0, 16]
-                    "wrong value at region[{}][{}]", (i / 4), (i % 4));
+            // each allocation's data block follows its front guard
+            let guard = uceil(GUARD_SIZE, 4);
+            for i in 0..count {
+                let data = 16 + (i * reserved + guard) * 4; // 16 to skip the bitmap
+                for j in data..(data + 4) {
+                    assert_eq!(0x30 + (i as u8), buff[j], "wrong value at region[{}][{}]", i, j - data);
+                }
             }
 
-            for i in 0..blocks.len() {
+            for i in 0..count {
                 mman.free(blocks[i].clone()).expect("could not free block");
             }
             assert_eq!(0, mman.used_blocks(), "incorrect used block count after freeing all");
             assert_eq!(124, mman.free_blocks(), "incorrect free block count after freeing all");
         }
     }
+
+
+    mod aligned {
+        use ::libc::memory::IOVec;
+        use ::libc::math::uceil;
+        use ::test_support::guarded_blocks;
+        use super::super::GUARD_SIZE;
+
+        #[test]
+        fn natural_alignment_is_plain_alloc() {
+            let buff = [0u8; 4200];
+            let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 1024);
+            let align = mman.block_alignment();
+            let blk = mman.alloc_aligned(1, align).expect("could not allocate");
+            assert_eq!(&buff[uceil(GUARD_SIZE, 1024) * 1024] as *const u8, blk.ptr);
+        }
+
+        #[test]
+        fn skips_unaligned_blocks() {
+            let buff = [0u8; 1024];
+            let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 8);
+            let align = mman.block_alignment() * 8;
+
+            for _ in 0..4 {
+                let blk = mman.alloc_aligned(2, align).expect("could not allocate");
+                assert_eq!(0, (blk.ptr as usize) % align, "block is not aligned");
+                assert_eq!(16, blk.size);
+            }
+            assert_eq!(4 * guarded_blocks(2, 8), mman.used_blocks());
+        }
+
+        #[test]
+        fn non_power_of_two_errors() {
+            let buff = [0u8; 1024];
+            let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 8);
+            assert!(mman.alloc_aligned(1, 24).is_err());
+        }
+    }
+
+
+    mod allocator {
+        use ::libc::memory::IOVec;
+        use ::test_support::guarded_blocks;
+        use ::traits::Allocator;
+
+        #[test]
+        fn bytes_round_to_blocks() {
+            let buff = [0u8; 4200];
+            let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 1024);
+            assert_eq!(4*1024, mman.capacity());
+
+            let heap: &mut Allocator = &mut mman;
+            let iov = heap.allocate(1500, 1).expect("could not allocate");
+            assert_eq!(2048, iov.size);
+            assert_eq!(guarded_blocks(2, 1024) * 1024, heap.used_bytes());
+            assert_eq!(4*1024 - heap.used_bytes(), heap.free_bytes());
+
+            // the requested size is enough to free the whole allocation
+            heap.deallocate(IOVec{ptr: iov.ptr, size: 1500}, 1).expect("could not free");
+            assert_eq!(0, heap.used_bytes());
+        }
+
+        #[test]
+        fn reallocate_preserves_contents() {
+            let buff = [0u8; 1024];
+            let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 16);
+
+            let heap: &mut Allocator = &mut mman;
+            let iov = heap.allocate(16, 1).expect("could not allocate");
+            unsafe { ::libc::memory::memset(iov.as_mut(), 0x5A, iov.size); }
+
+            let grown = heap.reallocate(iov, 64, 1).expect("could not reallocate");
+            assert_eq!(64, grown.size);
+            assert_eq!(guarded_blocks(4, 16) * 16, heap.used_bytes(), "old region was not freed");
+            for i in 0..16 { assert_eq!(0x5A, unsafe { *grown.ptr.offset(i) }); }
+        }
+    }
+
+
+    mod atomic_free {
+        use ::libc::memory::IOVec;
+        use ::os::error::AllocError;
+        use ::test_support::guarded_blocks;
+
+        #[test]
+        fn partial_free_changes_nothing() {
+            let buff = [0u8; 1024];
+            let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 16);
+            let first = mman.alloc(1).expect("could not allocate");
+            let second = mman.alloc(1).expect("could not allocate");
+            mman.alloc(1).expect("could not allocate");
+            mman.free(second).expect("could not free");
+
+            // the second allocation is already free, so the first must not be freed either
+            let err = mman.free(IOVec{ptr: first.ptr, size: 32}).err().expect("freeing a free block did not error");
+            assert_eq!(AllocError::DoubleFree(guarded_blocks(1, 16)), err);
+            assert_eq!(2 * guarded_blocks(1, 16), mman.used_blocks());
+            mman.free(first).expect("first block was freed by the failed free");
+        }
+
+        #[test]
+        fn double_free_reports_block() {
+            let buff = [0u8; 1024];
+            let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 16);
+            mman.alloc(2).expect("could not allocate");
+            let blk = mman.alloc(1).expect("could not allocate");
+            mman.free(blk).expect("could not free");
+            assert_eq!(Err(AllocError::DoubleFree(guarded_blocks(2, 16))), mman.free(blk));
+        }
+    }
+
+    #[cfg(feature = "heap_debug")]
+    mod integrity {
+        use ::libc::memory::IOVec;
+        use ::os::error::AllocError;
+        use super::super::{CANARY_BYTE, GUARD_SIZE, POISON_BYTE};
+
+        #[test]
+        fn guards_surround_allocation() {
+            let buff = [0u8; 1024];
+            let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 16);
+            let blk = mman.alloc(1).expect("could not allocate");
+            assert_eq!(16, blk.size);
+            assert_eq!(3, mman.used_blocks(), "guard blocks were not reserved");
+            for i in 1..(GUARD_SIZE as isize + 1) {
+                assert_eq!(CANARY_BYTE, unsafe { *blk.ptr.offset(-i) });
+                assert_eq!(CANARY_BYTE, unsafe { *blk.ptr.offset(blk.size as isize + i - 1) });
+            }
+        }
+
+        #[test]
+        fn free_poisons_blocks() {
+            let buff = [0u8; 1024];
+            let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 16);
+            let blk = mman.alloc(1).expect("could not allocate");
+            unsafe { ::libc::memory::memset(blk.as_mut(), 0x11, blk.size); }
+            mman.free(blk).expect("could not free");
+            for i in 0..blk.size { assert_eq!(POISON_BYTE, unsafe { *blk.ptr.offset(i as isize) }); }
+        }
+
+        #[test]
+        fn overrun_reports_block() {
+            let buff = [0u8; 1024];
+            let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 16);
+            let blk = mman.alloc(1).expect("could not allocate");
+            unsafe { ::libc::memory::memset(blk.as_mut(), 0x11, blk.size + 1); } // one byte too far
+
+            let err = mman.free(blk).err().expect("overrun was not detected");
+            assert_eq!(AllocError::Overrun(2), err);
+            assert_eq!(3, mman.used_blocks(), "blocks were freed despite the error");
+        }
+
+        #[test]
+        fn underrun_reports_block() {
+            let buff = [0u8; 1024];
+            let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 16);
+            let blk = mman.alloc(1).expect("could not allocate");
+            unsafe { *blk.as_mut().offset(-1) = 0x11; }
+            assert_eq!(Err(AllocError::Underrun(0)), mman.free(blk));
+        }
+
+        #[test]
+        fn use_after_free_reported_on_alloc() {
+            let buff = [0u8; 1024];
+            let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 16);
+            let blk = mman.alloc(1).expect("could not allocate");
+            mman.free(blk).expect("could not free");
+            unsafe { *blk.as_mut().offset(4) = 0x11; } // write to freed memory
+
+            let err = mman.alloc(1).err().expect("use after free was not detected");
+            assert_eq!(AllocError::UseAfterFree(1), err);
+            assert_eq!(0, mman.used_blocks(), "failed allocation kept its blocks");
+
+            // the block is re-poisoned, so the next allocation succeeds
+            mman.alloc(1).expect("could not allocate after reporting");
+        }
+
+        #[test]
+        fn double_free_reports_block() {
+            let buff = [0u8; 1024];
+            let mut mman = super::super::SlabAllocator::new(IOVec{ptr:&buff[0], size:buff.len()}, 16);
+            mman.alloc(1).expect("could not allocate");
+            let blk = mman.alloc(1).expect("could not allocate");
+            mman.free(blk).expect("could not free");
+            assert_eq!(Err(AllocError::DoubleFree(3)), mman.free(blk));
+        }
+    }
 }
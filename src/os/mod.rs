@@ -1,3 +1,6 @@
 pub mod error;
+pub mod log;
 pub mod mman;
+pub mod sync;
+pub mod time;
 
@@ -0,0 +1,123 @@
+extern crate core;
+
+
+//------------------------------------------------
+//
+// interrupt masking
+//
+// PRIMASK is a core register, so is only reachable on the target. The tests run on the host, where each thread gets
+// a simulated PRIMASK, so code that behaves differently with interrupts masked can still be exercised.
+//
+//------------------------------------------------
+
+/// Check whether interrupts are masked (PRIMASK is set).
+pub fn interrupts_masked() -> bool { primask::read() }
+
+/// Mask interrupts, returning whether they already were.
+pub fn mask_interrupts() -> bool {
+    let masked = primask::read();
+    primask::set();
+    masked
+}
+
+/// Unmask interrupts.
+///
+/// __NOTE:__ this ends any critical section the caller is nested in. Prefer `critical(...)`, which restores the
+/// previous state.
+pub fn unmask_interrupts() { primask::clear(); }
+
+/// Run the given function with interrupts masked, so no ISR can observe or modify state part way through it.
+///
+/// Critical sections nest: interrupts are only unmasked again if they were unmasked on entry.
+pub fn critical<F, R>(f: F) -> R where F: FnOnce() -> R {
+    let masked = mask_interrupts();
+    let result = f();
+    if !masked { unmask_interrupts(); }
+    result
+}
+
+#[cfg(not(test))]
+mod primask {
+    pub fn read() -> bool {
+        let primask: u32;
+        unsafe { asm!("mrs $0, primask" : "=r"(primask) ::: "volatile"); }
+        (primask & 1) != 0
+    }
+
+    pub fn set() { unsafe { asm!("cpsid i" ::: "memory" : "volatile"); } }
+
+    pub fn clear() { unsafe { asm!("cpsie i" ::: "memory" : "volatile"); } }
+}
+
+#[cfg(test)]
+mod primask {
+    use core::cell::Cell;
+
+    thread_local!(static PRIMASK: Cell<bool> = Cell::new(false));
+
+    pub fn read() -> bool { PRIMASK.with(|p| p.get()) }
+
+    pub fn set() { PRIMASK.with(|p| p.set(true)); }
+
+    pub fn clear() { PRIMASK.with(|p| p.set(false)); }
+}
+
+
+//------------------------------------------------
+//
+// barriers
+//
+//------------------------------------------------
+
+/// Data synchronization barrier: completes every explicit memory access (including writes to system control
+/// registers) before the next instruction.
+pub fn data_barrier() { barrier::dsb(); }
+
+/// Instruction synchronization barrier: flushes the pipeline, so the following instructions see the effect of
+/// completed system control register writes (the MPU configuration, say).
+pub fn instruction_barrier() { barrier::isb(); }
+
+#[cfg(not(test))]
+mod barrier {
+    pub fn dsb() { unsafe { asm!("dsb" ::: "memory" : "volatile"); } }
+
+    pub fn isb() { unsafe { asm!("isb" ::: "memory" : "volatile"); } }
+}
+
+#[cfg(test)]
+mod barrier {
+    use core::sync::atomic::{fence, Ordering};
+
+    pub fn dsb() { fence(Ordering::SeqCst); }
+
+    pub fn isb() { fence(Ordering::SeqCst); }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{critical, interrupts_masked, mask_interrupts, unmask_interrupts};
+
+    #[test]
+    fn critical_masks_and_restores() {
+        assert_eq!(false, interrupts_masked());
+        assert_eq!(7, critical(|| {
+            assert!(interrupts_masked());
+            7
+        }));
+        assert_eq!(false, interrupts_masked());
+    }
+
+    #[test]
+    fn critical_nests() {
+        assert_eq!(false, mask_interrupts());
+        critical(|| {
+            critical(|| assert!(interrupts_masked()));
+            assert!(interrupts_masked());
+        });
+        assert!(interrupts_masked(), "a nested critical section unmasked interrupts");
+        assert_eq!(true, mask_interrupts());
+        unmask_interrupts();
+        assert_eq!(false, interrupts_masked());
+    }
+}
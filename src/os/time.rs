@@ -0,0 +1,520 @@
+extern crate core;
+use core::cell::Cell;
+use core::ops::{Add, Sub};
+use core::ptr;
+
+use ::os::error::SysTickError;
+use ::os::sync;
+use ::traits::SysTick;
+
+const MICROS_PER_MILLI: u64 = 1_000;
+const MICROS_PER_SECOND: u64 = 1_000_000;
+
+
+/// A span of time, with microsecond resolution.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Eq)]
+#[derive(PartialOrd)]
+#[derive(Ord)]
+pub struct Duration {
+    micros: u64,
+}
+impl Duration {
+    pub fn from_micros(micros: u64) -> Duration { Duration{micros: micros} }
+    pub fn from_millis(millis: u64) -> Duration { Duration{micros: millis * MICROS_PER_MILLI} }
+    pub fn from_secs(secs: u64) -> Duration { Duration{micros: secs * MICROS_PER_SECOND} }
+
+    /// Get the whole microseconds in the duration.
+    pub fn as_micros(&self) -> u64 { self.micros }
+
+    /// Get the whole milliseconds in the duration.
+    pub fn as_millis(&self) -> u64 { self.micros / MICROS_PER_MILLI }
+
+    /// Get the whole seconds in the duration.
+    pub fn as_secs(&self) -> u64 { self.micros / MICROS_PER_SECOND }
+
+    pub fn checked_add(&self, other: Duration) -> Option<Duration> {
+        self.micros.checked_add(other.micros).map(Duration::from_micros)
+    }
+
+    pub fn checked_sub(&self, other: Duration) -> Option<Duration> {
+        self.micros.checked_sub(other.micros).map(Duration::from_micros)
+    }
+}
+impl Add for Duration {
+    type Output = Duration;
+    fn add(self, other: Duration) -> Duration { Duration{micros: self.micros + other.micros} }
+}
+impl Sub for Duration {
+    type Output = Duration;
+    fn sub(self, other: Duration) -> Duration { Duration{micros: self.micros - other.micros} }
+}
+
+/// A point on a `Clock`, measured from when it was started.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Eq)]
+#[derive(PartialOrd)]
+#[derive(Ord)]
+pub struct Instant {
+    micros: u64,
+}
+impl Instant {
+    /// Get the time between the clock starting and the instant.
+    pub fn since_start(&self) -> Duration { Duration::from_micros(self.micros) }
+
+    /// Get the time from `earlier` to the instant, or zero if `earlier` is actually later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_micros(self.micros.saturating_sub(earlier.micros))
+    }
+
+    pub fn checked_add(&self, d: Duration) -> Option<Instant> {
+        self.micros.checked_add(d.micros).map(|micros| Instant{micros: micros})
+    }
+}
+impl Add<Duration> for Instant {
+    type Output = Instant;
+    fn add(self, d: Duration) -> Instant { Instant{micros: self.micros + d.micros} }
+}
+impl Sub for Instant {
+    type Output = Duration;
+    fn sub(self, earlier: Instant) -> Duration { self.duration_since(earlier) }
+}
+
+/// Convert a count of `hz` clocks to whole microseconds, without overflowing for any `u64` count.
+pub fn counts_to_micros(counts: u64, hz: u32) -> u64 {
+    let hz = hz as u64;
+    (counts / hz) * MICROS_PER_SECOND + ((counts % hz) * MICROS_PER_SECOND) / hz
+}
+
+
+//------------------------------------------------
+//
+// clock
+//
+//------------------------------------------------
+
+/// How a `Clock` learns the SysTick has wrapped.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Mode {
+    /// The SysTick exception calls `Clock::tick()`. While the exception cannot be taken (interrupts are masked, or
+    /// it is pending behind a higher priority handler) the clock polls instead, so it keeps time in ISRs and critical
+    /// sections.
+    Interrupt,
+    /// The clock polls `SysTick::has_reset()` whenever it is read. A wrap is missed if the clock is not read for a
+    /// whole tick period.
+    Polled,
+}
+
+/// Monotonic time kept from a SysTick, as a 64 bit count of its wraps plus the progress through the current one.
+///
+/// The clock owns the SysTick's reload value and its COUNTFLAG: nothing else should call `has_reset()` on it.
+/// COUNTFLAG is set once per wrap and cleared by reading it, so whichever of `tick()` and `poll()` reads it first
+/// counts the wrap, and it is never counted twice.
+pub struct Clock<'a> {
+    systick: &'a SysTick,
+    clock_hz: u32,
+    tick_hz: u32,
+    reload: u32,
+    mode: Cell<Mode>,
+    ticks: Cell<u64>,
+}
+impl<'a> Clock<'a> {
+    /// Creates a clock ticking `tick_hz` times a second, from a SysTick fed by a `clock_hz` clock source. The SysTick
+    /// is configured, but not started.
+    pub fn new(systick: &'a SysTick, clock_hz: u32, tick_hz: u32) -> Result<Clock<'a>, SysTickError> {
+        systick.disable();
+        let reload = try!(systick.set_tick_rate(clock_hz, tick_hz));
+        Ok(Clock{
+            systick: systick,
+            clock_hz: clock_hz,
+            tick_hz: tick_hz,
+            reload: reload,
+            mode: Cell::new(Mode::Polled),
+            ticks: Cell::new(0),
+        })
+    }
+
+    /// Start counting from zero. In `Mode::Interrupt`, the SysTick exception must call `tick()`.
+    pub fn start(&self, mode: Mode) {
+        self.systick.disable();
+        self.mode.set(mode);
+        self.ticks.set(0);
+        self.systick.has_reset(); // clear a stale COUNTFLAG
+        self.systick.clear_pending_exception();
+        match mode {
+            Mode::Interrupt => self.systick.enable_interrupt(),
+            Mode::Polled => self.systick.disable_interrupt(),
+        }
+        self.systick.enable();
+    }
+
+    /// Get how the clock learns of wraps.
+    pub fn mode(&self) -> Mode { self.mode.get() }
+
+    /// Get the number of ticks a second.
+    pub fn tick_hz(&self) -> u32 { self.tick_hz }
+
+    /// Get the frequency of the SysTick's clock source.
+    pub fn clock_hz(&self) -> u32 { self.clock_hz }
+
+    /// Count a wrap of the SysTick, unless a poll already has. Called from the SysTick exception in
+    /// `Mode::Interrupt`.
+    pub fn tick(&self) {
+        sync::critical(|| {
+            if self.systick.has_reset() { self.count(); }
+        });
+    }
+
+    /// Count a wrap if the SysTick has hit 0 since it was last checked. Returns whether it had.
+    ///
+    /// In `Mode::Interrupt` the exception counts wraps, so this only checks when it cannot be taken: interrupts are
+    /// masked, or it is pending. A wrap counted here also clears the pending exception.
+    pub fn poll(&self) -> bool {
+        if self.mode.get() == Mode::Interrupt && !sync::interrupts_masked() && !self.systick.is_exception_pending() {
+            return false;
+        }
+
+        sync::critical(|| {
+            if !self.systick.has_reset() { return false; }
+            self.count();
+            if self.mode.get() == Mode::Interrupt { self.systick.clear_pending_exception(); }
+            true
+        })
+    }
+
+    /// Get the number of ticks since the clock started.
+    pub fn ticks(&self) -> u64 {
+        self.poll();
+        self.read_ticks()
+    }
+
+    /// Get the number of whole milliseconds since the clock started.
+    pub fn millis(&self) -> u64 { self.now().since_start().as_millis() }
+
+    /// Get the current time, to the resolution of the SysTick's clock source.
+    pub fn now(&self) -> Instant {
+        loop {
+            self.poll();
+            let ticks = self.read_ticks();
+            let current = self.systick.current_tick() as u32;
+            self.poll();
+            if self.read_ticks() != ticks { continue; } // wrapped while reading, the count may belong to either tick
+
+            let counts = ticks * (self.reload as u64 + 1) + (self.reload - current) as u64;
+            return Instant{micros: counts_to_micros(counts, self.clock_hz)};
+        }
+    }
+
+    /// Get the time since `earlier`.
+    pub fn elapsed(&self, earlier: Instant) -> Duration { self.now().duration_since(earlier) }
+
+    /// Get the instant `d` from now, to check with `expired()`.
+    pub fn deadline(&self, d: Duration) -> Instant { self.now() + d }
+
+    /// Check whether the deadline has been reached.
+    pub fn expired(&self, deadline: Instant) -> bool { self.now() >= deadline }
+
+    /// Busy-wait for at least `d`.
+    pub fn delay(&self, d: Duration) {
+        let deadline = self.deadline(d);
+        while !self.expired(deadline) {}
+    }
+
+    /// Busy-wait for at least `us` microseconds.
+    pub fn delay_us(&self, us: u64) { self.delay(Duration::from_micros(us)); }
+
+    /// Busy-wait for at least `ms` milliseconds.
+    pub fn delay_ms(&self, ms: u64) { self.delay(Duration::from_millis(ms)); }
+
+    // a u64 is two stores, so only called with interrupts masked
+    fn count(&self) {
+        let ticks = unsafe { ptr::read_volatile(self.ticks.as_ptr()) };
+        unsafe { ptr::write_volatile(self.ticks.as_ptr(), ticks + 1); }
+    }
+
+    // a u64 is two loads, so re-read until a tick did not land between them
+    fn read_ticks(&self) -> u64 {
+        loop {
+            let first = unsafe { ptr::read_volatile(self.ticks.as_ptr()) };
+            let second = unsafe { ptr::read_volatile(self.ticks.as_ptr()) };
+            if first == second { return first; }
+        }
+    }
+}
+
+
+//------------------------------------------------
+//
+// system clock
+//
+//------------------------------------------------
+
+// read and written with interrupts masked, as the SysTick exception reads it through `tick()`
+static mut CLOCK: Option<Clock<'static>> = None;
+
+/// Start the system clock on the given SysTick, replacing any previous one.
+pub fn start(systick: &'static SysTick, clock_hz: u32, tick_hz: u32, mode: Mode) -> Result<(), SysTickError> {
+    let clock = try!(Clock::new(systick, clock_hz, tick_hz));
+    sync::critical(|| unsafe {
+        clock.start(mode);
+        CLOCK = Some(clock);
+    });
+    Ok(())
+}
+
+/// Get the system clock, if started.
+pub fn clock() -> Option<&'static Clock<'static>> {
+    sync::critical(|| unsafe { CLOCK.as_ref() })
+}
+
+/// Count a wrap of the system clock's SysTick. Call from the SysTick exception when started in `Mode::Interrupt`.
+pub fn tick() {
+    if let Some(clock) = clock() { clock.tick(); }
+}
+
+fn system_clock() -> &'static Clock<'static> {
+    match clock() {
+        Some(clock) => clock,
+        None => panic!("the system clock has not been started"),
+    }
+}
+
+/// Get the current time on the system clock.
+pub fn now() -> Instant { system_clock().now() }
+
+/// Get the number of whole milliseconds since the system clock started.
+pub fn millis() -> u64 { system_clock().millis() }
+
+/// Get the instant `d` from now on the system clock.
+pub fn deadline(d: Duration) -> Instant { system_clock().deadline(d) }
+
+/// Check whether the system clock has reached the deadline.
+pub fn expired(deadline: Instant) -> bool { system_clock().expired(deadline) }
+
+/// Busy-wait for at least `us` microseconds.
+pub fn delay_us(us: u64) { system_clock().delay_us(us); }
+
+/// Busy-wait for at least `ms` milliseconds.
+pub fn delay_ms(ms: u64) { system_clock().delay_ms(ms); }
+
+
+#[cfg(test)]
+mod test {
+    mod duration {
+        use super::super::{Duration, counts_to_micros};
+
+        #[test]
+        fn conversions() {
+            assert_eq!(1_500_000, Duration::from_millis(1_500).as_micros());
+            assert_eq!(1, Duration::from_millis(1_500).as_secs());
+            assert_eq!(2, Duration::from_micros(2_999).as_millis());
+            assert_eq!(Duration::from_secs(2), Duration::from_millis(1_500) + Duration::from_micros(500_000));
+            assert_eq!(None, Duration::from_micros(1).checked_sub(Duration::from_micros(2)));
+        }
+
+        #[test]
+        fn counts() {
+            assert_eq!(1, counts_to_micros(120, 120_000_000));
+            assert_eq!(0, counts_to_micros(119, 120_000_000));
+            // decades of 120MHz clocks, which would overflow if scaled before dividing
+            let counts = 120_000_000u64 * 3_600 * 24 * 365 * 40;
+            assert_eq!(1_000_000 * 3_600 * 24 * 365 * 40, counts_to_micros(counts, 120_000_000));
+            assert_eq!(counts_to_micros(counts, 120_000_000) + 8, counts_to_micros(counts + 1_000, 120_000_000));
+        }
+    }
+
+    mod clock {
+        use core::cell::Cell;
+        use ::os::error::SysTickError;
+        use ::os::sync;
+        use ::traits::{ClockSource, SysTick};
+        use super::super::{Clock, Duration, Mode};
+
+        // counts down `step` every time the current value is read, as if that much time passed between reads
+        struct FakeSysTick {
+            step: usize,
+            reload: Cell<usize>,
+            current: Cell<usize>,
+            count_flag: Cell<bool>,
+            enabled: Cell<bool>,
+            interrupt: Cell<bool>,
+            pending: Cell<bool>,
+        }
+        impl FakeSysTick {
+            fn new(step: usize) -> FakeSysTick {
+                FakeSysTick{
+                    step: step,
+                    reload: Cell::new(0),
+                    current: Cell::new(0),
+                    count_flag: Cell::new(false),
+                    enabled: Cell::new(false),
+                    interrupt: Cell::new(false),
+                    pending: Cell::new(false),
+                }
+            }
+        }
+        impl SysTick for FakeSysTick {
+            fn enable(&self) { self.enabled.set(true); }
+            fn disable(&self) { self.enabled.set(false); }
+            fn has_reset(&self) -> bool {
+                let flag = self.count_flag.get();
+                self.count_flag.set(false);
+                flag
+            }
+            fn enable_interrupt(&self) { self.interrupt.set(true); }
+            fn disable_interrupt(&self) { self.interrupt.set(false); }
+            fn is_exception_pending(&self) -> bool { self.pending.get() }
+            fn clear_pending_exception(&self) { self.pending.set(false); }
+            fn set_tick_reload_value(&self, val: usize) { self.reload.set(val); }
+            fn current_tick(&self) -> usize {
+                let current = self.current.get();
+                if self.enabled.get() {
+                    if current >= self.step {
+                        self.current.set(current - self.step);
+                    } else {
+                        self.current.set(self.reload.get() + 1 - (self.step - current));
+                        self.count_flag.set(true);
+                        self.pending.set(self.interrupt.get());
+                    }
+                }
+                current
+            }
+            fn has_calibration_value(&self) -> bool { false }
+            fn calibration_value(&self) -> usize { 0 }
+            fn set_clock_source(&self, _: ClockSource) {}
+            fn clock_source(&self) -> ClockSource { ClockSource::Processor }
+            fn has_external_clock(&self) -> bool { false }
+            fn set_tick_rate(&self, clock_hz: u32, tick_hz: u32) -> Result<u32, SysTickError> {
+                if tick_hz == 0 { return Err(SysTickError::ZeroRate); }
+                let reload = clock_hz / tick_hz - 1;
+                self.reload.set(reload as usize);
+                self.current.set(0);
+                Ok(reload)
+            }
+        }
+
+        #[test]
+        fn configures_systick() {
+            let systick = FakeSysTick::new(1);
+            let clock = Clock::new(&systick, 1_000_000, 1_000).unwrap();
+            assert_eq!(999, systick.reload.get());
+            assert_eq!(false, systick.enabled.get());
+
+            clock.start(Mode::Interrupt);
+            assert!(systick.enabled.get() && systick.interrupt.get());
+            clock.start(Mode::Polled);
+            assert!(systick.enabled.get());
+            assert_eq!(false, systick.interrupt.get());
+
+            assert_eq!(Err(SysTickError::ZeroRate), Clock::new(&systick, 1_000_000, 0).map(|c| c.tick_hz()));
+        }
+
+        #[test]
+        fn polled_counts_wraps() {
+            // 1MHz source, 1ms ticks, 100us between reads
+            let systick = FakeSysTick::new(100);
+            let clock = Clock::new(&systick, 1_000_000, 1_000).unwrap();
+            clock.start(Mode::Polled);
+            systick.current.set(999);
+
+            let mut last = clock.now();
+            assert_eq!(0, last.since_start().as_micros());
+            for _ in 0..50 {
+                let now = clock.now();
+                match (now - last).as_micros() {
+                    100 => {},
+                    200 => assert_eq!(0, now.since_start().as_micros() % 1_000), // re-read after wrapping
+                    step => panic!("stepped {}us", step),
+                }
+                last = now;
+            }
+            assert_eq!(5_500, last.since_start().as_micros());
+            assert_eq!(5, clock.ticks());
+        }
+
+        #[test]
+        fn interrupt_ticks() {
+            let systick = FakeSysTick::new(250);
+            let clock = Clock::new(&systick, 1_000_000, 1_000).unwrap();
+            clock.start(Mode::Interrupt);
+            systick.current.set(999);
+
+            systick.count_flag.set(true);
+            clock.tick();
+            systick.count_flag.set(true);
+            clock.tick();
+            clock.tick(); // no wrap since the last one
+            assert_eq!(2, clock.ticks());
+            assert_eq!(2_000, clock.now().since_start().as_micros());
+            assert_eq!(2_250, clock.now().since_start().as_micros());
+            assert_eq!(2, clock.millis());
+
+            systick.count_flag.set(true);
+            assert_eq!(false, clock.poll()); // the exception will count it
+            assert_eq!(2, clock.ticks());
+
+            systick.pending.set(true);
+            assert!(clock.poll(), "did not count a wrap while the exception was pending");
+            assert_eq!(false, systick.pending.get());
+            clock.tick(); // the exception was already taken, and finds nothing to count
+            assert_eq!(3, clock.ticks());
+        }
+
+        #[test]
+        fn interrupt_masked_polls() {
+            let systick = FakeSysTick::new(250);
+            let clock = Clock::new(&systick, 1_000_000, 1_000).unwrap();
+            clock.start(Mode::Interrupt);
+            systick.current.set(999);
+
+            sync::critical(|| {
+                let start = clock.now();
+                clock.delay_ms(3);
+                let waited = clock.elapsed(start);
+                assert!(waited >= Duration::from_millis(3) && waited < Duration::from_micros(4_000));
+                assert_eq!(3, clock.ticks());
+                assert_eq!(false, systick.pending.get());
+            });
+        }
+
+        #[test]
+        fn delays() {
+            let systick = FakeSysTick::new(7);
+            let clock = Clock::new(&systick, 1_000_000, 1_000).unwrap();
+            clock.start(Mode::Polled);
+            systick.current.set(999);
+
+            let start = clock.now();
+            clock.delay_ms(3);
+            let waited = clock.elapsed(start);
+            assert!(waited >= Duration::from_millis(3) && waited < Duration::from_micros(3_020));
+
+            let start = clock.now();
+            clock.delay_us(15);
+            assert!(clock.elapsed(start) >= Duration::from_micros(15));
+        }
+
+        #[test]
+        fn deadlines() {
+            let systick = FakeSysTick::new(100);
+            let clock = Clock::new(&systick, 1_000_000, 1_000).unwrap();
+            clock.start(Mode::Polled);
+            systick.current.set(999);
+
+            let deadline = clock.deadline(Duration::from_micros(250));
+            assert_eq!(false, clock.expired(deadline));
+            assert_eq!(false, clock.expired(deadline));
+            assert!(clock.expired(deadline));
+            assert_eq!(None, deadline.checked_add(Duration::from_micros(::core::u64::MAX)));
+        }
+    }
+}
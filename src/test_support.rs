@@ -0,0 +1,68 @@
+extern crate core;
+use core::cell::Cell;
+
+use ::libc::math::uceil;
+use ::libc::memory::IOVec;
+use ::mcus::cortexm4::kinetis::k64::gates::{Gate, GateRegisters};
+use ::os::error::panic::ResetControl;
+use ::os::mman::GUARD_SIZE;
+
+
+/// Get the number of blocks a slab allocation of `block_count` blocks reserves, including the guard blocks at each
+/// end with `heap_debug`.
+pub fn guarded_blocks(block_count: usize, block_size: usize) -> usize {
+    block_count + 2 * uceil(GUARD_SIZE, block_size)
+}
+
+/// Clock gates that are always open, for drivers that only need a `GateToken`.
+pub struct OpenGates;
+impl GateRegisters for OpenGates {
+    fn set_gate(&self, _: Gate, _: bool) {}
+    fn is_gate_enabled(&self, _: Gate) -> bool { true }
+}
+
+/// Records the last IRQ enabled, and does nothing else.
+pub struct MockNvic {
+    pub enabled: Cell<Option<u8>>,
+}
+impl MockNvic {
+    pub fn new() -> MockNvic { MockNvic{enabled: Cell::new(None)} }
+}
+impl ::traits::NVIC for MockNvic {
+    fn enable_irq(&self, irq: u8) { self.enabled.set(Some(irq)) }
+    fn disable_irq(&self, _: u8) {}
+    fn is_enabled(&self, irq: u8) -> bool { self.enabled.get() == Some(irq) }
+    fn set_pending(&self, _: u8) {}
+    fn clear_pending(&self, _: u8) {}
+    fn is_pending(&self, _: u8) -> bool { false }
+    fn is_active(&self, _: u8) -> bool { false }
+    fn set_priority(&self, _: u8, _: u8) {}
+    fn get_priority(&self, _: u8) -> u8 { 0 }
+}
+
+/// An MCU with the given stack, and a heap and NVIC that are never used.
+pub struct FakeMcu {
+    nvic: MockNvic,
+    stack: IOVec,
+}
+impl FakeMcu {
+    pub fn new(stack: IOVec) -> FakeMcu { FakeMcu{nvic: MockNvic::new(), stack: stack} }
+}
+impl ::traits::MCU for FakeMcu {
+    fn get_nvic(&self) -> &::traits::NVIC { &self.nvic }
+    fn stack_memory(&self) -> IOVec { self.stack }
+    fn heap_memory(&self) -> IOVec { IOVec{ptr: 0x2000_0000 as *const u8, size: 0x1000} }
+}
+
+/// Counts resets and halts instead of performing them.
+pub struct MockReset {
+    pub resets: usize,
+    pub halts: usize,
+}
+impl MockReset {
+    pub fn new() -> MockReset { MockReset{resets: 0, halts: 0} }
+}
+impl ResetControl for MockReset {
+    fn reset(&mut self) { self.resets += 1; }
+    fn halt(&mut self) { self.halts += 1; }
+}
@@ -1,3 +1,7 @@
+extern crate core;
+use core::fmt;
+
+
 //------------------------------------------------
 //
 // mcu
@@ -70,6 +74,18 @@ pub trait NVIC {
 //
 //------------------------------------------------
 
+/// Clock a SysTick counts.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum ClockSource {
+    /// The core clock.
+    Processor,
+    /// The implementation defined reference clock, if there is one.
+    External,
+}
+
 /// Enables generic handling and initialization of a SysTick core module.
 pub trait SysTick {
     /// This function should enable the ticking of the SysTick module.
@@ -86,6 +102,11 @@ pub trait SysTick {
     /// Disables the SysTick exception. No other side effects should be done, unless required to
     /// execute the disabling.
     fn disable_interrupt(&self);
+    /// This function should indicate if the SysTick exception is pending, i.e. the counter has hit 0 with the
+    /// exception enabled but it has not been taken yet.
+    fn is_exception_pending(&self) -> bool;
+    /// This function should clear a pending SysTick exception, if any.
+    fn clear_pending_exception(&self);
 
     /// This function should set the value from which the counter will tick.
     fn set_tick_reload_value(&self, val: usize);
@@ -100,5 +121,194 @@ pub trait SysTick {
     /// or not. The caller is responsible for checking its validity.
     fn calibration_value(&self) -> usize;
 
-    // TODO: clock sourcing
+    /// This function should select the clock the counter decrements with.
+    fn set_clock_source(&self, src: ClockSource);
+    /// This function should return the clock the counter decrements with.
+    fn clock_source(&self) -> ClockSource;
+    /// This function should indicate if the implementation provides the external reference clock.
+    fn has_external_clock(&self) -> bool;
+
+    /// This function should set the reload value so the counter wraps `tick_hz` times a second, given the
+    /// frequency of the selected clock source, and restart the count. The reload value used is returned.
+    fn set_tick_rate(&self, clock_hz: u32, tick_hz: u32) -> Result<u32, ::os::error::SysTickError>;
+}
+
+
+//------------------------------------------------
+//
+// clocks
+//
+//------------------------------------------------
+
+/// A clock in an MCU's clock tree. Not every MCU has every clock.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Clock {
+    /// Output of the clock generator, which the core, bus, flexbus and flash clocks are divided from.
+    McgOut,
+    /// Core and system clock.
+    Core,
+    /// Bus clock, driving most peripherals.
+    Bus,
+    /// External bus interface clock.
+    FlexBus,
+    /// Flash memory clock.
+    Flash,
+    /// External reference from the system oscillator (OSCERCLK).
+    OscEr,
+    /// Internal reference from the clock generator (MCGIRCLK).
+    McgIr,
+    /// Low power oscillator, about 1kHz.
+    Lpo,
+    /// 32kHz reference for the low power peripherals (ERCLK32K).
+    Erclk32k,
+    /// FLL, PLL or 48MHz internal reference, as selected for peripherals like USB and SDHC (MCGPLLCLK/MCGFLLCLK).
+    PllFll,
+}
+
+/// Reports the frequencies of an MCU's clocks, as currently configured, so drivers can derive dividers and baud
+/// rates from them.
+pub trait Clocks {
+    /// Get the frequency of the clock in Hz, or None if it is not running or unknown.
+    fn frequency(&self, clock: Clock) -> Option<u32>;
+}
+
+
+//------------------------------------------------
+//
+// gpio
+//
+//------------------------------------------------
+
+/// A digital pin configured as an input.
+pub trait InputPin {
+    /// Check whether the pin reads high.
+    fn is_high(&self) -> bool;
+    /// Check whether the pin reads low.
+    fn is_low(&self) -> bool { !self.is_high() }
+}
+
+/// A digital pin configured as an output.
+pub trait OutputPin {
+    /// Drive the pin high.
+    fn set_high(&self);
+    /// Drive the pin low.
+    fn set_low(&self);
+    /// Drive the pin to the opposite level.
+    fn toggle(&self);
+    /// Check whether the pin is being driven high. This is the level set, not a read of the pin.
+    fn is_set_high(&self) -> bool;
+}
+
+
+//------------------------------------------------
+//
+// memory allocation
+//
+//------------------------------------------------
+
+/// Standard interface to a heap, regardless of the strategy used to manage it.
+///
+/// Sizes are given in bytes, and each allocator rounds them up to the granularity it manages. The `IOVec` returned
+/// by `allocate` describes the whole rounded region. When deallocating, the `IOVec` size may be anything from the
+/// requested size up to the returned size, and the alignment must be the one used to allocate.
+pub trait Allocator {
+    /// Allocates at least `size` bytes starting on an `align` byte boundary. The alignment must be a power of two.
+    fn allocate(&mut self, size: usize, align: usize) -> Result<::libc::memory::IOVec, ::os::error::AllocError>;
+
+    /// Returns a previous allocation to the heap.
+    fn deallocate(&mut self, iov: ::libc::memory::IOVec, align: usize) -> Result<(), ::os::error::AllocError>;
+
+    /// Resizes an allocation, preserving its contents up to the smaller of the two sizes.
+    ///
+    /// The default implementation allocates a new region, copies into it, and then frees the old region. If the old
+    /// region cannot be freed, the new one is released and the error returned.
+    fn reallocate(&mut self, iov: ::libc::memory::IOVec, size: usize, align: usize)
+        -> Result<::libc::memory::IOVec, ::os::error::AllocError>
+    {
+        let result = try!(self.allocate(size, align));
+        let keep = if iov.size < result.size { iov.size } else { result.size };
+        unsafe { ::libc::memory::memcpy(result.as_mut(), iov.as_mut(), keep); }
+
+        match self.deallocate(iov, align) {
+            Ok(_) => Ok(result),
+            Err(e) => {
+                try!(self.deallocate(result, align));
+                Err(e)
+            }
+        }
+    }
+
+    /// Get the total number of bytes the heap can hand out.
+    fn capacity(&self) -> usize;
+    /// Get the number of bytes currently allocated, including rounding.
+    fn used_bytes(&self) -> usize;
+    /// Get the number of bytes available, regardless of fragmentation.
+    fn free_bytes(&self) -> usize { self.capacity() - self.used_bytes() }
+}
+
+
+//------------------------------------------------
+//
+// logging
+//
+//------------------------------------------------
+
+/// Destination for the messages of `os::log`, such as a trace port or a serial line.
+///
+/// Backends are called from any context the application logs from, so they must not block indefinitely.
+pub trait LogBackend {
+    /// Write a single message. The backend adds any framing it needs (level prefix, line ending, ...).
+    fn log(&mut self, level: ::os::log::Level, args: fmt::Arguments) -> fmt::Result;
+}
+
+
+//------------------------------------------------
+//
+// serial
+//
+//------------------------------------------------
+
+/// How a serial port moves bytes between the caller and the hardware.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum SerialMode {
+    /// Bytes go straight to and from the hardware, spinning while it is busy.
+    Blocking,
+    /// Bytes are queued, and moved to and from the hardware by its interrupt.
+    Interrupt,
+}
+
+/// A byte oriented serial port, such as a UART.
+pub trait Serial {
+    /// Get how bytes are moved.
+    fn mode(&self) -> SerialMode;
+
+    /// Send or queue the byte without blocking. Returns false if there is no room for it.
+    fn try_write(&self, byte: u8) -> bool;
+    /// Get the next received byte without blocking, if there is one. Line errors are reported once, by the next read.
+    fn try_read(&self) -> Result<Option<u8>, ::os::error::SerialError>;
+    /// Block until every byte written has left the port.
+    fn flush(&self);
+
+    /// Send the byte, blocking until there is room for it.
+    fn write(&self, byte: u8) {
+        while !self.try_write(byte) {}
+    }
+
+    /// Send every byte, blocking until there is room for them.
+    fn write_all(&self, bytes: &[u8]) {
+        for byte in bytes { self.write(*byte); }
+    }
+
+    /// Block until a byte is received.
+    fn read(&self) -> Result<u8, ::os::error::SerialError> {
+        loop {
+            if let Some(byte) = try!(self.try_read()) { return Ok(byte); }
+        }
+    }
 }
@@ -0,0 +1,382 @@
+//! Generates GNU ld linker scripts from a description of an MCU's memory map.
+//!
+//! The description is checked before anything is generated, and the script carries `ASSERT`s for what can only be
+//! known at link time (such as the statics growing into the heap or stack).
+//!
+//! Within a RAM region the layout is always: statics (`.data`, `.bss`, `.noinit`), then the heap, then the stack
+//! ending at the top of the region. The script exports the symbols the `mcu!` definitions expect:
+//!
+//!   - `__data_load`, `__data_load_end`, `__data_begin`, `__data_end`
+//!   - `__bss_begin`, `__bss_end`
+//!   - `__heap_begin`, `__heap_end`
+//!   - `__stack_begin`, `__stack_end` (`__stack_end` is the initial stack pointer)
+
+use std::fmt::Write;
+
+
+//------------------------------------------------
+//
+// description
+//
+//------------------------------------------------
+
+/// What a region of memory can hold.
+#[derive(Copy)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Kind {
+    /// Read-only, executable memory. Holds the vector table, code, constants, and the `.data` load image.
+    Flash,
+    /// Read-write memory. Holds the statics, heap, and stack.
+    Ram,
+}
+
+/// A contiguous region of memory.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Region {
+    pub name: &'static str,
+    pub origin: u32,
+    pub length: u32,
+    pub kind: Kind,
+}
+impl Region {
+    /// Get the first address after the region.
+    pub fn end(&self) -> u64 { self.origin as u64 + self.length as u64 }
+}
+
+/// Bytes that must be placed at a fixed offset from the start of flash, such as the Kinetis flash configuration field.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct FixedBytes {
+    pub section: &'static str,
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// A block of RAM (the stack or heap) placed in a named region.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Placement {
+    pub region: &'static str,
+    pub size: u32,
+}
+
+/// The full memory map of an MCU.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct MemoryMap {
+    /// Name of the MCU, used in the generated header.
+    pub name: &'static str,
+    /// Every region of memory. The first `Kind::Flash` region holds the vector table at its origin.
+    pub regions: Vec<Region>,
+    /// Name of the section holding the vector table.
+    pub vector_section: &'static str,
+    /// Bytes reserved for the vector table.
+    pub vector_table_size: u32,
+    /// Data that must be at a fixed offset in flash, after the vector table.
+    pub fixed: Vec<FixedBytes>,
+    /// RAM region holding `.data`, `.bss`, and `.noinit`.
+    pub statics: &'static str,
+    pub heap: Placement,
+    pub stack: Placement,
+}
+
+
+//------------------------------------------------
+//
+// checking
+//
+//------------------------------------------------
+
+/// Check the memory map for mistakes that would give a broken or misleading linker script.
+///
+/// Errors are returned as a message naming the problem, for the build to fail with.
+pub fn validate(map: &MemoryMap) -> Result<(), String> {
+    for r in map.regions.iter() {
+        if r.length == 0 { return Err(format!("region {} is empty", r.name)); }
+        if r.end() > (1u64 << 32) { return Err(format!("region {} extends beyond the address space", r.name)); }
+    }
+    for i in 0..map.regions.len() {
+        for j in (i+1)..map.regions.len() {
+            let (a, b) = (&map.regions[i], &map.regions[j]);
+            if a.name == b.name { return Err(format!("region {} is defined twice", a.name)); }
+            if (a.origin as u64) < b.end() && (b.origin as u64) < a.end() {
+                return Err(format!("regions {} and {} overlap", a.name, b.name));
+            }
+        }
+    }
+
+    let flash = first_flash(map)?;
+    if map.vector_table_size > flash.length {
+        return Err(format!("vector table does not fit in {}", flash.name));
+    }
+    for f in map.fixed.iter() {
+        if f.offset < map.vector_table_size {
+            return Err(format!("{} at 0x{:X} overlaps the vector table", f.section, f.offset));
+        }
+        if f.offset as u64 + f.bytes.len() as u64 > flash.length as u64 {
+            return Err(format!("{} does not fit in {}", f.section, flash.name));
+        }
+    }
+    for i in 0..map.fixed.len() {
+        for j in (i+1)..map.fixed.len() {
+            let (a, b) = (&map.fixed[i], &map.fixed[j]);
+            if a.offset < b.offset + b.bytes.len() as u32 && b.offset < a.offset + a.bytes.len() as u32 {
+                return Err(format!("{} and {} overlap", a.section, b.section));
+            }
+        }
+    }
+
+    ram_region(map, map.statics, "statics")?;
+    let heap = ram_region(map, map.heap.region, "heap")?;
+    let stack = ram_region(map, map.stack.region, "stack")?;
+    if map.heap.size == 0 { return Err(String::from("heap is empty")); }
+    if map.stack.size == 0 { return Err(String::from("stack is empty")); }
+    if map.heap.size > heap.length {
+        return Err(format!("heap (0x{:X} bytes) is larger than {}", map.heap.size, heap.name));
+    }
+    if map.stack.size > stack.length {
+        return Err(format!("stack (0x{:X} bytes) is larger than {}", map.stack.size, stack.name));
+    }
+    if heap.name == stack.name && (map.heap.size as u64 + map.stack.size as u64) > heap.length as u64 {
+        return Err(format!("stack and heap are larger than {}", heap.name));
+    }
+
+    let total_ram = map.regions.iter().filter(|r| r.kind == Kind::Ram).fold(0u64, |sum, r| sum + r.length as u64);
+    if (map.heap.size as u64 + map.stack.size as u64) > total_ram {
+        return Err(String::from("stack and heap are larger than all RAM"));
+    }
+
+    Ok(())
+}
+
+/// Get the region holding the vector table.
+fn first_flash(map: &MemoryMap) -> Result<&Region, String> {
+    match map.regions.iter().find(|r| r.kind == Kind::Flash) {
+        Some(r) => Ok(r),
+        None => Err(String::from("no flash region to hold the vector table")),
+    }
+}
+
+/// Get the named RAM region, for the given use.
+fn ram_region<'a>(map: &'a MemoryMap, name: &str, usage: &str) -> Result<&'a Region, String> {
+    match map.regions.iter().find(|r| r.name == name) {
+        None => Err(format!("{} region {} does not exist", usage, name)),
+        Some(r) if r.kind != Kind::Ram => Err(format!("{} region {} is not RAM", usage, name)),
+        Some(r) => Ok(r),
+    }
+}
+
+
+//------------------------------------------------
+//
+// generation
+//
+//------------------------------------------------
+
+/// Check the memory map and render the linker script.
+pub fn generate(map: &MemoryMap) -> Result<String, String> {
+    validate(map)?;
+    let flash = first_flash(map)?;
+
+    let mut out = String::new();
+    let _ = writeln!(out, "/* {} linker script. generated from the memory map in build.rs, do not edit. */\n", map.name);
+
+    let _ = writeln!(out, "MEMORY\n{{");
+    for r in map.regions.iter() {
+        let attrs = match r.kind { Kind::Flash => "rx", Kind::Ram => "rwx" };
+        let _ = writeln!(out, "    {} ({}) : ORIGIN = 0x{:08X}, LENGTH = 0x{:08X}", r.name, attrs, r.origin, r.length);
+    }
+    let _ = writeln!(out, "}}\n");
+
+    let _ = writeln!(out, "SECTIONS\n{{");
+
+    // fixed layout at the start of flash
+    let _ = writeln!(out, "    {} ORIGIN({}) :\n    {{\n        KEEP(*({}))\n    }} > {}",
+        map.vector_section, flash.name, map.vector_section, flash.name);
+    let _ = writeln!(out, "    ASSERT(SIZEOF({}) <= 0x{:X}, \"vector table is too large\")\n",
+        map.vector_section, map.vector_table_size);
+
+    let mut fixed = map.fixed.clone();
+    fixed.sort_by_key(|f| f.offset);
+    for f in fixed.iter() {
+        let _ = writeln!(out, "    {} ORIGIN({}) + 0x{:X} :\n    {{", f.section, flash.name, f.offset);
+        for b in f.bytes.iter() {
+            let _ = writeln!(out, "        BYTE(0x{:02X})", b);
+        }
+        let _ = writeln!(out, "    }} > {}\n", flash.name);
+    }
+
+    // code and constants
+    let _ = writeln!(out, "    .text :\n    {{\n        *(.text .text.*)\n        *(.rodata .rodata.*)\n        . = ALIGN(4);\n    }} > {}\n", flash.name);
+
+    // statics
+    let _ = writeln!(out, "    .data :\n    {{\n        . = ALIGN(4);\n        __data_begin = .;\n        *(.data .data.*)\n        . = ALIGN(4);\n        __data_end = .;\n    }} > {} AT> {}", map.statics, flash.name);
+    let _ = writeln!(out, "    __data_load = LOADADDR(.data);\n    __data_load_end = __data_load + SIZEOF(.data);\n");
+    let _ = writeln!(out, "    .bss (NOLOAD) :\n    {{\n        . = ALIGN(4);\n        __bss_begin = .;\n        *(.bss .bss.* COMMON)\n        . = ALIGN(4);\n        __bss_end = .;\n    }} > {}\n", map.statics);
+    let _ = writeln!(out, "    .noinit (NOLOAD) :\n    {{\n        *(.noinit .noinit.*)\n        . = ALIGN(8);\n    }} > {}", map.statics);
+    let _ = writeln!(out, "    __statics_end = .;\n");
+
+    // heap, after the statics if they share a region
+    if map.heap.region == map.statics {
+        let _ = writeln!(out, "    __heap_begin = __statics_end;");
+    } else {
+        let _ = writeln!(out, "    __heap_begin = ORIGIN({});", map.heap.region);
+    }
+    let _ = writeln!(out, "    __heap_end = __heap_begin + 0x{:X};", map.heap.size);
+    let _ = writeln!(out, "    ASSERT(__heap_end <= ORIGIN({0}) + LENGTH({0}), \"heap does not fit in {0}\")\n", map.heap.region);
+
+    // stack, at the top of its region
+    let _ = writeln!(out, "    __stack_end = ORIGIN({0}) + LENGTH({0});", map.stack.region);
+    let _ = writeln!(out, "    __stack_begin = __stack_end - 0x{:X};", map.stack.size);
+    if map.stack.region == map.heap.region {
+        let _ = writeln!(out, "    ASSERT(__stack_begin >= __heap_end, \"stack overlaps the heap\")");
+    }
+    if map.stack.region == map.statics {
+        let _ = writeln!(out, "    ASSERT(__stack_begin >= __statics_end, \"stack overlaps the statics\")");
+    }
+
+    let _ = writeln!(out, "}}");
+    Ok(out)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{FixedBytes, Kind, MemoryMap, Placement, Region};
+
+    fn k64_like() -> MemoryMap {
+        MemoryMap{
+            name: "test",
+            regions: vec![
+                Region{name: "FLASH", origin: 0x0000_0000, length: 0x0010_0000, kind: Kind::Flash},
+                Region{name: "SRAM_L", origin: 0x1FFF_0000, length: 0x0001_0000, kind: Kind::Ram},
+                Region{name: "SRAM_U", origin: 0x2000_0000, length: 0x0003_0000, kind: Kind::Ram},
+            ],
+            vector_section: ".interrupt_table",
+            vector_table_size: 0x400,
+            fixed: vec![FixedBytes{section: ".flash_config", offset: 0x400, bytes: vec![0xFF; 16]}],
+            statics: "SRAM_L",
+            heap: Placement{region: "SRAM_U", size: 0x3_0000},
+            stack: Placement{region: "SRAM_L", size: 0x4000},
+        }
+    }
+
+    mod validate {
+        use super::k64_like;
+        use super::super::{validate, FixedBytes, Kind, Region};
+
+        #[test]
+        fn valid_map() {
+            assert_eq!(Ok(()), validate(&k64_like()));
+        }
+
+        #[test]
+        fn overlapping_regions() {
+            let mut map = k64_like();
+            map.regions[1].length = 0x1_0001; // one byte into SRAM_U
+            assert!(validate(&map).unwrap_err().contains("overlap"));
+        }
+
+        #[test]
+        fn touching_regions_do_not_overlap() {
+            let mut map = k64_like();
+            map.regions.push(Region{name: "EXTRA", origin: 0x2003_0000, length: 0x100, kind: Kind::Ram});
+            assert_eq!(Ok(()), validate(&map));
+        }
+
+        #[test]
+        fn fixed_bytes_over_vector_table() {
+            let mut map = k64_like();
+            map.fixed[0].offset = 0x3F8;
+            assert!(validate(&map).unwrap_err().contains("vector table"));
+        }
+
+        #[test]
+        fn overlapping_fixed_bytes() {
+            let mut map = k64_like();
+            map.fixed.push(FixedBytes{section: ".other", offset: 0x408, bytes: vec![0; 4]});
+            assert!(validate(&map).unwrap_err().contains("overlap"));
+        }
+
+        #[test]
+        fn heap_larger_than_region() {
+            let mut map = k64_like();
+            map.heap.size = 0x3_0001;
+            assert!(validate(&map).unwrap_err().contains("heap"));
+        }
+
+        #[test]
+        fn stack_and_heap_share_region() {
+            let mut map = k64_like();
+            map.stack.region = "SRAM_U";
+            map.heap.size = 0x2_F000;
+            assert!(validate(&map).unwrap_err().contains("stack and heap"));
+
+            map.heap.size = 0x2_C000;
+            assert_eq!(Ok(()), validate(&map));
+        }
+
+        #[test]
+        fn larger_than_all_ram() {
+            let mut map = k64_like();
+            map.regions[2].kind = Kind::Flash; // only SRAM_L left
+            map.heap.region = "SRAM_L";
+            map.heap.size = 0x1_0000;
+            assert!(validate(&map).is_err());
+        }
+
+        #[test]
+        fn stack_in_flash() {
+            let mut map = k64_like();
+            map.stack.region = "FLASH";
+            assert!(validate(&map).unwrap_err().contains("not RAM"));
+        }
+
+        #[test]
+        fn unknown_region() {
+            let mut map = k64_like();
+            map.statics = "SRAM_X";
+            assert!(validate(&map).unwrap_err().contains("does not exist"));
+        }
+    }
+
+    mod generate {
+        use super::k64_like;
+        use super::super::generate;
+
+        #[test]
+        fn exports_symbols() {
+            let script = generate(&k64_like()).expect("could not generate");
+            for sym in ["__data_load", "__data_load_end", "__data_begin", "__data_end", "__bss_begin", "__bss_end",
+                        "__heap_begin", "__heap_end", "__stack_begin", "__stack_end"].iter() {
+                assert!(script.contains(&format!("{} =", sym)), "{} is not defined", sym);
+            }
+        }
+
+        #[test]
+        fn memory_regions() {
+            let script = generate(&k64_like()).expect("could not generate");
+            assert!(script.contains("SRAM_L (rwx) : ORIGIN = 0x1FFF0000, LENGTH = 0x00010000"));
+            assert!(script.contains(".flash_config ORIGIN(FLASH) + 0x400"));
+            assert!(script.contains(".noinit (NOLOAD)"));
+        }
+
+        #[test]
+        fn stack_guards() {
+            let script = generate(&k64_like()).expect("could not generate");
+            assert!(script.contains("__heap_begin = ORIGIN(SRAM_U);"));
+            assert!(script.contains("ASSERT(__stack_begin >= __statics_end"));
+            assert!(!script.contains("ASSERT(__stack_begin >= __heap_end"), "stack and heap are in different regions");
+        }
+
+        #[test]
+        fn invalid_map_is_not_generated() {
+            let mut map = k64_like();
+            map.stack.size = 0;
+            assert!(generate(&map).is_err());
+        }
+    }
+}